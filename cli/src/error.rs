@@ -0,0 +1,26 @@
+//! Errors shown to the user of the command-line client
+
+use std::path::PathBuf;
+
+use securebeam_core::Error;
+
+/// Why a command failed
+#[derive(Debug, thiserror::Error)]
+pub enum CliError {
+    #[error("{}", describe(.0))]
+    Core(#[from] Error),
+    #[error("Cannot access {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("{0}")]
+    Invalid(String),
+}
+
+/// Core errors display generic text; the user of a local tool may see why
+fn describe(error: &Error) -> String {
+    match (error, error.details()) {
+        (Error::WrongCode, _) => "The codes do not match; check the code and try again".into(),
+        (Error::MitmDetected, _) => "The verifier was not confirmed".into(),
+        (_, Some(details)) => format!("{}: {}", error, details),
+        (_, None) => error.to_string(),
+    }
+}
@@ -0,0 +1,206 @@
+//! SecureBeam Command-Line Client
+//!
+//! Sends and receives files, directories and text without the desktop app,
+//! e.g. on servers and CI machines. Transfers work with the desktop client
+//! on the other side.
+
+mod error;
+mod transfer;
+mod ui;
+
+use clap::{Parser, Subcommand};
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use securebeam_core::{
+    code::DEFAULT_WORD_COUNT,
+    mailbox::DEFAULT_APPID,
+    session::{Payload, Servers},
+    transit::{TransitMode, DEFAULT_TOR_SOCKS},
+    DEFAULT_MAILBOX, DEFAULT_RELAY,
+};
+
+use crate::error::CliError;
+
+/// Secure peer-to-peer file transfer
+#[derive(Debug, Parser)]
+#[command(name = "securebeam", version)]
+struct Cli {
+    /// Mailbox server URL
+    #[arg(long, global = true, env = "SECUREBEAM_MAILBOX", default_value = DEFAULT_MAILBOX)]
+    mailbox: String,
+
+    /// Transit relay, e.g. tcp://relay.example.com:4001 (repeatable)
+    #[arg(
+        long = "relay",
+        global = true,
+        env = "SECUREBEAM_RELAY",
+        value_delimiter = ',',
+        default_value = DEFAULT_RELAY
+    )]
+    relays: Vec<String>,
+
+    /// Application ID; both sides must use the same one
+    #[arg(long, global = true, default_value = DEFAULT_APPID)]
+    appid: String,
+
+    /// Connect to the peer only through Tor, never revealing our IP address
+    #[arg(long, global = true)]
+    tor: bool,
+
+    /// Tor SOCKS proxy used with --tor
+    #[arg(long, global = true, env = "SECUREBEAM_TOR_SOCKS", default_value = DEFAULT_TOR_SOCKS)]
+    tor_socks: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Send a file, a directory or a text message
+    Send {
+        /// File or directory to send
+        #[arg(required_unless_present = "text", conflicts_with = "text")]
+        path: Option<PathBuf>,
+
+        /// Send this text instead of a file; `-` reads it from standard input
+        #[arg(long)]
+        text: Option<String>,
+
+        /// Number of words in the generated code
+        #[arg(long, default_value_t = DEFAULT_WORD_COUNT)]
+        words: usize,
+
+        /// Ask to confirm the verifier before sending
+        #[arg(long)]
+        verify: bool,
+    },
+    /// Receive using the code shown by the sender
+    Receive {
+        code: String,
+
+        /// Directory to save received files in
+        #[arg(short, long, default_value = ".")]
+        output: PathBuf,
+
+        /// Ask to confirm the verifier before receiving
+        #[arg(long)]
+        verify: bool,
+    },
+}
+
+async fn run(cli: Cli) -> Result<(), CliError> {
+    let servers = Servers {
+        mailbox_url: cli.mailbox,
+        relays: cli.relays,
+        appid: cli.appid,
+    };
+    transfer::validate(&servers)?;
+    let mode = if cli.tor {
+        TransitMode::Tor {
+            socks_addr: cli.tor_socks,
+        }
+    } else {
+        TransitMode::Direct
+    };
+
+    match cli.command {
+        Command::Send {
+            path,
+            text,
+            words,
+            verify,
+        } => {
+            let payload = match (path, text) {
+                (_, Some(text)) if text == "-" => Payload::Text(read_stdin()?),
+                (_, Some(text)) => Payload::Text(text),
+                (Some(path), None) if path.is_dir() => Payload::Directory(path),
+                (Some(path), None) => Payload::File(path),
+                (None, None) => unreachable!("clap requires a path or --text"),
+            };
+            transfer::send(&servers, &mode, payload, words, verify, ui::show_code).await
+        }
+        Command::Receive {
+            code,
+            output,
+            verify,
+        } => transfer::receive(&servers, &mode, &code, &output, verify).await,
+    }
+}
+
+fn read_stdin() -> Result<String, CliError> {
+    let mut text = String::new();
+    std::io::stdin()
+        .read_to_string(&mut text)
+        .map_err(|e| CliError::Io(PathBuf::from("standard input"), e))?;
+    Ok(text)
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run(Cli::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli_definition() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_send_takes_a_path_or_text() {
+        let cli = Cli::try_parse_from(["securebeam", "send", "--text", "hi"]).unwrap();
+        assert!(
+            matches!(cli.command, Command::Send { text: Some(t), path: None, .. } if t == "hi")
+        );
+
+        assert!(Cli::try_parse_from(["securebeam", "send"]).is_err());
+        assert!(Cli::try_parse_from(["securebeam", "send", "a.txt", "--text", "hi"]).is_err());
+    }
+
+    #[test]
+    fn test_server_overrides() {
+        let cli = Cli::try_parse_from([
+            "securebeam",
+            "receive",
+            "7-guitarist-revenge",
+            "--mailbox",
+            "http://localhost:8080",
+            "--relay",
+            "tcp://a:4001",
+            "--relay",
+            "tcp://b:4001",
+        ])
+        .unwrap();
+        assert_eq!(cli.mailbox, "http://localhost:8080");
+        assert_eq!(cli.relays, ["tcp://a:4001", "tcp://b:4001"]);
+        assert!(!cli.tor);
+    }
+
+    #[test]
+    fn test_tor_mode() {
+        let cli = Cli::try_parse_from([
+            "securebeam",
+            "send",
+            "a.txt",
+            "--tor",
+            "--tor-socks",
+            "127.0.0.1:9150",
+        ])
+        .unwrap();
+        assert!(cli.tor);
+        assert_eq!(cli.tor_socks, "127.0.0.1:9150");
+    }
+}
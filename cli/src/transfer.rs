@@ -0,0 +1,165 @@
+//! Sending and receiving
+//!
+//! The transfer itself is run by the core session module; this drives it
+//! from the command line and stops it on Ctrl-C.
+
+use std::path::Path;
+
+use tokio::sync::mpsc;
+
+use securebeam_core::{
+    code,
+    session::{Payload, Received, Receiver, Sender, Servers},
+    transit::{RelayHint, TransitMode},
+};
+
+use crate::error::CliError;
+use crate::ui;
+
+/// Check the relay URLs before connecting anywhere
+pub fn validate(servers: &Servers) -> Result<(), CliError> {
+    if servers.relays.is_empty() {
+        return Err(CliError::Invalid(
+            "At least one relay is required".to_string(),
+        ));
+    }
+    match servers
+        .relays
+        .iter()
+        .find(|relay| RelayHint::new(relay).parse().is_none())
+    {
+        Some(relay) => Err(CliError::Invalid(format!(
+            "Invalid relay {}, expected tcp://host:port",
+            relay
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Send `payload` with a newly allocated code, passed to `show_code`
+pub async fn send(
+    servers: &Servers,
+    mode: &TransitMode,
+    payload: Payload,
+    words: usize,
+    verify: bool,
+    show_code: impl FnOnce(&str),
+) -> Result<(), CliError> {
+    // The session shows the server's message of the day
+    let mut mailbox = servers.signaling().connect_mailbox(&servers.appid).await?;
+    let code = code::allocate_code(&mut mailbox, words).await?;
+    show_code(&code);
+
+    let mut sender = Sender::new(&code, payload)
+        .with_servers(servers.clone())
+        .with_transit_mode(mode.clone());
+    if verify {
+        sender = sender.with_verifier_check(ui::confirm_verifier);
+    }
+    let (events, shown) = mpsc::unbounded_channel();
+    let (result, ()) = tokio::join!(sender.run(events, ctrl_c()), ui::show_events(shown));
+    result?;
+    ui::status("Transfer complete");
+    Ok(())
+}
+
+/// Receive with `code` into the directory `output`
+pub async fn receive(
+    servers: &Servers,
+    mode: &TransitMode,
+    code: &str,
+    output: &Path,
+    verify: bool,
+) -> Result<(), CliError> {
+    let code = code.trim().to_lowercase();
+    code::validate_code(&code)?;
+    if !output.is_dir() {
+        return Err(CliError::Invalid(format!(
+            "{} is not a directory",
+            output.display()
+        )));
+    }
+
+    let mut receiver = Receiver::new(&code, output)
+        .with_servers(servers.clone())
+        .with_transit_mode(mode.clone());
+    if verify {
+        receiver = receiver.with_verifier_check(ui::confirm_verifier);
+    }
+    let (events, shown) = mpsc::unbounded_channel();
+    let (result, ()) = tokio::join!(receiver.run(events, ctrl_c()), ui::show_events(shown));
+    match result? {
+        Received::Path(path) => ui::status(&format!("Received {}", path.display())),
+        Received::Text(text) => println!("{}", text),
+    }
+    Ok(())
+}
+
+/// Completes when the user presses Ctrl-C
+async fn ctrl_c() {
+    if tokio::signal::ctrl_c().await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+#[cfg(test)]
+#[path = "../../core/tests/support/mod.rs"]
+mod servers;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tokio::sync::oneshot;
+
+    #[test]
+    fn test_relays_are_validated() {
+        let mut servers = Servers {
+            mailbox_url: "http://localhost:8080".to_string(),
+            relays: vec!["tcp://relay.example.com:4001".to_string()],
+            appid: "test".to_string(),
+        };
+        assert!(validate(&servers).is_ok());
+
+        servers.relays.push("relay.example.com".to_string());
+        assert!(validate(&servers).is_err());
+
+        servers.relays.clear();
+        assert!(validate(&servers).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_send_and_receive_file() {
+        let servers = Servers {
+            mailbox_url: servers::mailbox_server(json!({})).await,
+            relays: vec![servers::relay_server().await],
+            appid: "test".to_string(),
+        };
+        let source = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let path = source.path().join("notes.txt");
+        std::fs::write(&path, "hello from the command line").unwrap();
+
+        let (code_tx, code_rx) = oneshot::channel();
+        let sending = send(
+            &servers,
+            &TransitMode::Direct,
+            Payload::File(path),
+            2,
+            false,
+            |code| code_tx.send(code.to_string()).unwrap(),
+        );
+        let receiving = async {
+            let code = code_rx.await.unwrap();
+            receive(&servers, &TransitMode::Direct, &code, output.path(), false).await
+        };
+        let (sent, received) = tokio::join!(sending, receiving);
+
+        sent.unwrap();
+        received.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(output.path().join("notes.txt")).unwrap(),
+            "hello from the command line"
+        );
+    }
+}
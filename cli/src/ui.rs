@@ -0,0 +1,102 @@
+//! Terminal output
+//!
+//! Everything but received text goes to standard error, so
+//! `securebeam receive CODE > file` captures only the message.
+
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use securebeam_core::{
+    session::{Event, Status},
+    ConnectionKind, OfferType,
+};
+
+/// Tell the user how to receive
+pub fn show_code(code: &str) {
+    eprintln!("Wormhole code is: {}", code);
+    eprintln!("On the other computer, run:");
+    eprintln!();
+    eprintln!("    securebeam receive {}", code);
+    eprintln!();
+}
+
+pub fn status(message: &str) {
+    eprintln!("{}", message);
+}
+
+/// A human-readable size
+pub fn size(bytes: u64) -> String {
+    HumanBytes(bytes).to_string()
+}
+
+/// Progress bar for `total` bytes; hidden when standard error is not a terminal
+pub fn progress_bar(total: u64) -> ProgressBar {
+    let bar = ProgressBar::new(total);
+    bar.set_style(
+        ProgressStyle::with_template(
+            "{bar:40.cyan/blue} {bytes}/{total_bytes} {binary_bytes_per_sec} ETA {eta}",
+        )
+        .expect("valid progress template")
+        .progress_chars("=> "),
+    );
+    bar
+}
+
+/// Ask whether the verifier matches the other side's
+pub async fn confirm_verifier(verifier: String) -> bool {
+    eprint!(
+        "Does {} match the verifier shown on the other side? [y/N] ",
+        verifier
+    );
+
+    let answer = tokio::task::spawn_blocking(|| {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line).map(|_| line)
+    })
+    .await;
+    matches!(answer, Ok(Ok(line)) if matches!(line.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Report the events of a transfer until it ends
+pub async fn show_events(mut events: UnboundedReceiver<Event>) {
+    let mut total = 0;
+    let mut bar: Option<ProgressBar> = None;
+    while let Some(event) = events.recv().await {
+        match event {
+            Event::Motd(motd) => status(&motd),
+            Event::Verifier(verifier) => status(&format!("Verifier: {}", verifier)),
+            Event::Status(Status::WaitingForPeer) => status("Waiting for the other side..."),
+            Event::Status(Status::Connected(kind)) => status(match kind {
+                ConnectionKind::Direct => "Connected directly",
+                ConnectionKind::Relay => "Connected through the relay",
+                ConnectionKind::Tor => "Connected through Tor",
+            }),
+            Event::Status(Status::Transferring) => bar = Some(progress_bar(total)),
+            Event::Offer(offer) => {
+                if !matches!(offer.offer_type, OfferType::Message(_)) {
+                    status(&format!(
+                        "{} ({})",
+                        offer.name(),
+                        size(offer.transfer_size())
+                    ));
+                }
+                total = offer.transfer_size();
+            }
+            Event::Progress(progress) => {
+                if let Some(bar) = &bar {
+                    bar.set_position(progress.bytes_transferred);
+                }
+            }
+            Event::Done => {
+                if let Some(bar) = bar.take() {
+                    bar.finish();
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some(bar) = bar {
+        bar.abandon();
+    }
+}
@@ -0,0 +1,260 @@
+//! Transfer errors reported to the frontend
+//!
+//! Core errors display deliberately generic text. Failed transfers carry a
+//! [`TransferErrorKind`] the frontend can explain instead, plus a detail
+//! built from [`Error::details`] with anything secret-looking removed.
+
+use serde::{Deserialize, Serialize};
+
+use securebeam_core::Error;
+
+/// Longest detail passed to the frontend, in characters
+const MAX_DETAIL_LEN: usize = 200;
+
+/// Hex strings at least this long are treated as keys or key hashes
+const MIN_SECRET_HEX_LEN: usize = 16;
+
+/// What made a transfer fail
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TransferErrorKind {
+    /// The two sides used different codes
+    WrongCode,
+    /// The peer declined the offer or stopped the transfer
+    PeerRejected,
+    /// A server, relay or the peer could not be reached or went away
+    Network,
+    /// The data received does not match the offer
+    Integrity,
+    /// The disk ran out of space
+    DiskFull,
+    Other,
+}
+
+impl TransferErrorKind {
+    /// Short description for the user
+    fn message(self) -> &'static str {
+        match self {
+            Self::WrongCode => "The code does not match the one used by the other side",
+            Self::PeerRejected => "The other side declined or stopped the transfer",
+            Self::Network => "The connection failed",
+            Self::Integrity => "The received data is damaged or incomplete",
+            Self::DiskFull => "Not enough disk space",
+            Self::Other => "The transfer failed",
+        }
+    }
+}
+
+/// Why a transfer failed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, thiserror::Error)]
+#[error("{message}")]
+pub struct TransferError {
+    pub kind: TransferErrorKind,
+    /// Short description for the user
+    pub message: String,
+    /// What went wrong, safe to show to the user
+    pub detail: Option<String>,
+}
+
+impl TransferError {
+    pub fn new(kind: TransferErrorKind) -> Self {
+        Self {
+            kind,
+            message: kind.message().to_string(),
+            detail: None,
+        }
+    }
+
+    /// Add a detail, removing anything that looks secret
+    pub fn with_detail(mut self, detail: &str) -> Self {
+        let detail = safe_detail(detail);
+        self.detail = (!detail.is_empty()).then_some(detail);
+        self
+    }
+
+    /// Reclassify an unexplained error as `kind`, the likely cause where it happened
+    pub fn or_kind(mut self, kind: TransferErrorKind) -> Self {
+        if self.kind == TransferErrorKind::Other {
+            self.kind = kind;
+            self.message = kind.message().to_string();
+        }
+        self
+    }
+
+    /// Remove the wormhole code from the detail
+    pub fn redact(mut self, code: &str) -> Self {
+        if let Some(detail) = &mut self.detail {
+            if !code.is_empty() {
+                *detail = detail.replace(code, "[code]");
+            }
+        }
+        self
+    }
+}
+
+impl From<Error> for TransferError {
+    fn from(error: Error) -> Self {
+        let kind = match &error {
+            Error::WrongCode | Error::InvalidCode(_) | Error::Crypto(_) | Error::MitmDetected => {
+                TransferErrorKind::WrongCode
+            }
+            Error::Connection(_)
+            | Error::ServerUnavailable(_)
+            | Error::SessionNotFound
+            | Error::SessionExpired
+            | Error::PeerDisconnected => TransferErrorKind::Network,
+            Error::Io(e) if is_disk_full(e) => TransferErrorKind::DiskFull,
+            Error::Rejected(_) => TransferErrorKind::PeerRejected,
+            Error::Io(_) | Error::Protocol(_) | Error::Transfer(_) | Error::Cancelled => {
+                TransferErrorKind::Other
+            }
+        };
+        let detail = match &error {
+            // I/O messages may name local paths; their kind is enough
+            Error::Io(e) => e.kind().to_string(),
+            error => error.details().unwrap_or_default().to_string(),
+        };
+        Self::new(kind).with_detail(&detail)
+    }
+}
+
+/// Whether an I/O error means the disk or quota is full
+fn is_disk_full(error: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+
+    matches!(
+        error.kind(),
+        ErrorKind::StorageFull | ErrorKind::QuotaExceeded | ErrorKind::FileTooLarge
+    )
+}
+
+/// Make `detail` safe to show: no control characters, no long hex strings
+/// such as keys or key hashes, and bounded in length
+fn safe_detail(detail: &str) -> String {
+    let redacted: Vec<String> = detail
+        .split(' ')
+        .map(|word| {
+            let hex = word.trim_matches(|c: char| !c.is_ascii_alphanumeric());
+            if hex.len() >= MIN_SECRET_HEX_LEN && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                word.replace(hex, "[redacted]")
+            } else {
+                word.to_string()
+            }
+        })
+        .collect();
+
+    let detail: String = redacted
+        .join(" ")
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect();
+    let detail = detail.trim();
+    if detail.chars().count() > MAX_DETAIL_LEN {
+        let cut: String = detail.chars().take(MAX_DETAIL_LEN - 1).collect();
+        format!("{}…", cut)
+    } else {
+        detail.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+
+    const KEY: &str = "0123456789abcdef0123456789abcdef";
+    const CODE: &str = "7-guitarist-revenge";
+
+    #[test]
+    fn test_kinds_and_redacted_details() {
+        let cases = [
+            (
+                Error::Crypto(format!("bad MAC with key {}", KEY)),
+                TransferErrorKind::WrongCode,
+                Some("bad MAC with key [redacted]"),
+            ),
+            (
+                Error::InvalidCode(format!("{} is not a code", CODE)),
+                TransferErrorKind::WrongCode,
+                Some("[code] is not a code"),
+            ),
+            (Error::MitmDetected, TransferErrorKind::WrongCode, None),
+            (
+                Error::Rejected("declined by user".to_string()),
+                TransferErrorKind::PeerRejected,
+                Some("declined by user"),
+            ),
+            (
+                Error::Connection(format!("relay closed channel {}:", KEY)),
+                TransferErrorKind::Network,
+                Some("relay closed channel [redacted]:"),
+            ),
+            (
+                Error::ServerUnavailable("down for\nmaintenance".to_string()),
+                TransferErrorKind::Network,
+                Some("down formaintenance"),
+            ),
+            (Error::PeerDisconnected, TransferErrorKind::Network, None),
+            (
+                Error::Transfer(format!("SHA-256 mismatch, expected {}", KEY)),
+                TransferErrorKind::Other,
+                Some("SHA-256 mismatch, expected [redacted]"),
+            ),
+            (
+                Error::Io(std::io::Error::new(
+                    ErrorKind::StorageFull,
+                    "/home/me/Downloads/secret.pdf",
+                )),
+                TransferErrorKind::DiskFull,
+                Some("no storage space"),
+            ),
+            (
+                Error::Io(std::io::Error::new(
+                    ErrorKind::PermissionDenied,
+                    "/home/me/Downloads/secret.pdf",
+                )),
+                TransferErrorKind::Other,
+                Some("permission denied"),
+            ),
+        ];
+
+        for (error, kind, detail) in cases {
+            let name = format!("{:?}", error);
+            let transfer = TransferError::from(error).redact(CODE);
+            assert_eq!(transfer.kind, kind, "{}", name);
+            assert_eq!(transfer.message, kind.message(), "{}", name);
+            assert_eq!(transfer.detail.as_deref(), detail, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_integrity_is_set_where_it_happens() {
+        let error = TransferError::from(Error::Transfer("short read".to_string()))
+            .or_kind(TransferErrorKind::Integrity);
+        assert_eq!(error.kind, TransferErrorKind::Integrity);
+        assert_eq!(error.message, TransferErrorKind::Integrity.message());
+
+        // A known cause is kept
+        let error =
+            TransferError::from(Error::PeerDisconnected).or_kind(TransferErrorKind::Integrity);
+        assert_eq!(error.kind, TransferErrorKind::Network);
+    }
+
+    #[test]
+    fn test_long_details_are_cut() {
+        let error = TransferError::new(TransferErrorKind::Other).with_detail(&"x".repeat(500));
+        let detail = error.detail.unwrap();
+        assert_eq!(detail.chars().count(), MAX_DETAIL_LEN);
+        assert!(detail.ends_with('…'));
+
+        let error = TransferError::new(TransferErrorKind::Other).with_detail(" \u{7}");
+        assert_eq!(error.detail, None);
+    }
+
+    #[test]
+    fn test_serialized_kind() {
+        let json = serde_json::to_value(TransferError::new(TransferErrorKind::DiskFull)).unwrap();
+        assert_eq!(json["kind"], "disk-full");
+        assert_eq!(json["detail"], serde_json::Value::Null);
+    }
+}
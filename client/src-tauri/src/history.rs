@@ -0,0 +1,368 @@
+//! Transfer history
+//!
+//! Every transfer that ends, whether it completed, failed or was cancelled,
+//! is appended as one JSON line to `history.jsonl` in the platform config
+//! directory. Entries describe what was transferred, never how: wormhole
+//! codes, keys and error details are not recorded.
+
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use securebeam_core::ConnectionKind;
+
+use crate::error::TransferErrorKind;
+use crate::Direction;
+
+/// Errors reading or writing the history
+#[derive(Debug, thiserror::Error)]
+pub enum HistoryError {
+    #[error("Could not find config directory")]
+    NoConfigDir,
+    #[error("Cannot access {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("Cannot encode history: {0}")]
+    Encode(serde_json::Error),
+}
+
+/// How a transfer ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+/// A transfer that has ended
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub direction: Direction,
+    /// File or directory name, if the transfer got as far as the offer
+    pub name: Option<String>,
+    /// Size of the file or directory contents in bytes
+    pub size: Option<u64>,
+    /// SHA-256 of the file; directories have none
+    pub hash: Option<String>,
+    /// Start of the transfer in seconds since the Unix epoch
+    pub started_at: u64,
+    pub duration_secs: f64,
+    /// How the peers were connected, if they were
+    pub connection: Option<ConnectionKind>,
+    pub outcome: Outcome,
+    /// Why a failed transfer failed
+    pub error: Option<TransferErrorKind>,
+}
+
+/// File formats the history can be exported to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Json,
+    Csv,
+}
+
+/// The history log file
+pub struct History {
+    path: Option<PathBuf>,
+    /// Serializes writes to the file
+    lock: Mutex<()>,
+}
+
+impl History {
+    /// The history in the platform config directory
+    pub fn open_default() -> Self {
+        Self {
+            path: dirs::config_dir().map(|dir| dir.join("securebeam").join("history.jsonl")),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// The history stored at `path`
+    pub fn open(path: PathBuf) -> Self {
+        Self {
+            path: Some(path),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Append an entry
+    pub fn record(&self, entry: &HistoryEntry) -> Result<(), HistoryError> {
+        let path = self.path()?;
+        let io_error = |e| HistoryError::Io(path.to_path_buf(), e);
+        let mut line = serde_json::to_string(entry).map_err(HistoryError::Encode)?;
+        line.push('\n');
+
+        let _guard = self.lock.lock().unwrap();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(io_error)?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(io_error)?;
+        file.write_all(line.as_bytes()).map_err(io_error)
+    }
+
+    /// All entries, oldest first
+    ///
+    /// Lines that cannot be read, e.g. after a crash mid-write, are skipped.
+    pub fn entries(&self) -> Result<Vec<HistoryEntry>, HistoryError> {
+        let path = self.path()?;
+        let _guard = self.lock.lock().unwrap();
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(HistoryError::Io(path.to_path_buf(), e)),
+        };
+        Ok(content
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+
+    /// Forget all entries
+    pub fn clear(&self) -> Result<(), HistoryError> {
+        let path = self.path()?;
+        let _guard = self.lock.lock().unwrap();
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(HistoryError::Io(path.to_path_buf(), e))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Write all entries to `dest`
+    pub fn export(&self, dest: &Path, format: ExportFormat) -> Result<(), HistoryError> {
+        let entries = self.entries()?;
+        let content = match format {
+            ExportFormat::Json => {
+                serde_json::to_string_pretty(&entries).map_err(HistoryError::Encode)?
+            }
+            ExportFormat::Csv => to_csv(&entries),
+        };
+        std::fs::write(dest, content).map_err(|e| HistoryError::Io(dest.to_path_buf(), e))
+    }
+
+    fn path(&self) -> Result<&Path, HistoryError> {
+        self.path.as_deref().ok_or(HistoryError::NoConfigDir)
+    }
+}
+
+/// Columns of the CSV export
+const CSV_HEADER: &str =
+    "started_at,direction,name,size,hash,duration_secs,connection,outcome,error";
+
+fn to_csv(entries: &[HistoryEntry]) -> String {
+    let mut csv = format!("{}\n", CSV_HEADER);
+    for entry in entries {
+        let fields = [
+            entry.started_at.to_string(),
+            label(&entry.direction),
+            entry.name.clone().unwrap_or_default(),
+            entry.size.map(|size| size.to_string()).unwrap_or_default(),
+            entry.hash.clone().unwrap_or_default(),
+            format!("{:.3}", entry.duration_secs),
+            entry.connection.as_ref().map(label).unwrap_or_default(),
+            label(&entry.outcome),
+            entry.error.as_ref().map(label).unwrap_or_default(),
+        ];
+        let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// The serialized name of an enum value
+fn label<T: Serialize>(value: &T) -> String {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|value| value.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+/// Quote a CSV field if needed
+///
+/// File names come from the peer, so fields that a spreadsheet would run
+/// as a formula are prefixed with `'`.
+fn csv_field(field: &str) -> String {
+    let field = if field.starts_with(['=', '+', '-', '@']) {
+        format!("'{}", field)
+    } else {
+        field.to_string()
+    };
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A history file in an empty directory for one test
+    fn history(name: &str) -> (History, PathBuf) {
+        let dir = std::env::temp_dir().join(format!(
+            "securebeam-history-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        (History::open(dir.join("history.jsonl")), dir)
+    }
+
+    fn sent(name: &str) -> HistoryEntry {
+        HistoryEntry {
+            direction: Direction::Send,
+            name: Some(name.to_string()),
+            size: Some(1024),
+            hash: Some("ab".repeat(32)),
+            started_at: 1_700_000_000,
+            duration_secs: 1.5,
+            connection: Some(ConnectionKind::Direct),
+            outcome: Outcome::Completed,
+            error: None,
+        }
+    }
+
+    fn failed() -> HistoryEntry {
+        HistoryEntry {
+            direction: Direction::Receive,
+            name: None,
+            size: None,
+            hash: None,
+            started_at: 1_700_000_100,
+            duration_secs: 0.25,
+            connection: None,
+            outcome: Outcome::Failed,
+            error: Some(TransferErrorKind::WrongCode),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let (history, _dir) = history("round-trip");
+        assert!(history.entries().unwrap().is_empty());
+
+        history.record(&sent("report.pdf")).unwrap();
+        history.record(&failed()).unwrap();
+        assert_eq!(history.entries().unwrap(), [sent("report.pdf"), failed()]);
+    }
+
+    #[test]
+    fn test_unreadable_lines_are_skipped() {
+        let (history, dir) = history("unreadable");
+        history.record(&sent("a.txt")).unwrap();
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(dir.join("history.jsonl"))
+            .unwrap();
+        file.write_all(b"{\"direction\":\"se").unwrap();
+        drop(file);
+
+        history.record(&sent("b.txt")).unwrap();
+        assert_eq!(history.entries().unwrap(), [sent("a.txt")]);
+    }
+
+    #[test]
+    fn test_clear() {
+        let (history, _dir) = history("clear");
+        history.clear().unwrap();
+
+        history.record(&sent("report.pdf")).unwrap();
+        history.clear().unwrap();
+        assert!(history.entries().unwrap().is_empty());
+
+        history.record(&failed()).unwrap();
+        assert_eq!(history.entries().unwrap(), [failed()]);
+    }
+
+    #[test]
+    fn test_records_hold_no_secrets() {
+        let (history, dir) = history("secrets");
+        history.record(&sent("report.pdf")).unwrap();
+        history.record(&failed()).unwrap();
+
+        let content = std::fs::read_to_string(dir.join("history.jsonl")).unwrap();
+        for line in content.lines() {
+            let record: serde_json::Map<String, serde_json::Value> =
+                serde_json::from_str(line).unwrap();
+            let mut keys: Vec<&str> = record.keys().map(String::as_str).collect();
+            keys.sort();
+            assert_eq!(
+                keys,
+                [
+                    "connection",
+                    "direction",
+                    "duration_secs",
+                    "error",
+                    "hash",
+                    "name",
+                    "outcome",
+                    "size",
+                    "started_at"
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_csv_export() {
+        let (history, dir) = history("csv");
+        history.record(&sent("report.pdf")).unwrap();
+        history.record(&failed()).unwrap();
+
+        let dest = dir.join("history.csv");
+        history.export(&dest, ExportFormat::Csv).unwrap();
+        let csv = std::fs::read_to_string(dest).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(
+            lines[1],
+            format!(
+                "1700000000,send,report.pdf,1024,{},1.500,direct,completed,",
+                "ab".repeat(32)
+            )
+        );
+        assert_eq!(lines[2], "1700000100,receive,,,,0.250,,failed,wrong-code");
+    }
+
+    #[test]
+    fn test_csv_escaping() {
+        assert_eq!(csv_field("plain.txt"), "plain.txt");
+        assert_eq!(csv_field("a,b.txt"), "\"a,b.txt\"");
+        assert_eq!(csv_field("say \"hi\".txt"), "\"say \"\"hi\"\".txt\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field("cr\rlf"), "\"cr\rlf\"");
+    }
+
+    #[test]
+    fn test_csv_formula_guard() {
+        for name in ["=cmd()", "+1", "-1", "@SUM(A1)"] {
+            assert_eq!(csv_field(name), format!("'{}", name));
+        }
+        assert_eq!(
+            csv_field("=HYPERLINK(\"x\",\"y\")"),
+            "\"'=HYPERLINK(\"\"x\"\",\"\"y\"\")\""
+        );
+        assert_eq!(csv_field("a=b"), "a=b");
+    }
+
+    #[test]
+    fn test_json_export() {
+        let (history, dir) = history("json");
+        history.record(&sent("report.pdf")).unwrap();
+
+        let dest = dir.join("history.json");
+        history.export(&dest, ExportFormat::Json).unwrap();
+        let exported: Vec<HistoryEntry> =
+            serde_json::from_str(&std::fs::read_to_string(dest).unwrap()).unwrap();
+        assert_eq!(exported, [sent("report.pdf")]);
+    }
+}
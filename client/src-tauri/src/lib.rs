@@ -3,43 +3,381 @@
 //! Provides commands for the Vue.js frontend to interact with
 //! the SecureBeam core library for P2P file transfers.
 
-use rand::Rng;
+mod error;
+mod history;
+mod profiles;
+mod settings;
+
 use serde::{Deserialize, Serialize};
-use std::time::Instant;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use tauri::{Emitter, State};
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, oneshot, Mutex, MutexGuard};
+use tokio::task::JoinHandle;
 
 use securebeam_core::{
-    crypto::{derive_key, Purpose, Side, Spake2Exchange},
-    establish_transit, FileAnswer, FileOffer, FileTransfer, Message, SignalingClient, TransitHints,
-    TransitRole, DEFAULT_MAILBOX, DEFAULT_RELAY,
+    code,
+    session::{Event, Payload, Receiver, Sender, Status, CANCEL_GRACE},
+    ConnectionKind, Error, FileOffer, FileTransfer, MailboxConnection, OfferType, TransferProgress,
 };
 
+use error::{TransferError, TransferErrorKind};
+use history::{ExportFormat, History, HistoryEntry, Outcome};
+use profiles::ServerProfile;
+use settings::{Settings, SettingsStore, SETTINGS_CHANGED_EVENT};
+
+/// Identifies a transfer in commands and events
+pub type TransferId = u64;
+
+/// Running transfers by ID
+type Transfers = Arc<Mutex<HashMap<TransferId, TransferState>>>;
+
 /// Application state
 pub struct AppState {
-    /// Current transfer in progress
-    pub transfer: Mutex<Option<TransferState>>,
+    /// Transfers in progress; each removes itself when it ends
+    pub transfers: Transfers,
+    /// ID for the next transfer
+    next_id: AtomicU64,
+    /// Cached settings, saved on every change
+    pub settings: SettingsStore,
+    /// Log of transfers that have ended
+    pub history: Arc<History>,
 }
 
-impl Default for AppState {
-    fn default() -> Self {
+impl AppState {
+    pub fn new(settings: SettingsStore, history: History) -> Self {
         Self {
-            transfer: Mutex::new(None),
+            transfers: Transfers::default(),
+            next_id: AtomicU64::new(1),
+            settings,
+            history: Arc::new(history),
+        }
+    }
+
+    /// The server profile to use for a new transfer
+    fn active_profile(&self) -> ServerProfile {
+        self.settings.get().servers.active()
+    }
+
+    /// Lock the running transfers to add one, if the concurrency limit allows
+    ///
+    /// Holding the lock until the new transfer is inserted keeps it from
+    /// removing itself before it was added.
+    async fn admit(&self) -> Result<MutexGuard<'_, HashMap<TransferId, TransferState>>, String> {
+        let mut transfers = self.transfers.lock().await;
+        // Tasks that panicked never removed themselves
+        transfers.retain(|_, transfer| !transfer.task.is_finished());
+
+        let limit = self.settings.get().max_concurrent_transfers;
+        if transfers.len() >= limit {
+            return Err(format!(
+                "{} transfers are already running, wait for one to finish",
+                limit
+            ));
+        }
+        Ok(transfers)
+    }
+
+    /// Events for a new transfer described by `info`
+    fn track(&self, app: &tauri::AppHandle, mut info: TransferInfo) -> TransferEvents {
+        info.id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        TransferEvents {
+            app: app.clone(),
+            id: info.id,
+            info: Arc::new(std::sync::Mutex::new(info)),
+            history: self.history.clone(),
+            started: Instant::now(),
+            transferring: Arc::default(),
         }
     }
 }
 
-/// Current transfer state
+/// A running transfer
 pub struct TransferState {
+    /// What `list_transfers` reports, kept up to date by its events
+    info: Arc<std::sync::Mutex<TransferInfo>>,
+    /// Asks the transfer task to stop
+    cancel: oneshot::Sender<()>,
+    /// The transfer task, aborted if it does not stop in time
+    task: JoinHandle<()>,
+}
+
+/// Whether a transfer sends or receives
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Send,
+    Receive,
+}
+
+/// A running transfer, as listed for the frontend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferInfo {
+    pub id: TransferId,
+    pub direction: Direction,
     pub code: String,
+    /// File or directory being sent, or the folder receiving
     pub path: String,
-    pub offer: Option<FileOffer>,
     pub is_directory: bool,
+    /// Latest status message
+    pub status: String,
+    pub bytes_transferred: u64,
+    pub total_bytes: u64,
+    /// File or directory name, once the offer is known
+    pub name: Option<String>,
+    /// Size of the file or directory contents
+    pub size: Option<u64>,
+    /// SHA-256 of the file, if offered
+    pub hash: Option<String>,
+    /// How the peers are connected, once they are
+    pub connection: Option<ConnectionKind>,
+    /// Seconds since the Unix epoch
+    pub started_at: u64,
+}
+
+impl TransferInfo {
+    fn new(direction: Direction, code: &str, path: &str, is_directory: bool) -> Self {
+        Self {
+            id: 0,
+            direction,
+            code: code.to_string(),
+            path: path.to_string(),
+            is_directory,
+            status: "Starting...".to_string(),
+            bytes_transferred: 0,
+            total_bytes: 0,
+            name: None,
+            size: None,
+            hash: None,
+            connection: None,
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        }
+    }
+}
+
+/// Emits the events of one transfer, each tagged with its ID
+///
+/// Also keeps the transfer's entry in `list_transfers` up to date and
+/// records it in the history when it ends.
+#[derive(Clone)]
+struct TransferEvents {
+    app: tauri::AppHandle,
+    id: TransferId,
+    info: Arc<std::sync::Mutex<TransferInfo>>,
+    history: Arc<History>,
+    started: Instant,
+    /// Whether data is being sent or received
+    transferring: Arc<AtomicBool>,
+}
+
+impl TransferEvents {
+    /// Pass on the events of the core session until it ends
+    async fn forward(&self, mut events: mpsc::UnboundedReceiver<Event>) {
+        while let Some(event) = events.recv().await {
+            self.handle(event);
+        }
+    }
+
+    fn handle(&self, event: Event) {
+        let direction = self.info.lock().unwrap().direction;
+        match event {
+            Event::Status(Status::Connecting) => self.status("Connecting to server..."),
+            Event::Motd(motd) => self.status(&motd),
+            Event::Status(Status::WaitingForPeer) => self.status(match direction {
+                Direction::Send => "Waiting for receiver...",
+                Direction::Receive => "Exchanging keys...",
+            }),
+            Event::Status(Status::KeyExchanged) => {
+                self.status("Key exchange complete. Establishing P2P connection...")
+            }
+            Event::Status(Status::Connected(kind)) => self.connected(kind),
+            Event::Status(Status::WaitingForAnswer) => self.status("Waiting for acceptance..."),
+            Event::Status(Status::Transferring) => {
+                self.transferring.store(true, Ordering::Relaxed);
+                self.status(transferring_status(direction));
+            }
+            Event::Offer(offer) => {
+                self.describe(&offer);
+                if direction == Direction::Receive {
+                    self.offer(FileOfferInfo {
+                        name: offer.name().to_string(),
+                        size: offer.transfer_size(),
+                        compressed: offer.is_compressed(),
+                        is_directory: matches!(offer.offer_type, OfferType::Directory(_)),
+                    });
+                }
+            }
+            Event::Progress(progress) => self.progress(progress, direction),
+            // The frontend has no verifier display yet
+            Event::Verifier(_) => {}
+            Event::Done => self.complete(),
+        }
+    }
+
+    /// Report how the session ended, unless it completed
+    fn finish<T>(&self, result: securebeam_core::Result<T>) {
+        match result {
+            Ok(_) => {}
+            Err(Error::Cancelled) => self.cancelled(),
+            Err(e) => {
+                let error = TransferError::from(e);
+                // Bad data is the likely cause of unexplained failures mid-transfer
+                if self.transferring.load(Ordering::Relaxed) {
+                    self.failed(error.or_kind(TransferErrorKind::Integrity));
+                } else {
+                    self.failed(error);
+                }
+            }
+        }
+    }
+
+    fn status(&self, status: &str) {
+        self.info.lock().unwrap().status = status.to_string();
+        let _ = self.app.emit(
+            "transfer-status",
+            TransferStatusInfo {
+                id: self.id,
+                status: status.to_string(),
+            },
+        );
+    }
+
+    fn progress(&self, progress: TransferProgress, direction: Direction) {
+        {
+            let mut info = self.info.lock().unwrap();
+            info.bytes_transferred = progress.bytes_transferred;
+            info.total_bytes = progress.total_bytes;
+        }
+        let info = TransferProgressInfo {
+            id: self.id,
+            bytes_transferred: progress.bytes_transferred,
+            total_bytes: progress.total_bytes,
+            percentage: progress.percentage(),
+            speed_mbps: progress.speed_bps / (1024.0 * 1024.0),
+            eta_seconds: progress.eta_seconds,
+            status: transferring_status(direction).to_string(),
+        };
+        let _ = self.app.emit("transfer-progress", info);
+    }
+
+    /// Note what is being transferred
+    fn describe(&self, offer: &FileOffer) {
+        let mut info = self.info.lock().unwrap();
+        info.name = Some(offer.name().to_string());
+        info.total_bytes = offer.transfer_size();
+        match &offer.offer_type {
+            OfferType::File(file) => {
+                info.is_directory = false;
+                info.size = Some(file.original_size.unwrap_or(file.file_size));
+                info.hash = file.hash.clone();
+            }
+            OfferType::Directory(dir) => {
+                info.is_directory = true;
+                info.size = Some(dir.num_bytes);
+                info.hash = None;
+            }
+            OfferType::Message(text) => {
+                info.is_directory = false;
+                info.size = Some(text.len() as u64);
+                info.hash = None;
+            }
+        }
+    }
+
+    fn connected(&self, kind: ConnectionKind) {
+        self.info.lock().unwrap().connection = Some(kind);
+    }
+
+    fn offer(&self, offer: FileOfferInfo) {
+        let _ = self
+            .app
+            .emit("file-offer", TransferOfferInfo { id: self.id, offer });
+    }
+
+    fn complete(&self) {
+        self.status("Transfer complete!");
+        self.record(Outcome::Completed, None);
+        let _ = self.app.emit("transfer-complete", self.id);
+    }
+
+    /// Report a failure; the wormhole code never reaches the frontend
+    fn failed(&self, error: TransferError) {
+        let code = self.info.lock().unwrap().code.clone();
+        let error = error.redact(&code);
+        eprintln!(
+            "Transfer {} failed: {:?} {}",
+            self.id,
+            error.kind,
+            error.detail.as_deref().unwrap_or_default()
+        );
+        self.status(&error.message);
+        self.record(Outcome::Failed, Some(error.kind));
+        let _ = self
+            .app
+            .emit("transfer-error", TransferErrorInfo { id: self.id, error });
+    }
+
+    fn cancelled(&self) {
+        self.status("Transfer cancelled");
+        self.record(Outcome::Cancelled, None);
+        let _ = self.app.emit("transfer-cancelled", self.id);
+    }
+
+    /// Add the transfer to the history; the code is left out
+    fn record(&self, outcome: Outcome, error: Option<TransferErrorKind>) {
+        let entry = {
+            let info = self.info.lock().unwrap();
+            HistoryEntry {
+                direction: info.direction,
+                name: info.name.clone(),
+                size: info.size,
+                hash: info.hash.clone(),
+                started_at: info.started_at,
+                duration_secs: self.started.elapsed().as_secs_f64(),
+                connection: info.connection,
+                outcome,
+                error,
+            }
+        };
+        if let Err(e) = self.history.record(&entry) {
+            eprintln!("History error: {}", e);
+        }
+    }
+}
+
+fn transferring_status(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Send => "Transferring...",
+        Direction::Receive => "Receiving...",
+    }
+}
+
+/// Completes when the transfer is cancelled
+///
+/// The cancel sender is dropped without sending when the transfer ends.
+async fn cancelled(cancel: oneshot::Receiver<()>) {
+    if cancel.await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Status message of a transfer for the frontend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferStatusInfo {
+    pub id: TransferId,
+    pub status: String,
 }
 
 /// Transfer progress info for frontend
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TransferProgressInfo {
+    pub id: TransferId,
     pub bytes_transferred: u64,
     pub total_bytes: u64,
     pub percentage: f64,
@@ -57,51 +395,81 @@ pub struct FileOfferInfo {
     pub is_directory: bool,
 }
 
-/// Generate word lists for codes
-const ADJECTIVES: &[&str] = &[
-    "purple", "green", "blue", "red", "orange", "yellow", "silver", "golden", "crimson", "azure",
-    "amber", "coral", "ivory", "jade", "lime", "navy",
-];
-
-const NOUNS: &[&str] = &[
-    "sausages",
-    "elephants",
-    "guitars",
-    "planets",
-    "mountains",
-    "rivers",
-    "clouds",
-    "forests",
-    "dragons",
-    "unicorns",
-    "wizards",
-    "knights",
-    "castles",
-    "oceans",
-    "comets",
-    "crystals",
-];
+/// File offer received by a transfer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferOfferInfo {
+    pub id: TransferId,
+    #[serde(flatten)]
+    pub offer: FileOfferInfo,
+}
+
+/// Failure of a transfer for the frontend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferErrorInfo {
+    pub id: TransferId,
+    #[serde(flatten)]
+    pub error: TransferError,
+}
+
+/// Connect to the mailbox server of the active profile
+///
+/// When the server is unavailable its reason (e.g. maintenance) is passed
+/// on to the user.
+async fn connect_mailbox(state: &AppState) -> Result<MailboxConnection, String> {
+    let profile = state.active_profile();
+    profile
+        .signaling()
+        .connect_mailbox(&profile.appid)
+        .await
+        .map_err(|e| match e {
+            Error::ServerUnavailable(reason) => reason,
+            e => e.to_string(),
+        })
+}
 
 /// Generate a new wormhole code for sending
+///
+/// The nameplate is allocated by the mailbox server; the words come from
+/// the PGP wordlist (`DEFAULT_WORD_COUNT` unless `words` is given).
 #[tauri::command]
-fn generate_code() -> Result<String, String> {
-    let mut rng = rand::thread_rng();
-    let number: u32 = rng.gen_range(1..1000);
-    let adj = ADJECTIVES[rng.gen_range(0..ADJECTIVES.len())];
-    let noun = NOUNS[rng.gen_range(0..NOUNS.len())];
-    Ok(format!("{}-{}-{}", number, adj, noun))
+async fn generate_code(state: State<'_, AppState>, words: Option<usize>) -> Result<String, String> {
+    let mut conn = connect_mailbox(&state).await?;
+    code::allocate_code(&mut conn, words.unwrap_or(code::DEFAULT_WORD_COUNT))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// The mailbox server's message of the day, if it has one
+#[tauri::command]
+async fn get_server_motd(state: State<'_, AppState>) -> Result<Option<String>, String> {
+    let conn = connect_mailbox(&state).await?;
+    Ok(conn.motd().map(str::to_string))
 }
 
 /// Parse a wormhole code into its components
+///
+/// Codes with words that are not in the wordlist are rejected before any
+/// connection is made.
 #[tauri::command]
 fn parse_code(code: String) -> Result<(String, String), String> {
-    let parts: Vec<&str> = code.split('-').collect();
-    if parts.len() < 3 {
-        return Err("Invalid code format. Expected: number-word-word".to_string());
-    }
-    let nameplate = parts[0].to_string();
-    let password = parts[1..].join("-");
-    Ok((nameplate, password))
+    code::validate_code(&code)
+        .and_then(|()| code::parse_code(&code.to_lowercase()))
+        .map_err(|e| e.details().unwrap_or("Invalid code").to_string())
+}
+
+/// Complete a partially typed code
+///
+/// Nameplates are completed from those active on the mailbox server,
+/// words from the wordlist.
+#[tauri::command]
+async fn complete_code(state: State<'_, AppState>, partial: String) -> Result<Vec<String>, String> {
+    let nameplates = if partial.contains('-') {
+        Vec::new()
+    } else {
+        let mut conn = connect_mailbox(&state).await?;
+        conn.list_nameplates().await.map_err(|e| e.to_string())?
+    };
+    Ok(code::complete_code(&partial, &nameplates))
 }
 
 /// Prepare a file for sending
@@ -138,7 +506,7 @@ async fn prepare_directory(path: String) -> Result<FileOfferInfo, String> {
     })
 }
 
-/// Start sending a file
+/// Start sending a file, returning the transfer's ID
 #[tauri::command]
 async fn start_send(
     app: tauri::AppHandle,
@@ -146,385 +514,159 @@ async fn start_send(
     path: String,
     code: String,
     is_directory: bool,
-) -> Result<(), String> {
-    let transfer_state = TransferState {
-        code: code.clone(),
-        path: path.clone(),
-        offer: None,
-        is_directory,
-    };
+) -> Result<TransferId, String> {
+    let mut transfers = state.admit().await?;
+    let events = state.track(
+        &app,
+        TransferInfo::new(Direction::Send, &code, &path, is_directory),
+    );
 
-    *state.transfer.lock().await = Some(transfer_state);
+    let payload = if is_directory {
+        Payload::Directory(PathBuf::from(path))
+    } else {
+        Payload::File(PathBuf::from(path))
+    };
+    let profile = state.active_profile();
+    let sender = Sender::new(&code, payload)
+        .with_servers(profile.servers())
+        .with_transit_mode(profile.transit_mode());
 
     // Spawn the transfer task
-    let app_handle = app.clone();
-    tokio::spawn(async move {
-        if let Err(e) = run_sender(app_handle, path, code, is_directory).await {
-            eprintln!("Transfer error: {}", e);
+    let (cancel, cancel_rx) = oneshot::channel();
+    let task = tokio::spawn({
+        let (events, running) = (events.clone(), state.transfers.clone());
+        async move {
+            let (session_events, forwarded) = mpsc::unbounded_channel();
+            let (result, ()) = tokio::join!(
+                sender.run(session_events, cancelled(cancel_rx)),
+                events.forward(forwarded)
+            );
+            events.finish(result);
+            running.lock().await.remove(&events.id);
         }
     });
 
-    Ok(())
-}
-
-/// Run the sender transfer
-async fn run_sender(
-    app: tauri::AppHandle,
-    path: String,
-    code: String,
-    is_directory: bool,
-) -> Result<(), String> {
-    // Emit status
-    let _ = app.emit("transfer-status", "Connecting to server...");
-
-    // Prepare the offer
-    let file_transfer = FileTransfer::new();
-    let offer = if is_directory {
-        file_transfer
-            .prepare_directory_offer(&path)
-            .await
-            .map_err(|e| e.to_string())?
-    } else {
-        file_transfer
-            .prepare_file_offer(&path)
-            .await
-            .map_err(|e| e.to_string())?
-    };
-
-    // Connect to mailbox server
-    let client = SignalingClient::new(DEFAULT_MAILBOX);
-    let mut session = client.connect(&code).await.map_err(|e| e.to_string())?;
-
-    let _ = app.emit("transfer-status", "Waiting for receiver...");
-
-    // SPAKE2 key exchange
-    let mut exchange = Spake2Exchange::new(code.as_bytes(), Side::A);
-    let our_pake_msg = exchange.start().map_err(|e| e.to_string())?;
-
-    // Send our PAKE message
-    session
-        .send(&serde_json::json!({"pake": our_pake_msg.to_hex()}).to_string())
-        .await
-        .map_err(|e| e.to_string())?;
-
-    // Wait for peer's PAKE message
-    let peer_msg_text = session
-        .receive()
-        .await
-        .map_err(|e| e.to_string())?
-        .ok_or("Connection closed")?;
-
-    let peer_json: serde_json::Value =
-        serde_json::from_str(&peer_msg_text).map_err(|e| e.to_string())?;
-    let peer_pake_hex = peer_json["pake"].as_str().ok_or("Missing pake field")?;
-    let peer_pake_msg = securebeam_core::crypto::Spake2Message::from_hex(peer_pake_hex)
-        .map_err(|e| e.to_string())?;
-
-    // Complete key exchange
-    let shared_key = exchange.finish(&peer_pake_msg).map_err(|e| e.to_string())?;
-
-    let _ = app.emit(
-        "transfer-status",
-        "Key exchange complete. Establishing connection...",
+    transfers.insert(
+        events.id,
+        TransferState {
+            info: events.info,
+            cancel,
+            task,
+        },
     );
-
-    // Derive transit key
-    let transit_key = derive_key(&shared_key, &Purpose::Transit, 32).map_err(|e| e.to_string())?;
-
-    // Exchange transit hints
-    let our_hints = TransitHints {
-        direct_hints: vec![],
-        relay_hints: vec![securebeam_core::transit::RelayHint {
-            url: DEFAULT_RELAY.to_string(),
-        }],
-    };
-
-    session
-        .send(&serde_json::to_string(&our_hints).map_err(|e| e.to_string())?)
-        .await
-        .map_err(|e| e.to_string())?;
-
-    let peer_hints_text = session
-        .receive()
-        .await
-        .map_err(|e| e.to_string())?
-        .ok_or("Connection closed")?;
-
-    let peer_hints: TransitHints =
-        serde_json::from_str(&peer_hints_text).map_err(|e| e.to_string())?;
-
-    // Merge hints
-    let mut combined_hints = our_hints;
-    combined_hints.direct_hints.extend(peer_hints.direct_hints);
-    combined_hints.relay_hints.extend(peer_hints.relay_hints);
-
-    // Establish transit connection
-    let _ = app.emit("transfer-status", "Establishing P2P connection...");
-
-    let mut transit = establish_transit(TransitRole::Sender, &combined_hints, &transit_key)
-        .await
-        .map_err(|e| e.to_string())?;
-
-    // Send offer
-    let offer_msg = Message::offer(offer.clone());
-    transit
-        .send(&offer_msg.to_bytes().map_err(|e| e.to_string())?)
-        .await
-        .map_err(|e| e.to_string())?;
-
-    let _ = app.emit("transfer-status", "Waiting for acceptance...");
-
-    // Wait for answer
-    let answer_bytes = transit.receive().await.map_err(|e| e.to_string())?;
-    let answer_msg = Message::from_bytes(&answer_bytes).map_err(|e| e.to_string())?;
-
-    match answer_msg {
-        Message::Answer(answer) => {
-            if !answer.is_accepted() {
-                return Err("Transfer rejected by receiver".to_string());
-            }
-        }
-        _ => return Err("Unexpected message type".to_string()),
-    }
-
-    let _ = app.emit("transfer-status", "Transferring...");
-
-    // Send file with progress
-    let total_size = offer.transfer_size();
-    let start_time = Instant::now();
-    let app_clone = app.clone();
-
-    let progress_callback = move |progress: securebeam_core::TransferProgress| {
-        let elapsed = start_time.elapsed().as_secs_f64();
-        let speed_bps = if elapsed > 0.0 {
-            progress.bytes_transferred as f64 / elapsed
-        } else {
-            0.0
-        };
-
-        let eta = if speed_bps > 0.0 && progress.bytes_transferred < total_size {
-            Some((total_size - progress.bytes_transferred) as f64 / speed_bps)
-        } else {
-            None
-        };
-
-        let info = TransferProgressInfo {
-            bytes_transferred: progress.bytes_transferred,
-            total_bytes: total_size,
-            percentage: progress.percentage(),
-            speed_mbps: speed_bps / (1024.0 * 1024.0),
-            eta_seconds: eta,
-            status: "Transferring...".to_string(),
-        };
-
-        let _ = app_clone.emit("transfer-progress", info);
-    };
-
-    if is_directory {
-        file_transfer
-            .send_directory(&mut transit, &path, &offer, progress_callback)
-            .await
-            .map_err(|e| e.to_string())?;
-    } else {
-        file_transfer
-            .send_file(&mut transit, &path, &offer, progress_callback)
-            .await
-            .map_err(|e| e.to_string())?;
-    }
-
-    // Wait for ACK
-    let ack_bytes = transit.receive().await.map_err(|e| e.to_string())?;
-    let _ack_msg = Message::from_bytes(&ack_bytes).map_err(|e| e.to_string())?;
-
-    let _ = app.emit("transfer-status", "Transfer complete!");
-    let _ = app.emit("transfer-complete", ());
-
-    Ok(())
+    Ok(events.id)
 }
 
-/// Start receiving a file
+/// Start receiving a file, returning the transfer's ID
 #[tauri::command]
 async fn start_receive(
     app: tauri::AppHandle,
+    state: State<'_, AppState>,
     code: String,
     save_path: String,
-) -> Result<(), String> {
-    let app_handle = app.clone();
-    tokio::spawn(async move {
-        if let Err(e) = run_receiver(app_handle, code, save_path).await {
-            eprintln!("Receive error: {}", e);
+) -> Result<TransferId, String> {
+    let mut transfers = state.admit().await?;
+    let events = state.track(
+        &app,
+        TransferInfo::new(Direction::Receive, &code, &save_path, false),
+    );
+
+    // Text messages come from the command-line client and cannot be shown here
+    let profile = state.active_profile();
+    let receiver = Receiver::new(&code, save_path)
+        .with_servers(profile.servers())
+        .with_transit_mode(profile.transit_mode())
+        .with_overwrite(true)
+        .with_text(false);
+
+    let (cancel, cancel_rx) = oneshot::channel();
+    let task = tokio::spawn({
+        let (events, running) = (events.clone(), state.transfers.clone());
+        async move {
+            let (session_events, forwarded) = mpsc::unbounded_channel();
+            let (result, ()) = tokio::join!(
+                receiver.run(session_events, cancelled(cancel_rx)),
+                events.forward(forwarded)
+            );
+            events.finish(result);
+            running.lock().await.remove(&events.id);
         }
     });
 
-    Ok(())
-}
-
-/// Run the receiver transfer
-async fn run_receiver(
-    app: tauri::AppHandle,
-    code: String,
-    save_path: String,
-) -> Result<(), String> {
-    let _ = app.emit("transfer-status", "Connecting to server...");
-
-    // Connect to mailbox server
-    let client = SignalingClient::new(DEFAULT_MAILBOX);
-    let mut session = client.connect(&code).await.map_err(|e| e.to_string())?;
-
-    let _ = app.emit("transfer-status", "Exchanging keys...");
-
-    // SPAKE2 key exchange
-    let mut exchange = Spake2Exchange::new(code.as_bytes(), Side::B);
-    let our_pake_msg = exchange.start().map_err(|e| e.to_string())?;
-
-    // Send our PAKE message
-    session
-        .send(&serde_json::json!({"pake": our_pake_msg.to_hex()}).to_string())
-        .await
-        .map_err(|e| e.to_string())?;
-
-    // Wait for peer's PAKE message
-    let peer_msg_text = session
-        .receive()
-        .await
-        .map_err(|e| e.to_string())?
-        .ok_or("Connection closed")?;
-
-    let peer_json: serde_json::Value =
-        serde_json::from_str(&peer_msg_text).map_err(|e| e.to_string())?;
-    let peer_pake_hex = peer_json["pake"].as_str().ok_or("Missing pake field")?;
-    let peer_pake_msg = securebeam_core::crypto::Spake2Message::from_hex(peer_pake_hex)
-        .map_err(|e| e.to_string())?;
-
-    // Complete key exchange
-    let shared_key = exchange.finish(&peer_pake_msg).map_err(|e| e.to_string())?;
-
-    let _ = app.emit(
-        "transfer-status",
-        "Key exchange complete. Establishing connection...",
+    transfers.insert(
+        events.id,
+        TransferState {
+            info: events.info,
+            cancel,
+            task,
+        },
     );
+    Ok(events.id)
+}
 
-    // Derive transit key
-    let transit_key = derive_key(&shared_key, &Purpose::Transit, 32).map_err(|e| e.to_string())?;
-
-    // Exchange transit hints
-    let our_hints = TransitHints {
-        direct_hints: vec![],
-        relay_hints: vec![securebeam_core::transit::RelayHint {
-            url: DEFAULT_RELAY.to_string(),
-        }],
-    };
-
-    session
-        .send(&serde_json::to_string(&our_hints).map_err(|e| e.to_string())?)
-        .await
-        .map_err(|e| e.to_string())?;
-
-    let peer_hints_text = session
-        .receive()
-        .await
-        .map_err(|e| e.to_string())?
-        .ok_or("Connection closed")?;
-
-    let peer_hints: TransitHints =
-        serde_json::from_str(&peer_hints_text).map_err(|e| e.to_string())?;
-
-    // Merge hints
-    let mut combined_hints = our_hints;
-    combined_hints.direct_hints.extend(peer_hints.direct_hints);
-    combined_hints.relay_hints.extend(peer_hints.relay_hints);
-
-    // Establish transit connection
-    let _ = app.emit("transfer-status", "Establishing P2P connection...");
-
-    let mut transit = establish_transit(TransitRole::Receiver, &combined_hints, &transit_key)
-        .await
-        .map_err(|e| e.to_string())?;
-
-    // Receive offer
-    let offer_bytes = transit.receive().await.map_err(|e| e.to_string())?;
-    let offer_msg = Message::from_bytes(&offer_bytes).map_err(|e| e.to_string())?;
-
-    let offer = match offer_msg {
-        Message::Offer(o) => o,
-        _ => return Err("Expected offer message".to_string()),
-    };
+/// List the transfers in progress, oldest first
+#[tauri::command]
+async fn list_transfers(state: State<'_, AppState>) -> Result<Vec<TransferInfo>, String> {
+    let transfers = state.transfers.lock().await;
+    let mut list: Vec<TransferInfo> = transfers
+        .values()
+        .filter(|transfer| !transfer.task.is_finished())
+        .map(|transfer| transfer.info.lock().unwrap().clone())
+        .collect();
+    list.sort_by_key(|info| info.id);
+    Ok(list)
+}
 
-    // Emit offer info for user confirmation (simplified - auto-accept for now)
-    let offer_info = FileOfferInfo {
-        name: offer.name().to_string(),
-        size: offer.transfer_size(),
-        compressed: offer.is_compressed(),
-        is_directory: matches!(offer.offer_type, securebeam_core::OfferType::Directory(_)),
+/// Cancel a transfer in progress
+///
+/// The peer is told, the mailbox is closed as lonely and anything the
+/// receiver wrote so far is removed.
+#[tauri::command]
+async fn cancel_transfer(state: State<'_, AppState>, id: TransferId) -> Result<(), String> {
+    let transfer = state.transfers.lock().await.remove(&id);
+    let Some(TransferState {
+        cancel, mut task, ..
+    }) = transfer
+    else {
+        return Err(format!("No transfer {} in progress", id));
     };
-    let _ = app.emit("file-offer", offer_info.clone());
+    if task.is_finished() || cancel.send(()).is_err() {
+        return Err(format!("No transfer {} in progress", id));
+    }
 
-    // Accept the transfer
-    let answer = FileAnswer::accept();
-    let answer_msg = Message::answer(answer);
-    transit
-        .send(&answer_msg.to_bytes().map_err(|e| e.to_string())?)
+    // Let the task wind down, but never wait on a stuck connection forever
+    if tokio::time::timeout(CANCEL_GRACE * 2, &mut task)
         .await
-        .map_err(|e| e.to_string())?;
-
-    let _ = app.emit("transfer-status", "Receiving...");
-
-    // Receive file with progress
-    let file_transfer = FileTransfer::new();
-    let total_size = offer.transfer_size();
-    let start_time = Instant::now();
-    let app_clone = app.clone();
-
-    let progress_callback = move |progress: securebeam_core::TransferProgress| {
-        let elapsed = start_time.elapsed().as_secs_f64();
-        let speed_bps = if elapsed > 0.0 {
-            progress.bytes_transferred as f64 / elapsed
-        } else {
-            0.0
-        };
-
-        let eta = if speed_bps > 0.0 && progress.bytes_transferred < total_size {
-            Some((total_size - progress.bytes_transferred) as f64 / speed_bps)
-        } else {
-            None
-        };
-
-        let info = TransferProgressInfo {
-            bytes_transferred: progress.bytes_transferred,
-            total_bytes: total_size,
-            percentage: progress.percentage(),
-            speed_mbps: speed_bps / (1024.0 * 1024.0),
-            eta_seconds: eta,
-            status: "Receiving...".to_string(),
-        };
-
-        let _ = app_clone.emit("transfer-progress", info);
-    };
-
-    let dest_path = format!("{}/{}", save_path, offer.name());
-
-    if offer_info.is_directory {
-        file_transfer
-            .receive_directory(&mut transit, &dest_path, &offer, progress_callback)
-            .await
-            .map_err(|e| e.to_string())?;
-    } else {
-        file_transfer
-            .receive_file(&mut transit, &dest_path, &offer, progress_callback)
-            .await
-            .map_err(|e| e.to_string())?;
+        .is_err()
+    {
+        task.abort();
     }
+    Ok(())
+}
 
-    // Send ACK
-    transit
-        .send(&Message::Ack.to_bytes().map_err(|e| e.to_string())?)
-        .await
-        .map_err(|e| e.to_string())?;
+/// Transfers that have ended, oldest first
+#[tauri::command]
+fn get_history(state: State<'_, AppState>) -> Result<Vec<HistoryEntry>, String> {
+    state.history.entries().map_err(|e| e.to_string())
+}
 
-    let _ = app.emit("transfer-status", "Transfer complete!");
-    let _ = app.emit("transfer-complete", ());
+/// Forget all past transfers
+#[tauri::command]
+fn clear_history(state: State<'_, AppState>) -> Result<(), String> {
+    state.history.clear().map_err(|e| e.to_string())
+}
 
-    Ok(())
+/// Save the history to `path` as JSON or CSV
+#[tauri::command]
+fn export_history(
+    state: State<'_, AppState>,
+    path: String,
+    format: ExportFormat,
+) -> Result<(), String> {
+    state
+        .history
+        .export(Path::new(&path), format)
+        .map_err(|e| e.to_string())
 }
 
 /// Format file size for display
@@ -551,23 +693,39 @@ fn get_version() -> String {
     securebeam_core::VERSION.to_string()
 }
 
+/// Apply a change to the settings, save them and notify the frontend
+fn update_settings<F>(app: &tauri::AppHandle, state: &AppState, change: F) -> Result<(), String>
+where
+    F: FnOnce(&mut Settings) -> Result<(), String>,
+{
+    let settings = state.settings.update(change).map_err(|e| e.to_string())?;
+    let _ = app.emit(SETTINGS_CHANGED_EVENT, settings);
+    Ok(())
+}
+
+/// Get all settings
+#[tauri::command]
+fn get_settings(state: State<'_, AppState>) -> Settings {
+    state.settings.get()
+}
+
+/// Replace all settings
+#[tauri::command]
+fn set_settings(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    settings: Settings,
+) -> Result<(), String> {
+    update_settings(&app, &state, |current| {
+        *current = settings;
+        Ok(())
+    })
+}
+
 /// Get the saved download path
 #[tauri::command]
-fn get_download_path() -> Option<String> {
-    // Try to read from a config file or use platform defaults
-    if let Some(config_dir) = dirs::config_dir() {
-        let config_file = config_dir.join("securebeam").join("config.json");
-        if config_file.exists() {
-            if let Ok(content) = std::fs::read_to_string(&config_file) {
-                if let Ok(config) = serde_json::from_str::<serde_json::Value>(&content) {
-                    if let Some(path) = config["download_path"].as_str() {
-                        return Some(path.to_string());
-                    }
-                }
-            }
-        }
-    }
-    None
+fn get_download_path(state: State<'_, AppState>) -> Option<String> {
+    state.settings.get().download_path
 }
 
 /// Get the default download path
@@ -584,42 +742,67 @@ fn get_default_download_path() -> String {
 
 /// Set the download path
 #[tauri::command]
-fn set_download_path(path: String) -> Result<(), String> {
-    let config_dir = dirs::config_dir()
-        .ok_or("Could not find config directory")?
-        .join("securebeam");
-
-    std::fs::create_dir_all(&config_dir).map_err(|e| e.to_string())?;
+fn set_download_path(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    path: String,
+) -> Result<(), String> {
+    update_settings(&app, &state, |settings| {
+        settings.download_path = Some(path);
+        Ok(())
+    })
+}
 
-    let config_file = config_dir.join("config.json");
+/// List the server profiles, the built-in one first
+#[tauri::command]
+fn list_server_profiles(state: State<'_, AppState>) -> Vec<ServerProfile> {
+    state.settings.get().servers.all()
+}
 
-    // Read existing config or create new
-    let mut config: serde_json::Value = if config_file.exists() {
-        let content = std::fs::read_to_string(&config_file).map_err(|e| e.to_string())?;
-        serde_json::from_str(&content).unwrap_or(serde_json::json!({}))
-    } else {
-        serde_json::json!({})
-    };
+/// Get the profile used for transfers
+#[tauri::command]
+fn get_active_profile(state: State<'_, AppState>) -> ServerProfile {
+    state.active_profile()
+}
 
-    config["download_path"] = serde_json::Value::String(path);
+/// Add or replace a custom server profile
+#[tauri::command]
+fn save_server_profile(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    profile: ServerProfile,
+) -> Result<(), String> {
+    update_settings(&app, &state, |settings| settings.servers.upsert(profile))
+}
 
-    std::fs::write(
-        &config_file,
-        serde_json::to_string_pretty(&config).map_err(|e| e.to_string())?,
-    )
-    .map_err(|e| e.to_string())?;
+/// Remove a custom server profile
+#[tauri::command]
+fn delete_server_profile(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    name: String,
+) -> Result<(), String> {
+    update_settings(&app, &state, |settings| settings.servers.remove(&name))
+}
 
-    Ok(())
+/// Switch the profile used for future transfers
+#[tauri::command]
+fn set_active_profile(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    name: String,
+) -> Result<(), String> {
+    update_settings(&app, &state, |settings| settings.servers.activate(&name))
 }
 
-/// Test connection to the signaling server
+/// Test connection to the active profile's signaling server
 #[tauri::command]
-async fn test_signaling_connection() -> bool {
+async fn test_signaling_connection(state: State<'_, AppState>) -> Result<bool, String> {
     use tokio::time::{timeout, Duration};
 
+    let url = state.active_profile().health_url();
     let result = timeout(Duration::from_secs(5), async {
         // Try HTTP request to the health endpoint
-        let url = format!("{}/health", DEFAULT_MAILBOX);
         match reqwest::get(&url).await {
             Ok(response) => response.status().is_success(),
             Err(_) => false,
@@ -627,25 +810,30 @@ async fn test_signaling_connection() -> bool {
     })
     .await;
 
-    result.unwrap_or(false)
+    Ok(result.unwrap_or(false))
 }
 
-/// Test connection to the relay server
+/// Test connection to the active profile's relay servers
+///
+/// Succeeds if any relay accepts a connection.
 #[tauri::command]
-async fn test_relay_connection() -> bool {
+async fn test_relay_connection(state: State<'_, AppState>) -> Result<bool, String> {
     use tokio::time::{timeout, Duration};
 
+    let relays = state.active_profile().relays;
     let result = timeout(Duration::from_secs(5), async {
-        // Try TCP connection to relay server
-        let relay_url = DEFAULT_RELAY.trim_start_matches("tcp://");
-        match tokio::net::TcpStream::connect(relay_url).await {
-            Ok(_) => true,
-            Err(_) => false,
+        // Try TCP connection to each relay server
+        for relay in relays {
+            let relay_url = relay.trim_start_matches("tcp://");
+            if tokio::net::TcpStream::connect(relay_url).await.is_ok() {
+                return true;
+            }
         }
+        false
     })
     .await;
 
-    result.unwrap_or(false)
+    Ok(result.unwrap_or(false))
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -653,19 +841,36 @@ pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_dialog::init())
-        .manage(AppState::default())
+        .manage(AppState::new(
+            SettingsStore::open_default(),
+            History::open_default(),
+        ))
         .invoke_handler(tauri::generate_handler![
             generate_code,
             parse_code,
+            complete_code,
+            get_server_motd,
             prepare_file,
             prepare_directory,
             start_send,
             start_receive,
+            list_transfers,
+            cancel_transfer,
+            get_history,
+            clear_history,
+            export_history,
             format_size,
             get_version,
+            get_settings,
+            set_settings,
             get_download_path,
             get_default_download_path,
             set_download_path,
+            list_server_profiles,
+            get_active_profile,
+            save_server_profile,
+            delete_server_profile,
+            set_active_profile,
             test_signaling_connection,
             test_relay_connection,
         ])
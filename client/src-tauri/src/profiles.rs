@@ -0,0 +1,390 @@
+//! Server profiles
+//!
+//! A profile names the mailbox server, transit relays and application ID
+//! used for transfers, and whether to reach the peer only through Tor, so
+//! teams running their own servers can point the client at them. Custom
+//! profiles are part of the settings; the built-in profile uses the public
+//! SecureBeam servers and cannot be edited or removed.
+
+use serde::{Deserialize, Serialize};
+
+use securebeam_core::{
+    mailbox::DEFAULT_APPID,
+    session::Servers,
+    transit::{RelayHint, TransitMode},
+    SignalingClient, DEFAULT_MAILBOX, DEFAULT_RELAY,
+};
+
+/// Name of the built-in profile
+pub const DEFAULT_PROFILE: &str = "SecureBeam";
+
+/// Longest accepted profile name
+const MAX_NAME_LEN: usize = 64;
+
+/// Servers used for a transfer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerProfile {
+    pub name: String,
+    /// Mailbox server base URL, e.g. `https://mailbox.example.com`
+    pub mailbox_url: String,
+    /// Transit relays, e.g. `tcp://relay.example.com:4001`
+    pub relays: Vec<String>,
+    /// Application ID; both sides of a transfer must use the same one
+    pub appid: String,
+    /// Tor SOCKS proxy, e.g. `127.0.0.1:9050`; when set, transit
+    /// connections go only through Tor and never reveal our IP address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tor_socks: Option<String>,
+}
+
+impl ServerProfile {
+    /// The profile for the public SecureBeam servers
+    pub fn builtin() -> Self {
+        Self {
+            name: DEFAULT_PROFILE.to_string(),
+            mailbox_url: DEFAULT_MAILBOX.to_string(),
+            relays: vec![DEFAULT_RELAY.to_string()],
+            appid: DEFAULT_APPID.to_string(),
+            tor_socks: None,
+        }
+    }
+
+    /// Check the profile, returning a message for the user if it is invalid
+    pub fn validate(&self) -> Result<(), String> {
+        let name = self.name.trim();
+        if name.is_empty() || name.len() > MAX_NAME_LEN || name != self.name {
+            return Err(format!(
+                "Profile name must be 1 to {} characters without surrounding spaces",
+                MAX_NAME_LEN
+            ));
+        }
+        if name.eq_ignore_ascii_case(DEFAULT_PROFILE) {
+            return Err(format!("{} is the built-in profile", DEFAULT_PROFILE));
+        }
+
+        let url = reqwest::Url::parse(&self.mailbox_url)
+            .map_err(|_| format!("Invalid mailbox URL: {}", self.mailbox_url))?;
+        if !matches!(url.scheme(), "http" | "https" | "ws" | "wss") || url.host().is_none() {
+            return Err("Mailbox URL must be an http(s):// or ws(s):// URL".to_string());
+        }
+
+        if self.relays.is_empty() {
+            return Err("At least one relay is required".to_string());
+        }
+        if let Some(relay) = self
+            .relays
+            .iter()
+            .find(|relay| RelayHint::new(relay).parse().is_none())
+        {
+            return Err(format!("Invalid relay {}, expected tcp://host:port", relay));
+        }
+
+        if self.appid.is_empty() || self.appid.contains(char::is_whitespace) {
+            return Err("Application ID must be non-empty without spaces".to_string());
+        }
+
+        if let Some(socks) = &self.tor_socks {
+            if socks.parse::<std::net::SocketAddr>().is_err() {
+                return Err(format!(
+                    "Invalid Tor proxy {}, expected an address like 127.0.0.1:9050",
+                    socks
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Client for the profile's mailbox server
+    pub fn signaling(&self) -> SignalingClient {
+        SignalingClient::new(self.mailbox_url.trim_end_matches('/'))
+    }
+
+    /// The servers a transfer with this profile uses
+    pub fn servers(&self) -> Servers {
+        Servers {
+            mailbox_url: self.mailbox_url.clone(),
+            relays: self.relays.clone(),
+            appid: self.appid.clone(),
+        }
+    }
+
+    /// How transfers with this profile reach the peer
+    pub fn transit_mode(&self) -> TransitMode {
+        match &self.tor_socks {
+            Some(socks_addr) => TransitMode::Tor {
+                socks_addr: socks_addr.clone(),
+            },
+            None => TransitMode::Direct,
+        }
+    }
+
+    /// The mailbox server's health endpoint
+    pub fn health_url(&self) -> String {
+        let base = self
+            .mailbox_url
+            .trim_end_matches('/')
+            .replacen("wss://", "https://", 1)
+            .replacen("ws://", "http://", 1);
+        format!("{}/health", base)
+    }
+}
+
+/// Custom profiles and the one in use
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profiles {
+    /// Name of the profile used for transfers
+    pub active: String,
+    /// Custom profiles, without the built-in one
+    pub profiles: Vec<ServerProfile>,
+}
+
+impl Default for Profiles {
+    fn default() -> Self {
+        Self {
+            active: DEFAULT_PROFILE.to_string(),
+            profiles: Vec::new(),
+        }
+    }
+}
+
+impl Profiles {
+    /// Check every profile and that the active one exists
+    pub fn validate(&self) -> Result<(), String> {
+        for (i, profile) in self.profiles.iter().enumerate() {
+            profile.validate()?;
+            if self.profiles[..i].iter().any(|p| p.name == profile.name) {
+                return Err(format!("Duplicate profile {}", profile.name));
+            }
+        }
+        if self.active != DEFAULT_PROFILE && !self.profiles.iter().any(|p| p.name == self.active) {
+            return Err(format!("No profile named {}", self.active));
+        }
+        Ok(())
+    }
+
+    /// Drop invalid or duplicate profiles, e.g. after hand-editing the config
+    ///
+    /// Switches back to the built-in profile if the active one is dropped.
+    pub fn repair(&mut self) {
+        let mut seen = Vec::new();
+        self.profiles.retain(|p| {
+            let keep = p.validate().is_ok() && !seen.contains(&p.name);
+            seen.push(p.name.clone());
+            keep
+        });
+        if !self.profiles.iter().any(|p| p.name == self.active) {
+            self.active = DEFAULT_PROFILE.to_string();
+        }
+    }
+
+    /// All profiles, the built-in one first
+    pub fn all(&self) -> Vec<ServerProfile> {
+        std::iter::once(ServerProfile::builtin())
+            .chain(self.profiles.iter().cloned())
+            .collect()
+    }
+
+    /// The profile used for transfers
+    ///
+    /// Falls back to the built-in profile if the active one is missing or
+    /// was broken by hand-editing the config file.
+    pub fn active(&self) -> ServerProfile {
+        self.profiles
+            .iter()
+            .find(|p| p.name == self.active && p.validate().is_ok())
+            .cloned()
+            .unwrap_or_else(ServerProfile::builtin)
+    }
+
+    /// Add a profile, or replace the one with the same name
+    pub fn upsert(&mut self, profile: ServerProfile) -> Result<(), String> {
+        profile.validate()?;
+        match self.profiles.iter_mut().find(|p| p.name == profile.name) {
+            Some(existing) => *existing = profile,
+            None => self.profiles.push(profile),
+        }
+        Ok(())
+    }
+
+    /// Remove a custom profile, switching back to the built-in one if it was active
+    pub fn remove(&mut self, name: &str) -> Result<(), String> {
+        let before = self.profiles.len();
+        self.profiles.retain(|p| p.name != name);
+        if self.profiles.len() == before {
+            return Err(format!("No custom profile named {}", name));
+        }
+        if self.active == name {
+            self.active = DEFAULT_PROFILE.to_string();
+        }
+        Ok(())
+    }
+
+    /// Use the named profile for future transfers
+    pub fn activate(&mut self, name: &str) -> Result<(), String> {
+        if name != DEFAULT_PROFILE && !self.profiles.iter().any(|p| p.name == name) {
+            return Err(format!("No profile named {}", name));
+        }
+        self.active = name.to_string();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn team(name: &str) -> ServerProfile {
+        ServerProfile {
+            name: name.to_string(),
+            mailbox_url: "https://mailbox.example.com".to_string(),
+            relays: vec!["tcp://relay.example.com:4001".to_string()],
+            appid: "example.com/transfer".to_string(),
+            tor_socks: None,
+        }
+    }
+
+    fn invalid(change: impl FnOnce(&mut ServerProfile)) -> String {
+        let mut profile = team("Team");
+        change(&mut profile);
+        profile.validate().unwrap_err()
+    }
+
+    #[test]
+    fn test_validate_mailbox_url() {
+        for url in [
+            "https://mailbox.example.com",
+            "http://localhost:3030/",
+            "wss://mailbox.example.com/v1",
+            "ws://10.0.0.1:3030",
+        ] {
+            let profile = ServerProfile {
+                mailbox_url: url.to_string(),
+                ..team("Team")
+            };
+            assert_eq!(profile.validate(), Ok(()), "{}", url);
+        }
+
+        for url in [
+            "ftp://mailbox.example.com",
+            "file:///tmp/mailbox",
+            "mailbox",
+        ] {
+            let err = invalid(|p| p.mailbox_url = url.to_string());
+            assert!(err.contains("URL"), "{}: {}", url, err);
+        }
+    }
+
+    #[test]
+    fn test_validate_relays() {
+        let err = invalid(|p| p.relays.clear());
+        assert_eq!(err, "At least one relay is required");
+
+        for relay in [
+            "relay.example.com:4001",
+            "tcp://relay.example.com",
+            "udp://relay:1",
+        ] {
+            let err = invalid(|p| p.relays.push(relay.to_string()));
+            assert!(err.starts_with("Invalid relay"), "{}: {}", relay, err);
+        }
+    }
+
+    #[test]
+    fn test_validate_appid_and_name() {
+        for appid in ["", "example.com transfer"] {
+            let err = invalid(|p| p.appid = appid.to_string());
+            assert!(err.starts_with("Application ID"), "{:?}: {}", appid, err);
+        }
+
+        for name in ["", " Team", "securebeam"] {
+            assert!(team(name).validate().is_err(), "{:?}", name);
+        }
+        assert!(team(&"x".repeat(MAX_NAME_LEN + 1)).validate().is_err());
+    }
+
+    #[test]
+    fn test_tor_proxy() {
+        assert_eq!(team("Team").transit_mode(), TransitMode::Direct);
+
+        let profile = ServerProfile {
+            tor_socks: Some("127.0.0.1:9050".to_string()),
+            ..team("Team")
+        };
+        assert_eq!(profile.validate(), Ok(()));
+        assert_eq!(
+            profile.transit_mode(),
+            TransitMode::Tor {
+                socks_addr: "127.0.0.1:9050".to_string()
+            }
+        );
+
+        let err = invalid(|p| p.tor_socks = Some("localhost".to_string()));
+        assert!(err.starts_with("Invalid Tor proxy"));
+    }
+
+    #[test]
+    fn test_switch_profiles() {
+        let mut profiles = Profiles::default();
+        assert_eq!(profiles.active(), ServerProfile::builtin());
+
+        profiles.upsert(team("Team")).unwrap();
+        profiles.activate("Team").unwrap();
+        assert_eq!(profiles.active(), team("Team"));
+        assert_eq!(profiles.validate(), Ok(()));
+
+        // Unknown profiles are refused and the active one is kept
+        assert!(profiles.activate("Other").is_err());
+        assert_eq!(profiles.active, "Team");
+
+        profiles.activate(DEFAULT_PROFILE).unwrap();
+        assert_eq!(profiles.active(), ServerProfile::builtin());
+
+        profiles.activate("Team").unwrap();
+        profiles.remove("Team").unwrap();
+        assert_eq!(profiles.active, DEFAULT_PROFILE);
+        assert!(profiles.remove("Team").is_err());
+    }
+
+    #[test]
+    fn test_rejected_profiles_are_not_used() {
+        let mut profiles = Profiles::default();
+        let broken = ServerProfile {
+            relays: Vec::new(),
+            ..team("Team")
+        };
+        assert!(profiles.upsert(broken.clone()).is_err());
+        assert!(profiles.profiles.is_empty());
+        assert!(profiles.activate("Team").is_err());
+
+        // A hand-edited config may still contain one
+        profiles.profiles.push(broken);
+        profiles.active = "Team".to_string();
+        assert!(profiles.validate().is_err());
+        assert_eq!(profiles.active(), ServerProfile::builtin());
+
+        profiles.repair();
+        assert!(profiles.profiles.is_empty());
+        assert_eq!(profiles.active, DEFAULT_PROFILE);
+    }
+
+    #[test]
+    fn test_duplicate_profiles() {
+        let mut profiles = Profiles::default();
+        profiles.upsert(team("Team")).unwrap();
+        let moved = ServerProfile {
+            appid: "example.com/other".to_string(),
+            ..team("Team")
+        };
+        profiles.upsert(moved.clone()).unwrap();
+        assert_eq!(profiles.profiles, vec![moved]);
+
+        profiles.profiles.push(team("Team"));
+        assert_eq!(
+            profiles.validate(),
+            Err("Duplicate profile Team".to_string())
+        );
+        profiles.repair();
+        assert_eq!(profiles.profiles.len(), 1);
+    }
+}
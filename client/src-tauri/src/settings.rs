@@ -0,0 +1,365 @@
+//! Client settings
+//!
+//! Settings live in `config.json` in the platform config directory and are
+//! cached in memory. The file carries a `version`; older files are migrated
+//! when loaded and written back in the current format. Writes go to a
+//! temporary file that is renamed over the old one, so a crash never leaves
+//! a half-written config behind.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use crate::profiles::Profiles;
+
+/// Version of the settings format written by this build
+pub const SETTINGS_VERSION: u64 = 1;
+
+/// Event emitted with the new settings whenever they change
+pub const SETTINGS_CHANGED_EVENT: &str = "settings-changed";
+
+/// Transfers allowed to run at once unless configured otherwise
+pub const DEFAULT_MAX_TRANSFERS: usize = 3;
+
+/// Highest accepted concurrency limit
+const MAX_TRANSFERS_LIMIT: usize = 16;
+
+/// Upgrades from older versions, indexed by the version they start from
+const MIGRATIONS: [fn(&mut Map<String, Value>); SETTINGS_VERSION as usize] = [from_unversioned];
+
+/// Errors reading or writing the settings
+#[derive(Debug, thiserror::Error)]
+pub enum SettingsError {
+    #[error("Could not find config directory")]
+    NoConfigDir,
+    #[error("Cannot access {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("Invalid settings in {0}: {1}")]
+    Parse(PathBuf, serde_json::Error),
+    #[error("Settings version {0} is newer than this version of SecureBeam")]
+    TooNew(u64),
+    #[error("{0}")]
+    Invalid(String),
+}
+
+/// Everything the client remembers between runs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub version: u64,
+    /// Folder received files are saved to; the platform default when unset
+    pub download_path: Option<String>,
+    /// Server profiles and the one in use
+    pub servers: Profiles,
+    /// Transfers allowed to run at once
+    pub max_concurrent_transfers: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            version: SETTINGS_VERSION,
+            download_path: None,
+            servers: Profiles::default(),
+            max_concurrent_transfers: DEFAULT_MAX_TRANSFERS,
+        }
+    }
+}
+
+impl Settings {
+    /// Check the settings before they are saved
+    pub fn validate(&self) -> Result<(), String> {
+        if self.download_path.as_deref() == Some("") {
+            return Err("Download path must not be empty".to_string());
+        }
+        if !(1..=MAX_TRANSFERS_LIMIT).contains(&self.max_concurrent_transfers) {
+            return Err(format!(
+                "Concurrent transfers must be between 1 and {}",
+                MAX_TRANSFERS_LIMIT
+            ));
+        }
+        self.servers.validate()
+    }
+}
+
+/// Cached settings backed by the config file
+pub struct SettingsStore {
+    path: Option<PathBuf>,
+    cache: RwLock<Settings>,
+}
+
+impl SettingsStore {
+    /// Open the settings in the platform config directory
+    ///
+    /// A config file that cannot be read is moved aside to `config.json.bak`
+    /// and replaced by defaults, so the app still starts.
+    pub fn open_default() -> Self {
+        let Some(path) = dirs::config_dir().map(|dir| dir.join("securebeam").join("config.json"))
+        else {
+            eprintln!("Settings error: {}", SettingsError::NoConfigDir);
+            return Self {
+                path: None,
+                cache: RwLock::new(Settings::default()),
+            };
+        };
+
+        Self::open_or_reset(path)
+    }
+
+    /// Open the settings at `path`, falling back to defaults if they cannot be read
+    fn open_or_reset(path: PathBuf) -> Self {
+        match Self::open(path.clone()) {
+            Ok(store) => store,
+            Err(e) => {
+                eprintln!("Settings error: {}", e);
+                let backup = path.with_extension("json.bak");
+                if let Err(e) = std::fs::rename(&path, &backup) {
+                    eprintln!("Cannot move broken settings aside: {}", e);
+                }
+                Self {
+                    path: Some(path),
+                    cache: RwLock::new(Settings::default()),
+                }
+            }
+        }
+    }
+
+    /// Open the settings stored at `path`, migrating older versions
+    pub fn open(path: PathBuf) -> Result<Self, SettingsError> {
+        let settings = match std::fs::read_to_string(&path) {
+            Ok(content) => {
+                let value: Value = serde_json::from_str(&content)
+                    .map_err(|e| SettingsError::Parse(path.clone(), e))?;
+                let migrated =
+                    value.get("version").and_then(Value::as_u64) != Some(SETTINGS_VERSION);
+                let mut settings = migrate(value).map_err(|e| match e {
+                    MigrateError::TooNew(version) => SettingsError::TooNew(version),
+                    MigrateError::Parse(e) => SettingsError::Parse(path.clone(), e),
+                })?;
+                settings.servers.repair();
+                if settings.download_path.as_deref() == Some("") {
+                    settings.download_path = None;
+                }
+                if !(1..=MAX_TRANSFERS_LIMIT).contains(&settings.max_concurrent_transfers) {
+                    settings.max_concurrent_transfers = DEFAULT_MAX_TRANSFERS;
+                }
+                if migrated {
+                    write_atomic(&path, &settings)?;
+                }
+                settings
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Settings::default(),
+            Err(e) => return Err(SettingsError::Io(path, e)),
+        };
+
+        Ok(Self {
+            path: Some(path),
+            cache: RwLock::new(settings),
+        })
+    }
+
+    /// The current settings
+    pub fn get(&self) -> Settings {
+        self.cache.read().unwrap().clone()
+    }
+
+    /// Change the settings and save them, returning the new settings
+    ///
+    /// Nothing is changed if `change` fails, the result is invalid or the
+    /// file cannot be written.
+    pub fn update<F>(&self, change: F) -> Result<Settings, SettingsError>
+    where
+        F: FnOnce(&mut Settings) -> Result<(), String>,
+    {
+        let mut cache = self.cache.write().unwrap();
+        let mut settings = cache.clone();
+        change(&mut settings).map_err(SettingsError::Invalid)?;
+        settings.version = SETTINGS_VERSION;
+        settings.validate().map_err(SettingsError::Invalid)?;
+
+        let path = self.path.as_ref().ok_or(SettingsError::NoConfigDir)?;
+        write_atomic(path, &settings)?;
+        *cache = settings.clone();
+        Ok(settings)
+    }
+}
+
+enum MigrateError {
+    TooNew(u64),
+    Parse(serde_json::Error),
+}
+
+/// Bring a settings document of any known version up to date
+fn migrate(mut value: Value) -> Result<Settings, MigrateError> {
+    let Value::Object(map) = &mut value else {
+        return Err(MigrateError::Parse(serde::de::Error::custom(
+            "expected a JSON object",
+        )));
+    };
+    let version = map.get("version").and_then(Value::as_u64).unwrap_or(0);
+    if version > SETTINGS_VERSION {
+        return Err(MigrateError::TooNew(version));
+    }
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(map);
+    }
+    map.insert("version".to_string(), SETTINGS_VERSION.into());
+    serde_json::from_value(value).map_err(MigrateError::Parse)
+}
+
+/// Version 0: the untyped file edited in place before settings were versioned
+fn from_unversioned(map: &mut Map<String, Value>) {
+    if !matches!(map.get("download_path"), Some(Value::String(_))) {
+        map.remove("download_path");
+    }
+    // Unreadable profiles were silently ignored
+    let servers_ok = map
+        .get("servers")
+        .is_some_and(|servers| serde_json::from_value::<Profiles>(servers.clone()).is_ok());
+    if !servers_ok {
+        map.remove("servers");
+    }
+}
+
+/// Write `settings` to a temporary file and rename it over `path`
+fn write_atomic(path: &Path, settings: &Settings) -> Result<(), SettingsError> {
+    let io_error = |e| SettingsError::Io(path.to_path_buf(), e);
+    let json = serde_json::to_string_pretty(settings)
+        .map_err(|e| SettingsError::Parse(path.to_path_buf(), e))?;
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(io_error)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    let mut file = std::fs::File::create(&tmp).map_err(io_error)?;
+    file.write_all(json.as_bytes()).map_err(io_error)?;
+    file.sync_all().map_err(io_error)?;
+    std::fs::rename(&tmp, path).map_err(io_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory for one test's config file
+    fn config_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "securebeam-settings-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn files(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_migrate_unversioned_config() {
+        let dir = config_dir("migrate");
+        let path = dir.join("config.json");
+        std::fs::write(&path, r#"{"download_path": "/home/me/Downloads"}"#).unwrap();
+
+        let settings = SettingsStore::open(path.clone()).unwrap().get();
+        assert_eq!(
+            settings,
+            Settings {
+                download_path: Some("/home/me/Downloads".to_string()),
+                ..Settings::default()
+            }
+        );
+
+        // The file is written back in the current format
+        let saved: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved["version"], SETTINGS_VERSION);
+        assert_eq!(saved["download_path"], "/home/me/Downloads");
+        assert_eq!(files(&dir), ["config.json"]);
+    }
+
+    #[test]
+    fn test_migrate_drops_unreadable_values() {
+        let settings = migrate(serde_json::json!({
+            "download_path": 42,
+            "servers": "not profiles",
+        }))
+        .ok()
+        .unwrap();
+        assert_eq!(settings, Settings::default());
+
+        assert!(matches!(
+            migrate(serde_json::json!({ "version": SETTINGS_VERSION + 1 })),
+            Err(MigrateError::TooNew(_))
+        ));
+    }
+
+    #[test]
+    fn test_missing_config_uses_defaults() {
+        let dir = config_dir("missing");
+        let store = SettingsStore::open(dir.join("config.json")).unwrap();
+        assert_eq!(store.get(), Settings::default());
+        // Nothing is written until the settings change
+        assert!(files(&dir).is_empty());
+    }
+
+    #[test]
+    fn test_corrupt_config_uses_defaults() {
+        let dir = config_dir("corrupt");
+        let path = dir.join("config.json");
+        std::fs::write(&path, "{ not json").unwrap();
+
+        assert!(matches!(
+            SettingsStore::open(path.clone()),
+            Err(SettingsError::Parse(..))
+        ));
+        let store = SettingsStore::open_or_reset(path);
+        assert_eq!(store.get(), Settings::default());
+
+        // The broken file is kept for the user to inspect
+        assert_eq!(files(&dir), ["config.json.bak"]);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("config.json.bak")).unwrap(),
+            "{ not json"
+        );
+    }
+
+    #[test]
+    fn test_update_writes_atomically() {
+        let dir = config_dir("update");
+        let path = dir.join("config.json");
+        let store = SettingsStore::open(path.clone()).unwrap();
+
+        let settings = store
+            .update(|s| {
+                s.max_concurrent_transfers = 5;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(settings.max_concurrent_transfers, 5);
+        assert_eq!(files(&dir), ["config.json"]);
+        assert_eq!(SettingsStore::open(path).unwrap().get(), settings);
+    }
+
+    #[test]
+    fn test_invalid_update_changes_nothing() {
+        let dir = config_dir("invalid");
+        let store = SettingsStore::open(dir.join("config.json")).unwrap();
+
+        let result = store.update(|s| {
+            s.max_concurrent_transfers = 0;
+            Ok(())
+        });
+        assert!(matches!(result, Err(SettingsError::Invalid(_))));
+        assert_eq!(store.get(), Settings::default());
+        assert!(files(&dir).is_empty());
+    }
+}
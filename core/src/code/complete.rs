@@ -0,0 +1,112 @@
+//! Code entry assistance
+//!
+//! Completes partially typed codes against the active nameplates and the
+//! wordlist, and rejects codes that could never have been generated.
+
+use super::{parse_code, words_for};
+use crate::{Error, Result};
+
+/// Complete a partially typed code
+///
+/// Before the first `-` the nameplate is completed from `nameplates`
+/// (as listed by the mailbox server); afterwards the last word is completed
+/// from the wordlist for its position. Returns full codes, sorted.
+pub fn complete_code(partial: &str, nameplates: &[String]) -> Vec<String> {
+    let partial = partial.trim_start().to_lowercase();
+
+    let Some((nameplate, words)) = partial.split_once('-') else {
+        let mut matches: Vec<&String> = nameplates
+            .iter()
+            .filter(|np| np.starts_with(&partial))
+            .collect();
+        matches.sort_by_key(|np| (np.len(), *np));
+        return matches.iter().map(|np| format!("{}-", np)).collect();
+    };
+    if nameplate.is_empty() || !nameplate.chars().all(|c| c.is_ascii_digit()) {
+        return Vec::new();
+    }
+
+    let mut words: Vec<&str> = words.split('-').collect();
+    let prefix = words.pop().unwrap_or_default();
+    let typed_ok = words
+        .iter()
+        .enumerate()
+        .all(|(i, word)| words_for(i).contains(word));
+    if !typed_ok {
+        return Vec::new();
+    }
+
+    let done = &partial[..partial.len() - prefix.len()];
+    let mut matches: Vec<String> = words_for(words.len())
+        .iter()
+        .filter(|word| word.starts_with(prefix))
+        .map(|word| format!("{}{}", done, word))
+        .collect();
+    matches.sort();
+    matches
+}
+
+/// Check that every word of a code is in the wordlist for its position
+pub fn validate_code(code: &str) -> Result<()> {
+    let (_, password) = parse_code(&code.to_lowercase())?;
+
+    for (i, word) in password.split('-').enumerate() {
+        if !words_for(i).contains(&word) {
+            let reason = if words_for(i + 1).contains(&word) {
+                "is in the wrong position"
+            } else {
+                "is not in the wordlist"
+            };
+            return Err(Error::InvalidCode(format!(
+                "Word {} \"{}\" {}",
+                i + 1,
+                word,
+                reason
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_complete_nameplate() {
+        let nameplates: Vec<String> = ["7", "71", "12", "700"].map(String::from).to_vec();
+        assert_eq!(complete_code("7", &nameplates), ["7-", "71-", "700-"]);
+        assert_eq!(complete_code("", &nameplates).len(), 4);
+        assert!(complete_code("9", &nameplates).is_empty());
+    }
+
+    #[test]
+    fn test_complete_words_by_position() {
+        // First word comes from the odd list, second from the even list
+        assert_eq!(complete_code("7-crossov", &[]), ["7-crossover"]);
+        assert_eq!(
+            complete_code("7-Crossover-clockw", &[]),
+            ["7-crossover-clockwork"]
+        );
+        assert!(complete_code("7-clockw", &[]).is_empty());
+        assert_eq!(complete_code("7-crossover-", &[]).len(), 256);
+
+        // An earlier mistyped word yields no completions
+        assert!(complete_code("7-crossovr-clock", &[]).is_empty());
+        assert!(complete_code("x-crossover", &[]).is_empty());
+    }
+
+    #[test]
+    fn test_validate_code() {
+        assert!(validate_code("7-crossover-clockwork").is_ok());
+        assert!(validate_code("7-Crossover-Clockwork").is_ok());
+
+        let swapped = validate_code("7-clockwork-crossover").unwrap_err();
+        assert!(swapped.details().unwrap().contains("wrong position"));
+
+        let typo = validate_code("7-crossover-clockwrok").unwrap_err();
+        assert!(typo.details().unwrap().contains("Word 2"));
+
+        assert!(validate_code("crossover-clockwork").is_err());
+    }
+}
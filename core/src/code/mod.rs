@@ -0,0 +1,140 @@
+//! Wormhole code generation
+//!
+//! A code such as `7-crossover-clockwork` is a nameplate allocated by the
+//! mailbox server followed by words from the PGP wordlist. The nameplate is
+//! public; only the words are secret, so each word adds 8 bits of entropy.
+//! Words alternate between the odd and even lists, starting with an odd one.
+
+mod complete;
+mod wordlist;
+
+pub use complete::{complete_code, validate_code};
+pub use wordlist::{EVEN_WORDS, ODD_WORDS};
+
+use rand::seq::SliceRandom;
+
+use crate::mailbox::MailboxConnection;
+use crate::{Error, Result};
+
+/// Number of words in a generated code
+pub const DEFAULT_WORD_COUNT: usize = 2;
+
+/// Largest number of words accepted in a generated code
+pub const MAX_WORD_COUNT: usize = 16;
+
+/// Bits of entropy contributed by each word
+const BITS_PER_WORD: u32 = 8;
+
+/// Generate the secret part of a code from `word_count` words
+pub fn generate_password(word_count: usize) -> Result<String> {
+    if !(1..=MAX_WORD_COUNT).contains(&word_count) {
+        return Err(Error::InvalidCode(format!(
+            "Word count must be between 1 and {}",
+            MAX_WORD_COUNT
+        )));
+    }
+
+    let mut rng = rand::thread_rng();
+    let words: Vec<&str> = (0..word_count)
+        .map(|i| {
+            *words_for(i)
+                .choose(&mut rng)
+                .expect("wordlists are not empty")
+        })
+        .collect();
+    Ok(words.join("-"))
+}
+
+/// The wordlist used for the word at `position` (0-based) of a code
+pub fn words_for(position: usize) -> &'static [&'static str; 256] {
+    if position.is_multiple_of(2) {
+        &ODD_WORDS
+    } else {
+        &EVEN_WORDS
+    }
+}
+
+/// Build a code from a nameplate and `word_count` random words
+pub fn generate_code(nameplate: &str, word_count: usize) -> Result<String> {
+    Ok(format!("{}-{}", nameplate, generate_password(word_count)?))
+}
+
+/// Allocate a nameplate from the mailbox server and build a code with it
+pub async fn allocate_code(conn: &mut MailboxConnection, word_count: usize) -> Result<String> {
+    // Validate before reserving a nameplate on the server
+    let password = generate_password(word_count)?;
+    let nameplate = conn.allocate().await?;
+    Ok(format!("{}-{}", nameplate, password))
+}
+
+/// Split a code into its nameplate and password
+pub fn parse_code(code: &str) -> Result<(String, String)> {
+    let invalid = || Error::InvalidCode("Expected a code like 7-crossover-clockwork".to_string());
+
+    let (nameplate, password) = code.trim().split_once('-').ok_or_else(invalid)?;
+    if nameplate.is_empty() || !nameplate.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    if password.split('-').any(str::is_empty) {
+        return Err(invalid());
+    }
+    Ok((nameplate.to_string(), password.to_string()))
+}
+
+/// Entropy in bits of a generated code with `word_count` words
+///
+/// The nameplate is not counted since it is sent to the server in clear.
+pub fn code_entropy(word_count: usize) -> u32 {
+    word_count as u32 * BITS_PER_WORD
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_wordlists_are_distinct() {
+        let words: HashSet<&str> = EVEN_WORDS.iter().chain(ODD_WORDS.iter()).copied().collect();
+        assert_eq!(words.len(), 512);
+    }
+
+    #[test]
+    fn test_words_alternate_odd_and_even() {
+        let password = generate_password(5).unwrap();
+        let words: Vec<&str> = password.split('-').collect();
+        assert_eq!(words.len(), 5);
+        for (i, word) in words.iter().enumerate() {
+            assert!(
+                ODD_WORDS.contains(word) == (i % 2 == 0),
+                "{} in wrong list",
+                word
+            );
+        }
+    }
+
+    #[test]
+    fn test_word_count_bounds() {
+        assert!(generate_password(0).is_err());
+        assert!(generate_password(MAX_WORD_COUNT + 1).is_err());
+        assert!(generate_password(MAX_WORD_COUNT).is_ok());
+    }
+
+    #[test]
+    fn test_generate_and_parse() {
+        let code = generate_code("7", 3).unwrap();
+        let (nameplate, password) = parse_code(&code).unwrap();
+        assert_eq!(nameplate, "7");
+        assert_eq!(password.split('-').count(), 3);
+
+        assert!(parse_code("crossover-clockwork").is_err());
+        assert!(parse_code("7-").is_err());
+        assert!(parse_code("7-crossover--clockwork").is_err());
+    }
+
+    #[test]
+    fn test_code_entropy() {
+        assert_eq!(code_entropy(DEFAULT_WORD_COUNT), 16);
+        assert_eq!(code_entropy(4), 32);
+    }
+}
@@ -0,0 +1,110 @@
+//! Hashcash stamps for the mailbox server permission step
+//!
+//! Servers protected against abuse ask for a stamp whose SHA-1 hash starts
+//! with a number of zero bits before clients may bind. Stamps use the
+//! hashcash version 1 format `1:bits:date:resource:ext:rand:counter`.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rand::RngCore;
+use sha1::{Digest, Sha1};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{Error, Result};
+
+/// Hardest challenge a client will attempt
+///
+/// Each bit doubles the expected work; 32 bits already takes minutes.
+pub const MAX_HASHCASH_BITS: u32 = 32;
+
+/// Mint a stamp for `resource` whose hash has `bits` leading zero bits
+///
+/// This is CPU bound; run it on a blocking thread.
+pub fn mint(resource: &str, bits: u32) -> Result<String> {
+    if bits > MAX_HASHCASH_BITS {
+        return Err(Error::Protocol(format!(
+            "Server asks for {} bits of hashcash, at most {} are supported",
+            bits, MAX_HASHCASH_BITS
+        )));
+    }
+    if resource.contains(':') {
+        return Err(Error::Protocol("Invalid hashcash resource".to_string()));
+    }
+
+    let mut salt = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let prefix = format!(
+        "1:{}:{}:{}::{}:",
+        bits,
+        today(),
+        resource,
+        STANDARD.encode(salt)
+    );
+
+    let stamp = (0u64..)
+        .map(|counter| format!("{}{:x}", prefix, counter))
+        .find(|stamp| leading_zero_bits(&Sha1::digest(stamp.as_bytes())) >= bits)
+        .expect("a stamp is found long before the counter wraps");
+    Ok(stamp)
+}
+
+/// Number of leading zero bits of a hash
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+/// Today's UTC date as `YYMMDD`
+fn today() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = civil_date((secs / 86_400) as i64);
+    format!("{:02}{:02}{:02}", year % 100, month, day)
+}
+
+/// Convert days since 1970-01-01 to a (year, month, day) date
+fn civil_date(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mint() {
+        let stamp = mint("a1b2c3", 10).unwrap();
+        let fields: Vec<&str> = stamp.split(':').collect();
+        assert_eq!(fields.len(), 7);
+        assert_eq!(&fields[..2], ["1", "10"]);
+        assert_eq!(fields[3], "a1b2c3");
+        assert!(leading_zero_bits(&Sha1::digest(stamp.as_bytes())) >= 10);
+
+        assert!(mint("a1b2c3", MAX_HASHCASH_BITS + 1).is_err());
+        assert!(mint("a:b", 1).is_err());
+    }
+
+    #[test]
+    fn test_civil_date() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(11_016), (2000, 2, 29));
+        assert_eq!(civil_date(20_744), (2026, 10, 18));
+    }
+}
@@ -5,11 +5,15 @@
 //!
 //! # Modules
 //!
+//! - `code` - Wormhole code generation from the PGP wordlist
 //! - `crypto` - Cryptographic operations (SPAKE2, NaCl SecretBox, HKDF)
+//! - `hashcash` - Proof-of-work stamps for the mailbox permission step
 //! - `protocol` - Protocol definitions and message types
 //! - `transfer` - File transfer logic with compression
 //! - `transit` - P2P connection establishment (direct + relay)
 //! - `network` - Network abstractions and WebSocket client
+//! - `mailbox` - Mailbox server protocol client with automatic reconnect
+//! - `session` - Complete send and receive flows with an event stream
 //!
 //! # Security
 //!
@@ -24,9 +28,13 @@
 //! - Path traversal protection for archive extraction
 //! - Input validation with size limits
 
+pub mod code;
 pub mod crypto;
+pub mod hashcash;
+pub mod mailbox;
 pub mod network;
 pub mod protocol;
+pub mod session;
 pub mod transfer;
 pub mod transit;
 
@@ -35,10 +43,15 @@ pub use crypto::{
     derive_key, derive_phase_key, derive_verifier, Nonce, Purpose, SecretBox, Spake2Exchange,
     Spake2Message,
 };
+pub use mailbox::{MailboxConnection, MailboxMessage, Mood};
 pub use network::SignalingClient;
 pub use protocol::{FileAnswer, FileOffer, Message, OfferType};
+pub use session::{Event, Payload, Received, Receiver, Sender, Servers, Status};
 pub use transfer::{FileTransfer, TransferProgress};
-pub use transit::{establish_transit, TransitConnection, TransitHints, TransitRole};
+pub use transit::{
+    establish_transit, establish_transit_with_mode, ConnectionKind, TransitConnection,
+    TransitHints, TransitMode, TransitRole,
+};
 
 /// Library version
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -85,8 +98,20 @@ pub enum Error {
     #[error("Authentication failed")]
     WrongCode,
 
+    #[error("Invalid code")]
+    InvalidCode(String),
+
     #[error("Security verification failed")]
     MitmDetected,
+
+    #[error("Server unavailable")]
+    ServerUnavailable(String),
+
+    #[error("Transfer rejected")]
+    Rejected(String),
+
+    #[error("Transfer cancelled")]
+    Cancelled,
 }
 
 impl Error {
@@ -100,6 +125,9 @@ impl Error {
             Error::Protocol(s) => Some(s),
             Error::Crypto(s) => Some(s),
             Error::Transfer(s) => Some(s),
+            Error::InvalidCode(s) => Some(s),
+            Error::ServerUnavailable(s) => Some(s),
+            Error::Rejected(s) => Some(s),
             _ => None,
         }
     }
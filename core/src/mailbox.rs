@@ -0,0 +1,833 @@
+//! Mailbox server protocol client
+//!
+//! Speaks the Magic Wormhole server protocol (bind, allocate, claim, open,
+//! add, close). When the websocket drops, the client reconnects, re-binds
+//! with the same side and reopens its mailbox, asking the server to replay
+//! only the messages after the last one it received. Servers that ask for
+//! a hashcash stamp in their welcome message get one before each bind.
+
+use std::collections::{HashSet, VecDeque};
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    connect_async, tungstenite::Message as WsMessage, MaybeTlsStream, WebSocketStream,
+};
+
+use crate::{hashcash, Error, Result};
+
+/// Application ID used by SecureBeam clients
+pub const DEFAULT_APPID: &str = "securebeam.eu/file-transfer";
+
+/// Reconnect attempts before a dropped connection is reported
+pub const DEFAULT_MAX_RECONNECTS: u32 = 5;
+
+/// Delay before the first reconnect attempt, doubled on each retry
+const RECONNECT_DELAY: Duration = Duration::from_millis(250);
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Messages from client to server
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum ClientMessage {
+    #[serde(rename = "submit-permissions")]
+    SubmitPermissions {
+        method: String,
+        stamp: String,
+    },
+    Bind {
+        appid: String,
+        side: String,
+    },
+    List,
+    Allocate,
+    Claim {
+        nameplate: String,
+    },
+    Release {
+        nameplate: Option<String>,
+    },
+    Open {
+        mailbox: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        last_seen: Option<u64>,
+    },
+    Add {
+        phase: String,
+        body: String,
+    },
+    Close {
+        mailbox: Option<String>,
+        mood: Mood,
+    },
+}
+
+/// Messages from server to client
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum ServerMessage {
+    Welcome {
+        #[serde(default)]
+        welcome: WelcomeInfo,
+    },
+    Nameplates {
+        nameplates: Vec<NameplateInfo>,
+    },
+    Allocated {
+        nameplate: String,
+    },
+    Claimed {
+        mailbox: String,
+    },
+    Released,
+    Message {
+        side: String,
+        phase: String,
+        body: String,
+        id: u64,
+    },
+    Closed,
+    Ack,
+    Error {
+        error: String,
+    },
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct WelcomeInfo {
+    motd: Option<String>,
+    error: Option<String>,
+    #[serde(rename = "permission-required")]
+    permission_required: Option<PermissionRequired>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct PermissionRequired {
+    hashcash: Option<HashcashChallenge>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct HashcashChallenge {
+    bits: u32,
+    resource: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct NameplateInfo {
+    id: String,
+}
+
+/// How a transfer ended, reported to the server when closing the mailbox
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mood {
+    /// The transfer completed
+    Happy,
+    /// The peer never showed up
+    Lonely,
+    /// The key exchange or verification failed, e.g. a wrong code
+    Scary,
+    /// Something else went wrong
+    Errory,
+}
+
+impl Mood {
+    /// The mood matching the result of a transfer
+    pub fn for_result<T>(result: &Result<T>) -> Self {
+        match result {
+            Ok(_) => Mood::Happy,
+            Err(Error::WrongCode | Error::MitmDetected | Error::Crypto(_)) => Mood::Scary,
+            Err(Error::SessionExpired | Error::SessionNotFound | Error::Cancelled) => Mood::Lonely,
+            Err(_) => Mood::Errory,
+        }
+    }
+}
+
+/// A message from the peer, read from the mailbox
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MailboxMessage {
+    /// Server-assigned message ID
+    pub id: u64,
+    /// The peer's side
+    pub side: String,
+    /// Phase of the message (e.g. "pake", "version", "0")
+    pub phase: String,
+    /// Message body
+    pub body: Vec<u8>,
+}
+
+/// Connection to a mailbox server that survives websocket drops
+pub struct MailboxConnection {
+    url: String,
+    appid: String,
+    side: String,
+    ws: Option<WsStream>,
+    nameplate: Option<String>,
+    mailbox: Option<String>,
+    /// Highest message ID received from the server
+    last_seen: Option<u64>,
+    /// Peer messages received while waiting for a response
+    inbox: VecDeque<MailboxMessage>,
+    /// (side, phase) pairs already received, to drop duplicates
+    received: HashSet<(String, String)>,
+    /// Phases of our own messages the server replayed, so they are stored
+    echoed: HashSet<String>,
+    max_reconnects: u32,
+    /// Message of the day from the server's welcome
+    motd: Option<String>,
+}
+
+impl MailboxConnection {
+    /// Connect to the mailbox server at `url` (ws:// or wss://) and bind
+    pub async fn connect(url: &str, appid: &str) -> Result<Self> {
+        let mut conn = Self {
+            url: url.to_string(),
+            appid: appid.to_string(),
+            side: hex::encode(rand::random::<[u8; 5]>()),
+            ws: None,
+            nameplate: None,
+            mailbox: None,
+            last_seen: None,
+            inbox: VecDeque::new(),
+            received: HashSet::new(),
+            echoed: HashSet::new(),
+            max_reconnects: DEFAULT_MAX_RECONNECTS,
+            motd: None,
+        };
+        conn.establish().await?;
+        Ok(conn)
+    }
+
+    /// Set how many times a dropped connection is re-established
+    pub fn with_max_reconnects(mut self, max_reconnects: u32) -> Self {
+        self.max_reconnects = max_reconnects;
+        self
+    }
+
+    /// Our side identifier
+    pub fn side(&self) -> &str {
+        &self.side
+    }
+
+    /// The server's message of the day, to show to the user
+    pub fn motd(&self) -> Option<&str> {
+        self.motd.as_deref()
+    }
+
+    /// List the active nameplates of our app
+    pub async fn list_nameplates(&mut self) -> Result<Vec<String>> {
+        self.request(ClientMessage::List, |msg| match msg {
+            ServerMessage::Nameplates { nameplates } => {
+                Some(nameplates.into_iter().map(|np| np.id).collect())
+            }
+            _ => None,
+        })
+        .await
+    }
+
+    /// Allocate a fresh nameplate
+    pub async fn allocate(&mut self) -> Result<String> {
+        self.request(ClientMessage::Allocate, |msg| match msg {
+            ServerMessage::Allocated { nameplate } => Some(nameplate),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Claim a nameplate and return its mailbox ID
+    pub async fn claim(&mut self, nameplate: &str) -> Result<String> {
+        let claim = ClientMessage::Claim {
+            nameplate: nameplate.to_string(),
+        };
+        let mailbox = self
+            .request(claim, |msg| match msg {
+                ServerMessage::Claimed { mailbox } => Some(mailbox),
+                _ => None,
+            })
+            .await?;
+        self.nameplate = Some(nameplate.to_string());
+        Ok(mailbox)
+    }
+
+    /// Release the claimed nameplate
+    pub async fn release(&mut self) -> Result<()> {
+        let release = ClientMessage::Release {
+            nameplate: self.nameplate.clone(),
+        };
+        self.request(release, |msg| {
+            matches!(msg, ServerMessage::Released).then_some(())
+        })
+        .await?;
+        self.nameplate = None;
+        Ok(())
+    }
+
+    /// Open a mailbox; the peer's earlier messages are replayed
+    pub async fn open(&mut self, mailbox: &str) -> Result<()> {
+        let open = ClientMessage::Open {
+            mailbox: mailbox.to_string(),
+            last_seen: None,
+        };
+        self.request(open, ack).await?;
+        self.mailbox = Some(mailbox.to_string());
+        Ok(())
+    }
+
+    /// Add a message to the open mailbox
+    ///
+    /// If the connection drops before the server acknowledges the message,
+    /// it is only sent again when the replay after reconnecting shows that
+    /// the server did not store it.
+    pub async fn add(&mut self, phase: &str, body: &[u8]) -> Result<()> {
+        let add = ClientMessage::Add {
+            phase: phase.to_string(),
+            body: hex::encode(body),
+        };
+        let mut attempts = 0;
+        loop {
+            match self.try_request(&add, ack).await {
+                Err(Error::Connection(e)) => self.recover(&mut attempts, e).await?,
+                result => return result,
+            }
+            if self.echoed.contains(phase) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Receive the next message from the peer
+    pub async fn receive(&mut self) -> Result<MailboxMessage> {
+        let mut attempts = 0;
+        loop {
+            if let Some(message) = self.inbox.pop_front() {
+                return Ok(message);
+            }
+            match self.read().await {
+                Ok(ServerMessage::Error { error }) => return Err(Error::Protocol(error)),
+                Ok(_) => {}
+                Err(Error::Connection(e)) => self.recover(&mut attempts, e).await?,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Close the mailbox with the result of the transfer and return it
+    ///
+    /// The mood sent to the server follows from `result`; a failure to
+    /// close is only logged so that it does not mask the transfer result.
+    pub async fn finish<T>(&mut self, result: Result<T>) -> Result<T> {
+        if let Err(e) = self.close(Mood::for_result(&result)).await {
+            tracing::debug!("Failed to close mailbox: {:?}", e);
+        }
+        result
+    }
+
+    /// Close the mailbox with a mood and drop the connection
+    pub async fn close(&mut self, mood: Mood) -> Result<()> {
+        let close = ClientMessage::Close {
+            mailbox: self.mailbox.clone(),
+            mood,
+        };
+        self.request(close, |msg| {
+            matches!(msg, ServerMessage::Closed).then_some(())
+        })
+        .await?;
+        self.mailbox = None;
+
+        if let Some(mut ws) = self.ws.take() {
+            let _ = ws.close(None).await;
+        }
+        Ok(())
+    }
+
+    /// Send a request and wait for its response, reconnecting on drops
+    async fn request<T>(
+        &mut self,
+        message: ClientMessage,
+        expect: fn(ServerMessage) -> Option<T>,
+    ) -> Result<T> {
+        let mut attempts = 0;
+        loop {
+            match self.try_request(&message, expect).await {
+                Err(Error::Connection(e)) => self.recover(&mut attempts, e).await?,
+                result => return result,
+            }
+        }
+    }
+
+    /// Send a request and wait for its response on the current websocket
+    async fn try_request<T>(
+        &mut self,
+        message: &ClientMessage,
+        expect: fn(ServerMessage) -> Option<T>,
+    ) -> Result<T> {
+        let text = serde_json::to_string(message).map_err(|e| Error::Protocol(e.to_string()))?;
+        self.ws()?
+            .send(WsMessage::Text(text))
+            .await
+            .map_err(|e| Error::Connection(e.to_string()))?;
+
+        loop {
+            match self.read().await? {
+                ServerMessage::Error { error } => return Err(Error::Protocol(error)),
+                response => {
+                    if let Some(value) = expect(response) {
+                        return Ok(value);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Read the next server message, queueing peer messages in the inbox
+    async fn read(&mut self) -> Result<ServerMessage> {
+        loop {
+            let frame = match self.ws()?.next().await {
+                Some(Ok(frame)) => frame,
+                Some(Err(e)) => return Err(Error::Connection(e.to_string())),
+                None => return Err(Error::Connection("Connection closed".to_string())),
+            };
+            let text = match frame {
+                WsMessage::Text(text) => text,
+                WsMessage::Close(_) => {
+                    return Err(Error::Connection("Connection closed".to_string()))
+                }
+                _ => continue,
+            };
+
+            let message: ServerMessage =
+                serde_json::from_str(&text).map_err(|e| Error::Protocol(e.to_string()))?;
+            if let ServerMessage::Message {
+                side,
+                phase,
+                body,
+                id,
+            } = &message
+            {
+                self.last_seen = Some(self.last_seen.map_or(*id, |last| last.max(*id)));
+                if side == &self.side {
+                    self.echoed.insert(phase.clone());
+                } else if self.received.insert((side.clone(), phase.clone())) {
+                    let body = hex::decode(body).map_err(|e| Error::Protocol(e.to_string()))?;
+                    self.inbox.push_back(MailboxMessage {
+                        id: *id,
+                        side: side.clone(),
+                        phase: phase.clone(),
+                        body,
+                    });
+                }
+            }
+            return Ok(message);
+        }
+    }
+
+    fn ws(&mut self) -> Result<&mut WsStream> {
+        self.ws
+            .as_mut()
+            .ok_or_else(|| Error::Connection("Not connected".to_string()))
+    }
+
+    /// Re-establish a dropped connection, giving up after `max_reconnects`
+    async fn recover(&mut self, attempts: &mut u32, cause: String) -> Result<()> {
+        let mut cause = cause;
+        loop {
+            if *attempts >= self.max_reconnects {
+                return Err(Error::Connection(cause));
+            }
+            *attempts += 1;
+            tracing::warn!(
+                "Mailbox connection lost ({}), reconnecting (attempt {})",
+                cause,
+                attempts
+            );
+            tokio::time::sleep(RECONNECT_DELAY * 2u32.pow(*attempts - 1)).await;
+
+            match self.establish().await {
+                Ok(()) => return Ok(()),
+                Err(Error::Connection(e)) => cause = e,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Open the websocket, bind, and resume any claimed nameplate and mailbox
+    async fn establish(&mut self) -> Result<()> {
+        let (ws, _) = connect_async(&self.url)
+            .await
+            .map_err(|e| Error::Connection(e.to_string()))?;
+        self.ws = Some(ws);
+
+        let welcome = loop {
+            if let ServerMessage::Welcome { welcome } = self.read().await? {
+                break welcome;
+            }
+        };
+        // The server tells us why it cannot be used right now
+        if let Some(error) = welcome.error {
+            self.ws = None;
+            return Err(Error::ServerUnavailable(error));
+        }
+        if let Some(motd) = &welcome.motd {
+            tracing::info!("Server message: {}", motd);
+        }
+        self.motd = welcome.motd;
+
+        if let Some(permission) = welcome.permission_required {
+            let Some(challenge) = permission.hashcash else {
+                return Err(Error::Protocol(
+                    "Server requires an unsupported permission method".to_string(),
+                ));
+            };
+            let stamp = tokio::task::spawn_blocking(move || {
+                hashcash::mint(&challenge.resource, challenge.bits)
+            })
+            .await
+            .map_err(|e| Error::Protocol(e.to_string()))??;
+            let submit = ClientMessage::SubmitPermissions {
+                method: "hashcash".to_string(),
+                stamp,
+            };
+            self.try_request(&submit, ack).await?;
+        }
+
+        let bind = ClientMessage::Bind {
+            appid: self.appid.clone(),
+            side: self.side.clone(),
+        };
+        self.try_request(&bind, ack).await?;
+
+        if let Some(nameplate) = self.nameplate.clone() {
+            let claim = ClientMessage::Claim { nameplate };
+            self.try_request(&claim, |msg| {
+                matches!(msg, ServerMessage::Claimed { .. }).then_some(())
+            })
+            .await?;
+        }
+        if let Some(mailbox) = self.mailbox.clone() {
+            let open = ClientMessage::Open {
+                mailbox,
+                last_seen: self.last_seen,
+            };
+            self.try_request(&open, ack).await?;
+        }
+        Ok(())
+    }
+}
+
+fn ack(message: ServerMessage) -> Option<()> {
+    matches!(message, ServerMessage::Ack).then_some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use sha1::{Digest, Sha1};
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::accept_async;
+
+    /// Requests seen by the fake server, one list per connection
+    type Log = Arc<Mutex<Vec<Vec<Value>>>>;
+
+    /// A mailbox server holding two peer messages that drops the first
+    /// connection right after delivering the first one
+    async fn flaky_server() -> (String, Log) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/v1", listener.local_addr().unwrap());
+        let log: Log = Arc::default();
+
+        let server_log = log.clone();
+        tokio::spawn(async move {
+            let peer = [(1, "pake"), (2, "version")];
+            while let Ok((stream, _)) = listener.accept().await {
+                let mut ws = accept_async(stream).await.unwrap();
+                let first = {
+                    let mut log = server_log.lock().unwrap();
+                    log.push(Vec::new());
+                    log.len() == 1
+                };
+                let welcome = json!({"type": "welcome", "welcome": {}});
+                ws.send(WsMessage::Text(welcome.to_string())).await.unwrap();
+
+                while let Some(Ok(WsMessage::Text(text))) = ws.next().await {
+                    let request: Value = serde_json::from_str(&text).unwrap();
+                    server_log
+                        .lock()
+                        .unwrap()
+                        .last_mut()
+                        .unwrap()
+                        .push(request.clone());
+
+                    let mut replies = match request["type"].as_str().unwrap() {
+                        "claim" => vec![json!({"type": "claimed", "mailbox": "mb"})],
+                        "close" => vec![json!({"type": "closed"})],
+                        _ => vec![json!({"type": "ack"})],
+                    };
+                    if request["type"] == "open" {
+                        let after = request["last_seen"].as_u64().unwrap_or(0);
+                        let limit = if first { 1 } else { peer.len() };
+                        for (id, phase) in &peer[..limit] {
+                            if *id > after {
+                                replies.push(json!({"type": "message", "side": "peer",
+                                    "phase": phase, "body": "00", "id": id}));
+                            }
+                        }
+                    }
+                    for reply in replies {
+                        ws.send(WsMessage::Text(reply.to_string())).await.unwrap();
+                    }
+                    if first && request["type"] == "open" {
+                        // Drop without a close handshake
+                        break;
+                    }
+                }
+            }
+        });
+
+        (url, log)
+    }
+
+    /// A mailbox server that drops the first connection on its first add,
+    /// after storing the message if `store` is set; opens replay what was
+    /// stored, before the ack
+    async fn lossy_add_server(store: bool) -> (String, Log) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/v1", listener.local_addr().unwrap());
+        let log: Log = Arc::default();
+
+        let server_log = log.clone();
+        tokio::spawn(async move {
+            let mut stored: Vec<Value> = Vec::new();
+            let mut side = Value::Null;
+            while let Ok((stream, _)) = listener.accept().await {
+                let mut ws = accept_async(stream).await.unwrap();
+                let first = {
+                    let mut log = server_log.lock().unwrap();
+                    log.push(Vec::new());
+                    log.len() == 1
+                };
+                let welcome = json!({"type": "welcome", "welcome": {}});
+                ws.send(WsMessage::Text(welcome.to_string())).await.unwrap();
+
+                while let Some(Ok(WsMessage::Text(text))) = ws.next().await {
+                    let request: Value = serde_json::from_str(&text).unwrap();
+                    server_log
+                        .lock()
+                        .unwrap()
+                        .last_mut()
+                        .unwrap()
+                        .push(request.clone());
+
+                    let replies = match request["type"].as_str().unwrap() {
+                        "bind" => {
+                            side = request["side"].clone();
+                            vec![json!({"type": "ack"})]
+                        }
+                        "claim" => vec![json!({"type": "claimed", "mailbox": "mb"})],
+                        "open" => {
+                            let mut replies = stored.clone();
+                            replies.push(json!({"type": "ack"}));
+                            replies
+                        }
+                        "add" => {
+                            if store || !first {
+                                stored.push(json!({"type": "message", "side": side,
+                                    "phase": request["phase"], "body": request["body"],
+                                    "id": stored.len() + 1}));
+                            }
+                            if first {
+                                break;
+                            }
+                            vec![json!({"type": "ack"})]
+                        }
+                        _ => vec![json!({"type": "ack"})],
+                    };
+                    for reply in replies {
+                        ws.send(WsMessage::Text(reply.to_string())).await.unwrap();
+                    }
+                }
+            }
+        });
+
+        (url, log)
+    }
+
+    /// A mailbox server that sends `welcome` and acknowledges everything
+    async fn welcome_server(welcome: Value) -> (String, Log) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/v1", listener.local_addr().unwrap());
+        let log: Log = Arc::default();
+
+        let server_log = log.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let mut ws = accept_async(stream).await.unwrap();
+                server_log.lock().unwrap().push(Vec::new());
+                let welcome = json!({"type": "welcome", "welcome": welcome.clone()});
+                ws.send(WsMessage::Text(welcome.to_string())).await.unwrap();
+                while let Some(Ok(WsMessage::Text(text))) = ws.next().await {
+                    let request = serde_json::from_str(&text).unwrap();
+                    server_log.lock().unwrap().last_mut().unwrap().push(request);
+                    let ack = json!({"type": "ack"});
+                    if ws.send(WsMessage::Text(ack.to_string())).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        (url, log)
+    }
+
+    #[tokio::test]
+    async fn test_welcome_motd_and_error() {
+        let (url, _) = welcome_server(json!({"motd": "maintenance at 18:00"})).await;
+        let conn = MailboxConnection::connect(&url, DEFAULT_APPID)
+            .await
+            .unwrap();
+        assert_eq!(conn.motd(), Some("maintenance at 18:00"));
+
+        let (url, _) = welcome_server(json!({"error": "down for maintenance"})).await;
+        match MailboxConnection::connect(&url, DEFAULT_APPID).await {
+            Err(e @ Error::ServerUnavailable(_)) => {
+                assert_eq!(e.details(), Some("down for maintenance"))
+            }
+            other => panic!("expected the server to be unavailable: {:?}", other.err()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hashcash_before_bind() {
+        let challenge = json!({"hashcash": {"bits": 8, "resource": "c0ffee"}});
+        let (url, log) = welcome_server(json!({"permission-required": challenge})).await;
+        MailboxConnection::connect(&url, DEFAULT_APPID)
+            .await
+            .unwrap();
+
+        let log = log.lock().unwrap();
+        let requests: Vec<&str> = log[0].iter().map(|r| r["type"].as_str().unwrap()).collect();
+        assert_eq!(requests, ["submit-permissions", "bind"]);
+        assert_eq!(log[0][0]["method"], "hashcash");
+        let stamp = log[0][0]["stamp"].as_str().unwrap();
+        assert_eq!(stamp.split(':').nth(3), Some("c0ffee"));
+        assert_eq!(Sha1::digest(stamp.as_bytes())[0], 0);
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_permission_is_refused() {
+        let (url, _) = welcome_server(json!({"permission-required": {"unknown": {}}})).await;
+        let result = MailboxConnection::connect(&url, DEFAULT_APPID).await;
+        assert!(matches!(result, Err(Error::Protocol(_))));
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_resumes_after_last_seen() {
+        let (url, log) = flaky_server().await;
+
+        let mut conn = MailboxConnection::connect(&url, DEFAULT_APPID)
+            .await
+            .unwrap();
+        let mailbox = conn.claim("7").await.unwrap();
+        conn.open(&mailbox).await.unwrap();
+
+        let first = conn.receive().await.unwrap();
+        assert_eq!((first.id, first.phase.as_str()), (1, "pake"));
+
+        // The websocket drops here; the client resumes transparently
+        let second = conn.receive().await.unwrap();
+        assert_eq!((second.id, second.phase.as_str()), (2, "version"));
+        assert_eq!(second.body, vec![0]);
+
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 2);
+        let resumed: Vec<&str> = log[1].iter().map(|r| r["type"].as_str().unwrap()).collect();
+        assert_eq!(resumed, ["bind", "claim", "open"]);
+        assert_eq!(log[0][0]["side"], log[1][0]["side"]);
+        assert_eq!(log[1][1]["nameplate"], "7");
+        assert_eq!(log[1][2]["last_seen"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_add_is_not_repeated_once_stored() {
+        for store in [true, false] {
+            let (url, log) = lossy_add_server(store).await;
+
+            let mut conn = MailboxConnection::connect(&url, DEFAULT_APPID)
+                .await
+                .unwrap();
+            let mailbox = conn.claim("7").await.unwrap();
+            conn.open(&mailbox).await.unwrap();
+            conn.add("pake", b"key").await.unwrap();
+
+            let log = log.lock().unwrap();
+            assert_eq!(log.len(), 2);
+            let resumed: Vec<&str> = log[1].iter().map(|r| r["type"].as_str().unwrap()).collect();
+            if store {
+                assert_eq!(resumed, ["bind", "claim", "open"]);
+            } else {
+                assert_eq!(resumed, ["bind", "claim", "open", "add"]);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_finish_reports_mood() {
+        let (url, log) = flaky_server().await;
+
+        let mut conn = MailboxConnection::connect(&url, DEFAULT_APPID)
+            .await
+            .unwrap();
+        let mailbox = conn.claim("7").await.unwrap();
+        conn.open(&mailbox).await.unwrap();
+
+        let result = conn.finish::<()>(Err(Error::WrongCode)).await;
+        assert!(matches!(result, Err(Error::WrongCode)));
+
+        let log = log.lock().unwrap();
+        let close = log.last().unwrap().last().unwrap();
+        assert_eq!(close["type"], "close");
+        assert_eq!(close["mailbox"], "mb");
+        assert_eq!(close["mood"], "scary");
+    }
+
+    #[test]
+    fn test_mood_for_result() {
+        assert_eq!(Mood::for_result(&Ok(())), Mood::Happy);
+        assert_eq!(
+            Mood::for_result::<()>(&Err(Error::MitmDetected)),
+            Mood::Scary
+        );
+        assert_eq!(
+            Mood::for_result::<()>(&Err(Error::SessionExpired)),
+            Mood::Lonely
+        );
+        assert_eq!(Mood::for_result::<()>(&Err(Error::Cancelled)), Mood::Lonely);
+        assert_eq!(
+            Mood::for_result::<()>(&Err(Error::PeerDisconnected)),
+            Mood::Errory
+        );
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_without_reconnects() {
+        let (url, _log) = flaky_server().await;
+
+        let mut conn = MailboxConnection::connect(&url, DEFAULT_APPID)
+            .await
+            .unwrap()
+            .with_max_reconnects(0);
+        let mailbox = conn.claim("7").await.unwrap();
+        conn.open(&mailbox).await.unwrap();
+        conn.receive().await.unwrap();
+
+        assert!(matches!(conn.receive().await, Err(Error::Connection(_))));
+    }
+}
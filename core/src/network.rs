@@ -4,6 +4,7 @@ use futures::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
 use tokio_tungstenite::{connect_async, tungstenite::Message};
 
+use crate::mailbox::MailboxConnection;
 use crate::{Error, Result};
 
 /// Signaling client for connecting to the SecureBeam server
@@ -45,11 +46,7 @@ impl SignalingClient {
 
     /// Connect to a session using WebSocket
     pub async fn connect(&self, code: &str) -> Result<SessionConnection> {
-        let ws_url = self
-            .server_url
-            .replace("http://", "ws://")
-            .replace("https://", "wss://");
-        let url = format!("{}/ws/{}", ws_url, code);
+        let url = format!("{}/ws/{}", self.ws_url(), code);
 
         let (ws_stream, _) = connect_async(&url)
             .await
@@ -62,6 +59,17 @@ impl SignalingClient {
             read: Box::new(read),
         })
     }
+
+    /// Connect to the mailbox protocol endpoint and bind as `appid`
+    pub async fn connect_mailbox(&self, appid: &str) -> Result<MailboxConnection> {
+        MailboxConnection::connect(&format!("{}/v1", self.ws_url()), appid).await
+    }
+
+    fn ws_url(&self) -> String {
+        self.server_url
+            .replace("http://", "ws://")
+            .replace("https://", "wss://")
+    }
 }
 
 /// Session information returned by the server
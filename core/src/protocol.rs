@@ -38,6 +38,8 @@ pub enum OfferType {
     File(FileMetadata),
     /// Directory offer (sent as TAR)
     Directory(DirectoryMetadata),
+    /// Text message, carried by the offer itself
+    Message(String),
 }
 
 /// Metadata for a single file
@@ -97,6 +99,9 @@ pub enum AnswerType {
     /// Accept the transfer
     #[serde(rename = "file_ack")]
     FileAck(String), // "ok"
+    /// Confirm a text message was received
+    #[serde(rename = "message_ack")]
+    MessageAck(String), // "ok"
     /// Reject the transfer
     #[serde(rename = "error")]
     Error(String),
@@ -142,19 +147,30 @@ impl FileOffer {
         }
     }
 
+    /// Create a text message offer
+    pub fn message(text: String) -> Self {
+        Self {
+            offer_type: OfferType::Message(text),
+        }
+    }
+
     /// Get the filename or directory name
     pub fn name(&self) -> &str {
         match &self.offer_type {
             OfferType::File(f) => &f.filename,
             OfferType::Directory(d) => &d.dir_name,
+            OfferType::Message(_) => "message",
         }
     }
 
     /// Get the transfer size (what will be sent over the wire)
+    ///
+    /// Text messages travel in the offer, so nothing follows them.
     pub fn transfer_size(&self) -> u64 {
         match &self.offer_type {
             OfferType::File(f) => f.file_size,
             OfferType::Directory(d) => d.archive_size,
+            OfferType::Message(_) => 0,
         }
     }
 
@@ -163,6 +179,7 @@ impl FileOffer {
         match &self.offer_type {
             OfferType::File(f) => f.compressed,
             OfferType::Directory(d) => d.compressed,
+            OfferType::Message(_) => false,
         }
     }
 }
@@ -175,6 +192,13 @@ impl FileAnswer {
         }
     }
 
+    /// Confirm a text message
+    pub fn message_ack() -> Self {
+        Self {
+            answer_type: AnswerType::MessageAck("ok".to_string()),
+        }
+    }
+
     /// Create a reject answer
     pub fn reject(reason: String) -> Self {
         Self {
@@ -184,7 +208,10 @@ impl FileAnswer {
 
     /// Check if the answer is accepted
     pub fn is_accepted(&self) -> bool {
-        matches!(&self.answer_type, AnswerType::FileAck(_))
+        matches!(
+            &self.answer_type,
+            AnswerType::FileAck(_) | AnswerType::MessageAck(_)
+        )
     }
 }
 
@@ -278,6 +305,29 @@ mod tests {
         assert!(answer.is_accepted());
     }
 
+    #[test]
+    fn test_message_offer_wire_format() {
+        let bytes = Message::offer(FileOffer::message("hi".to_string()))
+            .to_bytes()
+            .unwrap();
+        assert_eq!(bytes, br#"{"type":"offer","offer":{"message":"hi"}}"#);
+        assert!(matches!(
+            Message::from_bytes(&bytes).unwrap(),
+            Message::Offer(FileOffer {
+                offer_type: OfferType::Message(text)
+            }) if text == "hi"
+        ));
+
+        let answer = Message::answer(FileAnswer::message_ack())
+            .to_bytes()
+            .unwrap();
+        assert_eq!(
+            answer,
+            br#"{"type":"answer","answer":{"message_ack":"ok"}}"#
+        );
+        assert!(FileAnswer::message_ack().is_accepted());
+    }
+
     #[test]
     fn test_file_answer_reject() {
         let answer = FileAnswer::reject("No space".to_string());
@@ -0,0 +1,667 @@
+//! High-level sending and receiving
+//!
+//! [`Sender`] and [`Receiver`] run a whole transfer: they meet the peer in
+//! the mailbox named by the code's nameplate, agree on a key with SPAKE2,
+//! swap transit hints, open the transit connection and exchange the offer,
+//! answer, data and acknowledgement. What happens along the way is reported
+//! as [`Event`]s on a channel, so the desktop client, the command-line
+//! client and tests share one implementation.
+//!
+//! The mailbox connection reconnects on its own, so a dropped websocket
+//! during the key or hint exchange does not end the transfer.
+//!
+//! The transit hints carry a key confirmation, so peers that typed
+//! different codes learn so instead of waiting for a relay that never pairs
+//! them. Peers that send no confirmation are not checked.
+
+use std::future::Future;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use futures::future::BoxFuture;
+use tokio::sync::mpsc;
+
+use crate::code;
+use crate::crypto::{
+    derive_key, derive_verifier, format_verifier, Purpose, Side, Spake2Exchange, Spake2Message,
+};
+use crate::mailbox::{MailboxConnection, Mood, DEFAULT_APPID};
+use crate::network::SignalingClient;
+use crate::protocol::{AnswerType, FileAnswer, FileOffer, Message, OfferType};
+use crate::transfer::{FileTransfer, TransferProgress};
+use crate::transit::{
+    establish_transit_with_mode, ConnectionKind, RelayHint, TransitConnection, TransitHints,
+    TransitMode, TransitRole, DEFAULT_RELAY,
+};
+use crate::{Error, Result, DEFAULT_MAILBOX};
+
+/// How long a transfer that ended may spend notifying the peer and server
+pub const CANCEL_GRACE: Duration = Duration::from_secs(3);
+
+/// Reason sent to the peer when a transfer is cancelled
+const CANCELLED: &str = "Transfer cancelled";
+
+/// Key derivation purpose of the key confirmation in the hints
+const CONFIRM_PURPOSE: &str = "securebeam:confirm";
+
+/// Mailbox phase carrying the SPAKE2 message
+const PAKE_PHASE: &str = "pake";
+
+/// Mailbox phase carrying the transit hints
+const HINTS_PHASE: &str = "hints";
+
+/// Servers used for a transfer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Servers {
+    /// Mailbox server base URL, e.g. `https://mailbox.example.com`
+    pub mailbox_url: String,
+    /// Transit relays, e.g. `tcp://relay.example.com:4001`
+    pub relays: Vec<String>,
+    /// Application ID; both sides of a transfer must use the same one
+    pub appid: String,
+}
+
+impl Default for Servers {
+    fn default() -> Self {
+        Self {
+            mailbox_url: DEFAULT_MAILBOX.to_string(),
+            relays: vec![DEFAULT_RELAY.to_string()],
+            appid: DEFAULT_APPID.to_string(),
+        }
+    }
+}
+
+impl Servers {
+    /// Client for the mailbox server
+    pub fn signaling(&self) -> SignalingClient {
+        SignalingClient::new(self.mailbox_url.trim_end_matches('/'))
+    }
+
+    /// Relay hints to offer the peer
+    pub fn relay_hints(&self) -> Vec<RelayHint> {
+        self.relays.iter().map(|url| RelayHint::new(url)).collect()
+    }
+}
+
+/// What happened in a transfer
+#[derive(Debug, Clone)]
+pub enum Event {
+    Status(Status),
+    /// Message of the day from the mailbox server, to show to the user
+    Motd(String),
+    /// What is being transferred: our offer when sending, the peer's when
+    /// receiving
+    Offer(FileOffer),
+    Progress(TransferProgress),
+    /// Verifier derived from the shared key, to compare with the peer's
+    Verifier(String),
+    /// The transfer completed
+    Done,
+}
+
+/// Stages of a transfer, in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// Connecting to the mailbox server
+    Connecting,
+    /// Waiting for the peer to join and exchange keys
+    WaitingForPeer,
+    /// Keys agreed; opening the transit connection
+    KeyExchanged,
+    /// Transit connection open
+    Connected(ConnectionKind),
+    /// Offer sent; waiting for the receiver to accept it
+    WaitingForAnswer,
+    /// Data is being sent or received
+    Transferring,
+}
+
+/// Where a transfer reports its events
+pub type Events = mpsc::UnboundedSender<Event>;
+
+/// What to send
+#[derive(Debug, Clone)]
+pub enum Payload {
+    File(PathBuf),
+    Directory(PathBuf),
+    Text(String),
+}
+
+/// What was received
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Received {
+    /// File or directory saved at this path
+    Path(PathBuf),
+    Text(String),
+}
+
+/// Asks the user whether the verifier matches the peer's
+type VerifierCheck = Box<dyn FnOnce(String) -> BoxFuture<'static, bool> + Send>;
+
+/// Sends a file, directory or text with a code
+pub struct Sender {
+    code: String,
+    payload: Payload,
+    servers: Servers,
+    transit_mode: TransitMode,
+    verifier_check: Option<VerifierCheck>,
+}
+
+impl Sender {
+    /// Send `payload` to whoever uses `code`
+    pub fn new(code: &str, payload: Payload) -> Self {
+        Self {
+            code: code.to_string(),
+            payload,
+            servers: Servers::default(),
+            transit_mode: TransitMode::default(),
+            verifier_check: None,
+        }
+    }
+
+    pub fn with_servers(mut self, servers: Servers) -> Self {
+        self.servers = servers;
+        self
+    }
+
+    /// How to reach the peer; Tor mode never reveals our IP address
+    pub fn with_transit_mode(mut self, mode: TransitMode) -> Self {
+        self.transit_mode = mode;
+        self
+    }
+
+    /// Only continue past the key exchange if `check` accepts the verifier
+    pub fn with_verifier_check<F, Fut>(mut self, check: F) -> Self
+    where
+        F: FnOnce(String) -> Fut + Send + 'static,
+        Fut: Future<Output = bool> + Send + 'static,
+    {
+        self.verifier_check = Some(Box::new(move |verifier| Box::pin(check(verifier))));
+        self
+    }
+
+    /// Run the transfer until it ends or `cancel` completes
+    ///
+    /// A cancelled transfer tells the peer and fails with
+    /// [`Error::Cancelled`].
+    pub async fn run<C>(self, events: Events, cancel: C) -> Result<()>
+    where
+        C: Future<Output = ()>,
+    {
+        let mut links = Links::default();
+        let result = tokio::select! {
+            result = self.transfer(&events, &mut links) => result,
+            () = cancel => Err(Error::Cancelled),
+        };
+        links.settle(&result).await;
+        if result.is_ok() {
+            let _ = events.send(Event::Done);
+        }
+        result
+    }
+
+    async fn transfer(self, events: &Events, links: &mut Links) -> Result<()> {
+        let file_transfer = FileTransfer::new();
+        let offer = match &self.payload {
+            Payload::File(path) => file_transfer.prepare_file_offer(path).await?,
+            Payload::Directory(path) => file_transfer.prepare_directory_offer(path).await?,
+            Payload::Text(text) => FileOffer::message(text.clone()),
+        };
+        let _ = events.send(Event::Offer(offer.clone()));
+
+        let peer = Peer {
+            servers: &self.servers,
+            transit_mode: &self.transit_mode,
+            code: &self.code,
+            side: Side::A,
+            verifier_check: self.verifier_check,
+        };
+        let transit = peer.connect(events, links).await?;
+        let transit = links.transit.insert(transit);
+
+        transit
+            .send(&Message::offer(offer.clone()).to_bytes()?)
+            .await?;
+        let _ = events.send(Event::Status(Status::WaitingForAnswer));
+        match Message::from_bytes(&transit.receive().await?)? {
+            Message::Answer(FileAnswer {
+                answer_type: AnswerType::Error(reason),
+            }) => return Err(Error::Rejected(reason)),
+            Message::Answer(_) => {}
+            Message::Error { message } => return Err(Error::Rejected(message)),
+            _ => return Err(unexpected_message()),
+        }
+
+        let _ = events.send(Event::Status(Status::Transferring));
+        let progress = progress_reporter(events);
+        match &self.payload {
+            Payload::File(path) => {
+                file_transfer
+                    .send_file(transit, path, &offer, progress)
+                    .await?
+            }
+            Payload::Directory(path) => {
+                file_transfer
+                    .send_directory(transit, path, &offer, progress)
+                    .await?
+            }
+            // The message was the offer itself
+            Payload::Text(_) => return Ok(()),
+        }
+
+        match Message::from_bytes(&transit.receive().await?)? {
+            Message::Ack => Ok(()),
+            Message::Error { message } => Err(Error::Rejected(message)),
+            _ => Err(unexpected_message()),
+        }
+    }
+}
+
+/// Receives with a code into a directory
+pub struct Receiver {
+    code: String,
+    output: PathBuf,
+    servers: Servers,
+    transit_mode: TransitMode,
+    verifier_check: Option<VerifierCheck>,
+    overwrite: bool,
+    accept_text: bool,
+}
+
+impl Receiver {
+    /// Receive from whoever sends with `code`, saving files in `output`
+    pub fn new(code: &str, output: impl Into<PathBuf>) -> Self {
+        Self {
+            code: code.to_string(),
+            output: output.into(),
+            servers: Servers::default(),
+            transit_mode: TransitMode::default(),
+            verifier_check: None,
+            overwrite: false,
+            accept_text: true,
+        }
+    }
+
+    pub fn with_servers(mut self, servers: Servers) -> Self {
+        self.servers = servers;
+        self
+    }
+
+    /// How to reach the peer; Tor mode never reveals our IP address
+    pub fn with_transit_mode(mut self, mode: TransitMode) -> Self {
+        self.transit_mode = mode;
+        self
+    }
+
+    /// Only continue past the key exchange if `check` accepts the verifier
+    pub fn with_verifier_check<F, Fut>(mut self, check: F) -> Self
+    where
+        F: FnOnce(String) -> Fut + Send + 'static,
+        Fut: Future<Output = bool> + Send + 'static,
+    {
+        self.verifier_check = Some(Box::new(move |verifier| Box::pin(check(verifier))));
+        self
+    }
+
+    /// Whether to replace a file or directory that already exists
+    pub fn with_overwrite(mut self, overwrite: bool) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Whether to accept text messages; rejected ones fail the transfer
+    pub fn with_text(mut self, accept_text: bool) -> Self {
+        self.accept_text = accept_text;
+        self
+    }
+
+    /// Run the transfer until it ends or `cancel` completes
+    ///
+    /// A cancelled transfer tells the peer, removes what was written so far
+    /// and fails with [`Error::Cancelled`].
+    pub async fn run<C>(self, events: Events, cancel: C) -> Result<Received>
+    where
+        C: Future<Output = ()>,
+    {
+        let mut links = Links::default();
+        let result = tokio::select! {
+            result = self.transfer(&events, &mut links) => result,
+            () = cancel => Err(Error::Cancelled),
+        };
+        links.settle(&result).await;
+        if result.is_ok() {
+            let _ = events.send(Event::Done);
+        }
+        result
+    }
+
+    async fn transfer(self, events: &Events, links: &mut Links) -> Result<Received> {
+        let peer = Peer {
+            servers: &self.servers,
+            transit_mode: &self.transit_mode,
+            code: &self.code,
+            side: Side::B,
+            verifier_check: self.verifier_check,
+        };
+        let transit = peer.connect(events, links).await?;
+        let transit = links.transit.insert(transit);
+
+        let offer = match Message::from_bytes(&transit.receive().await?)? {
+            Message::Offer(offer) => offer,
+            Message::Error { message } => return Err(Error::Rejected(message)),
+            _ => return Err(unexpected_message()),
+        };
+        let _ = events.send(Event::Offer(offer.clone()));
+
+        if let OfferType::Message(text) = &offer.offer_type {
+            if !self.accept_text {
+                reject(transit, "Text messages are not supported").await;
+                return Err(Error::Transfer(
+                    "The other side sent a text message, which is not accepted here".to_string(),
+                ));
+            }
+            transit
+                .send(&Message::answer(FileAnswer::message_ack()).to_bytes()?)
+                .await?;
+            return Ok(Received::Text(text.clone()));
+        }
+
+        let dest = match destination(&self.output, offer.name(), self.overwrite) {
+            Ok(dest) => dest,
+            Err((reason, error)) => {
+                reject(transit, reason).await;
+                return Err(error);
+            }
+        };
+        // Never remove something that was there before the transfer
+        if !dest.exists() {
+            links.partial = Some(dest.clone());
+        }
+
+        transit
+            .send(&Message::answer(FileAnswer::accept()).to_bytes()?)
+            .await?;
+        let _ = events.send(Event::Status(Status::Transferring));
+
+        let file_transfer = FileTransfer::new();
+        let progress = progress_reporter(events);
+        if let OfferType::Directory(_) = offer.offer_type {
+            file_transfer
+                .receive_directory(transit, &dest, &offer, progress)
+                .await?;
+        } else {
+            file_transfer
+                .receive_file(transit, &dest, &offer, progress)
+                .await?;
+        }
+
+        transit.send(&Message::Ack.to_bytes()?).await?;
+        links.partial = None;
+        Ok(Received::Path(dest))
+    }
+}
+
+/// One side of the key exchange
+struct Peer<'a> {
+    servers: &'a Servers,
+    transit_mode: &'a TransitMode,
+    code: &'a str,
+    side: Side,
+    verifier_check: Option<VerifierCheck>,
+}
+
+impl Peer<'_> {
+    /// Meet the peer, agree on a key and open the transit connection
+    async fn connect(self, events: &Events, links: &mut Links) -> Result<TransitConnection> {
+        let _ = events.send(Event::Status(Status::Connecting));
+        let (nameplate, _) = code::parse_code(self.code)?;
+        let mailbox = links.mailbox.insert(
+            self.servers
+                .signaling()
+                .connect_mailbox(&self.servers.appid)
+                .await?,
+        );
+        if let Some(motd) = mailbox.motd() {
+            let _ = events.send(Event::Motd(motd.to_string()));
+        }
+        let mailbox_id = mailbox.claim(&nameplate).await?;
+        mailbox.open(&mailbox_id).await?;
+
+        let _ = events.send(Event::Status(Status::WaitingForPeer));
+        let shared_key = exchange_keys(mailbox, self.code, self.side).await?;
+        // Both sides are in the mailbox now; free the nameplate for others
+        if let Err(e) = mailbox.release().await {
+            tracing::debug!("Failed to release nameplate: {:?}", e);
+        }
+        let _ = events.send(Event::Status(Status::KeyExchanged));
+
+        let verifier = format_verifier(&derive_verifier(&shared_key)?);
+        let _ = events.send(Event::Verifier(verifier.clone()));
+        if let Some(check) = self.verifier_check {
+            if !check(verifier).await {
+                return Err(Error::MitmDetected);
+            }
+        }
+
+        let ours = TransitHints {
+            relay_hints: self.servers.relay_hints(),
+            ..TransitHints::default()
+        };
+        let hints = exchange_hints(mailbox, ours, self.transit_mode, &shared_key).await?;
+        let transit_key = derive_key(&shared_key, &Purpose::Transit, 32)?;
+        let role = match self.side {
+            Side::A => TransitRole::Sender,
+            Side::B => TransitRole::Receiver,
+        };
+        let transit =
+            establish_transit_with_mode(role, &hints, &transit_key, self.transit_mode).await?;
+        let _ = events.send(Event::Status(Status::Connected(transit.kind())));
+        Ok(transit)
+    }
+}
+
+/// Connections and files of a running transfer
+///
+/// They live outside the transfer future so they can still be used to
+/// wind down after the transfer failed or was cancelled.
+#[derive(Default)]
+struct Links {
+    mailbox: Option<MailboxConnection>,
+    transit: Option<TransitConnection>,
+    /// File or directory created by the receiver
+    partial: Option<PathBuf>,
+}
+
+impl Links {
+    /// Report the outcome to the mailbox server; after a cancel also tell
+    /// the peer, and after any failure remove what the receiver wrote
+    async fn settle<T>(&mut self, result: &Result<T>) {
+        let cancelled = matches!(result, Err(Error::Cancelled));
+        let mood = Mood::for_result(result);
+        let notify = async {
+            if cancelled {
+                if let Some(transit) = &mut self.transit {
+                    if let Ok(bytes) = Message::error(CANCELLED.to_string()).to_bytes() {
+                        let _ = transit.send(&bytes).await;
+                    }
+                }
+            }
+            if let Some(mailbox) = &mut self.mailbox {
+                let _ = mailbox.close(mood).await;
+            }
+        };
+        let _ = tokio::time::timeout(CANCEL_GRACE, notify).await;
+
+        if let Some(partial) = self.partial.take() {
+            let removed = if partial.is_dir() {
+                tokio::fs::remove_dir_all(&partial).await
+            } else {
+                tokio::fs::remove_file(&partial).await
+            };
+            if let Err(e) = removed {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    tracing::warn!("Cannot remove {}: {}", partial.display(), e);
+                }
+            }
+        }
+    }
+}
+
+/// Run the SPAKE2 exchange over the mailbox, returning the shared key
+async fn exchange_keys(mailbox: &mut MailboxConnection, code: &str, side: Side) -> Result<Vec<u8>> {
+    let mut exchange = Spake2Exchange::new(code.as_bytes(), side);
+    let ours = exchange.start()?;
+    let message = serde_json::json!({ "pake": ours.to_hex() });
+    mailbox
+        .add(PAKE_PHASE, message.to_string().as_bytes())
+        .await?;
+
+    let message = receive_json(mailbox, PAKE_PHASE).await?;
+    let theirs = message["pake"].as_str().ok_or_else(unexpected_message)?;
+    exchange.finish(&Spake2Message::from_hex(theirs)?)
+}
+
+/// Swap transit hints with the peer, returning both sides' hints
+///
+/// Only the hints `mode` allows are sent; in Tor mode none reveal our IP
+/// address.
+async fn exchange_hints(
+    mailbox: &mut MailboxConnection,
+    ours: TransitHints,
+    mode: &TransitMode,
+    shared_key: &[u8],
+) -> Result<TransitHints> {
+    let ours = mode.outgoing_hints(&ours);
+    let confirm = key_confirmation(shared_key)?;
+
+    let mut message = serde_json::to_value(&ours).map_err(|e| Error::Protocol(e.to_string()))?;
+    message["confirm"] = confirm.clone().into();
+    mailbox
+        .add(HINTS_PHASE, message.to_string().as_bytes())
+        .await?;
+
+    let message = receive_json(mailbox, HINTS_PHASE).await?;
+    if let Some(theirs) = message.get("confirm") {
+        if theirs.as_str() != Some(confirm.as_str()) {
+            return Err(Error::WrongCode);
+        }
+    }
+    let theirs: TransitHints = serde_json::from_value(message).map_err(|_| unexpected_message())?;
+
+    let mut hints = ours;
+    hints.merge(theirs);
+    Ok(hints)
+}
+
+/// Value both sides derive from the shared key; it differs if the codes did
+fn key_confirmation(shared_key: &[u8]) -> Result<String> {
+    let purpose = Purpose::Custom(CONFIRM_PURPOSE.to_string());
+    Ok(hex::encode(derive_key(shared_key, &purpose, 32)?))
+}
+
+/// The peer's next mailbox message, which must be a JSON object in `phase`
+async fn receive_json(mailbox: &mut MailboxConnection, phase: &str) -> Result<serde_json::Value> {
+    let message = mailbox.receive().await?;
+    if message.phase != phase {
+        return Err(unexpected_message());
+    }
+    serde_json::from_slice(&message.body).map_err(|_| unexpected_message())
+}
+
+fn unexpected_message() -> Error {
+    Error::Protocol("Unexpected message from the other side".to_string())
+}
+
+/// Decline the offer, telling the peer why
+async fn reject(transit: &mut TransitConnection, reason: &str) {
+    if let Ok(bytes) = Message::answer(FileAnswer::reject(reason.to_string())).to_bytes() {
+        let _ = transit.send(&bytes).await;
+    }
+}
+
+/// Where to save an offered file or directory
+///
+/// Names that would leave `output` are refused, as are existing paths
+/// unless `overwrite` is set. A refusal carries the reason for the peer.
+fn destination(
+    output: &Path,
+    name: &str,
+    overwrite: bool,
+) -> std::result::Result<PathBuf, (&'static str, Error)> {
+    let mut components = Path::new(name).components();
+    if !matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    ) {
+        return Err((
+            "Invalid file name",
+            Error::Transfer(format!("Refusing to save as {:?}", name)),
+        ));
+    }
+    let dest = output.join(name);
+    if dest.exists() && !overwrite {
+        return Err((
+            "File already exists",
+            Error::Transfer(format!("{} already exists", dest.display())),
+        ));
+    }
+    Ok(dest)
+}
+
+/// Progress callback that fills in speed and time remaining
+fn progress_reporter(events: &Events) -> impl FnMut(TransferProgress) {
+    let events = events.clone();
+    let started = Instant::now();
+    move |mut progress| {
+        let elapsed = started.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            progress.speed_bps = progress.bytes_transferred as f64 / elapsed;
+        }
+        if progress.speed_bps > 0.0 && progress.bytes_transferred < progress.total_bytes {
+            progress.eta_seconds = Some(
+                (progress.total_bytes - progress.bytes_transferred) as f64 / progress.speed_bps,
+            );
+        }
+        let _ = events.send(Event::Progress(progress));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_destination_stays_in_output() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            destination(dir.path(), "report.pdf", false).unwrap(),
+            dir.path().join("report.pdf")
+        );
+
+        for name in ["", ".", "..", "../evil", "a/b", "/etc/passwd"] {
+            assert!(
+                destination(dir.path(), name, true).is_err(),
+                "{:?} accepted",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_destination_keeps_existing_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("report.pdf"), "old").unwrap();
+
+        let (reason, _) = destination(dir.path(), "report.pdf", false).unwrap_err();
+        assert_eq!(reason, "File already exists");
+        assert!(destination(dir.path(), "report.pdf", true).is_ok());
+    }
+
+    #[test]
+    fn test_key_confirmation() {
+        let confirm = key_confirmation(&[0x42; 32]).unwrap();
+        assert_eq!(confirm.len(), 64);
+        assert_eq!(confirm, key_confirmation(&[0x42; 32]).unwrap());
+        assert_ne!(confirm, key_confirmation(&[0x43; 32]).unwrap());
+    }
+}
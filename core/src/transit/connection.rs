@@ -3,6 +3,7 @@
 //! Provides a unified interface for encrypted transit connections,
 //! whether they are direct or via relay.
 
+use serde::{Deserialize, Serialize};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpStream;
 
@@ -36,11 +37,24 @@ impl TransitRole {
     }
 }
 
+/// How a transit connection reaches the peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionKind {
+    /// Straight to the peer
+    Direct,
+    /// Through a transit relay
+    Relay,
+    /// Through the Tor network
+    Tor,
+}
+
 /// An encrypted transit connection
 pub struct TransitConnection {
     stream: TcpStream,
     secretbox: SecretBox,
     role: TransitRole,
+    kind: ConnectionKind,
     /// Sequence number for sender
     send_seq: u64,
     /// Sequence number for receiver
@@ -49,22 +63,37 @@ pub struct TransitConnection {
 
 impl TransitConnection {
     /// Create a new transit connection from an established TCP stream
+    ///
+    /// The connection counts as direct unless [`with_kind`](Self::with_kind)
+    /// says otherwise.
     pub fn new(stream: TcpStream, transit_key: &[u8], role: TransitRole) -> Result<Self> {
         let secretbox = SecretBox::new(transit_key)?;
         Ok(Self {
             stream,
             secretbox,
             role,
+            kind: ConnectionKind::Direct,
             send_seq: 0,
             recv_seq: 0,
         })
     }
 
+    /// Record how the stream reaches the peer
+    pub fn with_kind(mut self, kind: ConnectionKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
     /// Get the role
     pub fn role(&self) -> TransitRole {
         self.role
     }
 
+    /// How the connection reaches the peer
+    pub fn kind(&self) -> ConnectionKind {
+        self.kind
+    }
+
     /// Send encrypted data
     pub async fn send(&mut self, data: &[u8]) -> Result<()> {
         // Encrypt the data
@@ -14,6 +14,9 @@ pub struct TransitHints {
     /// Relay server hints
     #[serde(default)]
     pub relay_hints: Vec<RelayHint>,
+    /// Tor onion service hints (onion address:port pairs)
+    #[serde(default)]
+    pub tor_hints: Vec<TorHint>,
 }
 
 impl TransitHints {
@@ -37,16 +40,40 @@ impl TransitHints {
         });
     }
 
+    /// Add a Tor onion service hint
+    pub fn add_tor(&mut self, onion_address: &str, port: u16, priority: i32) {
+        self.tor_hints
+            .push(TorHint::with_priority(onion_address, port, priority));
+    }
+
     /// Merge hints from another set
     pub fn merge(&mut self, other: TransitHints) {
         self.direct_hints.extend(other.direct_hints);
         self.relay_hints.extend(other.relay_hints);
+        self.tor_hints.extend(other.tor_hints);
     }
 
-    /// Sort direct hints by priority (higher = better)
+    /// Sort direct and tor hints by priority (higher = better)
     pub fn sort_by_priority(&mut self) {
         self.direct_hints
-            .sort_by(|a, b| b.priority.cmp(&a.priority));
+            .sort_by_key(|h| std::cmp::Reverse(h.priority));
+        self.tor_hints
+            .sort_by_key(|h| std::cmp::Reverse(h.priority));
+    }
+
+    /// Drop every hint that would reveal an IP address
+    ///
+    /// Direct hints are removed entirely and tor hints that do not point to
+    /// an onion service are discarded. Relay hints are kept since they are
+    /// only ever dialed through the Tor SOCKS proxy in Tor mode.
+    pub fn suppress_ip_hints(&mut self) {
+        self.direct_hints.clear();
+        self.tor_hints.retain(|h| h.is_onion());
+    }
+
+    /// Check whether any hint would reveal an IP address
+    pub fn reveals_ip(&self) -> bool {
+        !self.direct_hints.is_empty() || self.tor_hints.iter().any(|h| !h.is_onion())
     }
 }
 
@@ -113,6 +140,46 @@ impl RelayHint {
     }
 }
 
+/// A Tor onion service hint
+///
+/// Tor hints are only dialed through a local Tor SOCKS proxy and never
+/// resolved locally.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TorHint {
+    /// Onion address (e.g., "abcdef...xyz.onion")
+    pub hostname: String,
+    /// Port number of the onion service
+    pub port: u16,
+    /// Priority (higher = prefer)
+    #[serde(default)]
+    pub priority: i32,
+}
+
+impl TorHint {
+    /// Create a new tor hint
+    pub fn new(hostname: &str, port: u16) -> Self {
+        Self::with_priority(hostname, port, 0)
+    }
+
+    /// Create with priority
+    pub fn with_priority(hostname: &str, port: u16, priority: i32) -> Self {
+        Self {
+            hostname: hostname.to_string(),
+            port,
+            priority,
+        }
+    }
+
+    /// Check that the hostname is an onion address
+    pub fn is_onion(&self) -> bool {
+        let hostname = self.hostname.to_ascii_lowercase();
+        match hostname.strip_suffix(".onion") {
+            Some(label) => !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric()),
+            None => false,
+        }
+    }
+}
+
 /// Gather local hints for direct connection
 #[allow(dead_code)]
 pub async fn gather_local_hints(listen_port: u16) -> TransitHints {
@@ -189,4 +256,37 @@ mod tests {
         hints1.merge(hints2);
         assert_eq!(hints1.direct_hints.len(), 2);
     }
+
+    #[test]
+    fn test_tor_hint_is_onion() {
+        let onion = "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion";
+        assert!(TorHint::new(onion, 4001).is_onion());
+        assert!(!TorHint::new("relay.example.com", 4001).is_onion());
+        assert!(!TorHint::new("192.168.1.1", 4001).is_onion());
+        assert!(!TorHint::new(".onion", 4001).is_onion());
+    }
+
+    #[test]
+    fn test_suppress_ip_hints() {
+        let mut hints = TransitHints::new();
+        hints.add_direct("192.168.1.1:8080".parse().unwrap(), 5);
+        hints.add_relay("tcp://relay.example.com:4001");
+        hints.add_tor("exampleonionaddress.onion", 9001, 0);
+        hints.add_tor("10.0.0.1", 9001, 0);
+        assert!(hints.reveals_ip());
+
+        hints.suppress_ip_hints();
+        assert!(!hints.reveals_ip());
+        assert!(hints.direct_hints.is_empty());
+        assert_eq!(hints.tor_hints.len(), 1);
+        assert_eq!(hints.relay_hints.len(), 1);
+    }
+
+    #[test]
+    fn test_hints_without_tor_field_deserialize() {
+        let json = r#"{"direct_hints":[],"relay_hints":[{"url":"tcp://relay.example.com:4001"}]}"#;
+        let hints: TransitHints = serde_json::from_str(json).unwrap();
+        assert!(hints.tor_hints.is_empty());
+        assert_eq!(hints.relay_hints.len(), 1);
+    }
 }
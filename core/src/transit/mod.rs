@@ -5,16 +5,22 @@
 //! 2. Fall back to relay server if direct fails
 //!
 //! The transit is encrypted using a key derived from the wormhole session.
+//!
+//! In Tor mode direct hints are replaced by onion service hints and every
+//! connection is made through a local Tor SOCKS proxy, so neither the peer
+//! nor the relay learns our IP address.
 
 mod connection;
 mod direct;
 mod hints;
 mod relay;
+mod tor;
 
-pub use connection::{TransitConnection, TransitRole};
+pub use connection::{ConnectionKind, TransitConnection, TransitRole};
 pub use direct::try_direct_connection;
-pub use hints::{DirectHint, RelayHint, TransitHints};
+pub use hints::{DirectHint, RelayHint, TorHint, TransitHints};
 pub use relay::connect_via_relay;
+pub use tor::{connect_via_relay_tor, socks_connect, try_tor_connection, DEFAULT_TOR_SOCKS};
 
 use crate::{Error, Result};
 
@@ -24,6 +30,111 @@ pub const DEFAULT_RELAY: &str = "tcp://relay.securebeam.eu:4001";
 /// Transit handshake timeout in seconds
 pub const HANDSHAKE_TIMEOUT_SECS: u64 = 30;
 
+/// How transit connections are established
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TransitMode {
+    /// Direct connections with relay fallback
+    #[default]
+    Direct,
+    /// Onion services and relays only, all dialed through a Tor SOCKS proxy
+    Tor {
+        /// Address of the local Tor SOCKS port (e.g., "127.0.0.1:9050")
+        socks_addr: String,
+    },
+}
+
+impl TransitMode {
+    /// Tor mode using the default local SOCKS port
+    pub fn tor() -> Self {
+        TransitMode::Tor {
+            socks_addr: DEFAULT_TOR_SOCKS.to_string(),
+        }
+    }
+
+    /// Check if this is Tor mode
+    pub fn is_tor(&self) -> bool {
+        matches!(self, TransitMode::Tor { .. })
+    }
+
+    /// Filter our own hints before they are sent to the peer
+    ///
+    /// In Tor mode all IP-revealing hints are removed.
+    pub fn outgoing_hints(&self, hints: &TransitHints) -> TransitHints {
+        let mut hints = hints.clone();
+        if self.is_tor() {
+            hints.suppress_ip_hints();
+        }
+        hints
+    }
+}
+
+/// Establish a transit connection using the given mode
+pub async fn establish_transit_with_mode(
+    role: TransitRole,
+    hints: &TransitHints,
+    transit_key: &[u8],
+    mode: &TransitMode,
+) -> Result<TransitConnection> {
+    match mode {
+        TransitMode::Direct => establish_transit(role, hints, transit_key).await,
+        TransitMode::Tor { socks_addr } => {
+            establish_transit_tor(role, hints, transit_key, socks_addr).await
+        }
+    }
+}
+
+/// Establish a transit connection through Tor
+///
+/// Direct hints are never used. Onion hints are tried first, then the
+/// relays, all through the SOCKS proxy at `socks_addr`.
+pub async fn establish_transit_tor(
+    role: TransitRole,
+    hints: &TransitHints,
+    transit_key: &[u8],
+    socks_addr: &str,
+) -> Result<TransitConnection> {
+    if !hints.direct_hints.is_empty() {
+        tracing::debug!(
+            "Ignoring {} direct hints in Tor mode",
+            hints.direct_hints.len()
+        );
+    }
+
+    if !hints.tor_hints.is_empty() {
+        tracing::info!("Trying {} tor connection hints", hints.tor_hints.len());
+
+        match try_tor_connection(role, &hints.tor_hints, socks_addr, transit_key).await {
+            Ok(conn) => {
+                tracing::info!("Tor connection established");
+                return Ok(conn);
+            }
+            Err(e) => {
+                tracing::warn!("Tor connection failed: {}", e);
+            }
+        }
+    }
+
+    if !hints.relay_hints.is_empty() {
+        tracing::info!("Falling back to relay connection via Tor");
+
+        for relay in &hints.relay_hints {
+            match connect_via_relay_tor(role, relay, socks_addr, transit_key).await {
+                Ok(conn) => {
+                    tracing::info!("Relay connection established via {} over Tor", relay.url);
+                    return Ok(conn);
+                }
+                Err(e) => {
+                    tracing::warn!("Relay {} failed over Tor: {}", relay.url, e);
+                }
+            }
+        }
+    }
+
+    Err(Error::Connection(
+        "All transit connection attempts failed".to_string(),
+    ))
+}
+
 /// Establish a transit connection
 ///
 /// Tries direct connection first, falls back to relay if needed.
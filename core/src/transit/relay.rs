@@ -8,7 +8,7 @@ use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::time::timeout;
 
-use super::connection::{perform_handshake, TransitConnection, TransitRole};
+use super::connection::{perform_handshake, ConnectionKind, TransitConnection, TransitRole};
 use super::hints::RelayHint;
 use super::HANDSHAKE_TIMEOUT_SECS;
 use crate::{Error, Result};
@@ -60,6 +60,22 @@ pub async fn connect_via_relay(
         .map_err(|_| Error::Connection("Relay connect timed out".to_string()))?
         .map_err(|e| Error::Connection(format!("Relay connect failed: {}", e)))?;
 
+    relay_handshake(&mut stream, role, transit_key).await?;
+
+    // Create encrypted connection
+    Ok(TransitConnection::new(stream, transit_key, role)?.with_kind(ConnectionKind::Relay))
+}
+
+/// Perform the relay handshake and the transit handshake on a connected stream
+///
+/// Shared by plain TCP and Tor (SOCKS) relay connections.
+pub(super) async fn relay_handshake(
+    stream: &mut TcpStream,
+    role: TransitRole,
+    transit_key: &[u8],
+) -> Result<()> {
+    let timeout_duration = Duration::from_secs(HANDSHAKE_TIMEOUT_SECS);
+
     // Compute channel ID from transit key
     let mut hasher = Sha256::new();
     hasher.update(transit_key);
@@ -82,7 +98,7 @@ pub async fn connect_via_relay(
         .map_err(|e| Error::Connection(format!("Relay handshake write failed: {}", e)))?;
 
     // Read relay response (read byte by byte to avoid borrowing issues)
-    let response = timeout(timeout_duration, read_line(&mut stream))
+    let response = timeout(timeout_duration, read_line(stream))
         .await
         .map_err(|_| Error::Connection("Relay response timed out".to_string()))??;
 
@@ -96,8 +112,5 @@ pub async fn connect_via_relay(
     tracing::debug!("Relay accepted, performing transit handshake");
 
     // Now perform the transit handshake over the relay
-    perform_handshake(&mut stream, role, transit_key).await?;
-
-    // Create encrypted connection
-    TransitConnection::new(stream, transit_key, role)
+    perform_handshake(stream, role, transit_key).await
 }
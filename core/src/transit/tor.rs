@@ -0,0 +1,300 @@
+//! Tor transit via a local SOCKS5 proxy
+//!
+//! In Tor mode every outgoing transit connection (onion hints and relay
+//! hints alike) is made through the Tor SOCKS port. Hostnames are always
+//! passed to the proxy unresolved so that no DNS lookup leaks locally.
+
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+use super::connection::{perform_handshake, ConnectionKind, TransitConnection, TransitRole};
+use super::hints::{RelayHint, TorHint};
+use super::relay::relay_handshake;
+use super::HANDSHAKE_TIMEOUT_SECS;
+use crate::{Error, Result};
+
+/// Default Tor SOCKS port (tor daemon)
+pub const DEFAULT_TOR_SOCKS: &str = "127.0.0.1:9050";
+
+const SOCKS_VERSION: u8 = 0x05;
+const SOCKS_NO_AUTH: u8 = 0x00;
+const SOCKS_CMD_CONNECT: u8 = 0x01;
+const SOCKS_ATYP_IPV4: u8 = 0x01;
+const SOCKS_ATYP_DOMAIN: u8 = 0x03;
+const SOCKS_ATYP_IPV6: u8 = 0x04;
+
+/// Open a TCP stream to `host:port` through a SOCKS5 proxy
+///
+/// The hostname is sent as a domain name (ATYP 0x03) so that the proxy
+/// performs the resolution.
+pub async fn socks_connect(socks_addr: &str, host: &str, port: u16) -> Result<TcpStream> {
+    if host.is_empty() || host.len() > 255 {
+        return Err(Error::Connection("Invalid SOCKS target host".to_string()));
+    }
+
+    let mut stream = TcpStream::connect(socks_addr)
+        .await
+        .map_err(|e| Error::Connection(format!("SOCKS proxy connect failed: {}", e)))?;
+
+    // Greeting: version 5, one method, no authentication
+    stream
+        .write_all(&[SOCKS_VERSION, 1, SOCKS_NO_AUTH])
+        .await
+        .map_err(|e| Error::Connection(format!("SOCKS write failed: {}", e)))?;
+
+    let mut method = [0u8; 2];
+    stream
+        .read_exact(&mut method)
+        .await
+        .map_err(|e| Error::Connection(format!("SOCKS read failed: {}", e)))?;
+    if method != [SOCKS_VERSION, SOCKS_NO_AUTH] {
+        return Err(Error::Connection(
+            "SOCKS proxy refused authentication method".to_string(),
+        ));
+    }
+
+    // CONNECT request with domain name address
+    let mut request = Vec::with_capacity(7 + host.len());
+    request.extend_from_slice(&[SOCKS_VERSION, SOCKS_CMD_CONNECT, 0x00, SOCKS_ATYP_DOMAIN]);
+    request.push(host.len() as u8);
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    stream
+        .write_all(&request)
+        .await
+        .map_err(|e| Error::Connection(format!("SOCKS write failed: {}", e)))?;
+
+    // Reply: VER REP RSV ATYP BND.ADDR BND.PORT
+    let mut reply = [0u8; 4];
+    stream
+        .read_exact(&mut reply)
+        .await
+        .map_err(|e| Error::Connection(format!("SOCKS read failed: {}", e)))?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(Error::Connection("Invalid SOCKS reply".to_string()));
+    }
+    if reply[1] != 0x00 {
+        return Err(Error::Connection(format!(
+            "SOCKS connect failed with code {}",
+            reply[1]
+        )));
+    }
+
+    let addr_len = match reply[3] {
+        SOCKS_ATYP_IPV4 => 4,
+        SOCKS_ATYP_IPV6 => 16,
+        SOCKS_ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            stream
+                .read_exact(&mut len)
+                .await
+                .map_err(|e| Error::Connection(format!("SOCKS read failed: {}", e)))?;
+            len[0] as usize
+        }
+        _ => return Err(Error::Connection("Invalid SOCKS address type".to_string())),
+    };
+
+    // Discard the bound address and port
+    let mut bound = vec![0u8; addr_len + 2];
+    stream
+        .read_exact(&mut bound)
+        .await
+        .map_err(|e| Error::Connection(format!("SOCKS read failed: {}", e)))?;
+
+    Ok(stream)
+}
+
+/// Try to establish a connection to the peer's onion service
+pub async fn try_tor_connection(
+    role: TransitRole,
+    hints: &[TorHint],
+    socks_addr: &str,
+    transit_key: &[u8],
+) -> Result<TransitConnection> {
+    let timeout_duration = Duration::from_secs(HANDSHAKE_TIMEOUT_SECS);
+
+    for hint in hints {
+        if !hint.is_onion() {
+            tracing::debug!("Skipping non-onion tor hint");
+            continue;
+        }
+        tracing::debug!("Trying onion service {}:{}", hint.hostname, hint.port);
+
+        match timeout(
+            timeout_duration,
+            try_single_tor_connection(hint, socks_addr, role, transit_key),
+        )
+        .await
+        {
+            Ok(Ok(conn)) => {
+                tracing::info!("Tor connection successful");
+                return Ok(conn);
+            }
+            Ok(Err(e)) => {
+                tracing::debug!("Tor connection failed: {}", e);
+            }
+            Err(_) => {
+                tracing::debug!("Tor connection timed out");
+            }
+        }
+    }
+
+    Err(Error::Connection(
+        "All tor connection attempts failed".to_string(),
+    ))
+}
+
+/// Try a single onion service connection
+async fn try_single_tor_connection(
+    hint: &TorHint,
+    socks_addr: &str,
+    role: TransitRole,
+    transit_key: &[u8],
+) -> Result<TransitConnection> {
+    let mut stream = socks_connect(socks_addr, &hint.hostname, hint.port).await?;
+
+    // Perform handshake
+    perform_handshake(&mut stream, role, transit_key).await?;
+
+    // Create encrypted connection
+    Ok(TransitConnection::new(stream, transit_key, role)?.with_kind(ConnectionKind::Tor))
+}
+
+/// Connect to a relay server through the Tor SOCKS proxy
+pub async fn connect_via_relay_tor(
+    role: TransitRole,
+    relay: &RelayHint,
+    socks_addr: &str,
+    transit_key: &[u8],
+) -> Result<TransitConnection> {
+    let (host, port) = relay
+        .parse()
+        .ok_or_else(|| Error::Connection(format!("Invalid relay URL: {}", relay.url)))?;
+
+    tracing::debug!("Connecting to relay at {}:{} via Tor", host, port);
+
+    let timeout_duration = Duration::from_secs(HANDSHAKE_TIMEOUT_SECS);
+
+    let mut stream = timeout(timeout_duration, socks_connect(socks_addr, &host, port))
+        .await
+        .map_err(|_| Error::Connection("Relay connect timed out".to_string()))??;
+
+    relay_handshake(&mut stream, role, transit_key).await?;
+
+    // Create encrypted connection
+    Ok(TransitConnection::new(stream, transit_key, role)?.with_kind(ConnectionKind::Tor))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    const ONION: &str = "exampleonionaddressforsecurebeamtests.onion";
+
+    /// Minimal SOCKS5 stub: accepts one CONNECT, records the requested
+    /// target and then behaves as the transit peer.
+    async fn socks_stub(transit_key: Vec<u8>) -> (String, tokio::task::JoinHandle<(String, u16)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        let handle = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [SOCKS_VERSION, 1, SOCKS_NO_AUTH]);
+            stream
+                .write_all(&[SOCKS_VERSION, SOCKS_NO_AUTH])
+                .await
+                .unwrap();
+
+            let mut head = [0u8; 5];
+            stream.read_exact(&mut head).await.unwrap();
+            assert_eq!(
+                head[..4],
+                [SOCKS_VERSION, SOCKS_CMD_CONNECT, 0, SOCKS_ATYP_DOMAIN]
+            );
+            let mut host = vec![0u8; head[4] as usize];
+            stream.read_exact(&mut host).await.unwrap();
+            let mut port = [0u8; 2];
+            stream.read_exact(&mut port).await.unwrap();
+
+            stream
+                .write_all(&[SOCKS_VERSION, 0, 0, SOCKS_ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+
+            // Act as the receiving peer behind the onion service
+            perform_handshake(&mut stream, TransitRole::Receiver, &transit_key)
+                .await
+                .unwrap();
+            let mut conn =
+                TransitConnection::new(stream, &transit_key, TransitRole::Receiver).unwrap();
+            let data = conn.receive().await.unwrap();
+            assert_eq!(data, b"hello over tor");
+
+            (String::from_utf8(host).unwrap(), u16::from_be_bytes(port))
+        });
+
+        (addr, handle)
+    }
+
+    #[tokio::test]
+    async fn test_tor_connection_via_socks_stub() {
+        let transit_key = vec![0x42u8; 32];
+        let (socks_addr, stub) = socks_stub(transit_key.clone()).await;
+
+        let hints = vec![TorHint::new(ONION, 9001)];
+        let mut conn = try_tor_connection(TransitRole::Sender, &hints, &socks_addr, &transit_key)
+            .await
+            .unwrap();
+        assert_eq!(conn.kind(), ConnectionKind::Tor);
+        conn.send(b"hello over tor").await.unwrap();
+
+        let (host, port) = stub.await.unwrap();
+        assert_eq!(host, ONION);
+        assert_eq!(port, 9001);
+    }
+
+    #[tokio::test]
+    async fn test_non_onion_hints_are_not_dialed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let socks_addr = listener.local_addr().unwrap().to_string();
+
+        let hints = vec![TorHint::new("192.168.1.1", 9001)];
+        let result = try_tor_connection(TransitRole::Sender, &hints, &socks_addr, &[0u8; 32]).await;
+        assert!(result.is_err());
+
+        // The proxy must never have been contacted
+        let accepted = timeout(Duration::from_millis(100), listener.accept()).await;
+        assert!(accepted.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_socks_connect_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).await.unwrap();
+            stream
+                .write_all(&[SOCKS_VERSION, SOCKS_NO_AUTH])
+                .await
+                .unwrap();
+            let mut request = [0u8; 64];
+            let _ = stream.read(&mut request).await.unwrap();
+            // General SOCKS server failure
+            stream
+                .write_all(&[SOCKS_VERSION, 0x01, 0, SOCKS_ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+        });
+
+        assert!(socks_connect(&addr, ONION, 9001).await.is_err());
+    }
+}
@@ -0,0 +1,223 @@
+//! End-to-end tests of the session module
+//!
+//! Both sides run against a local stand-in for the mailbox server and a
+//! local transit relay.
+
+mod support;
+
+use std::path::Path;
+use std::time::Duration;
+
+use serde_json::json;
+use tokio::sync::mpsc;
+
+use securebeam_core::{
+    session::{Event, Payload, Received, Receiver, Sender, Servers, Status},
+    transit::TransitMode,
+    ConnectionKind, Error,
+};
+use support::{mailbox_server, relay_server};
+
+const CODE: &str = "7-guitarist-revenge";
+
+async fn servers() -> Servers {
+    Servers {
+        mailbox_url: mailbox_server(json!({})).await,
+        relays: vec![relay_server().await],
+        ..Servers::default()
+    }
+}
+
+/// Run `sender` and `receiver` against each other, returning their results
+/// and events
+async fn transfer(
+    sender: Sender,
+    receiver: Receiver,
+) -> (
+    securebeam_core::Result<()>,
+    Vec<Event>,
+    securebeam_core::Result<Received>,
+    Vec<Event>,
+) {
+    let (sender_tx, sender_rx) = mpsc::unbounded_channel();
+    let (receiver_tx, receiver_rx) = mpsc::unbounded_channel();
+    let sending = tokio::spawn(sender.run(sender_tx, std::future::pending()));
+    let receiving = tokio::spawn(receiver.run(receiver_tx, std::future::pending()));
+
+    let sent = sending.await.unwrap();
+    let received = receiving.await.unwrap();
+    (
+        sent,
+        collect(sender_rx).await,
+        received,
+        collect(receiver_rx).await,
+    )
+}
+
+async fn collect(mut events: mpsc::UnboundedReceiver<Event>) -> Vec<Event> {
+    let mut collected = Vec::new();
+    while let Some(event) = events.recv().await {
+        collected.push(event);
+    }
+    collected
+}
+
+fn verifier(events: &[Event]) -> Option<&str> {
+    events.iter().find_map(|event| match event {
+        Event::Verifier(verifier) => Some(verifier.as_str()),
+        _ => None,
+    })
+}
+
+fn write_file(dir: &Path, name: &str, size: usize) -> Vec<u8> {
+    let content: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+    std::fs::write(dir.join(name), &content).unwrap();
+    content
+}
+
+#[tokio::test]
+async fn test_send_and_receive_file() {
+    let servers = servers().await;
+    let source = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    let content = write_file(source.path(), "data.bin", 200_000);
+
+    let sender = Sender::new(CODE, Payload::File(source.path().join("data.bin")))
+        .with_servers(servers.clone());
+    let receiver = Receiver::new(CODE, output.path()).with_servers(servers);
+    let (sent, sender_events, received, receiver_events) = transfer(sender, receiver).await;
+
+    sent.unwrap();
+    let dest = output.path().join("data.bin");
+    assert_eq!(received.unwrap(), Received::Path(dest.clone()));
+    assert_eq!(std::fs::read(dest).unwrap(), content);
+
+    assert!(verifier(&sender_events).is_some());
+    assert_eq!(verifier(&sender_events), verifier(&receiver_events));
+    for events in [&sender_events, &receiver_events] {
+        assert!(events.iter().any(|event| matches!(
+            event,
+            Event::Status(Status::Connected(ConnectionKind::Relay))
+        )));
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, Event::Progress(p) if p.bytes_transferred > 0)));
+        assert!(matches!(events.last(), Some(Event::Done)));
+    }
+}
+
+#[tokio::test]
+async fn test_send_and_receive_text() {
+    let servers = servers().await;
+    let output = tempfile::tempdir().unwrap();
+
+    let sender =
+        Sender::new(CODE, Payload::Text("hello there".to_string())).with_servers(servers.clone());
+    let receiver = Receiver::new(CODE, output.path()).with_servers(servers);
+    let (sent, _, received, receiver_events) = transfer(sender, receiver).await;
+
+    sent.unwrap();
+    assert_eq!(received.unwrap(), Received::Text("hello there".to_string()));
+    assert!(receiver_events
+        .iter()
+        .any(|event| matches!(event, Event::Offer(offer) if offer.name() == "message")));
+}
+
+#[tokio::test]
+async fn test_receiver_keeps_existing_files() {
+    let servers = servers().await;
+    let source = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    write_file(source.path(), "data.bin", 1000);
+    std::fs::write(output.path().join("data.bin"), "mine").unwrap();
+
+    let sender = Sender::new(CODE, Payload::File(source.path().join("data.bin")))
+        .with_servers(servers.clone());
+    let receiver = Receiver::new(CODE, output.path()).with_servers(servers);
+    let (sent, _, received, _) = transfer(sender, receiver).await;
+
+    assert!(matches!(sent, Err(Error::Rejected(reason)) if reason == "File already exists"));
+    assert!(matches!(received, Err(Error::Transfer(_))));
+    assert_eq!(
+        std::fs::read_to_string(output.path().join("data.bin")).unwrap(),
+        "mine"
+    );
+}
+
+#[tokio::test]
+async fn test_tor_mode_only_dials_through_the_proxy() {
+    let servers = servers().await;
+    let output = tempfile::tempdir().unwrap();
+    // Nothing listens here, so the reachable relay must not be used
+    let tor = TransitMode::Tor {
+        socks_addr: "127.0.0.1:1".to_string(),
+    };
+
+    let sender = Sender::new(CODE, Payload::Text("hello there".to_string()))
+        .with_servers(servers.clone())
+        .with_transit_mode(tor.clone());
+    let receiver = Receiver::new(CODE, output.path())
+        .with_servers(servers)
+        .with_transit_mode(tor);
+    let (sent, sender_events, received, _) = transfer(sender, receiver).await;
+
+    assert!(matches!(sent, Err(Error::Connection(_))));
+    assert!(matches!(received, Err(Error::Connection(_))));
+    assert!(!sender_events
+        .iter()
+        .any(|event| matches!(event, Event::Status(Status::Connected(_)))));
+}
+
+#[tokio::test]
+async fn test_unavailable_server_ends_the_transfer() {
+    let servers = Servers {
+        mailbox_url: mailbox_server(json!({"error": "down for maintenance"})).await,
+        ..servers().await
+    };
+    let output = tempfile::tempdir().unwrap();
+
+    let (events, _rx) = mpsc::unbounded_channel();
+    let result = Receiver::new(CODE, output.path())
+        .with_servers(servers)
+        .run(events, std::future::pending())
+        .await;
+
+    assert!(
+        matches!(result, Err(Error::ServerUnavailable(reason)) if reason == "down for maintenance")
+    );
+}
+
+#[tokio::test]
+async fn test_motd_is_reported() {
+    let servers = Servers {
+        mailbox_url: mailbox_server(json!({"motd": "maintenance at 18:00"})).await,
+        ..servers().await
+    };
+    let output = tempfile::tempdir().unwrap();
+
+    let (events, rx) = mpsc::unbounded_channel();
+    let result = Receiver::new(CODE, output.path())
+        .with_servers(servers)
+        .run(events, tokio::time::sleep(Duration::from_millis(200)))
+        .await;
+
+    assert!(matches!(result, Err(Error::Cancelled)));
+    let events = collect(rx).await;
+    assert!(events
+        .iter()
+        .any(|event| matches!(event, Event::Motd(motd) if motd == "maintenance at 18:00")));
+}
+
+#[tokio::test]
+async fn test_cancel_while_waiting() {
+    let servers = servers().await;
+    let output = tempfile::tempdir().unwrap();
+
+    let (events, _rx) = mpsc::unbounded_channel();
+    let result = Receiver::new(CODE, output.path())
+        .with_servers(servers)
+        .run(events, tokio::time::sleep(Duration::from_millis(200)))
+        .await;
+
+    assert!(matches!(result, Err(Error::Cancelled)));
+}
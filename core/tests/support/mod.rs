@@ -0,0 +1,141 @@
+//! Local stand-ins for the mailbox server and the transit relay
+//!
+//! Shared by the end-to-end tests here and in the command-line client.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_tungstenite::accept_async;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+/// Messages of one mailbox and the connections that opened it
+#[derive(Default)]
+struct Mailbox {
+    messages: Vec<Value>,
+    open: Vec<(String, mpsc::UnboundedSender<String>)>,
+}
+
+/// Speak enough of the mailbox protocol for two sides to meet, sending
+/// `welcome` to every client
+pub async fn mailbox_server(welcome: Value) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mailboxes: Arc<Mutex<HashMap<String, Mailbox>>> = Arc::default();
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let mailboxes = mailboxes.clone();
+            let welcome = welcome.clone();
+            tokio::spawn(async move {
+                let Ok(ws) = accept_async(stream).await else {
+                    return;
+                };
+                let (mut write, mut read) = ws.split();
+                let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+                tokio::spawn(async move {
+                    while let Some(text) = rx.recv().await {
+                        if write.send(WsMessage::Text(text)).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+                let send = |value: Value| {
+                    let _ = tx.send(value.to_string());
+                };
+                send(json!({"type": "welcome", "welcome": welcome}));
+
+                let mut side = String::new();
+                let mut opened = None;
+                while let Some(Ok(WsMessage::Text(text))) = read.next().await {
+                    let request: Value = serde_json::from_str(&text).unwrap();
+                    match request["type"].as_str().unwrap() {
+                        "bind" => {
+                            side = request["side"].as_str().unwrap().to_string();
+                            send(json!({"type": "ack"}));
+                        }
+                        "claim" => {
+                            let mailbox = format!("mb-{}", request["nameplate"].as_str().unwrap());
+                            send(json!({"type": "claimed", "mailbox": mailbox}));
+                        }
+                        "allocate" => send(json!({"type": "allocated", "nameplate": "7"})),
+                        "release" => send(json!({"type": "released"})),
+                        "open" => {
+                            let id = request["mailbox"].as_str().unwrap().to_string();
+                            let after = request["last_seen"].as_u64().unwrap_or(0);
+                            let mut mailboxes = mailboxes.lock().unwrap();
+                            let mailbox = mailboxes.entry(id.clone()).or_default();
+                            for message in &mailbox.messages {
+                                if message["id"].as_u64().unwrap() > after {
+                                    send(message.clone());
+                                }
+                            }
+                            mailbox.open.push((side.clone(), tx.clone()));
+                            opened = Some(id);
+                            send(json!({"type": "ack"}));
+                        }
+                        "add" => {
+                            let mut mailboxes = mailboxes.lock().unwrap();
+                            let mailbox = mailboxes.get_mut(opened.as_ref().unwrap()).unwrap();
+                            let message = json!({"type": "message", "side": side,
+                                "phase": request["phase"], "body": request["body"],
+                                "id": mailbox.messages.len() + 1});
+                            mailbox.messages.push(message.clone());
+                            send(json!({"type": "ack"}));
+                            for (other, peer) in &mailbox.open {
+                                if *other != side {
+                                    let _ = peer.send(message.to_string());
+                                }
+                            }
+                        }
+                        "close" => send(json!({"type": "closed"})),
+                        _ => send(json!({"type": "ack"})),
+                    }
+                }
+            });
+        }
+    });
+    format!("http://{}", addr)
+}
+
+/// Pair relay clients by channel and pipe their bytes
+pub async fn relay_server() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let waiting: Arc<Mutex<HashMap<String, TcpStream>>> = Arc::default();
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let waiting = waiting.clone();
+            tokio::spawn(async move {
+                let mut line = Vec::new();
+                let mut byte = [0u8; 1];
+                while byte[0] != b'\n' {
+                    if stream.read_exact(&mut byte).await.is_err() {
+                        return;
+                    }
+                    line.push(byte[0]);
+                }
+                let line = String::from_utf8(line).unwrap();
+                let channel = line.split_whitespace().nth(2).unwrap().to_string();
+
+                let peer = waiting.lock().unwrap().remove(&channel);
+                match peer {
+                    Some(mut peer) => {
+                        peer.write_all(b"ok\n").await.unwrap();
+                        stream.write_all(b"ok\n").await.unwrap();
+                        let _ = tokio::io::copy_bidirectional(&mut peer, &mut stream).await;
+                    }
+                    None => {
+                        waiting.lock().unwrap().insert(channel, stream);
+                    }
+                }
+            });
+        }
+    });
+    format!("tcp://{}", addr)
+}
@@ -0,0 +1,175 @@
+//! Admin HTTP listener for the Transit Relay
+//!
+//! The relay port speaks raw TCP, so health checks and Prometheus scrapes
+//! are served from a separate HTTP port, along with a listing of the
+//! channels being relayed.
+
+use axum::{extract::State, http::header, response::IntoResponse, routing::get, Json, Router};
+use serde::Serialize;
+use tokio::net::TcpListener;
+
+use crate::relay::RelayServer;
+
+#[derive(Serialize)]
+pub struct HealthResponse {
+    status: String,
+    service: String,
+    version: String,
+}
+
+/// A channel being relayed, as listed by `/sessions`
+#[derive(Serialize)]
+pub struct SessionResponse {
+    channel: String,
+    first_side: String,
+    second_side: String,
+    started_at: u64,
+    duration_secs: u64,
+    bytes_first_to_second: u64,
+    bytes_second_to_first: u64,
+}
+
+/// Build the admin router
+pub fn router(relay: RelayServer) -> Router {
+    Router::new()
+        .route("/health", get(health_check))
+        .route("/metrics", get(metrics_handler))
+        .route("/sessions", get(sessions_handler))
+        .with_state(relay)
+}
+
+/// Serve the admin endpoints until the listener fails
+pub async fn serve(listener: TcpListener, relay: RelayServer) -> std::io::Result<()> {
+    axum::serve(listener, router(relay)).await
+}
+
+/// Health check endpoint
+async fn health_check() -> Json<HealthResponse> {
+    Json(HealthResponse {
+        status: "ok".to_string(),
+        service: "securebeam-relay".to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+    })
+}
+
+/// Prometheus metrics endpoint
+async fn metrics_handler(State(relay): State<RelayServer>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        relay.render_metrics().await,
+    )
+}
+
+/// Live sessions with the bytes relayed in each direction
+async fn sessions_handler(State(relay): State<RelayServer>) -> Json<Vec<SessionResponse>> {
+    let sessions = relay.sessions().await;
+    Json(
+        sessions
+            .into_iter()
+            .map(|s| SessionResponse {
+                channel: s.channel_id,
+                first_side: s.first_side,
+                second_side: s.second_side,
+                started_at: s.started_at,
+                duration_secs: s.duration.as_secs(),
+                bytes_first_to_second: s.bytes_first_to_second,
+                bytes_second_to_first: s.bytes_second_to_first,
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    async fn get(path: &str) -> String {
+        get_from(RelayServer::new(), path).await
+    }
+
+    async fn get_from(relay: RelayServer, path: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, relay));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(
+                format!(
+                    "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                    path
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_health_endpoint() {
+        let response = get("/health").await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("\"status\":\"ok\""));
+        assert!(response.contains("\"service\":\"securebeam-relay\""));
+    }
+
+    #[tokio::test]
+    async fn test_metrics_endpoint() {
+        let response = get("/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("text/plain; version=0.0.4"));
+        assert!(response.contains("securebeam_relay_active_channels 0"));
+        assert!(response.contains("# TYPE securebeam_relay_pairing_latency_seconds histogram"));
+    }
+
+    #[tokio::test]
+    async fn test_sessions_endpoint() {
+        let response = get("/sessions").await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("[]"));
+
+        let relay = RelayServer::new();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = relay.clone();
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                let relay = server.clone();
+                tokio::spawn(async move { relay.handle_connection(socket).await });
+            }
+        });
+
+        let mut peers = Vec::new();
+        for side in ["sender", "receiver"] {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let line = format!("please relay {} for {}\n", "ab".repeat(16), side);
+            stream.write_all(line.as_bytes()).await.unwrap();
+            let mut ok = [0u8; 3];
+            stream.read_exact(&mut ok).await.unwrap();
+            assert_eq!(&ok, b"ok\n");
+            peers.push(stream);
+        }
+        peers[0].write_all(b"12345").await.unwrap();
+        let mut buf = [0u8; 5];
+        peers[1].read_exact(&mut buf).await.unwrap();
+
+        let mut response = String::new();
+        for _ in 0..50 {
+            response = get_from(relay.clone(), "/sessions").await;
+            if response.contains("\"bytes_first_to_second\":5") {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert!(response.contains(&format!("\"channel\":\"{}\"", "ab".repeat(16))));
+        assert!(response.contains("\"first_side\":\"sender\""));
+        assert!(response.contains("\"bytes_first_to_second\":5"));
+        assert!(response.contains("\"bytes_second_to_first\":0"));
+    }
+}
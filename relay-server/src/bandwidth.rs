@@ -0,0 +1,237 @@
+//! Bandwidth limiting and per-session accounting
+//!
+//! A token bucket throttles the relay copy loops, and every relayed
+//! channel keeps byte counters that are logged when the session ends.
+
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Token bucket rate limiter
+///
+/// Consumers take tokens up front and sleep off any deficit, so a bucket
+/// shared between many connections divides its rate fairly between them.
+/// A rate of 0 means unlimited.
+pub struct TokenBucket {
+    /// Refill rate in bytes per second
+    rate: u64,
+    /// Maximum burst size in bytes
+    capacity: u64,
+    state: Mutex<BucketState>,
+}
+
+struct BucketState {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Create a bucket refilling at `rate` bytes per second with a one second burst
+    pub fn new(rate: u64) -> Self {
+        Self::with_capacity(rate, rate)
+    }
+
+    /// Create a bucket with an explicit burst capacity
+    pub fn with_capacity(rate: u64, capacity: u64) -> Self {
+        Self {
+            rate,
+            capacity,
+            state: Mutex::new(BucketState {
+                tokens: capacity as f64,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Check if this bucket imposes no limit
+    pub fn is_unlimited(&self) -> bool {
+        self.rate == 0
+    }
+
+    /// Take `bytes` tokens, waiting until the bucket can cover them
+    pub async fn consume(&self, bytes: u64) {
+        if let Some(wait) = self.reserve(bytes) {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Take tokens and return how long the caller must wait to stay within the rate
+    fn reserve(&self, bytes: u64) -> Option<Duration> {
+        if self.is_unlimited() {
+            return None;
+        }
+
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * self.rate as f64).min(self.capacity as f64);
+        state.last_refill = now;
+        state.tokens -= bytes as f64;
+
+        if state.tokens >= 0.0 {
+            None
+        } else {
+            Some(Duration::from_secs_f64(-state.tokens / self.rate as f64))
+        }
+    }
+}
+
+/// Byte counters for one relayed channel
+pub struct SessionStats {
+    /// Session ID (unique per relay process)
+    pub id: u64,
+    /// Channel ID from the handshake
+    pub channel_id: String,
+    /// Side and source IP of the connection that waited first
+    pub first: (String, IpAddr),
+    /// Side and source IP of the connection that completed the pair
+    pub second: (String, IpAddr),
+    /// Unix timestamp (seconds) when the session started
+    pub started_at: u64,
+    started: Instant,
+    /// Bytes sent from the first connection to the second
+    bytes_first_to_second: AtomicU64,
+    /// Bytes sent from the second connection to the first
+    bytes_second_to_first: AtomicU64,
+    /// Milliseconds since start of the last relayed chunk
+    last_activity_ms: AtomicU64,
+}
+
+/// Which way data is flowing through a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    FirstToSecond,
+    SecondToFirst,
+}
+
+impl SessionStats {
+    pub fn new(
+        id: u64,
+        channel_id: String,
+        first: (String, IpAddr),
+        second: (String, IpAddr),
+    ) -> Self {
+        Self {
+            id,
+            channel_id,
+            first,
+            second,
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            started: Instant::now(),
+            bytes_first_to_second: AtomicU64::new(0),
+            bytes_second_to_first: AtomicU64::new(0),
+            last_activity_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Record bytes relayed in one direction
+    pub fn record(&self, direction: Direction, bytes: u64) {
+        let counter = match direction {
+            Direction::FirstToSecond => &self.bytes_first_to_second,
+            Direction::SecondToFirst => &self.bytes_second_to_first,
+        };
+        counter.fetch_add(bytes, Ordering::Relaxed);
+        self.last_activity_ms
+            .store(self.started.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Total bytes relayed in both directions
+    pub fn total_bytes(&self) -> u64 {
+        self.bytes_first_to_second.load(Ordering::Relaxed)
+            + self.bytes_second_to_first.load(Ordering::Relaxed)
+    }
+
+    /// Time since data last flowed in either direction
+    pub fn idle_for(&self) -> Duration {
+        let last = Duration::from_millis(self.last_activity_ms.load(Ordering::Relaxed));
+        self.started.elapsed().saturating_sub(last)
+    }
+
+    /// Take a point-in-time copy of the counters
+    pub fn snapshot(&self) -> SessionSnapshot {
+        SessionSnapshot {
+            id: self.id,
+            channel_id: self.channel_id.clone(),
+            first_side: self.first.0.clone(),
+            first_ip: self.first.1,
+            second_side: self.second.0.clone(),
+            second_ip: self.second.1,
+            started_at: self.started_at,
+            duration: self.started.elapsed(),
+            bytes_first_to_second: self.bytes_first_to_second.load(Ordering::Relaxed),
+            bytes_second_to_first: self.bytes_second_to_first.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Point-in-time copy of a session's counters
+#[derive(Debug, Clone)]
+pub struct SessionSnapshot {
+    pub id: u64,
+    pub channel_id: String,
+    pub first_side: String,
+    pub first_ip: IpAddr,
+    pub second_side: String,
+    pub second_ip: IpAddr,
+    pub started_at: u64,
+    pub duration: Duration,
+    pub bytes_first_to_second: u64,
+    pub bytes_second_to_first: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unlimited_bucket_never_waits() {
+        let bucket = TokenBucket::new(0);
+        assert!(bucket.is_unlimited());
+        assert!(bucket.reserve(u64::MAX / 2).is_none());
+    }
+
+    #[test]
+    fn test_bucket_burst_then_wait() {
+        let bucket = TokenBucket::new(1000);
+
+        // The initial burst is free
+        assert!(bucket.reserve(1000).is_none());
+
+        // The next 500 bytes must wait about half a second
+        let wait = bucket.reserve(500).unwrap();
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_shared_bucket_accumulates_debt() {
+        let bucket = TokenBucket::with_capacity(1000, 0);
+        let first = bucket.reserve(100).unwrap();
+        let second = bucket.reserve(100).unwrap();
+        assert!(second > first);
+    }
+
+    #[test]
+    fn test_session_counters() {
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let stats = SessionStats::new(
+            1,
+            "abcd".to_string(),
+            ("sender".to_string(), ip),
+            ("receiver".to_string(), ip),
+        );
+
+        stats.record(Direction::FirstToSecond, 100);
+        stats.record(Direction::SecondToFirst, 5);
+        assert_eq!(stats.total_bytes(), 105);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.bytes_first_to_second, 100);
+        assert_eq!(snapshot.bytes_second_to_first, 5);
+        assert_eq!(snapshot.first_side, "sender");
+        assert!(stats.idle_for() < Duration::from_secs(1));
+    }
+}
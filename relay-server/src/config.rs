@@ -0,0 +1,106 @@
+use std::env;
+use std::time::Duration;
+
+use crate::relay::RelayLimits;
+
+/// Relay server configuration
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub host: String,
+    pub port: u16,
+    /// Host for the admin HTTP listener (/health, /metrics)
+    pub admin_host: String,
+    /// Port for the admin HTTP listener (0 = disabled)
+    pub admin_port: u16,
+    /// Seconds a client may take to send its handshake line
+    pub handshake_timeout_secs: u64,
+    /// Seconds a half-open channel waits for its peer before being reaped
+    pub pending_timeout_secs: u64,
+    /// Maximum pending channels per source IP
+    pub max_pending_per_ip: usize,
+    /// Maximum pending channels overall
+    pub max_pending_total: usize,
+    /// Maximum candidate connections from one side on a single channel
+    pub max_candidates_per_channel: usize,
+    /// Upload rate per connection in bytes per second (0 = unlimited)
+    pub connection_rate_limit: u64,
+    /// Total relay rate in bytes per second (0 = unlimited)
+    pub global_rate_limit: u64,
+    /// Maximum bytes relayed per channel (0 = unlimited)
+    pub channel_quota_bytes: u64,
+    /// Seconds without traffic before a relayed channel is closed (0 = never)
+    pub idle_timeout_secs: u64,
+}
+
+impl Config {
+    /// Load configuration from environment variables
+    pub fn from_env() -> Self {
+        dotenvy::dotenv().ok();
+
+        let defaults = Self::default();
+
+        Self {
+            host: env::var("RELAY_HOST").unwrap_or(defaults.host),
+            port: env_parse("RELAY_PORT").unwrap_or(defaults.port),
+            admin_host: env::var("RELAY_ADMIN_HOST").unwrap_or(defaults.admin_host),
+            admin_port: env_parse("RELAY_ADMIN_PORT").unwrap_or(defaults.admin_port),
+            handshake_timeout_secs: env_parse("RELAY_HANDSHAKE_TIMEOUT_SECS")
+                .unwrap_or(defaults.handshake_timeout_secs),
+            pending_timeout_secs: env_parse("RELAY_PENDING_TIMEOUT_SECS")
+                .unwrap_or(defaults.pending_timeout_secs),
+            max_pending_per_ip: env_parse("RELAY_MAX_PENDING_PER_IP")
+                .unwrap_or(defaults.max_pending_per_ip),
+            max_pending_total: env_parse("RELAY_MAX_PENDING").unwrap_or(defaults.max_pending_total),
+            max_candidates_per_channel: env_parse("RELAY_MAX_CANDIDATES_PER_CHANNEL")
+                .unwrap_or(defaults.max_candidates_per_channel),
+            connection_rate_limit: env_parse("RELAY_CONNECTION_RATE_LIMIT")
+                .unwrap_or(defaults.connection_rate_limit),
+            global_rate_limit: env_parse("RELAY_GLOBAL_RATE_LIMIT")
+                .unwrap_or(defaults.global_rate_limit),
+            channel_quota_bytes: env_parse("RELAY_CHANNEL_QUOTA_BYTES")
+                .unwrap_or(defaults.channel_quota_bytes),
+            idle_timeout_secs: env_parse("RELAY_IDLE_TIMEOUT_SECS")
+                .unwrap_or(defaults.idle_timeout_secs),
+        }
+    }
+
+    /// Get the connection limits for the relay
+    pub fn limits(&self) -> RelayLimits {
+        RelayLimits {
+            handshake_timeout: Duration::from_secs(self.handshake_timeout_secs),
+            pending_timeout: Duration::from_secs(self.pending_timeout_secs),
+            max_pending_per_ip: self.max_pending_per_ip,
+            max_pending_total: self.max_pending_total,
+            max_candidates_per_channel: self.max_candidates_per_channel,
+            connection_rate_limit: self.connection_rate_limit,
+            global_rate_limit: self.global_rate_limit,
+            channel_quota: self.channel_quota_bytes,
+            idle_timeout: Duration::from_secs(self.idle_timeout_secs),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            host: "0.0.0.0".to_string(),
+            port: 4001,
+            admin_host: "0.0.0.0".to_string(),
+            admin_port: 4002,
+            handshake_timeout_secs: 30,
+            pending_timeout_secs: 120, // 2 minutes default
+            max_pending_per_ip: 16,
+            max_pending_total: 4096,
+            max_candidates_per_channel: 4,
+            connection_rate_limit: 0,
+            global_rate_limit: 0,
+            channel_quota_bytes: 0,
+            idle_timeout_secs: 300, // 5 minutes default
+        }
+    }
+}
+
+/// Parse an environment variable, ignoring missing or malformed values
+fn env_parse<T: std::str::FromStr>(key: &str) -> Option<T> {
+    env::var(key).ok().and_then(|v| v.parse().ok())
+}
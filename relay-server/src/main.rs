@@ -7,13 +7,22 @@
 //! 1. Client connects and sends: "please relay {channel_id} for {side}\n"
 //! 2. Server responds: "ok\n"
 //! 3. When both sides connect, server pipes data between them
+//!
+//! Connections that wait too long for their peer, or that exceed the
+//! per-IP or global pending limits, are closed with an "error: ..." line.
+//!
+//! `/health` and `/metrics` are served over HTTP on a separate admin port.
 
+mod admin;
+mod bandwidth;
+mod config;
+mod metrics;
 mod relay;
 
-use std::env;
 use tokio::net::TcpListener;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+use config::Config;
 use relay::RelayServer;
 
 #[tokio::main]
@@ -28,23 +37,46 @@ async fn main() {
         .init();
 
     // Load configuration
-    dotenvy::dotenv().ok();
-    let host = env::var("RELAY_HOST").unwrap_or_else(|_| "0.0.0.0".to_string());
-    let port: u16 = env::var("RELAY_PORT")
-        .ok()
-        .and_then(|p| p.parse().ok())
-        .unwrap_or(4001);
+    let config = Config::from_env();
 
-    let addr = format!("{}:{}", host, port);
+    let addr = format!("{}:{}", config.host, config.port);
 
     tracing::info!(
         "Starting SecureBeam Transit Relay Server v{}",
         env!("CARGO_PKG_VERSION")
     );
     tracing::info!("Listening on {}", addr);
+    tracing::info!(
+        "Pending timeout {}s, max {} pending per IP, max {} pending total",
+        config.pending_timeout_secs,
+        config.max_pending_per_ip,
+        config.max_pending_total
+    );
+    tracing::info!(
+        "Rate limits: {} B/s per connection, {} B/s global, {} B quota per channel (0 = unlimited)",
+        config.connection_rate_limit,
+        config.global_rate_limit,
+        config.channel_quota_bytes
+    );
 
     // Create relay server
-    let relay = RelayServer::new();
+    let relay = RelayServer::with_limits(config.limits());
+
+    // Start the admin HTTP listener
+    if config.admin_port != 0 {
+        let admin_addr = format!("{}:{}", config.admin_host, config.admin_port);
+        let admin_listener = TcpListener::bind(&admin_addr)
+            .await
+            .expect("Failed to bind admin listener");
+        tracing::info!("Admin endpoints ready at http://{}", admin_addr);
+
+        let relay = relay.clone();
+        tokio::spawn(async move {
+            if let Err(e) = admin::serve(admin_listener, relay).await {
+                tracing::error!("Admin listener error: {}", e);
+            }
+        });
+    }
 
     // Start listening
     let listener = TcpListener::bind(&addr).await.expect("Failed to bind");
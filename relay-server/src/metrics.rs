@@ -0,0 +1,262 @@
+//! Prometheus metrics for the Transit Relay
+//!
+//! Counters are updated by the relay as connections are paired, rejected
+//! and closed. Gauges for pending and active channels are sampled from the
+//! relay state when `/metrics` is scraped.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds (seconds) of the pairing latency histogram buckets
+const LATENCY_BUCKETS: [f64; 10] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// Why a connection never made it to a relayed channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// The handshake line did not arrive in time
+    HandshakeTimeout,
+    /// The handshake line was malformed
+    BadHandshake,
+    /// The channel was already being relayed
+    ChannelInUse,
+    /// A pending connection limit was hit
+    PendingLimit,
+    /// The channel already held too many candidates
+    TooManyCandidates,
+    /// No peer arrived before the pending timeout
+    PeerTimeout,
+}
+
+impl Failure {
+    const ALL: [Failure; 6] = [
+        Failure::HandshakeTimeout,
+        Failure::BadHandshake,
+        Failure::ChannelInUse,
+        Failure::PendingLimit,
+        Failure::TooManyCandidates,
+        Failure::PeerTimeout,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Failure::HandshakeTimeout => "handshake_timeout",
+            Failure::BadHandshake => "bad_handshake",
+            Failure::ChannelInUse => "channel_in_use",
+            Failure::PendingLimit => "pending_limit",
+            Failure::TooManyCandidates => "too_many_candidates",
+            Failure::PeerTimeout => "peer_timeout",
+        }
+    }
+}
+
+/// Relay state sampled at scrape time
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RelayGauges {
+    /// Channels with at least one connection waiting for its peer
+    pub pending_channels: usize,
+    /// Connections waiting for their peer
+    pub pending_connections: usize,
+    /// Channels currently being relayed
+    pub active_channels: usize,
+    /// Bytes relayed so far by the active channels
+    pub active_bytes: u64,
+}
+
+/// Metrics collected by the relay
+#[derive(Default)]
+pub struct RelayMetrics {
+    connections: AtomicU64,
+    channels_paired: AtomicU64,
+    failures: [AtomicU64; Failure::ALL.len()],
+    /// Bytes relayed by channels that have already closed
+    closed_bytes: AtomicU64,
+    pairing_buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    pairing_count: AtomicU64,
+    pairing_sum_ms: AtomicU64,
+}
+
+impl RelayMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count an accepted TCP connection
+    pub fn connection_accepted(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a connection that was rejected or timed out
+    pub fn failure(&self, failure: Failure) {
+        let index = Failure::ALL.iter().position(|f| *f == failure).unwrap_or(0);
+        self.failures[index].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a channel pairing and how long the first side waited
+    pub fn channel_paired(&self, waited: Duration) {
+        self.channels_paired.fetch_add(1, Ordering::Relaxed);
+
+        let secs = waited.as_secs_f64();
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|b| secs <= *b) {
+            self.pairing_buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.pairing_count.fetch_add(1, Ordering::Relaxed);
+        self.pairing_sum_ms
+            .fetch_add(waited.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Record the bytes of a channel that just closed
+    pub fn channel_closed(&self, bytes: u64) {
+        self.closed_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Render all metrics in the Prometheus text format
+    pub fn render(&self, gauges: RelayGauges) -> String {
+        let mut out = String::new();
+
+        write_metric(
+            &mut out,
+            "securebeam_relay_pending_channels",
+            "gauge",
+            "Channels waiting for their peer",
+            gauges.pending_channels,
+        );
+        write_metric(
+            &mut out,
+            "securebeam_relay_pending_connections",
+            "gauge",
+            "Connections waiting for their peer",
+            gauges.pending_connections,
+        );
+        write_metric(
+            &mut out,
+            "securebeam_relay_active_channels",
+            "gauge",
+            "Channels currently being relayed",
+            gauges.active_channels,
+        );
+        write_metric(
+            &mut out,
+            "securebeam_relay_connections_total",
+            "counter",
+            "TCP connections accepted",
+            self.connections.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut out,
+            "securebeam_relay_channels_paired_total",
+            "counter",
+            "Channels where both sides connected",
+            self.channels_paired.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut out,
+            "securebeam_relay_bytes_total",
+            "counter",
+            "Bytes relayed in both directions",
+            self.closed_bytes.load(Ordering::Relaxed) + gauges.active_bytes,
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP securebeam_relay_handshake_failures_total Connections that were rejected or timed out before relaying"
+        );
+        let _ = writeln!(
+            out,
+            "# TYPE securebeam_relay_handshake_failures_total counter"
+        );
+        for (failure, counter) in Failure::ALL.iter().zip(&self.failures) {
+            let _ = writeln!(
+                out,
+                "securebeam_relay_handshake_failures_total{{reason=\"{}\"}} {}",
+                failure.label(),
+                counter.load(Ordering::Relaxed)
+            );
+        }
+
+        let name = "securebeam_relay_pairing_latency_seconds";
+        let _ = writeln!(
+            out,
+            "# HELP {} Time the first side waited for its peer",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        let mut cumulative = 0;
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&self.pairing_buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        let count = self.pairing_count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(
+            out,
+            "{}_sum {}",
+            name,
+            self.pairing_sum_ms.load(Ordering::Relaxed) as f64 / 1000.0
+        );
+        let _ = writeln!(out, "{}_count {}", name, count);
+
+        out
+    }
+}
+
+/// Write a single-value metric with its HELP and TYPE lines
+fn write_metric(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    value: impl std::fmt::Display,
+) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_counters_and_gauges() {
+        let metrics = RelayMetrics::new();
+        metrics.connection_accepted();
+        metrics.connection_accepted();
+        metrics.failure(Failure::BadHandshake);
+        metrics.failure(Failure::PeerTimeout);
+        metrics.failure(Failure::PeerTimeout);
+        metrics.channel_closed(100);
+
+        let text = metrics.render(RelayGauges {
+            pending_channels: 1,
+            pending_connections: 2,
+            active_channels: 3,
+            active_bytes: 50,
+        });
+        assert!(text.contains("securebeam_relay_pending_channels 1\n"));
+        assert!(text.contains("securebeam_relay_pending_connections 2\n"));
+        assert!(text.contains("securebeam_relay_active_channels 3\n"));
+        assert!(text.contains("securebeam_relay_connections_total 2\n"));
+        assert!(text.contains("securebeam_relay_bytes_total 150\n"));
+        assert!(text
+            .contains("securebeam_relay_handshake_failures_total{reason=\"bad_handshake\"} 1\n"));
+        assert!(
+            text.contains("securebeam_relay_handshake_failures_total{reason=\"peer_timeout\"} 2\n")
+        );
+    }
+
+    #[test]
+    fn test_pairing_latency_histogram() {
+        let metrics = RelayMetrics::new();
+        metrics.channel_paired(Duration::from_millis(20));
+        metrics.channel_paired(Duration::from_secs(3));
+
+        let text = metrics.render(RelayGauges::default());
+        assert!(text.contains("securebeam_relay_channels_paired_total 2\n"));
+        assert!(text.contains("securebeam_relay_pairing_latency_seconds_bucket{le=\"0.05\"} 1\n"));
+        assert!(text.contains("securebeam_relay_pairing_latency_seconds_bucket{le=\"2.5\"} 1\n"));
+        assert!(text.contains("securebeam_relay_pairing_latency_seconds_bucket{le=\"5\"} 2\n"));
+        assert!(text.contains("securebeam_relay_pairing_latency_seconds_count 2\n"));
+        assert!(text.contains("securebeam_relay_pairing_latency_seconds_sum 3.02\n"));
+    }
+}
@@ -2,12 +2,25 @@
 //!
 //! Implements the Magic Wormhole transit relay protocol.
 //! The relay simply connects two clients and pipes data between them.
+//!
+//! Like the upstream transit relay, a channel may hold several candidate
+//! connections from the same side. The first connection from a different
+//! side is paired with the oldest candidate and the remaining candidates
+//! are closed as redundant.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::io::AsyncWriteExt;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::TcpStream;
 use tokio::sync::RwLock;
+use tokio::time::timeout;
+
+use crate::bandwidth::{Direction, SessionSnapshot, SessionStats, TokenBucket};
+use crate::metrics::{Failure, RelayGauges, RelayMetrics};
 
 /// Error type for relay operations
 #[derive(Debug, thiserror::Error)]
@@ -21,37 +34,135 @@ pub enum RelayError {
     ChannelNotFound,
     #[error("Peer disconnected")]
     PeerDisconnected,
+    #[error("Handshake timed out")]
+    HandshakeTimeout,
+    #[error("Too many pending connections from {0}")]
+    TooManyPendingForIp(IpAddr),
+    #[error("Too many pending connections")]
+    TooManyPending,
+    #[error("Too many candidate connections for channel")]
+    TooManyCandidates,
+    #[error("Channel already in use")]
+    ChannelInUse,
+    #[error("Channel byte quota exceeded")]
+    QuotaExceeded,
+    #[error("Relay session idle for too long")]
+    IdleTimeout,
+}
+
+/// Maximum length of a channel ID in the handshake
+const MAX_CHANNEL_ID_LEN: usize = 128;
+
+/// Maximum length of a side identifier in the handshake
+const MAX_SIDE_LEN: usize = 64;
+
+/// Read buffer size for each relay direction
+const RELAY_BUFFER_SIZE: usize = 16 * 1024;
+
+/// Connection and bandwidth limits for the relay
+#[derive(Debug, Clone)]
+pub struct RelayLimits {
+    /// How long a client may take to send its handshake line
+    pub handshake_timeout: Duration,
+    /// How long a pending connection waits for its peer before being reaped
+    pub pending_timeout: Duration,
+    /// Maximum pending connections per source IP
+    pub max_pending_per_ip: usize,
+    /// Maximum pending connections overall
+    pub max_pending_total: usize,
+    /// Maximum candidate connections from one side waiting on a channel
+    pub max_candidates_per_channel: usize,
+    /// Upload rate per connection in bytes per second (0 = unlimited)
+    pub connection_rate_limit: u64,
+    /// Total relay rate across all connections in bytes per second (0 = unlimited)
+    pub global_rate_limit: u64,
+    /// Maximum bytes relayed per channel in both directions (0 = unlimited)
+    pub channel_quota: u64,
+    /// Close a relayed channel when no data flows for this long (zero = never)
+    pub idle_timeout: Duration,
+}
+
+impl Default for RelayLimits {
+    fn default() -> Self {
+        crate::config::Config::default().limits()
+    }
 }
 
 /// A pending connection waiting for its peer
 struct PendingConnection {
+    /// Unique ID so the reaper only removes the entry it was started for
+    id: u64,
     /// The TCP stream
     stream: TcpStream,
     /// The side identifier
     side: String,
+    /// Source IP of the connection
+    ip: IpAddr,
+    /// When the connection started waiting
+    since: Instant,
 }
 
 /// Transit Relay Server
 #[derive(Clone)]
 pub struct RelayServer {
-    /// Pending connections indexed by channel ID
-    pending: Arc<RwLock<HashMap<String, PendingConnection>>>,
+    /// Pending candidate connections indexed by channel ID (oldest first)
+    pending: Arc<RwLock<HashMap<String, Vec<PendingConnection>>>>,
+    /// Channels that are currently being relayed
+    active: Arc<RwLock<HashSet<String>>>,
+    /// Connection limits
+    limits: Arc<RelayLimits>,
+    /// Next pending connection / session ID
+    next_id: Arc<AtomicU64>,
+    /// Bucket shared by every relayed connection
+    global_bucket: Arc<TokenBucket>,
+    /// Counters of the sessions currently being relayed
+    sessions: Arc<RwLock<HashMap<u64, Arc<SessionStats>>>>,
+    /// Prometheus metrics
+    metrics: Arc<RelayMetrics>,
 }
 
 impl RelayServer {
     pub fn new() -> Self {
+        Self::with_limits(RelayLimits::default())
+    }
+
+    /// Create a relay server with custom limits
+    pub fn with_limits(limits: RelayLimits) -> Self {
         Self {
+            global_bucket: Arc::new(TokenBucket::new(limits.global_rate_limit)),
+            sessions: Arc::new(RwLock::new(HashMap::new())),
             pending: Arc::new(RwLock::new(HashMap::new())),
+            active: Arc::new(RwLock::new(HashSet::new())),
+            limits: Arc::new(limits),
+            next_id: Arc::new(AtomicU64::new(1)),
+            metrics: Arc::new(RelayMetrics::new()),
         }
     }
 
     /// Handle a new connection
     pub async fn handle_connection(&self, mut stream: TcpStream) -> Result<(), RelayError> {
+        let ip = stream.peer_addr()?.ip();
+        self.metrics.connection_accepted();
+
         // Read the handshake line (read byte by byte until newline)
-        let line = self.read_line(&mut stream).await?;
+        let line = match timeout(self.limits.handshake_timeout, self.read_line(&mut stream)).await {
+            Ok(line) => line.inspect_err(|_| self.metrics.failure(Failure::BadHandshake))?,
+            Err(_) => {
+                self.metrics.failure(Failure::HandshakeTimeout);
+                reject(&mut stream, "handshake timed out").await;
+                return Err(RelayError::HandshakeTimeout);
+            }
+        };
 
         // Parse handshake: "please relay {channel_id} for {side}\n"
-        let (channel_id, side) = self.parse_handshake(&line)?;
+        let (channel_id, side) = match self.parse_handshake(&line) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                self.metrics.failure(Failure::BadHandshake);
+                reject(&mut stream, "bad handshake").await;
+                return Err(e);
+            }
+        };
 
         tracing::info!(
             "Relay request for channel {} from side {}",
@@ -59,37 +170,198 @@ impl RelayServer {
             side
         );
 
-        // Send OK response
+        // Check if peer is already waiting, otherwise reserve a pending slot
+        let mut pending = self.pending.write().await;
+
+        if let Some(peer) = self.take_peer(&mut pending, &channel_id, &side).await {
+            drop(pending);
+            return match stream.write_all(b"ok\n").await {
+                Ok(()) => {
+                    self.connect_peers(&channel_id, peer, (stream, side, ip))
+                        .await
+                }
+                Err(e) => {
+                    self.active.write().await.remove(&channel_id);
+                    Err(e.into())
+                }
+            };
+        }
+
+        // No peer yet - enforce limits before we hold on to the socket
+        if let Err(refusal) = self.may_wait(&pending, &channel_id, ip).await {
+            drop(pending);
+            return Err(self.refuse(&mut stream, ip, &channel_id, refusal).await);
+        }
+
+        // Send OK response without holding up other connections; a peer
+        // that arrived meanwhile is paired right away
+        drop(pending);
         stream.write_all(b"ok\n").await?;
+        let mut pending = self.pending.write().await;
+        if let Some(peer) = self.take_peer(&mut pending, &channel_id, &side).await {
+            drop(pending);
+            return self
+                .connect_peers(&channel_id, peer, (stream, side, ip))
+                .await;
+        }
 
-        // Check if peer is already waiting
-        let peer = {
-            let mut pending = self.pending.write().await;
-            pending.remove(&channel_id)
-        };
+        // Concurrent handshakes may have used up the limits or paired the
+        // channel while the lock was released
+        if let Err(refusal) = self.may_wait(&pending, &channel_id, ip).await {
+            drop(pending);
+            return Err(self.refuse(&mut stream, ip, &channel_id, refusal).await);
+        }
+
+        // Wait for the peer
+        tracing::debug!("Waiting for peer on channel {}", channel_id);
 
-        if let Some(peer_conn) = peer {
-            // Peer is waiting - connect them
-            tracing::info!(
-                "Connecting channel {} ({} <-> {})",
-                channel_id,
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        pending
+            .entry(channel_id.clone())
+            .or_default()
+            .push(PendingConnection {
+                id,
+                stream,
                 side,
-                peer_conn.side
-            );
-            self.relay_streams(stream, peer_conn.stream).await?;
-        } else {
-            // No peer yet - wait for them
-            tracing::debug!("Waiting for peer on channel {}", channel_id);
+                ip,
+                since: Instant::now(),
+            });
+        drop(pending);
 
-            let mut pending = self.pending.write().await;
-            pending.insert(channel_id.clone(), PendingConnection { stream, side });
+        // Reap the connection if no peer arrives in time
+        tokio::time::sleep(self.limits.pending_timeout).await;
+        self.reap_pending(&channel_id, id).await;
+
+        Ok(())
+    }
+
+    /// Check that a connection from `ip` may wait for its peer on `channel_id`
+    async fn may_wait(
+        &self,
+        pending: &HashMap<String, Vec<PendingConnection>>,
+        channel_id: &str,
+        ip: IpAddr,
+    ) -> Result<(), RelayError> {
+        // A paired channel never accepts further connections
+        if self.active.read().await.contains(channel_id) {
+            return Err(RelayError::ChannelInUse);
+        }
 
-            // TODO: Add timeout for pending connections
+        let total: usize = pending.values().map(Vec::len).sum();
+        if total >= self.limits.max_pending_total {
+            return Err(RelayError::TooManyPending);
         }
 
+        let from_ip = pending.values().flatten().filter(|p| p.ip == ip).count();
+        if from_ip >= self.limits.max_pending_per_ip {
+            return Err(RelayError::TooManyPendingForIp(ip));
+        }
+
+        let candidates = pending.get(channel_id).map_or(0, Vec::len);
+        if candidates >= self.limits.max_candidates_per_channel {
+            return Err(RelayError::TooManyCandidates);
+        }
         Ok(())
     }
 
+    /// Record and report why a connection may not wait, then close it
+    async fn refuse(
+        &self,
+        stream: &mut TcpStream,
+        ip: IpAddr,
+        channel_id: &str,
+        error: RelayError,
+    ) -> RelayError {
+        let (failure, reason) = match &error {
+            RelayError::ChannelInUse => (Failure::ChannelInUse, "channel already in use"),
+            RelayError::TooManyPendingForIp(_) => (
+                Failure::PendingLimit,
+                "too many pending connections from your address",
+            ),
+            RelayError::TooManyCandidates => (
+                Failure::TooManyCandidates,
+                "too many connections for channel",
+            ),
+            _ => (Failure::PendingLimit, "too many pending connections"),
+        };
+        self.metrics.failure(failure);
+        tracing::warn!("Rejecting {} on channel {}: {}", ip, channel_id, error);
+        reject(stream, reason).await;
+        error
+    }
+
+    /// Take the oldest candidate from another side off `channel_id` and mark
+    /// the channel active, returning it with the redundant candidates
+    async fn take_peer(
+        &self,
+        pending: &mut HashMap<String, Vec<PendingConnection>>,
+        channel_id: &str,
+        side: &str,
+    ) -> Option<(PendingConnection, Vec<PendingConnection>)> {
+        let candidates = pending.get_mut(channel_id)?;
+        let pos = candidates.iter().position(|c| c.side != side)?;
+        let peer = candidates.remove(pos);
+        let redundant = std::mem::take(candidates);
+        pending.remove(channel_id);
+        self.active.write().await.insert(channel_id.to_string());
+        Some((peer, redundant))
+    }
+
+    /// Relay between a taken candidate and the connection that matched it,
+    /// closing the redundant candidates; the channel is free again after
+    async fn connect_peers(
+        &self,
+        channel_id: &str,
+        (peer_conn, redundant): (PendingConnection, Vec<PendingConnection>),
+        (stream, side, ip): (TcpStream, String, IpAddr),
+    ) -> Result<(), RelayError> {
+        self.metrics.channel_paired(peer_conn.since.elapsed());
+
+        // Close the other candidates from the peer's side
+        for mut conn in redundant {
+            tracing::debug!("Closing redundant connection on channel {}", channel_id);
+            reject(&mut conn.stream, "redundant connection").await;
+        }
+
+        // Peer is waiting - connect them
+        tracing::info!(
+            "Connecting channel {} ({} <-> {})",
+            channel_id,
+            side,
+            peer_conn.side
+        );
+
+        let result = self
+            .relay_streams(channel_id, peer_conn, (stream, side, ip))
+            .await;
+        self.active.write().await.remove(channel_id);
+        result
+    }
+
+    /// Remove a pending connection that is still waiting and close it
+    async fn reap_pending(&self, channel_id: &str, id: u64) {
+        let reaped = {
+            let mut pending = self.pending.write().await;
+            let Some(candidates) = pending.get_mut(channel_id) else {
+                return;
+            };
+            let reaped = candidates
+                .iter()
+                .position(|c| c.id == id)
+                .map(|pos| candidates.remove(pos));
+            if candidates.is_empty() {
+                pending.remove(channel_id);
+            }
+            reaped
+        };
+
+        if let Some(mut conn) = reaped {
+            self.metrics.failure(Failure::PeerTimeout);
+            tracing::info!("Reaping pending connection on channel {}", channel_id);
+            reject(&mut conn.stream, "timed out waiting for peer").await;
+        }
+    }
+
     /// Read a line from the stream (until newline)
     async fn read_line(&self, stream: &mut TcpStream) -> Result<String, RelayError> {
         use tokio::io::AsyncReadExt;
@@ -144,48 +416,204 @@ impl RelayServer {
                 "Channel ID too short".to_string(),
             ));
         }
+        if channel_id.len() > MAX_CHANNEL_ID_LEN || !is_hex(&channel_id) {
+            return Err(RelayError::InvalidHandshake(
+                "Channel ID must be hex".to_string(),
+            ));
+        }
+
+        // Validate side (a short token; pairing requires distinct sides)
+        if side.is_empty()
+            || side.len() > MAX_SIDE_LEN
+            || !side
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(RelayError::InvalidHandshake("Invalid side".to_string()));
+        }
 
         Ok((channel_id, side))
     }
 
+    /// Counters for all sessions currently being relayed
+    pub async fn sessions(&self) -> Vec<SessionSnapshot> {
+        let sessions = self.sessions.read().await;
+        sessions.values().map(|s| s.snapshot()).collect()
+    }
+
+    /// Render Prometheus metrics including the current relay state
+    pub async fn render_metrics(&self) -> String {
+        let (pending_channels, pending_connections) = {
+            let pending = self.pending.read().await;
+            (pending.len(), pending.values().map(Vec::len).sum())
+        };
+        let active_bytes = self
+            .sessions()
+            .await
+            .iter()
+            .map(|s| s.bytes_first_to_second + s.bytes_second_to_first)
+            .sum();
+
+        self.metrics.render(RelayGauges {
+            pending_channels,
+            pending_connections,
+            active_channels: self.active.read().await.len(),
+            active_bytes,
+        })
+    }
+
     /// Relay data between two streams
+    ///
+    /// Both directions are throttled by their own per-connection bucket and
+    /// the global bucket, and count towards the channel quota.
     async fn relay_streams(
         &self,
-        stream1: TcpStream,
-        stream2: TcpStream,
+        channel_id: &str,
+        first: PendingConnection,
+        second: (TcpStream, String, IpAddr),
     ) -> Result<(), RelayError> {
+        let (stream2, side2, ip2) = second;
+        let stats = Arc::new(SessionStats::new(
+            self.next_id.fetch_add(1, Ordering::Relaxed),
+            channel_id.to_string(),
+            (first.side, first.ip),
+            (side2, ip2),
+        ));
+        self.sessions.write().await.insert(stats.id, stats.clone());
+
         // Use into_split() to get owned halves that can be moved into spawned tasks
-        let (mut read1, mut write1) = stream1.into_split();
-        let (mut read2, mut write2) = stream2.into_split();
+        let (read1, write1) = first.stream.into_split();
+        let (read2, write2) = stream2.into_split();
 
         // Spawn two tasks to copy data in both directions
-        let task1 = tokio::spawn(async move { tokio::io::copy(&mut read1, &mut write2).await });
-
-        let task2 = tokio::spawn(async move { tokio::io::copy(&mut read2, &mut write1).await });
+        let mut task1 = tokio::spawn(pump(
+            read1,
+            write2,
+            Direction::FirstToSecond,
+            stats.clone(),
+            self.limits.clone(),
+            self.global_bucket.clone(),
+        ));
+        let mut task2 = tokio::spawn(pump(
+            read2,
+            write1,
+            Direction::SecondToFirst,
+            stats.clone(),
+            self.limits.clone(),
+            self.global_bucket.clone(),
+        ));
 
         // Wait for either direction to finish
-        tokio::select! {
-            result = task1 => {
-                match result {
-                    Ok(Ok(bytes)) => tracing::debug!("Direction 1 finished, {} bytes", bytes),
-                    Ok(Err(e)) => tracing::debug!("Direction 1 error: {}", e),
-                    Err(e) => tracing::debug!("Direction 1 task error: {}", e),
+        let (result, other) = tokio::select! {
+            result = &mut task1 => (result, task2),
+            result = &mut task2 => (result, task1),
+        };
+
+        let result = match result {
+            Ok(Ok(())) => {
+                // Clean EOF: let the other direction drain (bounded by the idle timeout)
+                match other.await {
+                    Ok(result) => result,
+                    Err(e) => {
+                        tracing::debug!("Relay task error: {}", e);
+                        Ok(())
+                    }
                 }
             }
-            result = task2 => {
-                match result {
-                    Ok(Ok(bytes)) => tracing::debug!("Direction 2 finished, {} bytes", bytes),
-                    Ok(Err(e)) => tracing::debug!("Direction 2 error: {}", e),
-                    Err(e) => tracing::debug!("Direction 2 task error: {}", e),
+            Ok(Err(e)) => {
+                other.abort();
+                Err(e)
+            }
+            Err(e) => {
+                tracing::debug!("Relay task error: {}", e);
+                other.abort();
+                Ok(())
+            }
+        };
+
+        self.sessions.write().await.remove(&stats.id);
+        self.metrics.channel_closed(stats.total_bytes());
+
+        let snapshot = stats.snapshot();
+        tracing::info!(
+            session = snapshot.id,
+            channel = %snapshot.channel_id,
+            first_side = %snapshot.first_side,
+            first_ip = %snapshot.first_ip,
+            second_side = %snapshot.second_side,
+            second_ip = %snapshot.second_ip,
+            started_at = snapshot.started_at,
+            duration_ms = snapshot.duration.as_millis() as u64,
+            bytes_first_to_second = snapshot.bytes_first_to_second,
+            bytes_second_to_first = snapshot.bytes_second_to_first,
+            "Relay connection closed"
+        );
+
+        result
+    }
+}
+
+/// Copy one direction of a relayed channel, enforcing rate limits and the quota
+async fn pump(
+    mut reader: OwnedReadHalf,
+    mut writer: OwnedWriteHalf,
+    direction: Direction,
+    stats: Arc<SessionStats>,
+    limits: Arc<RelayLimits>,
+    global_bucket: Arc<TokenBucket>,
+) -> Result<(), RelayError> {
+    let bucket = TokenBucket::new(limits.connection_rate_limit);
+    let mut buf = vec![0u8; RELAY_BUFFER_SIZE];
+
+    loop {
+        let read = reader.read(&mut buf);
+        let n = if limits.idle_timeout.is_zero() {
+            read.await?
+        } else {
+            match timeout(limits.idle_timeout, read).await {
+                Ok(n) => n?,
+                Err(_) => {
+                    // The channel is only idle if the other direction is quiet too
+                    if stats.idle_for() >= limits.idle_timeout {
+                        tracing::info!("Closing idle relay channel {}", stats.channel_id);
+                        return Err(RelayError::IdleTimeout);
+                    }
+                    continue;
                 }
             }
+        };
+
+        if n == 0 {
+            let _ = writer.shutdown().await;
+            return Ok(());
         }
 
-        tracing::info!("Relay connection closed");
-        Ok(())
+        if limits.channel_quota > 0 && stats.total_bytes() + n as u64 > limits.channel_quota {
+            tracing::warn!("Channel {} exceeded its byte quota", stats.channel_id);
+            return Err(RelayError::QuotaExceeded);
+        }
+
+        bucket.consume(n as u64).await;
+        global_bucket.consume(n as u64).await;
+
+        writer.write_all(&buf[..n]).await?;
+        stats.record(direction, n as u64);
     }
 }
 
+/// Check that a string only contains hex digits
+fn is_hex(s: &str) -> bool {
+    s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Send an error line to the client and close the connection
+async fn reject(stream: &mut TcpStream, reason: &str) {
+    let _ = stream
+        .write_all(format!("error: {}\n", reason).as_bytes())
+        .await;
+    let _ = stream.shutdown().await;
+}
+
 impl Default for RelayServer {
     fn default() -> Self {
         Self::new()
@@ -195,6 +623,207 @@ impl Default for RelayServer {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::net::SocketAddr;
+    use tokio::net::TcpListener;
+
+    const CHANNEL_A: &str = "0123456789abcdef0123456789abcdef";
+    const CHANNEL_B: &str = "fedcba9876543210fedcba9876543210";
+
+    fn test_limits() -> RelayLimits {
+        RelayLimits {
+            handshake_timeout: Duration::from_secs(5),
+            pending_timeout: Duration::from_secs(5),
+            max_pending_per_ip: 16,
+            max_pending_total: 16,
+            max_candidates_per_channel: 4,
+            connection_rate_limit: 0,
+            global_rate_limit: 0,
+            channel_quota: 0,
+            idle_timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Start a relay on an ephemeral port
+    async fn start_relay(limits: RelayLimits) -> (SocketAddr, RelayServer) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let relay = RelayServer::with_limits(limits);
+
+        let server = relay.clone();
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                let relay = server.clone();
+                tokio::spawn(async move {
+                    let _ = relay.handle_connection(socket).await;
+                });
+            }
+        });
+
+        (addr, relay)
+    }
+
+    /// Connect, send a handshake and return the stream with the relay's first reply
+    async fn connect(addr: SocketAddr, channel: &str, side: &str) -> (TcpStream, String) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(format!("please relay {} for {}\n", channel, side).as_bytes())
+            .await
+            .unwrap();
+        let line = read_reply(&mut stream).await;
+        (stream, line)
+    }
+
+    async fn read_reply(stream: &mut TcpStream) -> String {
+        let mut buf = Vec::new();
+        let mut byte = [0u8; 1];
+        while stream.read(&mut byte).await.unwrap() == 1 {
+            buf.push(byte[0]);
+            if byte[0] == b'\n' {
+                break;
+            }
+        }
+        String::from_utf8(buf).unwrap()
+    }
+
+    async fn pending_count(relay: &RelayServer) -> usize {
+        relay.pending.read().await.values().map(Vec::len).sum()
+    }
+
+    #[tokio::test]
+    async fn test_pairing_relays_data() {
+        let (addr, relay) = start_relay(test_limits()).await;
+
+        let (mut sender, reply) = connect(addr, CHANNEL_A, "sender").await;
+        assert_eq!(reply, "ok\n");
+        let (mut receiver, reply) = connect(addr, CHANNEL_A, "receiver").await;
+        assert_eq!(reply, "ok\n");
+
+        sender.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        receiver.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        assert_eq!(pending_count(&relay).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_pending_connection_is_reaped() {
+        let limits = RelayLimits {
+            pending_timeout: Duration::from_millis(100),
+            ..test_limits()
+        };
+        let (addr, relay) = start_relay(limits).await;
+
+        let (mut stream, reply) = connect(addr, CHANNEL_A, "sender").await;
+        assert_eq!(reply, "ok\n");
+        assert_eq!(pending_count(&relay).await, 1);
+
+        let reply = read_reply(&mut stream).await;
+        assert_eq!(reply, "error: timed out waiting for peer\n");
+        // Socket is closed after the error line
+        let mut buf = [0u8; 1];
+        assert_eq!(stream.read(&mut buf).await.unwrap(), 0);
+        assert_eq!(pending_count(&relay).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_timeout() {
+        let limits = RelayLimits {
+            handshake_timeout: Duration::from_millis(100),
+            ..test_limits()
+        };
+        let (addr, _relay) = start_relay(limits).await;
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let reply = read_reply(&mut stream).await;
+        assert_eq!(reply, "error: handshake timed out\n");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_handshakes_respect_limits() {
+        let limits = RelayLimits {
+            max_pending_total: 3,
+            max_candidates_per_channel: 2,
+            ..test_limits()
+        };
+        let (addr, relay) = start_relay(limits).await;
+
+        // Eight senders on one channel, then eight on their own channels
+        let channels: Vec<String> = std::iter::repeat_n(CHANNEL_A.to_string(), 8)
+            .chain((0..8).map(|i| format!("{:032x}", i)))
+            .collect();
+        for round in channels.chunks(8) {
+            let mut handshakes = tokio::task::JoinSet::new();
+            for channel in round {
+                let channel = channel.clone();
+                handshakes.spawn(async move {
+                    let (mut stream, reply) = connect(addr, &channel, "sender").await;
+                    if reply == "ok\n" {
+                        // Refused after the ok if the limits filled up meanwhile
+                        let _ = timeout(Duration::from_millis(200), read_reply(&mut stream)).await;
+                    }
+                    stream
+                });
+            }
+            let mut streams = Vec::new();
+            while let Some(stream) = handshakes.join_next().await {
+                streams.push(stream.unwrap());
+            }
+
+            let pending = relay.pending.read().await;
+            assert!(pending.get(CHANNEL_A).map_or(0, Vec::len) <= 2);
+            assert!(pending.values().map(Vec::len).sum::<usize>() <= 3);
+        }
+        assert_eq!(pending_count(&relay).await, 3);
+    }
+
+    #[tokio::test]
+    async fn test_per_ip_pending_limit() {
+        let limits = RelayLimits {
+            max_pending_per_ip: 1,
+            ..test_limits()
+        };
+        let (addr, relay) = start_relay(limits).await;
+
+        let (_first, reply) = connect(addr, CHANNEL_A, "sender").await;
+        assert_eq!(reply, "ok\n");
+
+        let (_second, reply) = connect(addr, CHANNEL_B, "sender").await;
+        assert_eq!(
+            reply,
+            "error: too many pending connections from your address\n"
+        );
+        assert_eq!(pending_count(&relay).await, 1);
+
+        // Completing a pending channel is still allowed at the limit
+        let (_peer, reply) = connect(addr, CHANNEL_A, "receiver").await;
+        assert_eq!(reply, "ok\n");
+    }
+
+    #[tokio::test]
+    async fn test_global_pending_limit() {
+        let limits = RelayLimits {
+            max_pending_total: 1,
+            ..test_limits()
+        };
+        let (addr, relay) = start_relay(limits).await;
+
+        let (_first, reply) = connect(addr, CHANNEL_A, "sender").await;
+        assert_eq!(reply, "ok\n");
+
+        let (_second, reply) = connect(addr, CHANNEL_B, "sender").await;
+        assert_eq!(reply, "error: too many pending connections\n");
+        assert_eq!(pending_count(&relay).await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_bad_handshake_is_rejected() {
+        let (addr, _relay) = start_relay(test_limits()).await;
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"hello\n").await.unwrap();
+        assert_eq!(read_reply(&mut stream).await, "error: bad handshake\n");
+    }
 
     #[test]
     fn test_parse_handshake() {
@@ -212,5 +841,298 @@ mod tests {
         assert!(relay
             .parse_handshake("please relay short for sender")
             .is_err());
+
+        // Non-hex channel
+        assert!(relay
+            .parse_handshake("please relay zzzz456789abcdef0123456789abcdef for sender")
+            .is_err());
+
+        // Invalid sides
+        assert!(relay
+            .parse_handshake("please relay 0123456789abcdef0123456789abcdef for ")
+            .is_err());
+        assert!(relay
+            .parse_handshake("please relay 0123456789abcdef0123456789abcdef for a b")
+            .is_err());
+        assert!(relay
+            .parse_handshake(&format!(
+                "please relay 0123456789abcdef0123456789abcdef for {}",
+                "a".repeat(MAX_SIDE_LEN + 1)
+            ))
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_same_side_is_not_paired() {
+        let (addr, relay) = start_relay(test_limits()).await;
+
+        let (mut first, reply) = connect(addr, CHANNEL_A, "sender").await;
+        assert_eq!(reply, "ok\n");
+        let (mut second, reply) = connect(addr, CHANNEL_A, "sender").await;
+        assert_eq!(reply, "ok\n");
+        assert_eq!(pending_count(&relay).await, 2);
+
+        // A different side pairs with the oldest candidate
+        let (mut receiver, reply) = connect(addr, CHANNEL_A, "receiver").await;
+        assert_eq!(reply, "ok\n");
+
+        first.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        receiver.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        // The other candidate is closed as redundant
+        assert_eq!(
+            read_reply(&mut second).await,
+            "error: redundant connection\n"
+        );
+        assert_eq!(pending_count(&relay).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_excess_candidates_rejected() {
+        let limits = RelayLimits {
+            max_candidates_per_channel: 2,
+            ..test_limits()
+        };
+        let (addr, relay) = start_relay(limits).await;
+
+        let (_first, reply) = connect(addr, CHANNEL_A, "sender").await;
+        assert_eq!(reply, "ok\n");
+        let (_second, reply) = connect(addr, CHANNEL_A, "sender").await;
+        assert_eq!(reply, "ok\n");
+
+        let (_third, reply) = connect(addr, CHANNEL_A, "sender").await;
+        assert_eq!(reply, "error: too many connections for channel\n");
+        assert_eq!(pending_count(&relay).await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_paired_channel_rejects_duplicates() {
+        let (addr, _relay) = start_relay(test_limits()).await;
+
+        let (_sender, reply) = connect(addr, CHANNEL_A, "sender").await;
+        assert_eq!(reply, "ok\n");
+        let (_receiver, reply) = connect(addr, CHANNEL_A, "receiver").await;
+        assert_eq!(reply, "ok\n");
+
+        for side in ["sender", "receiver", "other"] {
+            let (_extra, reply) = connect(addr, CHANNEL_A, side).await;
+            assert_eq!(reply, "error: channel already in use\n");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_channel_reusable_after_relay_ends() {
+        let (addr, relay) = start_relay(test_limits()).await;
+
+        let (sender, _) = connect(addr, CHANNEL_A, "sender").await;
+        let (receiver, _) = connect(addr, CHANNEL_A, "receiver").await;
+        drop(sender);
+        drop(receiver);
+
+        // Wait for the relay to notice the closed sockets
+        for _ in 0..50 {
+            if relay.active.read().await.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(relay.active.read().await.is_empty());
+
+        let (_again, reply) = connect(addr, CHANNEL_A, "sender").await;
+        assert_eq!(reply, "ok\n");
+    }
+
+    /// Pair two connections on a channel and return (sender, receiver)
+    async fn pair(addr: SocketAddr) -> (TcpStream, TcpStream) {
+        let (sender, reply) = connect(addr, CHANNEL_A, "sender").await;
+        assert_eq!(reply, "ok\n");
+        let (receiver, reply) = connect(addr, CHANNEL_A, "receiver").await;
+        assert_eq!(reply, "ok\n");
+        (sender, receiver)
+    }
+
+    /// Send `len` bytes from one end and time how long the other takes to read them
+    async fn timed_transfer(
+        sender: &mut TcpStream,
+        receiver: &mut TcpStream,
+        len: usize,
+    ) -> Duration {
+        let start = Instant::now();
+        let data = vec![0x42u8; len];
+        let (write, read) = tokio::join!(sender.write_all(&data), async {
+            let mut buf = vec![0u8; len];
+            receiver.read_exact(&mut buf).await.map(|_| buf)
+        });
+        write.unwrap();
+        assert_eq!(read.unwrap(), data);
+        start.elapsed()
+    }
+
+    #[tokio::test]
+    async fn test_connection_rate_limit() {
+        let limits = RelayLimits {
+            connection_rate_limit: 8 * 1024,
+            ..test_limits()
+        };
+        let (addr, _relay) = start_relay(limits).await;
+        let (mut sender, mut receiver) = pair(addr).await;
+
+        // One second of burst is free, the second 8 KiB has to wait
+        let elapsed = timed_transfer(&mut sender, &mut receiver, 16 * 1024).await;
+        assert!(elapsed >= Duration::from_millis(800), "{:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn test_global_rate_limit() {
+        let limits = RelayLimits {
+            global_rate_limit: 8 * 1024,
+            ..test_limits()
+        };
+        let (addr, _relay) = start_relay(limits).await;
+        let (mut sender, mut receiver) = pair(addr).await;
+
+        let elapsed = timed_transfer(&mut sender, &mut receiver, 16 * 1024).await;
+        assert!(elapsed >= Duration::from_millis(800), "{:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn test_unlimited_transfer_is_fast() {
+        let (addr, _relay) = start_relay(test_limits()).await;
+        let (mut sender, mut receiver) = pair(addr).await;
+
+        let elapsed = timed_transfer(&mut sender, &mut receiver, 256 * 1024).await;
+        assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn test_channel_quota() {
+        let limits = RelayLimits {
+            channel_quota: 1024,
+            ..test_limits()
+        };
+        let (addr, _relay) = start_relay(limits).await;
+        let (mut sender, mut receiver) = pair(addr).await;
+
+        sender.write_all(&[0u8; 1000]).await.unwrap();
+        let mut buf = [0u8; 1000];
+        receiver.read_exact(&mut buf).await.unwrap();
+
+        // This write crosses the quota, so the channel is closed
+        let _ = sender.write_all(&[0u8; 1000]).await;
+        let mut rest = Vec::new();
+        let _ = receiver.read_to_end(&mut rest).await;
+        assert!(rest.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout() {
+        let limits = RelayLimits {
+            idle_timeout: Duration::from_millis(100),
+            ..test_limits()
+        };
+        let (addr, relay) = start_relay(limits).await;
+        let (mut sender, mut receiver) = pair(addr).await;
+
+        let mut buf = [0u8; 1];
+        let read = timeout(Duration::from_secs(2), receiver.read(&mut buf)).await;
+        assert_eq!(read.unwrap().unwrap(), 0);
+        let read = timeout(Duration::from_secs(2), sender.read(&mut buf)).await;
+        assert_eq!(read.unwrap().unwrap(), 0);
+        assert!(relay.sessions().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_one_way_traffic_is_not_idle() {
+        let limits = RelayLimits {
+            idle_timeout: Duration::from_millis(200),
+            ..test_limits()
+        };
+        let (addr, _relay) = start_relay(limits).await;
+        let (mut sender, mut receiver) = pair(addr).await;
+
+        // The receiver never writes, but the channel stays open while the sender does
+        for _ in 0..5 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            sender.write_all(b"x").await.unwrap();
+            let mut buf = [0u8; 1];
+            receiver.read_exact(&mut buf).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_zero_idle_timeout_never_closes() {
+        let limits = RelayLimits {
+            idle_timeout: Duration::ZERO,
+            ..test_limits()
+        };
+        let (addr, relay) = start_relay(limits).await;
+        let (mut sender, mut receiver) = pair(addr).await;
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        sender.write_all(b"x").await.unwrap();
+        let mut buf = [0u8; 1];
+        receiver.read_exact(&mut buf).await.unwrap();
+        assert_eq!(relay.sessions().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_session_counters() {
+        let (addr, relay) = start_relay(test_limits()).await;
+        let (mut sender, mut receiver) = pair(addr).await;
+
+        timed_transfer(&mut sender, &mut receiver, 1000).await;
+        timed_transfer(&mut receiver, &mut sender, 10).await;
+
+        let sessions = relay.sessions().await;
+        assert_eq!(sessions.len(), 1);
+        let session = &sessions[0];
+        assert_eq!(session.channel_id, CHANNEL_A);
+        assert_eq!(session.first_side, "sender");
+        assert_eq!(session.second_side, "receiver");
+        assert_eq!(session.bytes_first_to_second, 1000);
+        assert_eq!(session.bytes_second_to_first, 10);
+
+        drop(sender);
+        drop(receiver);
+        for _ in 0..50 {
+            if relay.sessions().await.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(relay.sessions().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_metrics_track_pairing_and_failures() {
+        let (addr, relay) = start_relay(test_limits()).await;
+
+        let mut bad = TcpStream::connect(addr).await.unwrap();
+        bad.write_all(b"hello\n").await.unwrap();
+        assert!(read_reply(&mut bad).await.starts_with("error:"));
+
+        let (mut sender, _) = connect(addr, CHANNEL_A, "sender").await;
+        let (mut receiver, _) = connect(addr, CHANNEL_A, "receiver").await;
+        sender.write_all(b"12345").await.unwrap();
+        let mut buf = [0u8; 5];
+        receiver.read_exact(&mut buf).await.unwrap();
+
+        // Bytes are counted just after they are written to the peer
+        let mut text = relay.render_metrics().await;
+        for _ in 0..50 {
+            if text.contains("securebeam_relay_bytes_total 5\n") {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            text = relay.render_metrics().await;
+        }
+        assert!(text.contains("securebeam_relay_connections_total 3\n"));
+        assert!(text.contains("securebeam_relay_active_channels 1\n"));
+        assert!(text.contains("securebeam_relay_channels_paired_total 1\n"));
+        assert!(text.contains("securebeam_relay_bytes_total 5\n"));
+        assert!(text
+            .contains("securebeam_relay_handshake_failures_total{reason=\"bad_handshake\"} 1\n"));
     }
 }
@@ -0,0 +1,231 @@
+//! Message fan-out between clustered server replicas
+//!
+//! Clients of one mailbox may be connected to different replicas. Every
+//! broadcast is delivered to local clients and published on a shared
+//! Redis channel; each replica subscribes to that channel and delivers
+//! messages published by the other replicas to its own clients.
+
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::models::AppState;
+use crate::resp::{Client, Connection, RespError, Value};
+
+/// Pub/sub channel carrying mailbox broadcasts
+const CHANNEL: &str = "securebeam:mailbox-events";
+
+/// Delay before resubscribing after the subscription connection drops
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+
+/// A broadcast published by one replica
+#[derive(Debug, Serialize, Deserialize)]
+struct MailboxEvent {
+    /// Replica that published the event
+    node: Uuid,
+    mailbox: String,
+    sender_side: String,
+    message: String,
+}
+
+/// Connection of this replica to the cluster
+pub struct ClusterBus {
+    node_id: Uuid,
+    client: Arc<Client>,
+}
+
+impl ClusterBus {
+    pub fn new(client: Arc<Client>) -> Self {
+        Self {
+            node_id: Uuid::new_v4(),
+            client,
+        }
+    }
+
+    /// Publish a mailbox broadcast to the other replicas
+    pub async fn publish(
+        &self,
+        mailbox_id: &str,
+        sender_side: &str,
+        message: &str,
+    ) -> Result<(), RespError> {
+        let event = serde_json::to_string(&MailboxEvent {
+            node: self.node_id,
+            mailbox: mailbox_id.to_string(),
+            sender_side: sender_side.to_string(),
+            message: message.to_string(),
+        })
+        .expect("mailbox events always serialize");
+
+        self.client
+            .command(&[b"PUBLISH", CHANNEL.as_bytes(), event.as_bytes()])
+            .await?;
+        Ok(())
+    }
+
+    /// Subscribe to broadcasts from the other replicas
+    ///
+    /// Returns once the subscription is active. Messages are delivered to
+    /// `state` until it is dropped; a lost connection is retried.
+    pub async fn subscribe(self: &Arc<Self>, state: Weak<AppState>) -> Result<(), RespError> {
+        let conn = self.open_subscription().await?;
+
+        let bus = self.clone();
+        tokio::spawn(async move {
+            let mut conn = Some(conn);
+            loop {
+                if let Some(conn) = conn.take() {
+                    if !bus.forward(conn, &state).await {
+                        return;
+                    }
+                }
+                tracing::warn!("Cluster subscription lost, reconnecting");
+                tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+
+                match bus.open_subscription().await {
+                    Ok(c) => conn = Some(c),
+                    Err(e) => tracing::warn!("Cluster resubscribe failed: {}", e),
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn open_subscription(&self) -> Result<Connection, RespError> {
+        let mut conn = Connection::connect(self.client.endpoint()).await?;
+        conn.command(&[b"SUBSCRIBE", CHANNEL.as_bytes()]).await?;
+        Ok(conn)
+    }
+
+    /// Deliver events until the connection fails (true) or the state is gone (false)
+    async fn forward(&self, mut conn: Connection, state: &Weak<AppState>) -> bool {
+        loop {
+            let value = match conn.read().await {
+                Ok(value) => value,
+                Err(e) => {
+                    tracing::debug!("Cluster subscription error: {}", e);
+                    return true;
+                }
+            };
+            let Some(state) = state.upgrade() else {
+                return false;
+            };
+
+            let Some(event) = parse_event(value) else {
+                continue;
+            };
+            if event.node == self.node_id {
+                continue;
+            }
+            state
+                .deliver_to_mailbox(&event.mailbox, &event.sender_side, &event.message)
+                .await;
+        }
+    }
+}
+
+/// Extract a mailbox event from a pub/sub push
+fn parse_event(value: Value) -> Option<MailboxEvent> {
+    let mut parts = value.into_strings().ok()?.into_iter();
+    if parts.next()? != "message" {
+        return None;
+    }
+    let _channel = parts.next()?;
+    serde_json::from_str(&parts.next()?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::stub::RedisStub;
+    use crate::storage::RedisStorage;
+    use tokio::sync::mpsc;
+
+    /// Start a replica sharing the stub's storage and pub/sub channel
+    async fn node(stub: &RedisStub) -> Arc<AppState> {
+        let storage = RedisStorage::connect(Arc::new(Client::new(stub.addr())), 300)
+            .await
+            .unwrap();
+        let bus = Arc::new(ClusterBus::new(Arc::new(Client::new(stub.addr()))));
+        let state =
+            Arc::new(AppState::with_storage(Arc::new(storage), 300).with_cluster(bus.clone()));
+        bus.subscribe(Arc::downgrade(&state)).await.unwrap();
+        state
+    }
+
+    /// Connect a bound client with the mailbox open
+    async fn join(
+        state: &AppState,
+        side: &str,
+        mailbox: &str,
+    ) -> (Uuid, mpsc::UnboundedReceiver<String>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let id = state.register_client(tx).await;
+        state
+            .bind_client(id, "app".to_string(), side.to_string())
+            .await;
+        state.open_mailbox(mailbox, side, "app").await.unwrap();
+        state.set_client_mailbox(id, mailbox.to_string()).await;
+        (id, rx)
+    }
+
+    #[tokio::test]
+    async fn test_sender_on_node_a_reaches_receiver_on_node_b() {
+        let stub = RedisStub::start().await;
+        let node_a = node(&stub).await;
+        let node_b = node(&stub).await;
+
+        // The sender allocates on A, the receiver claims the code on B
+        let nameplate = node_a.allocate_nameplate("app").await.unwrap();
+        let mailbox_a = node_a
+            .claim_nameplate(&nameplate, "sender", "app")
+            .await
+            .unwrap();
+        let mailbox_b = node_b
+            .claim_nameplate(&nameplate, "receiver", "app")
+            .await
+            .unwrap();
+        assert_eq!(mailbox_a, mailbox_b);
+
+        let (_, mut sender_rx) = join(&node_a, "sender", &mailbox_a).await;
+        let (_, mut receiver_rx) = join(&node_b, "receiver", &mailbox_b).await;
+
+        let msg = node_a
+            .add_message(&mailbox_a, "sender", "pake", "abcd")
+            .await
+            .unwrap();
+        node_a
+            .broadcast_to_mailbox(&mailbox_a, "sender", "hello from A")
+            .await;
+
+        let received = tokio::time::timeout(Duration::from_secs(2), receiver_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(received, "hello from A");
+
+        // The stored message is visible on B as well
+        let messages = node_b.get_messages(&mailbox_b, "receiver", 0).await;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].id, msg.id);
+
+        // The sender does not get its own broadcast back
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), sender_rx.recv())
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_event_ignores_other_pushes() {
+        let subscribe = Value::Array(Some(vec![
+            Value::Bulk(Some(b"subscribe".to_vec())),
+            Value::Bulk(Some(CHANNEL.as_bytes().to_vec())),
+            Value::Integer(1),
+        ]));
+        assert!(parse_event(subscribe).is_none());
+    }
+}
@@ -1,11 +1,59 @@
 use std::env;
+use std::time::Duration;
+
+use crate::limits::Limits;
+use crate::tls::{TlsError, TlsPaths};
+use crate::welcome::{Welcome, WelcomeSettings};
 
 /// Server configuration
 #[derive(Debug, Clone)]
 pub struct Config {
     pub host: String,
     pub port: u16,
+    /// PEM certificate chain for serving wss:// directly
+    pub tls_cert_path: Option<String>,
+    /// PEM private key for the certificate
+    pub tls_key_path: Option<String>,
     pub session_timeout_secs: u64,
+    /// Whether claiming an unknown nameplate creates it
+    pub allow_claim_create: bool,
+    /// Storage backend for nameplates and mailboxes ("memory", "sqlite" or "redis")
+    pub storage_backend: String,
+    /// Path of the SQLite database when using the sqlite backend
+    pub database_path: String,
+    /// Redis server shared by all replicas when using the redis backend
+    pub redis_url: String,
+    /// Hashcash bits clients must prove before binding (0 = disabled)
+    pub hashcash_bits: u32,
+    /// Message of the day sent in the welcome message
+    pub welcome_motd: Option<String>,
+    /// Error sent in the welcome message, making the server unavailable
+    pub welcome_error: Option<String>,
+    /// JSON file with `motd` and `error`, reloaded when it changes
+    pub welcome_file: Option<String>,
+    /// Bearer token for the admin API (disabled when unset)
+    pub admin_token: Option<String>,
+    /// Take client addresses from X-Forwarded-For / X-Real-IP; only set this
+    /// when every client connects through a proxy that appends its address
+    pub trust_proxy_headers: bool,
+    /// Maximum concurrent connections per IP (0 = unlimited)
+    pub max_connections_per_ip: usize,
+    /// Protocol messages per second per IP (0 = unlimited)
+    pub rate_limit_per_ip: u32,
+    /// Messages per IP allowed in a burst above the rate
+    pub rate_limit_burst: u32,
+    /// Nameplates an IP may allocate per minute (0 = unlimited)
+    pub allocations_per_min: u32,
+    /// Failed claims per IP before claim backoff starts
+    pub claim_failures_allowed: u32,
+    /// First claim backoff in milliseconds, doubled per further failure
+    pub claim_backoff_base_ms: u64,
+    /// Longest claim backoff in seconds
+    pub claim_backoff_max_secs: u64,
+    /// Different peers an IP may share scary mailboxes with before it is banned (0 = never)
+    pub scary_strikes_to_ban: u32,
+    /// Seconds an IP stays banned
+    pub ban_secs: u64,
 }
 
 impl Config {
@@ -13,16 +61,89 @@ impl Config {
     pub fn from_env() -> Self {
         dotenvy::dotenv().ok();
 
+        let defaults = Self::default();
         Self {
             host: env::var("SERVER_HOST").unwrap_or_else(|_| "0.0.0.0".to_string()),
             port: env::var("SERVER_PORT")
                 .ok()
                 .and_then(|p| p.parse().ok())
                 .unwrap_or(3030),
+            tls_cert_path: env::var("TLS_CERT_PATH").ok().filter(|p| !p.is_empty()),
+            tls_key_path: env::var("TLS_KEY_PATH").ok().filter(|p| !p.is_empty()),
             session_timeout_secs: env::var("SESSION_TIMEOUT_SECS")
                 .ok()
                 .and_then(|t| t.parse().ok())
                 .unwrap_or(300), // 5 minutes default
+            allow_claim_create: env::var("ALLOW_CLAIM_CREATE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(false),
+            storage_backend: env::var("STORAGE_BACKEND").unwrap_or_else(|_| "memory".to_string()),
+            database_path: env::var("DATABASE_PATH")
+                .unwrap_or_else(|_| "securebeam.db".to_string()),
+            redis_url: env::var("REDIS_URL")
+                .unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string()),
+            hashcash_bits: env_parse("PERMISSION_HASHCASH_BITS").unwrap_or(defaults.hashcash_bits),
+            welcome_motd: env::var("WELCOME_MOTD").ok().filter(|m| !m.is_empty()),
+            welcome_error: env::var("WELCOME_ERROR").ok().filter(|e| !e.is_empty()),
+            welcome_file: env::var("WELCOME_FILE").ok().filter(|f| !f.is_empty()),
+            admin_token: env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty()),
+            trust_proxy_headers: env_parse("TRUST_PROXY_HEADERS")
+                .unwrap_or(defaults.trust_proxy_headers),
+            max_connections_per_ip: env_parse("MAX_CONNECTIONS_PER_IP")
+                .unwrap_or(defaults.max_connections_per_ip),
+            rate_limit_per_ip: env_parse("RATE_LIMIT_PER_IP").unwrap_or(defaults.rate_limit_per_ip),
+            rate_limit_burst: env_parse("RATE_LIMIT_BURST").unwrap_or(defaults.rate_limit_burst),
+            allocations_per_min: env_parse("ALLOCATIONS_PER_MIN")
+                .unwrap_or(defaults.allocations_per_min),
+            claim_failures_allowed: env_parse("CLAIM_FAILURES_ALLOWED")
+                .unwrap_or(defaults.claim_failures_allowed),
+            claim_backoff_base_ms: env_parse("CLAIM_BACKOFF_BASE_MS")
+                .unwrap_or(defaults.claim_backoff_base_ms),
+            claim_backoff_max_secs: env_parse("CLAIM_BACKOFF_MAX_SECS")
+                .unwrap_or(defaults.claim_backoff_max_secs),
+            scary_strikes_to_ban: env_parse("SCARY_STRIKES_TO_BAN")
+                .unwrap_or(defaults.scary_strikes_to_ban),
+            ban_secs: env_parse("BAN_SECS").unwrap_or(defaults.ban_secs),
+        }
+    }
+
+    /// Get the TLS certificate and key, if TLS is enabled
+    pub fn tls(&self) -> Result<Option<TlsPaths>, TlsError> {
+        match (&self.tls_cert_path, &self.tls_key_path) {
+            (Some(cert), Some(key)) => Ok(Some(TlsPaths {
+                cert: cert.into(),
+                key: key.into(),
+            })),
+            (None, None) => Ok(None),
+            _ => Err(TlsError::Incomplete),
+        }
+    }
+
+    /// Get the welcome message settings
+    pub fn welcome(&self) -> Welcome {
+        let welcome = Welcome::new(WelcomeSettings {
+            motd: self.welcome_motd.clone(),
+            error: self.welcome_error.clone(),
+        });
+        match &self.welcome_file {
+            Some(path) => welcome.with_file(path),
+            None => welcome,
+        }
+    }
+
+    /// Get the per-IP limits for clients
+    pub fn limits(&self) -> Limits {
+        Limits {
+            max_connections_per_ip: self.max_connections_per_ip,
+            messages_per_sec: self.rate_limit_per_ip,
+            message_burst: self.rate_limit_burst,
+            allocations_per_min: self.allocations_per_min,
+            claim_failures_allowed: self.claim_failures_allowed,
+            claim_backoff_base: Duration::from_millis(self.claim_backoff_base_ms),
+            claim_backoff_max: Duration::from_secs(self.claim_backoff_max_secs),
+            scary_strikes_to_ban: self.scary_strikes_to_ban,
+            ban_duration: Duration::from_secs(self.ban_secs),
         }
     }
 }
@@ -32,7 +153,33 @@ impl Default for Config {
         Self {
             host: "0.0.0.0".to_string(),
             port: 3030,
+            tls_cert_path: None,
+            tls_key_path: None,
             session_timeout_secs: 300,
+            allow_claim_create: false,
+            storage_backend: "memory".to_string(),
+            database_path: "securebeam.db".to_string(),
+            redis_url: "redis://127.0.0.1:6379".to_string(),
+            hashcash_bits: 0,
+            welcome_motd: None,
+            welcome_error: None,
+            welcome_file: None,
+            admin_token: None,
+            trust_proxy_headers: false,
+            max_connections_per_ip: 32,
+            rate_limit_per_ip: 20,
+            rate_limit_burst: 60,
+            allocations_per_min: 10,
+            claim_failures_allowed: 3,
+            claim_backoff_base_ms: 1000,
+            claim_backoff_max_secs: 300,
+            scary_strikes_to_ban: 3,
+            ban_secs: 3600,
         }
     }
 }
+
+/// Parse an environment variable, ignoring missing or malformed values
+fn env_parse<T: std::str::FromStr>(key: &str) -> Option<T> {
+    env::var(key).ok().and_then(|v| v.parse().ok())
+}
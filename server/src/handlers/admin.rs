@@ -0,0 +1,129 @@
+//! Admin API
+//!
+//! Requests must carry `Authorization: Bearer <ADMIN_TOKEN>`. Without a
+//! configured token the admin routes do not exist.
+
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::sync::Arc;
+
+use crate::models::AppState;
+use crate::welcome::WelcomeSettings;
+
+/// Close moods and mailbox outcomes since the server started
+pub async fn admin_stats(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response {
+    if let Err(status) = authorize(&state, &headers) {
+        return status.into_response();
+    }
+    Json(state.metrics.mood_stats()).into_response()
+}
+
+/// The current welcome message settings
+pub async fn admin_welcome(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response {
+    if let Err(status) = authorize(&state, &headers) {
+        return status.into_response();
+    }
+    Json(state.welcome.settings()).into_response()
+}
+
+/// Replace the MOTD and error until the welcome file changes
+///
+/// Setting an error puts the server in service-unavailable mode.
+pub async fn set_admin_welcome(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(settings): Json<WelcomeSettings>,
+) -> Response {
+    if let Err(status) = authorize(&state, &headers) {
+        return status.into_response();
+    }
+    state.welcome.set(settings);
+    Json(state.welcome.settings()).into_response()
+}
+
+/// Check the bearer token of an admin request
+fn authorize(state: &AppState, headers: &HeaderMap) -> Result<(), StatusCode> {
+    let expected = state.admin_token.as_deref().ok_or(StatusCode::NOT_FOUND)?;
+    let provided = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    if constant_time_eq(provided.as_bytes(), expected.as_bytes()) {
+        Ok(())
+    } else {
+        Err(StatusCode::UNAUTHORIZED)
+    }
+}
+
+/// Compare secrets without leaking the position of the first difference
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Mood;
+
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let value = format!("Bearer {}", token).parse().unwrap();
+        headers.insert(header::AUTHORIZATION, value);
+        headers
+    }
+
+    #[tokio::test]
+    async fn test_stats_require_token() {
+        let disabled = Arc::new(AppState::new(300));
+        let response = admin_stats(State(disabled), bearer("secret")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let state = Arc::new(AppState::new(300).with_admin_token(Some("secret".to_string())));
+        let response = admin_stats(State(state.clone()), HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = admin_stats(State(state.clone()), bearer("wrong")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        state.metrics.mailbox_closed(Mood::Lonely);
+        let response = admin_stats(State(state), bearer("secret")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let stats: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(stats["closes"]["lonely"], 1);
+        assert_eq!(stats["outcomes"]["happy"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_set_welcome() {
+        let state = Arc::new(AppState::new(300).with_admin_token(Some("secret".to_string())));
+        let settings = WelcomeSettings {
+            motd: None,
+            error: Some("down for maintenance".to_string()),
+        };
+
+        let response = set_admin_welcome(
+            State(state.clone()),
+            bearer("wrong"),
+            Json(settings.clone()),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(state.welcome.error(), None);
+
+        let response =
+            set_admin_welcome(State(state.clone()), bearer("secret"), Json(settings)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            state.welcome.info().error.as_deref(),
+            Some("down for maintenance")
+        );
+    }
+}
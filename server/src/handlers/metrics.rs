@@ -0,0 +1,12 @@
+use axum::{extract::State, http::header, response::IntoResponse};
+use std::sync::Arc;
+
+use crate::models::AppState;
+
+/// Prometheus metrics endpoint
+pub async fn metrics_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.render_metrics().await,
+    )
+}
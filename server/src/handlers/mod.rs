@@ -1,3 +1,7 @@
+mod admin;
 mod health;
+mod metrics;
 
+pub use admin::{admin_stats, admin_welcome, set_admin_welcome};
 pub use health::health_check;
+pub use metrics::metrics_handler;
@@ -0,0 +1,653 @@
+//! Rate limiting and abuse protection for the Mailbox Server
+//!
+//! Clients are tracked by IP address. Each address gets a cap on concurrent
+//! connections, token buckets for protocol messages and for allocated
+//! nameplates, and an exponential backoff after repeated failed claims,
+//! which slows down guessing of nameplates. A mailbox closed with the "scary" mood (failed PAKE) is
+//! refused from then on. A failed PAKE looks the same from both sides, so
+//! every side is blamed alike: an address that keeps ending up in scary
+//! mailboxes with different peers is banned for a while.
+//!
+//! The records live in memory and are local to each server process.
+
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::models::Mood;
+
+/// Limits applied to each client address
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /// Concurrent connections per IP (0 = unlimited)
+    pub max_connections_per_ip: usize,
+    /// Sustained protocol messages per second per IP (0 = unlimited)
+    pub messages_per_sec: u32,
+    /// Messages an IP may send in a burst above the sustained rate
+    pub message_burst: u32,
+    /// Nameplates an IP may allocate per minute, all at once if it likes (0 = unlimited)
+    pub allocations_per_min: u32,
+    /// Failed claims tolerated before backoff starts
+    pub claim_failures_allowed: u32,
+    /// First claim backoff, doubled with every further failure
+    pub claim_backoff_base: Duration,
+    /// Longest claim backoff
+    pub claim_backoff_max: Duration,
+    /// Different peers an IP may share scary mailboxes with before it is banned (0 = never)
+    pub scary_strikes_to_ban: u32,
+    /// How long a ban lasts
+    pub ban_duration: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Config::default().limits()
+    }
+}
+
+/// Why a client request was refused
+///
+/// The messages are sent to clients in `error` responses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum Rejection {
+    #[error("too many connections")]
+    TooManyConnections,
+    #[error("rate limited")]
+    RateLimited,
+    #[error("too many allocations")]
+    TooManyAllocations,
+    #[error("too many failed claims, retry in {0}s")]
+    ClaimBackoff(u64),
+    #[error("banned")]
+    Banned,
+    #[error("scary")]
+    Scary,
+}
+
+/// Tokens refilled at a steady rate up to a burst
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl Bucket {
+    fn full(burst: u32, now: Instant) -> Self {
+        Self {
+            tokens: burst as f64,
+            refilled_at: now,
+        }
+    }
+
+    /// Tokens available at `now`
+    fn level(&self, per_sec: f64, burst: u32, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        (self.tokens + elapsed.as_secs_f64() * per_sec).min(burst.max(1) as f64)
+    }
+
+    /// Take one token, if there is one
+    fn take(&mut self, per_sec: f64, burst: u32, now: Instant) -> bool {
+        self.tokens = self.level(per_sec, burst, now);
+        self.refilled_at = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// What is known about one client address
+#[derive(Debug)]
+struct ClientRecord {
+    connections: usize,
+    messages: Bucket,
+    allocations: Bucket,
+    claim_failures: u32,
+    claim_blocked_until: Option<Instant>,
+    /// Addresses this one shared a scary mailbox with
+    scary_peers: HashSet<IpAddr>,
+    banned_until: Option<Instant>,
+}
+
+impl ClientRecord {
+    fn new(limits: &Limits, now: Instant) -> Self {
+        Self {
+            connections: 0,
+            messages: Bucket::full(limits.message_burst, now),
+            allocations: Bucket::full(limits.allocations_per_min, now),
+            claim_failures: 0,
+            claim_blocked_until: None,
+            scary_peers: HashSet::new(),
+            banned_until: None,
+        }
+    }
+
+    fn check_ban(&self, now: Instant) -> Result<(), Rejection> {
+        match self.banned_until {
+            Some(until) if until > now => Err(Rejection::Banned),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the record holds nothing worth keeping
+    fn is_idle(&self, limits: &Limits, now: Instant) -> bool {
+        let refilled = |bucket: &Bucket, per_sec: f64, burst: u32| {
+            bucket.level(per_sec, burst, now) >= burst as f64
+        };
+        self.connections == 0
+            && refilled(
+                &self.messages,
+                limits.messages_per_sec as f64,
+                limits.message_burst,
+            )
+            && refilled(
+                &self.allocations,
+                limits.allocations_per_min as f64 / 60.0,
+                limits.allocations_per_min,
+            )
+            && self.claim_blocked_until.is_none_or(|until| until <= now)
+            && self.banned_until.is_none_or(|until| until <= now)
+    }
+}
+
+/// Sides that opened a mailbox and where they came from
+#[derive(Debug)]
+struct MailboxRecord {
+    sides: HashMap<String, IpAddr>,
+    scary: bool,
+    opened_at: Instant,
+}
+
+impl MailboxRecord {
+    fn new() -> Self {
+        Self {
+            sides: HashMap::new(),
+            scary: false,
+            opened_at: Instant::now(),
+        }
+    }
+}
+
+/// Per-IP limits and abuse heuristics shared by all connections
+#[derive(Debug)]
+pub struct Limiter {
+    limits: Limits,
+    clients: Mutex<HashMap<IpAddr, ClientRecord>>,
+    mailboxes: Mutex<HashMap<String, MailboxRecord>>,
+}
+
+impl Limiter {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            clients: Mutex::new(HashMap::new()),
+            mailboxes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Admit a new connection from `ip`
+    ///
+    /// The connection counts against the limit until the permit is dropped.
+    pub fn connect(self: &Arc<Self>, ip: IpAddr) -> Result<ConnectionPermit, Rejection> {
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+        let client = clients
+            .entry(ip)
+            .or_insert_with(|| ClientRecord::new(&self.limits, now));
+        client.check_ban(now)?;
+        if self.limits.max_connections_per_ip > 0
+            && client.connections >= self.limits.max_connections_per_ip
+        {
+            return Err(Rejection::TooManyConnections);
+        }
+        client.connections += 1;
+
+        Ok(ConnectionPermit {
+            limiter: self.clone(),
+            ip,
+        })
+    }
+
+    /// Take a token for one protocol message from `ip`
+    pub fn check_message(&self, ip: IpAddr) -> Result<(), Rejection> {
+        self.check_message_at(ip, Instant::now())
+    }
+
+    fn check_message_at(&self, ip: IpAddr, now: Instant) -> Result<(), Rejection> {
+        if self.limits.messages_per_sec == 0 {
+            return Ok(());
+        }
+        let mut clients = self.clients.lock().unwrap();
+        let client = clients
+            .entry(ip)
+            .or_insert_with(|| ClientRecord::new(&self.limits, now));
+
+        let per_sec = self.limits.messages_per_sec as f64;
+        if !client
+            .messages
+            .take(per_sec, self.limits.message_burst, now)
+        {
+            return Err(Rejection::RateLimited);
+        }
+        Ok(())
+    }
+
+    /// Take a token for allocating a nameplate from `ip`
+    ///
+    /// Banned or backed-off addresses may not allocate either.
+    pub fn check_allocate(&self, ip: IpAddr) -> Result<(), Rejection> {
+        self.check_allocate_at(ip, Instant::now())
+    }
+
+    fn check_allocate_at(&self, ip: IpAddr, now: Instant) -> Result<(), Rejection> {
+        self.check_claim_at(ip, now)?;
+        if self.limits.allocations_per_min == 0 {
+            return Ok(());
+        }
+        let mut clients = self.clients.lock().unwrap();
+        let client = clients
+            .entry(ip)
+            .or_insert_with(|| ClientRecord::new(&self.limits, now));
+
+        let per_sec = self.limits.allocations_per_min as f64 / 60.0;
+        if !client
+            .allocations
+            .take(per_sec, self.limits.allocations_per_min, now)
+        {
+            return Err(Rejection::TooManyAllocations);
+        }
+        Ok(())
+    }
+
+    /// Check whether `ip` may claim a nameplate right now
+    pub fn check_claim(&self, ip: IpAddr) -> Result<(), Rejection> {
+        self.check_claim_at(ip, Instant::now())
+    }
+
+    fn check_claim_at(&self, ip: IpAddr, now: Instant) -> Result<(), Rejection> {
+        let clients = self.clients.lock().unwrap();
+        let Some(client) = clients.get(&ip) else {
+            return Ok(());
+        };
+        client.check_ban(now)?;
+        match client.claim_blocked_until {
+            Some(until) if until > now => {
+                let wait = until.duration_since(now);
+                Err(Rejection::ClaimBackoff(wait.as_secs_f64().ceil() as u64))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Record a claim from `ip` that named an unknown or crowded nameplate
+    pub fn claim_failed(&self, ip: IpAddr) {
+        self.claim_failed_at(ip, Instant::now());
+    }
+
+    fn claim_failed_at(&self, ip: IpAddr, now: Instant) {
+        let mut clients = self.clients.lock().unwrap();
+        let client = clients
+            .entry(ip)
+            .or_insert_with(|| ClientRecord::new(&self.limits, now));
+        client.claim_failures += 1;
+
+        let excess = client
+            .claim_failures
+            .saturating_sub(self.limits.claim_failures_allowed);
+        if excess > 0 {
+            let backoff = self
+                .limits
+                .claim_backoff_base
+                .saturating_mul(1 << (excess - 1).min(16))
+                .min(self.limits.claim_backoff_max);
+            client.claim_blocked_until = Some(now + backoff);
+            tracing::warn!(
+                "Claim backoff of {:?} for {} after {} failures",
+                backoff,
+                ip,
+                client.claim_failures
+            );
+        }
+    }
+
+    /// Record a successful claim from `ip`, which resets its backoff
+    pub fn claim_succeeded(&self, ip: IpAddr) {
+        if let Some(client) = self.clients.lock().unwrap().get_mut(&ip) {
+            client.claim_failures = 0;
+            client.claim_blocked_until = None;
+        }
+    }
+
+    /// Check whether `ip` may open a mailbox
+    pub fn check_open(&self, mailbox_id: &str, ip: IpAddr) -> Result<(), Rejection> {
+        if let Some(client) = self.clients.lock().unwrap().get(&ip) {
+            client.check_ban(Instant::now())?;
+        }
+        self.check_mailbox(mailbox_id)
+    }
+
+    /// Remember that `side` at `ip` opened a mailbox
+    pub fn mailbox_opened(&self, mailbox_id: &str, side: &str, ip: IpAddr) {
+        self.mailboxes
+            .lock()
+            .unwrap()
+            .entry(mailbox_id.to_string())
+            .or_insert_with(MailboxRecord::new)
+            .sides
+            .insert(side.to_string(), ip);
+    }
+
+    /// Check whether messages may still be added to a mailbox
+    pub fn check_mailbox(&self, mailbox_id: &str) -> Result<(), Rejection> {
+        match self.mailboxes.lock().unwrap().get(mailbox_id) {
+            Some(mailbox) if mailbox.scary => Err(Rejection::Scary),
+            _ => Ok(()),
+        }
+    }
+
+    /// Take the mood `side` reported when closing a mailbox into account
+    ///
+    /// A scary mood means the key exchange failed: the mailbox is marked so
+    /// it cannot be used again. Either side may be the one guessing, so each
+    /// side notes the addresses of the others, the reporter included; one
+    /// peer alone can never get an address banned. Reports from sides that
+    /// never opened the mailbox are ignored.
+    pub fn report_mood(&self, mailbox_id: &str, side: &str, mood: Mood) {
+        if mood != Mood::Scary {
+            return;
+        }
+        let now = Instant::now();
+
+        let ips: Vec<IpAddr> = {
+            let mut mailboxes = self.mailboxes.lock().unwrap();
+            // Only sides that opened the mailbox may report, and only once
+            let Some(mailbox) = mailboxes.get_mut(mailbox_id) else {
+                return;
+            };
+            if mailbox.scary || !mailbox.sides.contains_key(side) {
+                return;
+            }
+            mailbox.scary = true;
+            mailbox.sides.values().copied().collect()
+        };
+        tracing::warn!("Mailbox {} marked scary by side {}", mailbox_id, side);
+
+        let mut clients = self.clients.lock().unwrap();
+        for &ip in &ips {
+            let client = clients
+                .entry(ip)
+                .or_insert_with(|| ClientRecord::new(&self.limits, now));
+            client
+                .scary_peers
+                .extend(ips.iter().filter(|&&peer| peer != ip));
+            if self.limits.scary_strikes_to_ban > 0
+                && client.scary_peers.len() >= self.limits.scary_strikes_to_ban as usize
+            {
+                client.scary_peers.clear();
+                client.banned_until = Some(now + self.limits.ban_duration);
+                tracing::warn!("Banned {} for {:?}", ip, self.limits.ban_duration);
+            }
+        }
+    }
+
+    /// Forget idle addresses and mailboxes older than `mailbox_timeout`
+    ///
+    /// Scary marks are kept for as long as the mailbox itself can exist.
+    pub fn prune(&self, mailbox_timeout: Duration) {
+        let now = Instant::now();
+        self.clients
+            .lock()
+            .unwrap()
+            .retain(|_, client| !client.is_idle(&self.limits, now));
+        self.mailboxes
+            .lock()
+            .unwrap()
+            .retain(|_, mailbox| now.duration_since(mailbox.opened_at) < mailbox_timeout);
+    }
+
+    fn disconnect(&self, ip: IpAddr) {
+        if let Some(client) = self.clients.lock().unwrap().get_mut(&ip) {
+            client.connections = client.connections.saturating_sub(1);
+        }
+    }
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        Self::new(Limits::default())
+    }
+}
+
+/// A connection slot of one IP, released on drop
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    limiter: Arc<Limiter>,
+    ip: IpAddr,
+}
+
+impl ConnectionPermit {
+    pub fn ip(&self) -> IpAddr {
+        self.ip
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.limiter.disconnect(self.ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 1));
+    const OTHER_IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 2));
+
+    fn limiter(limits: Limits) -> Arc<Limiter> {
+        Arc::new(Limiter::new(limits))
+    }
+
+    #[test]
+    fn test_connection_limit() {
+        let limiter = limiter(Limits {
+            max_connections_per_ip: 2,
+            ..Limits::default()
+        });
+
+        let first = limiter.connect(IP).unwrap();
+        let _second = limiter.connect(IP).unwrap();
+        assert_eq!(
+            limiter.connect(IP).unwrap_err(),
+            Rejection::TooManyConnections
+        );
+        assert!(limiter.connect(OTHER_IP).is_ok());
+
+        drop(first);
+        assert!(limiter.connect(IP).is_ok());
+    }
+
+    #[test]
+    fn test_message_rate_limit() {
+        let limiter = limiter(Limits {
+            messages_per_sec: 10,
+            message_burst: 3,
+            ..Limits::default()
+        });
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            limiter.check_message_at(IP, start).unwrap();
+        }
+        assert_eq!(
+            limiter.check_message_at(IP, start),
+            Err(Rejection::RateLimited)
+        );
+
+        // One token comes back every 100ms
+        let later = start + Duration::from_millis(100);
+        assert!(limiter.check_message_at(IP, later).is_ok());
+        assert!(limiter.check_message_at(IP, later).is_err());
+    }
+
+    #[test]
+    fn test_allocation_limit() {
+        let limiter = limiter(Limits {
+            allocations_per_min: 2,
+            claim_failures_allowed: 0,
+            ..Limits::default()
+        });
+        let start = Instant::now();
+
+        limiter.check_allocate_at(IP, start).unwrap();
+        limiter.check_allocate_at(IP, start).unwrap();
+        assert_eq!(
+            limiter.check_allocate_at(IP, start),
+            Err(Rejection::TooManyAllocations)
+        );
+        assert!(limiter.check_allocate_at(OTHER_IP, start).is_ok());
+
+        // One allocation comes back every 30s
+        let later = start + Duration::from_secs(30);
+        assert!(limiter.check_allocate_at(IP, later).is_ok());
+        assert!(limiter.check_allocate_at(IP, later).is_err());
+
+        // Guessers backing off may not allocate either
+        limiter.claim_failed_at(OTHER_IP, start);
+        assert!(matches!(
+            limiter.check_allocate_at(OTHER_IP, start),
+            Err(Rejection::ClaimBackoff(_))
+        ));
+    }
+
+    #[test]
+    fn test_claim_backoff_grows_and_resets() {
+        let limiter = limiter(Limits {
+            claim_failures_allowed: 2,
+            claim_backoff_base: Duration::from_secs(1),
+            claim_backoff_max: Duration::from_secs(3),
+            ..Limits::default()
+        });
+        let start = Instant::now();
+
+        limiter.claim_failed_at(IP, start);
+        limiter.claim_failed_at(IP, start);
+        assert!(limiter.check_claim_at(IP, start).is_ok());
+
+        limiter.claim_failed_at(IP, start);
+        assert_eq!(
+            limiter.check_claim_at(IP, start),
+            Err(Rejection::ClaimBackoff(1))
+        );
+        limiter.claim_failed_at(IP, start);
+        assert_eq!(
+            limiter.check_claim_at(IP, start),
+            Err(Rejection::ClaimBackoff(2))
+        );
+        limiter.claim_failed_at(IP, start);
+        assert_eq!(
+            limiter.check_claim_at(IP, start),
+            Err(Rejection::ClaimBackoff(3))
+        );
+        assert!(limiter
+            .check_claim_at(IP, start + Duration::from_secs(3))
+            .is_ok());
+        assert!(limiter.check_claim_at(OTHER_IP, start).is_ok());
+
+        limiter.claim_succeeded(IP);
+        limiter.claim_failed_at(IP, start);
+        assert!(limiter.check_claim_at(IP, start).is_ok());
+    }
+
+    #[test]
+    fn test_scary_mailbox_is_refused() {
+        let limiter = limiter(Limits::default());
+        limiter.mailbox_opened("mb", "a", IP);
+        limiter.mailbox_opened("mb", "b", OTHER_IP);
+
+        limiter.report_mood("mb", "a", Mood::Happy);
+        limiter.report_mood("mb", "stranger", Mood::Scary);
+        assert!(limiter.check_mailbox("mb").is_ok());
+
+        limiter.report_mood("mb", "a", Mood::Scary);
+        assert_eq!(limiter.check_mailbox("mb"), Err(Rejection::Scary));
+        assert_eq!(limiter.check_open("mb", IP), Err(Rejection::Scary));
+        assert!(limiter.check_open("other", IP).is_ok());
+    }
+
+    fn ip(n: u8) -> IpAddr {
+        IpAddr::V4(std::net::Ipv4Addr::new(198, 51, 100, n))
+    }
+
+    #[test]
+    fn test_scary_strikes_ban_the_guesser() {
+        let limiter = limiter(Limits {
+            scary_strikes_to_ban: 2,
+            ..Limits::default()
+        });
+
+        // One address failing the key exchange with different peers
+        for (mailbox, honest) in [("mb1", IP), ("mb2", ip(1))] {
+            limiter.mailbox_opened(mailbox, "honest", honest);
+            limiter.mailbox_opened(mailbox, "guesser", OTHER_IP);
+            limiter.report_mood(mailbox, "honest", Mood::Scary);
+            // Repeated reports of the same mailbox do not add strikes
+            limiter.report_mood(mailbox, "honest", Mood::Scary);
+        }
+
+        assert_eq!(limiter.check_claim(OTHER_IP), Err(Rejection::Banned));
+        assert_eq!(limiter.connect(OTHER_IP).unwrap_err(), Rejection::Banned);
+        assert!(limiter.check_claim(IP).is_ok());
+        assert!(limiter.check_claim(ip(1)).is_ok());
+    }
+
+    #[test]
+    fn test_scary_reports_cannot_ban_an_honest_peer() {
+        let limiter = limiter(Limits {
+            scary_strikes_to_ban: 3,
+            ..Limits::default()
+        });
+
+        // A malicious receiver keeps claiming the sender's nameplate,
+        // sending a bad PAKE message and closing as scary
+        for round in 0..10 {
+            let mailbox = format!("mb{}", round);
+            limiter.mailbox_opened(&mailbox, "sender", IP);
+            limiter.mailbox_opened(&mailbox, "attacker", OTHER_IP);
+            limiter.report_mood(&mailbox, "attacker", Mood::Scary);
+        }
+        assert!(limiter.check_claim(IP).is_ok());
+        assert!(limiter.connect(IP).is_ok());
+
+        // Doing so to different senders bans the reporter itself
+        for n in 1..=3 {
+            let mailbox = format!("other{}", n);
+            limiter.mailbox_opened(&mailbox, "sender", ip(n));
+            limiter.mailbox_opened(&mailbox, "attacker", OTHER_IP);
+            limiter.report_mood(&mailbox, "attacker", Mood::Scary);
+        }
+        assert_eq!(limiter.check_claim(OTHER_IP), Err(Rejection::Banned));
+        assert!(limiter.check_claim(IP).is_ok());
+    }
+
+    #[test]
+    fn test_prune_keeps_active_clients() {
+        let limiter = limiter(Limits::default());
+        let _permit = limiter.connect(IP).unwrap();
+        drop(limiter.connect(OTHER_IP).unwrap());
+        limiter.mailbox_opened("mb", "a", IP);
+
+        limiter.prune(Duration::from_secs(300));
+        let clients = limiter.clients.lock().unwrap();
+        assert!(clients.contains_key(&IP));
+        assert!(!clients.contains_key(&OTHER_IP));
+        drop(clients);
+        assert!(limiter.mailboxes.lock().unwrap().contains_key("mb"));
+
+        limiter.prune(Duration::ZERO);
+        assert!(limiter.mailboxes.lock().unwrap().is_empty());
+    }
+}
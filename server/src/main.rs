@@ -2,19 +2,32 @@
 //!
 //! Implements the Magic Wormhole server protocol for P2P file transfer signaling.
 
+mod cluster;
 mod config;
 mod handlers;
+mod limits;
+mod metrics;
 mod models;
+mod permission;
+mod resp;
+mod storage;
+mod tls;
+mod welcome;
 mod ws;
 
 use axum::{routing::get, Router};
+use axum_server::tls_rustls::RustlsConfig;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tower_http::cors::{Any, CorsLayer};
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+use crate::cluster::ClusterBus;
 use crate::config::Config;
-use crate::handlers::health_check;
+use crate::handlers::{
+    admin_stats, admin_welcome, health_check, metrics_handler, set_admin_welcome,
+};
 use crate::models::AppState;
 use crate::ws::{peer_ws_handler, ws_handler, PeerState};
 
@@ -38,11 +51,79 @@ async fn main() {
     );
     tracing::info!("Listening on {}:{}", config.host, config.port);
 
+    // Check the certificate before anything else so mistakes show up early
+    let tls = match config.tls().and_then(|paths| {
+        paths
+            .map(|paths| paths.load().map(|loaded| (paths, loaded)))
+            .transpose()
+    }) {
+        Ok(tls) => tls,
+        Err(e) => {
+            tracing::error!("Failed to load TLS certificate: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Open the storage backend for nameplates and mailboxes
+    let storage = match storage::from_config(&config).await {
+        Ok(storage) => storage,
+        Err(e) => {
+            tracing::error!("Failed to open {} storage: {}", config.storage_backend, e);
+            std::process::exit(1);
+        }
+    };
+    tracing::info!("Using {} storage", config.storage_backend);
+
     // Create shared state for mailbox protocol
-    let state = Arc::new(AppState::new(config.session_timeout_secs));
+    let mut state = AppState::with_storage(storage, config.session_timeout_secs)
+        .with_claim_create(config.allow_claim_create)
+        .with_hashcash(config.hashcash_bits)
+        .with_limits(config.limits())
+        .with_proxy_headers(config.trust_proxy_headers)
+        .with_admin_token(config.admin_token.clone())
+        .with_welcome(config.welcome());
+
+    // With shared storage, fan out broadcasts to the other replicas; the
+    // Redis URL was already checked when opening the storage
+    let redis = (config.storage_backend == "redis")
+        .then(|| resp::parse_url(&config.redis_url).expect("Redis URL is valid"));
+    let cluster = redis.as_ref().map(|endpoint| {
+        let client = resp::Client::new(endpoint.clone());
+        Arc::new(ClusterBus::new(Arc::new(client)))
+    });
+    if let Some(cluster) = &cluster {
+        state = state.with_cluster(cluster.clone());
+    }
+    let state = Arc::new(state);
+
+    if let (Some(cluster), Some(endpoint)) = (cluster, &redis) {
+        if let Err(e) = cluster.subscribe(Arc::downgrade(&state)).await {
+            tracing::error!("Failed to join cluster: {}", e);
+            std::process::exit(1);
+        }
+        // Only the address, so a password in the URL stays out of the logs
+        tracing::info!("Clustering enabled via {}", endpoint.addr);
+    }
+
+    // Watch the welcome file so the MOTD can change without a restart
+    if let Err(e) = state.welcome.reload_if_changed() {
+        tracing::error!("Failed to load welcome file: {}", e);
+    }
+    let welcome_state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(welcome::RELOAD_INTERVAL);
+        loop {
+            interval.tick().await;
+            match welcome_state.welcome.reload_if_changed() {
+                Ok(true) => tracing::info!("Reloaded welcome file"),
+                Ok(false) => {}
+                Err(e) => tracing::error!("Failed to reload welcome file: {}", e),
+            }
+        }
+    });
 
     // Create shared state for simple peer pairing
-    let peer_state = Arc::new(PeerState::new());
+    let peer_state = Arc::new(PeerState::new(state.clone()));
 
     // Spawn cleanup task for expired nameplates and mailboxes
     let cleanup_state = state.clone();
@@ -58,6 +139,11 @@ async fn main() {
     let app = Router::new()
         // Health check endpoint
         .route("/health", get(health_check))
+        // Prometheus metrics endpoint
+        .route("/metrics", get(metrics_handler))
+        // Admin API, enabled by ADMIN_TOKEN
+        .route("/admin/stats", get(admin_stats))
+        .route("/admin/welcome", get(admin_welcome).put(set_admin_welcome))
         // WebSocket endpoint for mailbox protocol (Magic Wormhole compatible)
         .route("/v1", get(ws_handler))
         .with_state(state)
@@ -75,11 +161,36 @@ async fn main() {
 
     // Create listener
     let addr = format!("{}:{}", config.host, config.port);
-    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+    let listener = match tokio::net::TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            tracing::error!("Failed to listen on {}: {}", addr, e);
+            std::process::exit(1);
+        }
+    };
 
-    tracing::info!("Mailbox server ready at ws://{}/v1", addr);
-    tracing::info!("Peer pairing ready at ws://{}/ws/{{code}}", addr);
+    let scheme = if tls.is_some() { "wss" } else { "ws" };
+    tracing::info!("Mailbox server ready at {}://{}/v1", scheme, addr);
+    tracing::info!("Peer pairing ready at {}://{}/ws/{{code}}", scheme, addr);
 
-    // Run server
-    axum::serve(listener, app).await.unwrap();
+    // Run server, keeping peer addresses for the per-IP limits
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+    let result = match tls {
+        Some((paths, loaded)) => {
+            let rustls = RustlsConfig::from_config(loaded);
+            #[cfg(unix)]
+            tokio::spawn(tls::reload_on_sighup(paths, rustls.clone()));
+            #[cfg(not(unix))]
+            let _ = paths;
+            let listener = listener.into_std().unwrap();
+            axum_server::from_tcp_rustls(listener, rustls)
+                .serve(app)
+                .await
+        }
+        None => axum::serve(listener, app).await,
+    };
+    if let Err(e) = result {
+        tracing::error!("Server error: {}", e);
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,482 @@
+//! Prometheus metrics for the Mailbox Server
+//!
+//! Counters are plain atomics updated from `AppState` operations and
+//! rendered in the Prometheus text exposition format on `/metrics`.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+
+use crate::models::Mood;
+
+/// Upper bounds (seconds) of the mailbox lifetime histogram buckets
+const LIFETIME_BUCKETS: [f64; 10] = [
+    1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0,
+];
+
+/// Phase labels used for message counters
+///
+/// Phases are client-chosen strings, so they are folded into a fixed set
+/// of labels to keep the label cardinality bounded.
+const PHASE_LABELS: [&str; 4] = ["pake", "version", "numbered", "other"];
+
+/// Outcome labels of removed mailboxes, from best to worst mood
+///
+/// A mailbox takes the worst mood its sides closed with. "quiet" mailboxes
+/// were closed without any mood and "pruney" ones expired.
+const OUTCOME_LABELS: [&str; 6] = ["happy", "lonely", "errory", "scary", "quiet", "pruney"];
+const QUIET: usize = 4;
+const PRUNEY: usize = 5;
+
+/// Metrics collected by the server
+#[derive(Default)]
+pub struct Metrics {
+    connected_clients: AtomicI64,
+    nameplates_allocated: AtomicU64,
+    nameplates_claimed: AtomicU64,
+    nameplates_expired: AtomicU64,
+    mailboxes_opened: AtomicU64,
+    mailbox_lifetime: Histogram,
+    messages: [AtomicU64; PHASE_LABELS.len()],
+    closes: [AtomicU64; Mood::ALL.len()],
+    outcomes: [AtomicU64; OUTCOME_LABELS.len()],
+    websocket_errors: AtomicU64,
+    protocol_errors: AtomicU64,
+}
+
+/// Close moods and mailbox outcomes, reported on the admin API
+#[derive(Debug, Clone, Serialize)]
+pub struct MoodStats {
+    /// Closes by the mood the side reported
+    pub closes: BTreeMap<&'static str, u64>,
+    /// Removed mailboxes by outcome
+    pub outcomes: BTreeMap<&'static str, u64>,
+}
+
+/// Current sizes of the state maps, sampled at scrape time
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StateGauges {
+    pub nameplates: usize,
+    pub mailboxes: usize,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn client_connected(&self) {
+        self.connected_clients.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn client_disconnected(&self) {
+        self.connected_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn nameplate_allocated(&self) {
+        self.nameplates_allocated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn nameplate_claimed(&self) {
+        self.nameplates_claimed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn nameplates_expired(&self, count: usize) {
+        self.nameplates_expired
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn mailbox_opened(&self) {
+        self.mailboxes_opened.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record the lifetime of a mailbox that was just removed
+    pub fn mailbox_removed(&self, lifetime_secs: f64) {
+        self.mailbox_lifetime.observe(lifetime_secs);
+    }
+
+    /// Count a message added to a mailbox
+    pub fn message_added(&self, phase: &str) {
+        self.messages[phase_index(phase)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a side closing a mailbox with a mood
+    pub fn mailbox_closed(&self, mood: Mood) {
+        self.closes[mood as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record the outcome of a mailbox closed by all its sides
+    pub fn mailbox_outcome(&self, moods: &[Mood]) {
+        let outcome = moods
+            .iter()
+            .filter_map(|m| OUTCOME_LABELS.iter().position(|l| *l == m.as_str()))
+            .max()
+            .unwrap_or(QUIET);
+        self.outcomes[outcome].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record mailboxes removed after expiring
+    pub fn mailboxes_pruned(&self, count: usize) {
+        self.outcomes[PRUNEY].fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    /// Snapshot of close moods and mailbox outcomes
+    pub fn mood_stats(&self) -> MoodStats {
+        MoodStats {
+            closes: Mood::ALL
+                .iter()
+                .map(|m| (m.as_str(), self.closes[*m as usize].load(Ordering::Relaxed)))
+                .collect(),
+            outcomes: OUTCOME_LABELS
+                .iter()
+                .zip(&self.outcomes)
+                .map(|(label, counter)| (*label, counter.load(Ordering::Relaxed)))
+                .collect(),
+        }
+    }
+
+    /// Count a WebSocket transport error
+    pub fn websocket_error(&self) {
+        self.websocket_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a protocol error sent back to a client
+    pub fn protocol_error(&self) {
+        self.protocol_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Render all metrics in the Prometheus text format
+    pub fn render(&self, gauges: StateGauges) -> String {
+        let mut out = String::new();
+
+        write_metric(
+            &mut out,
+            "securebeam_connected_clients",
+            "gauge",
+            "Currently connected WebSocket clients",
+            self.connected_clients.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut out,
+            "securebeam_nameplates_active",
+            "gauge",
+            "Nameplates currently held by the server",
+            gauges.nameplates,
+        );
+        write_metric(
+            &mut out,
+            "securebeam_mailboxes_active",
+            "gauge",
+            "Mailboxes currently held by the server",
+            gauges.mailboxes,
+        );
+        write_metric(
+            &mut out,
+            "securebeam_nameplates_allocated_total",
+            "counter",
+            "Nameplates allocated",
+            self.nameplates_allocated.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut out,
+            "securebeam_nameplates_claimed_total",
+            "counter",
+            "Nameplate claims",
+            self.nameplates_claimed.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut out,
+            "securebeam_nameplates_expired_total",
+            "counter",
+            "Nameplates removed after expiring",
+            self.nameplates_expired.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut out,
+            "securebeam_mailboxes_opened_total",
+            "counter",
+            "Mailbox opens",
+            self.mailboxes_opened.load(Ordering::Relaxed),
+        );
+
+        write_labeled(
+            &mut out,
+            "securebeam_messages_total",
+            "Messages added to mailboxes by phase",
+            "phase",
+            PHASE_LABELS.iter().copied().zip(&self.messages),
+        );
+        write_labeled(
+            &mut out,
+            "securebeam_mailbox_closes_total",
+            "Mailbox closes by reported mood",
+            "mood",
+            Mood::ALL.iter().map(|m| m.as_str()).zip(&self.closes),
+        );
+        write_labeled(
+            &mut out,
+            "securebeam_mailbox_outcomes_total",
+            "Removed mailboxes by outcome",
+            "outcome",
+            OUTCOME_LABELS.iter().copied().zip(&self.outcomes),
+        );
+
+        self.mailbox_lifetime.render(
+            &mut out,
+            "securebeam_mailbox_lifetime_seconds",
+            "Time from mailbox creation to removal",
+        );
+
+        write_metric(
+            &mut out,
+            "securebeam_websocket_errors_total",
+            "counter",
+            "WebSocket transport errors",
+            self.websocket_errors.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut out,
+            "securebeam_protocol_errors_total",
+            "counter",
+            "Error responses sent to clients",
+            self.protocol_errors.load(Ordering::Relaxed),
+        );
+
+        out
+    }
+}
+
+/// Map a phase name to its label index
+fn phase_index(phase: &str) -> usize {
+    match phase {
+        "pake" => 0,
+        "version" => 1,
+        p if !p.is_empty() && p.bytes().all(|b| b.is_ascii_digit()) => 2,
+        _ => 3,
+    }
+}
+
+/// Write a single-value metric with its HELP and TYPE lines
+fn write_metric(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    value: impl std::fmt::Display,
+) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// Write a counter with one value per label
+fn write_labeled<'a>(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    label: &str,
+    values: impl Iterator<Item = (&'a str, &'a AtomicU64)>,
+) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    for (value, counter) in values {
+        let _ = writeln!(
+            out,
+            "{}{{{}=\"{}\"}} {}",
+            name,
+            label,
+            value,
+            counter.load(Ordering::Relaxed)
+        );
+    }
+}
+
+/// Fixed-bucket histogram
+#[derive(Default)]
+struct Histogram {
+    buckets: [AtomicU64; LIFETIME_BUCKETS.len()],
+    count: AtomicU64,
+    /// Sum of observations in milliseconds
+    sum_ms: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, value_secs: f64) {
+        let value_secs = value_secs.max(0.0);
+        if let Some(i) = LIFETIME_BUCKETS.iter().position(|b| value_secs <= *b) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_ms
+            .fetch_add((value_secs * 1000.0) as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+
+        // Prometheus buckets are cumulative
+        let mut cumulative = 0;
+        for (bound, bucket) in LIFETIME_BUCKETS.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(
+            out,
+            "{}_sum {}",
+            name,
+            self.sum_ms.load(Ordering::Relaxed) as f64 / 1000.0
+        );
+        let _ = writeln!(out, "{}_count {}", name, count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_counters() {
+        let metrics = Metrics::new();
+        metrics.client_connected();
+        metrics.client_connected();
+        metrics.client_disconnected();
+        metrics.nameplate_allocated();
+        metrics.nameplate_claimed();
+        metrics.nameplate_claimed();
+        metrics.nameplates_expired(3);
+        metrics.websocket_error();
+
+        let text = metrics.render(StateGauges {
+            nameplates: 4,
+            mailboxes: 5,
+        });
+        assert!(text.contains("# TYPE securebeam_connected_clients gauge"));
+        assert!(text.contains("securebeam_connected_clients 1\n"));
+        assert!(text.contains("securebeam_nameplates_active 4\n"));
+        assert!(text.contains("securebeam_mailboxes_active 5\n"));
+        assert!(text.contains("securebeam_nameplates_allocated_total 1\n"));
+        assert!(text.contains("securebeam_nameplates_claimed_total 2\n"));
+        assert!(text.contains("securebeam_nameplates_expired_total 3\n"));
+        assert!(text.contains("securebeam_websocket_errors_total 1\n"));
+    }
+
+    #[test]
+    fn test_message_phase_labels() {
+        let metrics = Metrics::new();
+        metrics.message_added("pake");
+        metrics.message_added("version");
+        metrics.message_added("0");
+        metrics.message_added("17");
+        metrics.message_added("attacker-chosen-phase");
+
+        let text = metrics.render(StateGauges::default());
+        assert!(text.contains("securebeam_messages_total{phase=\"pake\"} 1\n"));
+        assert!(text.contains("securebeam_messages_total{phase=\"version\"} 1\n"));
+        assert!(text.contains("securebeam_messages_total{phase=\"numbered\"} 2\n"));
+        assert!(text.contains("securebeam_messages_total{phase=\"other\"} 1\n"));
+        assert!(!text.contains("attacker"));
+    }
+
+    #[test]
+    fn test_lifetime_histogram_is_cumulative() {
+        let metrics = Metrics::new();
+        metrics.mailbox_removed(0.5);
+        metrics.mailbox_removed(45.0);
+        metrics.mailbox_removed(7200.0);
+
+        let text = metrics.render(StateGauges::default());
+        assert!(text.contains("securebeam_mailbox_lifetime_seconds_bucket{le=\"1\"} 1\n"));
+        assert!(text.contains("securebeam_mailbox_lifetime_seconds_bucket{le=\"30\"} 1\n"));
+        assert!(text.contains("securebeam_mailbox_lifetime_seconds_bucket{le=\"60\"} 2\n"));
+        assert!(text.contains("securebeam_mailbox_lifetime_seconds_bucket{le=\"3600\"} 2\n"));
+        assert!(text.contains("securebeam_mailbox_lifetime_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(text.contains("securebeam_mailbox_lifetime_seconds_count 3\n"));
+        assert!(text.contains("securebeam_mailbox_lifetime_seconds_sum 7245.5\n"));
+    }
+
+    #[test]
+    fn test_mailbox_outcomes() {
+        let metrics = Metrics::new();
+        metrics.mailbox_closed(Mood::Happy);
+        metrics.mailbox_closed(Mood::Scary);
+        metrics.mailbox_outcome(&[Mood::Happy, Mood::Scary]);
+        metrics.mailbox_outcome(&[Mood::Happy, Mood::Happy]);
+        metrics.mailbox_outcome(&[Mood::Lonely]);
+        metrics.mailbox_outcome(&[]);
+        metrics.mailboxes_pruned(2);
+
+        let stats = metrics.mood_stats();
+        assert_eq!(stats.closes["happy"], 1);
+        assert_eq!(stats.closes["scary"], 1);
+        assert_eq!(stats.closes["errory"], 0);
+        assert_eq!(stats.outcomes["scary"], 1);
+        assert_eq!(stats.outcomes["happy"], 1);
+        assert_eq!(stats.outcomes["lonely"], 1);
+        assert_eq!(stats.outcomes["quiet"], 1);
+        assert_eq!(stats.outcomes["pruney"], 2);
+
+        let text = metrics.render(StateGauges::default());
+        assert!(text.contains("securebeam_mailbox_closes_total{mood=\"happy\"} 1\n"));
+        assert!(text.contains("securebeam_mailbox_outcomes_total{outcome=\"pruney\"} 2\n"));
+    }
+
+    #[tokio::test]
+    async fn test_close_mood_counted_only_for_open_sides() {
+        use crate::models::AppState;
+
+        let state = AppState::new(300);
+        let nameplate = state.allocate_nameplate("app").await.unwrap();
+        let mailbox = state.claim_nameplate(&nameplate, "a", "app").await.unwrap();
+        state.open_mailbox(&mailbox, "a", "app").await.unwrap();
+        state.open_mailbox(&mailbox, "b", "app").await.unwrap();
+
+        // Repeated closes and closes by sides that never opened are ignored
+        state.close_mailbox(&mailbox, "c", Some(Mood::Scary)).await;
+        state.close_mailbox(&mailbox, "a", Some(Mood::Happy)).await;
+        state.close_mailbox(&mailbox, "a", Some(Mood::Scary)).await;
+        state.close_mailbox(&mailbox, "b", Some(Mood::Happy)).await;
+        state.close_mailbox(&mailbox, "b", Some(Mood::Scary)).await;
+
+        let stats = state.metrics.mood_stats();
+        assert_eq!(stats.closes["happy"], 2);
+        assert_eq!(stats.closes["scary"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_state_operations_feed_metrics() {
+        use crate::models::AppState;
+
+        let state = AppState::new(300);
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let client = state.register_client(tx).await;
+
+        let nameplate = state.allocate_nameplate("app").await.unwrap();
+        let mailbox = state.claim_nameplate(&nameplate, "a", "app").await.unwrap();
+        state.open_mailbox(&mailbox, "a", "app").await.unwrap();
+        state
+            .add_message(&mailbox, "a", "pake", "00")
+            .await
+            .unwrap();
+        state.add_message(&mailbox, "a", "0", "00").await.unwrap();
+        state.close_mailbox(&mailbox, "a", Some(Mood::Happy)).await;
+
+        let text = state.render_metrics().await;
+        assert!(text.contains("securebeam_connected_clients 1\n"));
+        assert!(text.contains("securebeam_nameplates_active 1\n"));
+        assert!(text.contains("securebeam_nameplates_allocated_total 1\n"));
+        assert!(text.contains("securebeam_nameplates_claimed_total 1\n"));
+        assert!(text.contains("securebeam_mailboxes_opened_total 1\n"));
+        assert!(text.contains("securebeam_messages_total{phase=\"pake\"} 1\n"));
+        assert!(text.contains("securebeam_messages_total{phase=\"numbered\"} 1\n"));
+        assert!(text.contains("securebeam_mailbox_lifetime_seconds_count 1\n"));
+        assert!(text.contains("securebeam_mailbox_outcomes_total{outcome=\"happy\"} 1\n"));
+
+        state.unregister_client(client).await;
+        let text = state.render_metrics().await;
+        assert!(text.contains("securebeam_connected_clients 0\n"));
+    }
+}
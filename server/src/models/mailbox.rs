@@ -9,6 +9,8 @@ use chrono::{DateTime, Utc};
 use std::collections::HashSet;
 use uuid::Uuid;
 
+use super::Mood;
+
 /// A message stored in a mailbox
 #[derive(Debug, Clone)]
 pub struct MailboxMessage {
@@ -43,6 +45,8 @@ pub struct Mailbox {
     pub expires_at: DateTime<Utc>,
     /// Whether the mailbox is closed
     pub closed: bool,
+    /// Moods reported by the sides that closed the mailbox
+    pub moods: Vec<Mood>,
 }
 
 impl Mailbox {
@@ -58,6 +62,7 @@ impl Mailbox {
             created_at: now,
             expires_at: now + chrono::Duration::seconds(timeout_secs as i64),
             closed: false,
+            moods: Vec::new(),
         }
     }
 
@@ -67,8 +72,17 @@ impl Mailbox {
     }
 
     /// Open the mailbox for a side
+    ///
+    /// A side that already opened the mailbox may open it again, which lets
+    /// a reconnecting client resume.
     pub fn open(&mut self, side: &str) -> bool {
-        if self.opened_by.len() >= 2 || self.closed {
+        if self.closed {
+            return false;
+        }
+        if self.opened_by.contains(side) {
+            return true;
+        }
+        if self.opened_by.len() >= 2 {
             return false;
         }
         self.opened_by.insert(side.to_string())
@@ -113,12 +127,16 @@ impl Mailbox {
         &self.messages
     }
 
-    /// Close the mailbox
-    pub fn close(&mut self, side: &str) {
-        self.opened_by.remove(side);
+    /// Close the mailbox for a side, keeping the mood it reported
+    pub fn close(&mut self, side: &str, mood: Option<Mood>) -> bool {
+        let was_open = self.opened_by.remove(side);
+        if was_open {
+            self.moods.extend(mood);
+        }
         if self.opened_by.is_empty() {
             self.closed = true;
         }
+        was_open
     }
 
     /// Check if mailbox can be deleted
@@ -155,6 +173,7 @@ mod tests {
 
         assert!(mb.open("side-b"));
         assert!(!mb.open("side-c")); // Third open should fail
+        assert!(mb.open("side-a")); // Reopening resumes
 
         assert_eq!(mb.peer_count(), 2);
     }
@@ -188,12 +207,15 @@ mod tests {
         mb.open("side-a");
         mb.open("side-b");
 
-        mb.close("side-a");
+        mb.close("side-a", Some(Mood::Happy));
         assert!(!mb.closed);
         assert_eq!(mb.peer_count(), 1);
 
-        mb.close("side-b");
+        // Only sides that opened the mailbox report a mood
+        mb.close("side-c", Some(Mood::Scary));
+        mb.close("side-b", None);
         assert!(mb.closed);
         assert!(mb.can_delete());
+        assert_eq!(mb.moods, vec![Mood::Happy]);
     }
 }
@@ -7,11 +7,16 @@
 
 use serde::{Deserialize, Serialize};
 
+use crate::permission::HashcashChallenge;
+
 /// All messages from client to server
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 pub enum ClientMessage {
+    /// Complete the permission step asked for in the welcome message
+    #[serde(rename = "submit-permissions")]
+    SubmitPermissions { method: String, stamp: String },
     /// Bind to an application ID
     Bind { appid: String, side: String },
     /// List nameplates (optional, for UI)
@@ -23,7 +28,14 @@ pub enum ClientMessage {
     /// Release a nameplate
     Release { nameplate: Option<String> },
     /// Open a mailbox
-    Open { mailbox: String },
+    ///
+    /// A reconnecting client passes the ID of the last message it received
+    /// so that only newer messages are replayed.
+    Open {
+        mailbox: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        last_seen: Option<u64>,
+    },
     /// Add a message to the mailbox
     Add {
         phase: String,
@@ -85,6 +97,19 @@ pub struct WelcomeInfo {
     /// Server implementation info
     #[serde(skip_serializing_if = "Option::is_none")]
     pub server_version: Option<String>,
+    /// Permission step clients must complete before binding
+    #[serde(
+        rename = "permission-required",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub permission_required: Option<PermissionRequired>,
+}
+
+/// Permission methods a client may use, keyed by method name
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PermissionRequired {
+    /// Proof of work over a per-connection resource
+    pub hashcash: HashcashChallenge,
 }
 
 impl Default for WelcomeInfo {
@@ -92,7 +117,8 @@ impl Default for WelcomeInfo {
         Self {
             motd: None,
             error: None,
-            server_version: Some("SecureBeam/0.1.0".to_string()),
+            server_version: Some(concat!("SecureBeam/", env!("CARGO_PKG_VERSION")).to_string()),
+            permission_required: None,
         }
     }
 }
@@ -118,10 +144,8 @@ impl ServerMessage {
     }
 
     /// Create a welcome message
-    pub fn welcome() -> Self {
-        ServerMessage::Welcome {
-            welcome: WelcomeInfo::default(),
-        }
+    pub fn welcome(welcome: WelcomeInfo) -> Self {
+        ServerMessage::Welcome { welcome }
     }
 }
 
@@ -139,6 +163,26 @@ pub enum Mood {
     Errory,
 }
 
+impl Mood {
+    /// All moods, in the order they are reported
+    pub const ALL: [Mood; 4] = [Mood::Happy, Mood::Lonely, Mood::Scary, Mood::Errory];
+
+    /// The mood's name on the wire
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Mood::Happy => "happy",
+            Mood::Lonely => "lonely",
+            Mood::Scary => "scary",
+            Mood::Errory => "errory",
+        }
+    }
+
+    /// Parse the mood a client sent with `close`
+    pub fn parse(mood: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|m| m.as_str() == mood)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -166,9 +210,10 @@ mod tests {
 
     #[test]
     fn test_welcome_message() {
-        let msg = ServerMessage::welcome();
+        let msg = ServerMessage::welcome(WelcomeInfo::default());
         let json = msg.to_json();
         assert!(json.contains("\"type\":\"welcome\""));
-        assert!(json.contains("SecureBeam"));
+        assert!(json.contains(concat!("SecureBeam/", env!("CARGO_PKG_VERSION"))));
+        assert!(!json.contains("motd"));
     }
 }
@@ -4,6 +4,8 @@ mod nameplate;
 mod state;
 
 pub use mailbox::{Mailbox, MailboxMessage};
-pub use messages::{ClientMessage, NameplateInfo, ServerMessage};
+pub use messages::{
+    ClientMessage, Mood, NameplateInfo, PermissionRequired, ServerMessage, WelcomeInfo,
+};
 pub use nameplate::{generate_nameplate_id, Nameplate};
-pub use state::AppState;
+pub use state::{AppState, LifecycleError};
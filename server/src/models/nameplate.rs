@@ -105,6 +105,6 @@ mod tests {
     fn test_generate_nameplate_id() {
         let id = generate_nameplate_id();
         let num: u32 = id.parse().unwrap();
-        assert!(num >= 1 && num < 1000);
+        assert!((1..1000).contains(&num));
     }
 }
@@ -1,18 +1,48 @@
 //! Application state for the Mailbox Server
 //!
-//! Manages nameplates, mailboxes, and client connections.
+//! Manages client connections and routes nameplate and mailbox
+//! operations to the configured storage backend.
 
 #![allow(dead_code)]
 
+use chrono::{DateTime, Utc};
 use std::collections::HashMap;
+use std::sync::Arc;
 use tokio::sync::{mpsc, RwLock};
 use uuid::Uuid;
 
-use super::{generate_nameplate_id, Mailbox, MailboxMessage, Nameplate};
+use super::{MailboxMessage, Mood};
+use crate::cluster::ClusterBus;
+use crate::limits::{Limiter, Limits};
+use crate::metrics::{Metrics, StateGauges};
+use crate::permission::HashcashChallenge;
+use crate::storage::{
+    ClaimOutcome, CloseOutcome, MemoryStorage, OpenOutcome, ReleaseOutcome, Storage, StorageError,
+};
+use crate::welcome::Welcome;
 
 /// Sender for WebSocket messages
 pub type WsSender = mpsc::UnboundedSender<String>;
 
+/// Why a nameplate or mailbox operation was refused
+///
+/// The messages are sent to clients in `error` responses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum LifecycleError {
+    #[error("unknown nameplate")]
+    UnknownNameplate,
+    #[error("unknown mailbox")]
+    UnknownMailbox,
+    #[error("crowded")]
+    Crowded,
+    #[error("nameplate not claimed")]
+    NotClaimed,
+    #[error("no free nameplates")]
+    NoFreeNameplates,
+    #[error("storage unavailable")]
+    Storage,
+}
+
 /// Information about a connected client
 #[derive(Debug)]
 pub struct ClientConnection {
@@ -20,8 +50,11 @@ pub struct ClientConnection {
     pub sender: WsSender,
     pub appid: Option<String>,
     pub side: Option<String>,
+    pub nameplate_id: Option<String>,
     pub mailbox_id: Option<String>,
     pub last_seen_message_id: u64,
+    /// Challenge the client has to solve before binding
+    pub challenge: Option<HashcashChallenge>,
 }
 
 impl ClientConnection {
@@ -31,8 +64,10 @@ impl ClientConnection {
             sender,
             appid: None,
             side: None,
+            nameplate_id: None,
             mailbox_id: None,
             last_seen_message_id: 0,
+            challenge: None,
         }
     }
 
@@ -50,51 +85,122 @@ impl ClientConnection {
 
 /// Shared application state
 pub struct AppState {
-    /// Nameplates indexed by ID
-    pub nameplates: RwLock<HashMap<String, Nameplate>>,
-    /// Mailboxes indexed by ID
-    pub mailboxes: RwLock<HashMap<String, Mailbox>>,
+    /// Nameplates, mailboxes and messages
+    storage: Arc<dyn Storage>,
     /// Connected clients indexed by UUID
     pub clients: RwLock<HashMap<Uuid, ClientConnection>>,
     /// Default timeout in seconds
     pub timeout_secs: u64,
+    /// Whether claiming an unknown nameplate creates it
+    pub allow_claim_create: bool,
+    /// Hashcash bits clients must prove before binding (0 = no permission step)
+    pub hashcash_bits: u32,
+    /// Prometheus metrics
+    pub metrics: Metrics,
+    /// Per-IP rate limits and abuse heuristics
+    pub limiter: Arc<Limiter>,
+    /// Welcome message, MOTD and unavailability error
+    pub welcome: Welcome,
+    /// Bearer token required by the admin API
+    pub admin_token: Option<String>,
+    /// Whether client addresses come from proxy headers
+    pub trust_proxy_headers: bool,
+    /// Fan-out to other replicas when clustered
+    cluster: Option<Arc<ClusterBus>>,
 }
 
 impl AppState {
+    /// Create state backed by in-memory storage
     pub fn new(timeout_secs: u64) -> Self {
+        Self::with_storage(Arc::new(MemoryStorage::new(timeout_secs)), timeout_secs)
+    }
+
+    /// Create state backed by the given storage
+    pub fn with_storage(storage: Arc<dyn Storage>, timeout_secs: u64) -> Self {
         Self {
-            nameplates: RwLock::new(HashMap::new()),
-            mailboxes: RwLock::new(HashMap::new()),
+            storage,
             clients: RwLock::new(HashMap::new()),
             timeout_secs,
+            allow_claim_create: false,
+            hashcash_bits: 0,
+            metrics: Metrics::new(),
+            limiter: Arc::new(Limiter::default()),
+            welcome: Welcome::default(),
+            admin_token: None,
+            trust_proxy_headers: false,
+            cluster: None,
         }
     }
 
+    /// Let clients create nameplates by claiming them
+    pub fn with_claim_create(mut self, allow: bool) -> Self {
+        self.allow_claim_create = allow;
+        self
+    }
+
+    /// Require a hashcash stamp with `bits` leading zero bits before binding
+    pub fn with_hashcash(mut self, bits: u32) -> Self {
+        self.hashcash_bits = bits;
+        self
+    }
+
+    /// Apply the given per-IP limits
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limiter = Arc::new(Limiter::new(limits));
+        self
+    }
+
+    /// Send the given welcome message to clients
+    pub fn with_welcome(mut self, welcome: Welcome) -> Self {
+        self.welcome = welcome;
+        self
+    }
+
+    /// Enable the admin API with the given bearer token
+    pub fn with_admin_token(mut self, token: Option<String>) -> Self {
+        self.admin_token = token;
+        self
+    }
+
+    /// Take client addresses from X-Forwarded-For / X-Real-IP
+    pub fn with_proxy_headers(mut self, trust: bool) -> Self {
+        self.trust_proxy_headers = trust;
+        self
+    }
+
+    /// Publish broadcasts to the other replicas of a cluster
+    pub fn with_cluster(mut self, cluster: Arc<ClusterBus>) -> Self {
+        self.cluster = Some(cluster);
+        self
+    }
+
     // === Client Management ===
 
     /// Register a new client connection
     pub async fn register_client(&self, sender: WsSender) -> Uuid {
         let id = Uuid::new_v4();
-        let conn = ClientConnection::new(id, sender);
+        let mut conn = ClientConnection::new(id, sender);
+        if self.hashcash_bits > 0 {
+            conn.challenge = Some(HashcashChallenge::new(self.hashcash_bits));
+        }
 
         let mut clients = self.clients.write().await;
         clients.insert(id, conn);
+        self.metrics.client_connected();
 
         tracing::debug!("Client {} registered", id);
         id
     }
 
     /// Unregister a client connection
+    ///
+    /// The client's mailbox is left open so that it can reconnect and
+    /// resume; abandoned mailboxes expire after the timeout.
     pub async fn unregister_client(&self, client_id: Uuid) {
         let mut clients = self.clients.write().await;
-        if let Some(conn) = clients.remove(&client_id) {
+        if clients.remove(&client_id).is_some() {
             tracing::debug!("Client {} unregistered", client_id);
-
-            // Close mailbox if client had one open
-            if let (Some(side), Some(mailbox_id)) = (&conn.side, &conn.mailbox_id) {
-                drop(clients); // Release lock before calling close_mailbox
-                self.close_mailbox(mailbox_id, side).await;
-            }
+            self.metrics.client_disconnected();
         }
     }
 
@@ -115,6 +221,20 @@ impl AppState {
         }
     }
 
+    /// Get the challenge a client still has to solve
+    pub async fn get_client_challenge(&self, client_id: Uuid) -> Option<HashcashChallenge> {
+        let clients = self.clients.read().await;
+        clients.get(&client_id).and_then(|c| c.challenge.clone())
+    }
+
+    /// Let a client bind after it solved its challenge
+    pub async fn grant_permission(&self, client_id: Uuid) {
+        let mut clients = self.clients.write().await;
+        if let Some(conn) = clients.get_mut(&client_id) {
+            conn.challenge = None;
+        }
+    }
+
     /// Get client's side
     pub async fn get_client_side(&self, client_id: Uuid) -> Option<String> {
         let clients = self.clients.read().await;
@@ -127,6 +247,20 @@ impl AppState {
         clients.get(&client_id).and_then(|c| c.appid.clone())
     }
 
+    /// Set client's claimed nameplate
+    pub async fn set_client_nameplate(&self, client_id: Uuid, nameplate_id: Option<String>) {
+        let mut clients = self.clients.write().await;
+        if let Some(conn) = clients.get_mut(&client_id) {
+            conn.nameplate_id = nameplate_id;
+        }
+    }
+
+    /// Get client's claimed nameplate
+    pub async fn get_client_nameplate(&self, client_id: Uuid) -> Option<String> {
+        let clients = self.clients.read().await;
+        clients.get(&client_id).and_then(|c| c.nameplate_id.clone())
+    }
+
     /// Set client's mailbox
     pub async fn set_client_mailbox(&self, client_id: Uuid, mailbox_id: String) {
         let mut clients = self.clients.write().await;
@@ -146,104 +280,82 @@ impl AppState {
     // === Nameplate Management ===
 
     /// Allocate a new nameplate
-    pub async fn allocate_nameplate(&self, appid: &str) -> String {
-        let mut nameplates = self.nameplates.write().await;
-        let mut mailboxes = self.mailboxes.write().await;
-
-        // Generate unique nameplate ID
-        let nameplate_id = loop {
-            let candidate = generate_nameplate_id();
-            if !nameplates.contains_key(&candidate) {
-                break candidate;
+    pub async fn allocate_nameplate(&self, appid: &str) -> Result<String, LifecycleError> {
+        let nameplate_id = match self.storage.allocate_nameplate(appid).await {
+            Ok(nameplate_id) => nameplate_id,
+            Err(StorageError::NoFreeNameplates) => {
+                tracing::warn!("No free nameplates for app {}", appid);
+                return Err(LifecycleError::NoFreeNameplates);
+            }
+            Err(e) => {
+                tracing::error!("Storage error: {}", e);
+                return Err(LifecycleError::Storage);
             }
         };
-
-        // Create mailbox
-        let mailbox = Mailbox::new(appid.to_string(), self.timeout_secs);
-        let mailbox_id = mailbox.id.clone();
-
-        // Create nameplate
-        let nameplate = Nameplate::new(nameplate_id.clone(), mailbox_id.clone(), self.timeout_secs);
-
-        mailboxes.insert(mailbox_id, mailbox);
-        nameplates.insert(nameplate_id.clone(), nameplate);
+        self.metrics.nameplate_allocated();
 
         tracing::info!("Allocated nameplate: {}", nameplate_id);
-        nameplate_id
+        Ok(nameplate_id)
     }
 
-    /// Claim a nameplate
+    /// Claim a nameplate and return its mailbox ID
     pub async fn claim_nameplate(
         &self,
         nameplate_id: &str,
         side: &str,
         appid: &str,
-    ) -> Option<String> {
-        let mut nameplates = self.nameplates.write().await;
-
-        // Check if nameplate exists
-        if let Some(np) = nameplates.get_mut(nameplate_id) {
-            if np.is_expired() {
-                return None;
+    ) -> Result<String, LifecycleError> {
+        let outcome = self
+            .storage
+            .claim_nameplate(nameplate_id, side, appid, self.allow_claim_create)
+            .await;
+        match log_storage_error(outcome).ok_or(LifecycleError::Storage)? {
+            ClaimOutcome::Claimed { mailbox_id } => {
+                self.metrics.nameplate_claimed();
+                tracing::info!("Claimed nameplate: {}", nameplate_id);
+                Ok(mailbox_id)
             }
-            np.claim(side);
-            return Some(np.mailbox_id.clone());
+            ClaimOutcome::Unknown => Err(LifecycleError::UnknownNameplate),
+            ClaimOutcome::Crowded => Err(LifecycleError::Crowded),
         }
-
-        // Nameplate doesn't exist - create it (for receiver who enters code)
-        let mut mailboxes = self.mailboxes.write().await;
-
-        let mailbox = Mailbox::new(appid.to_string(), self.timeout_secs);
-        let mailbox_id = mailbox.id.clone();
-
-        let mut nameplate = Nameplate::new(
-            nameplate_id.to_string(),
-            mailbox_id.clone(),
-            self.timeout_secs,
-        );
-        nameplate.claim(side);
-
-        mailboxes.insert(mailbox_id.clone(), mailbox);
-        nameplates.insert(nameplate_id.to_string(), nameplate);
-
-        tracing::info!("Created and claimed nameplate: {}", nameplate_id);
-        Some(mailbox_id)
     }
 
-    /// Release a nameplate
-    pub async fn release_nameplate(&self, nameplate_id: &str, side: &str) -> bool {
-        let mut nameplates = self.nameplates.write().await;
-
-        if let Some(np) = nameplates.get_mut(nameplate_id) {
-            np.release(side);
-            if np.can_release() {
-                nameplates.remove(nameplate_id);
-                tracing::info!("Released and removed nameplate: {}", nameplate_id);
-            }
-            return true;
+    /// Release a side's claim on a nameplate
+    pub async fn release_nameplate(
+        &self,
+        nameplate_id: &str,
+        side: &str,
+    ) -> Result<(), LifecycleError> {
+        let outcome = self.storage.release_nameplate(nameplate_id, side).await;
+        match log_storage_error(outcome).ok_or(LifecycleError::Storage)? {
+            ReleaseOutcome::Released => Ok(()),
+            ReleaseOutcome::NotClaimed => Err(LifecycleError::NotClaimed),
         }
-        false
     }
 
     /// List all nameplates for an app
-    pub async fn list_nameplates(&self, _appid: &str) -> Vec<String> {
-        let nameplates = self.nameplates.read().await;
-        nameplates.keys().cloned().collect()
+    pub async fn list_nameplates(&self, appid: &str) -> Vec<String> {
+        log_storage_error(self.storage.list_nameplates(appid).await).unwrap_or_default()
     }
 
     // === Mailbox Management ===
 
-    /// Open a mailbox
-    pub async fn open_mailbox(&self, mailbox_id: &str, side: &str) -> bool {
-        let mut mailboxes = self.mailboxes.write().await;
-
-        if let Some(mb) = mailboxes.get_mut(mailbox_id) {
-            if mb.is_expired() || mb.closed {
-                return false;
+    /// Open a mailbox of an app
+    pub async fn open_mailbox(
+        &self,
+        mailbox_id: &str,
+        side: &str,
+        appid: &str,
+    ) -> Result<(), LifecycleError> {
+        let outcome = self.storage.open_mailbox(mailbox_id, side, appid).await;
+        match log_storage_error(outcome).ok_or(LifecycleError::Storage)? {
+            OpenOutcome::Opened => {
+                self.metrics.mailbox_opened();
+                Ok(())
             }
-            return mb.open(side);
+            OpenOutcome::Unknown => Err(LifecycleError::UnknownMailbox),
+            OpenOutcome::Crowded => Err(LifecycleError::Crowded),
         }
-        false
     }
 
     /// Add a message to a mailbox
@@ -254,15 +366,13 @@ impl AppState {
         phase: &str,
         body: &str,
     ) -> Option<MailboxMessage> {
-        let mut mailboxes = self.mailboxes.write().await;
-
-        if let Some(mb) = mailboxes.get_mut(mailbox_id) {
-            if mb.closed {
-                return None;
-            }
-            return Some(mb.add_message(side, phase, body));
-        }
-        None
+        let message = log_storage_error(
+            self.storage
+                .add_message(mailbox_id, side, phase, body)
+                .await,
+        )??;
+        self.metrics.message_added(phase);
+        Some(message)
     }
 
     /// Get messages from a mailbox for a specific side
@@ -272,46 +382,63 @@ impl AppState {
         for_side: &str,
         after_id: u64,
     ) -> Vec<MailboxMessage> {
-        let mailboxes = self.mailboxes.read().await;
-
-        if let Some(mb) = mailboxes.get(mailbox_id) {
-            return mb
-                .get_messages_after(after_id)
-                .into_iter()
-                .filter(|m| m.side != for_side)
-                .cloned()
-                .collect();
-        }
-        Vec::new()
+        let mut messages = self.get_messages_after(mailbox_id, after_id).await;
+        messages.retain(|m| m.side != for_side);
+        messages
     }
 
     /// Get all messages from a mailbox
     pub async fn get_all_messages(&self, mailbox_id: &str) -> Vec<MailboxMessage> {
-        let mailboxes = self.mailboxes.read().await;
-
-        if let Some(mb) = mailboxes.get(mailbox_id) {
-            return mb.get_all_messages().to_vec();
-        }
-        Vec::new()
+        self.get_messages_after(mailbox_id, 0).await
     }
 
-    /// Close a mailbox
-    pub async fn close_mailbox(&self, mailbox_id: &str, side: &str) -> bool {
-        let mut mailboxes = self.mailboxes.write().await;
+    /// Get messages from a mailbox with an ID greater than `after_id`
+    pub async fn get_messages_after(&self, mailbox_id: &str, after_id: u64) -> Vec<MailboxMessage> {
+        log_storage_error(self.storage.get_messages_after(mailbox_id, after_id).await)
+            .unwrap_or_default()
+    }
 
-        if let Some(mb) = mailboxes.get_mut(mailbox_id) {
-            mb.close(side);
-            if mb.can_delete() {
-                mailboxes.remove(mailbox_id);
+    /// Close a mailbox for a side with the mood it reported
+    pub async fn close_mailbox(&self, mailbox_id: &str, side: &str, mood: Option<Mood>) -> bool {
+        let outcome = self.storage.close_mailbox(mailbox_id, side, mood).await;
+        let was_open = match log_storage_error(outcome) {
+            Some(CloseOutcome::Removed {
+                was_open,
+                created_at,
+                moods,
+            }) => {
+                self.metrics.mailbox_removed(lifetime_secs(created_at));
+                self.metrics.mailbox_outcome(&moods);
                 tracing::info!("Closed and removed mailbox: {}", mailbox_id);
+                was_open
             }
-            return true;
+            Some(CloseOutcome::Closed { was_open }) => was_open,
+            Some(CloseOutcome::NotFound) | None => return false,
+        };
+        // Only moods the mailbox recorded are counted
+        if let (true, Some(mood)) = (was_open, mood) {
+            self.metrics.mailbox_closed(mood);
         }
-        false
+        true
     }
 
     /// Broadcast a message to all clients in a mailbox except the sender
+    ///
+    /// In a cluster the message is also published so that clients connected
+    /// to other replicas receive it.
     pub async fn broadcast_to_mailbox(&self, mailbox_id: &str, sender_side: &str, message: &str) {
+        self.deliver_to_mailbox(mailbox_id, sender_side, message)
+            .await;
+
+        if let Some(cluster) = &self.cluster {
+            if let Err(e) = cluster.publish(mailbox_id, sender_side, message).await {
+                tracing::error!("Failed to publish to cluster: {}", e);
+            }
+        }
+    }
+
+    /// Send a message to the local clients in a mailbox except the sender
+    pub async fn deliver_to_mailbox(&self, mailbox_id: &str, sender_side: &str, message: &str) {
         let clients = self.clients.read().await;
 
         for conn in clients.values() {
@@ -327,36 +454,51 @@ impl AppState {
 
     /// Clean up expired nameplates and mailboxes
     pub async fn cleanup_expired(&self) {
-        // Clean up nameplates
-        {
-            let mut nameplates = self.nameplates.write().await;
-            let expired: Vec<String> = nameplates
-                .iter()
-                .filter(|(_, np)| np.is_expired())
-                .map(|(id, _)| id.clone())
-                .collect();
-
-            for id in expired {
-                nameplates.remove(&id);
-                tracing::debug!("Cleaned up expired nameplate: {}", id);
-            }
+        self.limiter
+            .prune(std::time::Duration::from_secs(self.timeout_secs));
+
+        let Some(report) = log_storage_error(self.storage.cleanup_expired().await) else {
+            return;
+        };
+
+        self.metrics.nameplates_expired(report.nameplates);
+        self.metrics.mailboxes_pruned(report.mailboxes.len());
+        for created_at in &report.mailboxes {
+            self.metrics.mailbox_removed(lifetime_secs(*created_at));
         }
 
-        // Clean up mailboxes
-        {
-            let mut mailboxes = self.mailboxes.write().await;
-            let expired: Vec<String> = mailboxes
-                .iter()
-                .filter(|(_, mb)| mb.can_delete())
-                .map(|(id, _)| id.clone())
-                .collect();
-
-            for id in expired {
-                mailboxes.remove(&id);
-                tracing::debug!("Cleaned up expired mailbox: {}", id);
-            }
+        if report.nameplates > 0 || !report.mailboxes.is_empty() {
+            tracing::debug!(
+                "Cleaned up {} expired nameplates and {} mailboxes",
+                report.nameplates,
+                report.mailboxes.len()
+            );
         }
     }
+
+    // === Metrics ===
+
+    /// Render Prometheus metrics including the current state sizes
+    pub async fn render_metrics(&self) -> String {
+        let (nameplates, mailboxes) =
+            log_storage_error(self.storage.counts().await).unwrap_or_default();
+        self.metrics.render(StateGauges {
+            nameplates,
+            mailboxes,
+        })
+    }
+}
+
+/// Log a storage failure and turn it into `None`
+fn log_storage_error<T>(result: Result<T, StorageError>) -> Option<T> {
+    result
+        .map_err(|e| tracing::error!("Storage error: {}", e))
+        .ok()
+}
+
+/// Seconds between `created_at` and now
+fn lifetime_secs(created_at: DateTime<Utc>) -> f64 {
+    (Utc::now() - created_at).num_milliseconds() as f64 / 1000.0
 }
 
 impl Default for AppState {
@@ -0,0 +1,147 @@
+//! Permission step before `bind`
+//!
+//! Implements the hashcash method of the wormhole server "permissions"
+//! extension. The welcome message asks for a stamp with a number of
+//! leading zero bits over a resource string that is unique to the
+//! connection, so a stamp cannot be reused on another connection. Clients
+//! answer with `submit-permissions` before binding.
+
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use uuid::Uuid;
+
+/// The hashcash challenge advertised in the welcome message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HashcashChallenge {
+    /// Required leading zero bits of the stamp's SHA-1 hash
+    pub bits: u32,
+    /// String the stamp has to be minted for
+    pub resource: String,
+}
+
+impl HashcashChallenge {
+    /// A challenge with a fresh resource string
+    pub fn new(bits: u32) -> Self {
+        Self {
+            bits,
+            resource: Uuid::new_v4().simple().to_string(),
+        }
+    }
+
+    /// Check a stamp of the form `1:bits:date:resource:ext:rand:counter`
+    pub fn verify(&self, stamp: &str) -> Result<(), PermissionError> {
+        let fields: Vec<&str> = stamp.split(':').collect();
+        let [version, bits, _date, resource, _ext, _rand, _counter] = fields[..] else {
+            return Err(PermissionError::Malformed);
+        };
+        if version != "1" {
+            return Err(PermissionError::Malformed);
+        }
+        let bits: u32 = bits.parse().map_err(|_| PermissionError::Malformed)?;
+        if bits < self.bits {
+            return Err(PermissionError::TooEasy);
+        }
+        if resource != self.resource {
+            return Err(PermissionError::WrongResource);
+        }
+        if leading_zero_bits(&Sha1::digest(stamp.as_bytes())) < self.bits {
+            return Err(PermissionError::Insufficient);
+        }
+        Ok(())
+    }
+}
+
+/// Why a permission submission was rejected
+///
+/// The messages are sent to clients in `error` responses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum PermissionError {
+    #[error("permission required")]
+    Required,
+    #[error("unsupported permission method")]
+    UnsupportedMethod,
+    #[error("malformed hashcash stamp")]
+    Malformed,
+    #[error("hashcash stamp has too few bits")]
+    TooEasy,
+    #[error("hashcash stamp is for another resource")]
+    WrongResource,
+    #[error("hashcash stamp does not match its bits")]
+    Insufficient,
+}
+
+/// Number of leading zero bits of a hash
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Mint a stamp the way clients do
+    pub(crate) fn mint(challenge: &HashcashChallenge) -> String {
+        (0u64..)
+            .map(|counter| {
+                format!(
+                    "1:{}:261018:{}::c2VjdXJl:{:x}",
+                    challenge.bits, challenge.resource, counter
+                )
+            })
+            .find(|stamp| challenge.verify(stamp).is_ok())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_valid_stamp() {
+        let challenge = HashcashChallenge::new(12);
+        let stamp = mint(&challenge);
+        assert_eq!(challenge.verify(&stamp), Ok(()));
+        assert!(leading_zero_bits(&Sha1::digest(stamp.as_bytes())) >= 12);
+    }
+
+    #[test]
+    fn test_invalid_stamps() {
+        let challenge = HashcashChallenge::new(12);
+        let stamp = mint(&challenge);
+
+        // A stamp for another connection's resource
+        let other = HashcashChallenge::new(12);
+        assert_eq!(other.verify(&stamp), Err(PermissionError::WrongResource));
+
+        // Claiming fewer bits than required
+        let easy = stamp.replacen(":12:", ":4:", 1);
+        assert_eq!(challenge.verify(&easy), Err(PermissionError::TooEasy));
+
+        // Changing the counter breaks the proof of work
+        let forged = (0..)
+            .map(|i| format!("{}{}", stamp, i))
+            .find(|s| leading_zero_bits(&Sha1::digest(s.as_bytes())) < 12)
+            .unwrap();
+        assert_eq!(
+            challenge.verify(&forged),
+            Err(PermissionError::Insufficient)
+        );
+
+        assert_eq!(
+            challenge.verify("not-a-stamp"),
+            Err(PermissionError::Malformed)
+        );
+        let version = stamp.replacen("1:", "0:", 1);
+        assert_eq!(challenge.verify(&version), Err(PermissionError::Malformed));
+    }
+
+    #[test]
+    fn test_leading_zero_bits() {
+        assert_eq!(leading_zero_bits(&[0, 0, 0x10, 0xff]), 19);
+        assert_eq!(leading_zero_bits(&[0x80]), 0);
+        assert_eq!(leading_zero_bits(&[0, 0]), 16);
+    }
+}
@@ -0,0 +1,342 @@
+//! In-process Redis protocol stub for tests
+//!
+//! Implements the subset of commands used by the clustering backend,
+//! including key expiry and pub/sub, on an ephemeral local port. EVAL runs
+//! the backend's scripts natively rather than through Lua; like in Redis,
+//! each script runs without other commands in between.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+
+use super::{read_value, Value};
+use crate::storage::redis::JOIN_SIDES;
+
+enum Data {
+    Str(Vec<u8>),
+    Set(HashSet<Vec<u8>>),
+    List(Vec<Vec<u8>>),
+    ZSet(HashMap<Vec<u8>, f64>),
+}
+
+struct Entry {
+    data: Data,
+    expires: Option<Instant>,
+}
+
+#[derive(Default)]
+struct Db {
+    keys: HashMap<Vec<u8>, Entry>,
+}
+
+impl Db {
+    /// Look up a live key, dropping it if it has expired
+    fn get(&mut self, key: &[u8]) -> Option<&mut Entry> {
+        if self
+            .keys
+            .get(key)
+            .and_then(|e| e.expires)
+            .is_some_and(|at| Instant::now() >= at)
+        {
+            self.keys.remove(key);
+        }
+        self.keys.get_mut(key)
+    }
+
+    fn entry(&mut self, key: &[u8], empty: fn() -> Data) -> &mut Entry {
+        if self.get(key).is_none() {
+            self.keys.insert(
+                key.to_vec(),
+                Entry {
+                    data: empty(),
+                    expires: None,
+                },
+            );
+        }
+        self.keys.get_mut(key).expect("entry was just inserted")
+    }
+}
+
+/// A running Redis protocol stub
+pub struct RedisStub {
+    addr: String,
+}
+
+impl RedisStub {
+    /// Start the stub on an ephemeral port
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let db = Arc::new(Mutex::new(Db::default()));
+        let (events, _) = broadcast::channel::<(Vec<u8>, Vec<u8>)>(256);
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, db.clone(), events.clone()));
+            }
+        });
+
+        Self { addr }
+    }
+
+    /// Address to connect to
+    pub fn addr(&self) -> String {
+        self.addr.clone()
+    }
+}
+
+async fn serve(
+    stream: TcpStream,
+    db: Arc<Mutex<Db>>,
+    events: broadcast::Sender<(Vec<u8>, Vec<u8>)>,
+) {
+    let mut stream = BufReader::new(stream);
+
+    while let Ok(Value::Array(Some(items))) = read_value(&mut stream).await {
+        let args: Vec<Vec<u8>> = items
+            .into_iter()
+            .filter_map(|v| match v {
+                Value::Bulk(Some(b)) => Some(b),
+                _ => None,
+            })
+            .collect();
+        let name = String::from_utf8_lossy(&args[0]).to_uppercase();
+
+        if name == "SUBSCRIBE" {
+            subscribed(stream.into_inner(), args[1..].to_vec(), events.subscribe()).await;
+            return;
+        }
+
+        let reply = if name == "PUBLISH" {
+            let receivers = events.send((args[1].clone(), args[2].clone())).unwrap_or(0);
+            Reply::Int(receivers as i64)
+        } else {
+            execute(&mut db.lock().unwrap(), &name, &args[1..])
+        };
+
+        if stream.get_mut().write_all(&reply.encode()).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Forward published messages to a subscribed connection
+async fn subscribed(
+    mut stream: TcpStream,
+    channels: Vec<Vec<u8>>,
+    mut events: broadcast::Receiver<(Vec<u8>, Vec<u8>)>,
+) {
+    for (i, channel) in channels.iter().enumerate() {
+        let reply = Reply::Array(vec![
+            Reply::Bulk(b"subscribe".to_vec()),
+            Reply::Bulk(channel.clone()),
+            Reply::Int(i as i64 + 1),
+        ]);
+        if stream.write_all(&reply.encode()).await.is_err() {
+            return;
+        }
+    }
+
+    while let Ok((channel, payload)) = events.recv().await {
+        if !channels.contains(&channel) {
+            continue;
+        }
+        let reply = Reply::Array(vec![
+            Reply::Bulk(b"message".to_vec()),
+            Reply::Bulk(channel),
+            Reply::Bulk(payload),
+        ]);
+        if stream.write_all(&reply.encode()).await.is_err() {
+            return;
+        }
+    }
+}
+
+enum Reply {
+    Ok,
+    Nil,
+    Int(i64),
+    Bulk(Vec<u8>),
+    Array(Vec<Reply>),
+    Error(String),
+}
+
+impl Reply {
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            Reply::Ok => b"+OK\r\n".to_vec(),
+            Reply::Nil => b"$-1\r\n".to_vec(),
+            Reply::Int(n) => format!(":{}\r\n", n).into_bytes(),
+            Reply::Bulk(b) => {
+                let mut out = format!("${}\r\n", b.len()).into_bytes();
+                out.extend_from_slice(b);
+                out.extend_from_slice(b"\r\n");
+                out
+            }
+            Reply::Array(items) => {
+                let mut out = format!("*{}\r\n", items.len()).into_bytes();
+                for item in items {
+                    out.extend(item.encode());
+                }
+                out
+            }
+            Reply::Error(e) => format!("-ERR {}\r\n", e).into_bytes(),
+        }
+    }
+}
+
+fn int_arg(arg: &[u8]) -> i64 {
+    String::from_utf8_lossy(arg).parse().unwrap_or(0)
+}
+
+fn execute(db: &mut Db, name: &str, args: &[Vec<u8>]) -> Reply {
+    match name {
+        "PING" => Reply::Ok,
+        "SET" => {
+            let nx = args.iter().any(|a| a.eq_ignore_ascii_case(b"NX"));
+            let px = args
+                .iter()
+                .position(|a| a.eq_ignore_ascii_case(b"PX"))
+                .map(|i| int_arg(&args[i + 1]));
+            if nx && db.get(&args[0]).is_some() {
+                return Reply::Nil;
+            }
+            db.keys.insert(
+                args[0].clone(),
+                Entry {
+                    data: Data::Str(args[1].clone()),
+                    expires: px.map(|ms| Instant::now() + Duration::from_millis(ms as u64)),
+                },
+            );
+            Reply::Ok
+        }
+        "GET" => match db.get(&args[0]) {
+            Some(Entry {
+                data: Data::Str(v), ..
+            }) => Reply::Bulk(v.clone()),
+            _ => Reply::Nil,
+        },
+        "DEL" => Reply::Int(
+            args.iter()
+                .filter(|k| db.get(k).is_some() && db.keys.remove(*k).is_some())
+                .count() as i64,
+        ),
+        "EXISTS" => Reply::Int(db.get(&args[0]).is_some() as i64),
+        "PEXPIRE" => match db.get(&args[0]) {
+            Some(entry) => {
+                entry.expires =
+                    Some(Instant::now() + Duration::from_millis(int_arg(&args[1]).max(0) as u64));
+                Reply::Int(1)
+            }
+            None => Reply::Int(0),
+        },
+        "INCR" => {
+            let entry = db.entry(&args[0], || Data::Str(b"0".to_vec()));
+            match &mut entry.data {
+                Data::Str(v) => {
+                    let n = int_arg(v) + 1;
+                    *v = n.to_string().into_bytes();
+                    Reply::Int(n)
+                }
+                _ => Reply::Error("wrong type".to_string()),
+            }
+        }
+        "SADD" | "SREM" | "SCARD" | "SISMEMBER" | "SMEMBERS" => {
+            let entry = db.entry(&args[0], || Data::Set(HashSet::new()));
+            let Data::Set(set) = &mut entry.data else {
+                return Reply::Error("wrong type".to_string());
+            };
+            let reply = match name {
+                "SADD" => Reply::Int(set.insert(args[1].clone()) as i64),
+                "SREM" => Reply::Int(set.remove(&args[1]) as i64),
+                "SCARD" => Reply::Int(set.len() as i64),
+                "SISMEMBER" => Reply::Int(set.contains(&args[1]) as i64),
+                _ => Reply::Array(set.iter().cloned().map(Reply::Bulk).collect()),
+            };
+            if set.is_empty() {
+                db.keys.remove(&args[0]);
+            }
+            reply
+        }
+        "RPUSH" | "LRANGE" => {
+            let entry = db.entry(&args[0], || Data::List(Vec::new()));
+            let Data::List(list) = &mut entry.data else {
+                return Reply::Error("wrong type".to_string());
+            };
+            let reply = if name == "RPUSH" {
+                list.push(args[1].clone());
+                Reply::Int(list.len() as i64)
+            } else {
+                // Only full ranges are used
+                Reply::Array(list.iter().cloned().map(Reply::Bulk).collect())
+            };
+            if list.is_empty() {
+                db.keys.remove(&args[0]);
+            }
+            reply
+        }
+        "ZADD" | "ZREM" | "ZCARD" | "ZRANGE" => {
+            let entry = db.entry(&args[0], || Data::ZSet(HashMap::new()));
+            let Data::ZSet(zset) = &mut entry.data else {
+                return Reply::Error("wrong type".to_string());
+            };
+            let reply = match name {
+                "ZADD" => {
+                    let score = String::from_utf8_lossy(&args[1]).parse().unwrap_or(0.0);
+                    Reply::Int(zset.insert(args[2].clone(), score).is_none() as i64)
+                }
+                "ZREM" => Reply::Int(zset.remove(&args[1]).is_some() as i64),
+                "ZCARD" => Reply::Int(zset.len() as i64),
+                _ => {
+                    // Only full ranges are used
+                    let mut members: Vec<_> = zset.iter().collect();
+                    members.sort_by(|a, b| a.1.total_cmp(b.1));
+                    let with_scores = args.iter().any(|a| a.eq_ignore_ascii_case(b"WITHSCORES"));
+                    let mut items = Vec::new();
+                    for (member, score) in members {
+                        items.push(Reply::Bulk(member.clone()));
+                        if with_scores {
+                            items.push(Reply::Bulk(score.to_string().into_bytes()));
+                        }
+                    }
+                    Reply::Array(items)
+                }
+            };
+            if zset.is_empty() {
+                db.keys.remove(&args[0]);
+            }
+            reply
+        }
+        "EVAL" => eval(db, args),
+        other => Reply::Error(format!("unknown command '{}'", other)),
+    }
+}
+
+/// Run one of the known scripts with its keys and arguments
+fn eval(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let numkeys = int_arg(&args[1]).max(0) as usize;
+    let (keys, argv) = args[2..].split_at(numkeys.min(args.len() - 2));
+
+    if args[0] == JOIN_SIDES.as_bytes() {
+        let call = |db: &mut Db, command: &str, args: &[&[u8]]| {
+            let args: Vec<Vec<u8>> = args.iter().map(|a| a.to_vec()).collect();
+            match execute(db, command, &args) {
+                Reply::Int(n) => n,
+                _ => 0,
+            }
+        };
+        let (key, side) = (keys[0].as_slice(), argv[0].as_slice());
+        if call(db, "SISMEMBER", &[key, side]) == 0 {
+            if call(db, "SCARD", &[key]) >= int_arg(&argv[2]) {
+                return Reply::Int(0);
+            }
+            call(db, "SADD", &[key, side]);
+        }
+        call(db, "PEXPIRE", &[key, &argv[1]]);
+        return Reply::Int(1);
+    }
+    Reply::Error("unknown script".to_string())
+}
@@ -0,0 +1,242 @@
+//! In-memory storage backend
+//!
+//! State is lost when the server restarts.
+
+use async_trait::async_trait;
+use std::collections::HashMap;
+use tokio::sync::RwLock;
+
+use super::{
+    ClaimOutcome, CleanupReport, CloseOutcome, OpenOutcome, ReleaseOutcome, Result, Storage,
+    StorageError, ALLOCATE_ATTEMPTS,
+};
+use crate::models::{generate_nameplate_id, Mailbox, MailboxMessage, Mood, Nameplate};
+
+/// Storage backed by in-memory maps
+pub struct MemoryStorage {
+    /// Nameplates indexed by ID
+    nameplates: RwLock<HashMap<String, Nameplate>>,
+    /// Mailboxes indexed by ID
+    mailboxes: RwLock<HashMap<String, Mailbox>>,
+    /// Timeout in seconds for new nameplates and mailboxes
+    timeout_secs: u64,
+}
+
+impl MemoryStorage {
+    pub fn new(timeout_secs: u64) -> Self {
+        Self {
+            nameplates: RwLock::new(HashMap::new()),
+            mailboxes: RwLock::new(HashMap::new()),
+            timeout_secs,
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn allocate_nameplate(&self, appid: &str) -> Result<String> {
+        let mut nameplates = self.nameplates.write().await;
+        let mut mailboxes = self.mailboxes.write().await;
+
+        // Generate unique nameplate ID
+        let nameplate_id = (0..ALLOCATE_ATTEMPTS)
+            .map(|_| generate_nameplate_id())
+            .find(|candidate| !nameplates.contains_key(candidate))
+            .ok_or(StorageError::NoFreeNameplates)?;
+
+        // Create mailbox
+        let mailbox = Mailbox::new(appid.to_string(), self.timeout_secs);
+        let mailbox_id = mailbox.id.clone();
+
+        // Create nameplate
+        let nameplate = Nameplate::new(nameplate_id.clone(), mailbox_id.clone(), self.timeout_secs);
+
+        mailboxes.insert(mailbox_id, mailbox);
+        nameplates.insert(nameplate_id.clone(), nameplate);
+
+        Ok(nameplate_id)
+    }
+
+    async fn claim_nameplate(
+        &self,
+        nameplate_id: &str,
+        side: &str,
+        appid: &str,
+        create: bool,
+    ) -> Result<ClaimOutcome> {
+        let mut nameplates = self.nameplates.write().await;
+        let mut mailboxes = self.mailboxes.write().await;
+
+        if let Some(np) = nameplates.get_mut(nameplate_id) {
+            let owned = mailboxes
+                .get(&np.mailbox_id)
+                .is_some_and(|mb| mb.appid == appid);
+            if np.is_expired() || !owned {
+                return Ok(ClaimOutcome::Unknown);
+            }
+            if !np.is_claimed_by(side) && !np.claim(side) {
+                return Ok(ClaimOutcome::Crowded);
+            }
+            return Ok(ClaimOutcome::Claimed {
+                mailbox_id: np.mailbox_id.clone(),
+            });
+        }
+
+        if !create {
+            return Ok(ClaimOutcome::Unknown);
+        }
+
+        // Create the nameplate for a side that chose its own code
+        let mailbox = Mailbox::new(appid.to_string(), self.timeout_secs);
+        let mailbox_id = mailbox.id.clone();
+
+        let mut nameplate = Nameplate::new(
+            nameplate_id.to_string(),
+            mailbox_id.clone(),
+            self.timeout_secs,
+        );
+        nameplate.claim(side);
+
+        mailboxes.insert(mailbox_id.clone(), mailbox);
+        nameplates.insert(nameplate_id.to_string(), nameplate);
+
+        Ok(ClaimOutcome::Claimed { mailbox_id })
+    }
+
+    async fn release_nameplate(&self, nameplate_id: &str, side: &str) -> Result<ReleaseOutcome> {
+        let mut nameplates = self.nameplates.write().await;
+
+        let Some(np) = nameplates
+            .get_mut(nameplate_id)
+            .filter(|np| np.is_claimed_by(side))
+        else {
+            return Ok(ReleaseOutcome::NotClaimed);
+        };
+        np.release(side);
+        if np.can_release() {
+            nameplates.remove(nameplate_id);
+        }
+        Ok(ReleaseOutcome::Released)
+    }
+
+    async fn list_nameplates(&self, appid: &str) -> Result<Vec<String>> {
+        let nameplates = self.nameplates.read().await;
+        let mailboxes = self.mailboxes.read().await;
+        Ok(nameplates
+            .values()
+            .filter(|np| {
+                mailboxes
+                    .get(&np.mailbox_id)
+                    .is_some_and(|mb| mb.appid == appid)
+            })
+            .map(|np| np.id.clone())
+            .collect())
+    }
+
+    async fn open_mailbox(&self, mailbox_id: &str, side: &str, appid: &str) -> Result<OpenOutcome> {
+        let mut mailboxes = self.mailboxes.write().await;
+
+        let Some(mb) = mailboxes
+            .get_mut(mailbox_id)
+            .filter(|mb| mb.appid == appid && !mb.is_expired() && !mb.closed)
+        else {
+            return Ok(OpenOutcome::Unknown);
+        };
+        if mb.open(side) {
+            Ok(OpenOutcome::Opened)
+        } else {
+            Ok(OpenOutcome::Crowded)
+        }
+    }
+
+    async fn add_message(
+        &self,
+        mailbox_id: &str,
+        side: &str,
+        phase: &str,
+        body: &str,
+    ) -> Result<Option<MailboxMessage>> {
+        let mut mailboxes = self.mailboxes.write().await;
+
+        if let Some(mb) = mailboxes.get_mut(mailbox_id) {
+            if mb.closed {
+                return Ok(None);
+            }
+            return Ok(Some(mb.add_message(side, phase, body)));
+        }
+        Ok(None)
+    }
+
+    async fn get_messages_after(
+        &self,
+        mailbox_id: &str,
+        after_id: u64,
+    ) -> Result<Vec<MailboxMessage>> {
+        let mailboxes = self.mailboxes.read().await;
+
+        Ok(mailboxes
+            .get(mailbox_id)
+            .map(|mb| {
+                mb.get_messages_after(after_id)
+                    .into_iter()
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn close_mailbox(
+        &self,
+        mailbox_id: &str,
+        side: &str,
+        mood: Option<Mood>,
+    ) -> Result<CloseOutcome> {
+        let mut mailboxes = self.mailboxes.write().await;
+
+        let Some(mb) = mailboxes.get_mut(mailbox_id) else {
+            return Ok(CloseOutcome::NotFound);
+        };
+        let was_open = mb.close(side, mood);
+        if mb.can_delete() {
+            let mb = mailboxes.remove(mailbox_id).expect("mailbox exists");
+            return Ok(CloseOutcome::Removed {
+                was_open,
+                created_at: mb.created_at,
+                moods: mb.moods,
+            });
+        }
+        Ok(CloseOutcome::Closed { was_open })
+    }
+
+    async fn cleanup_expired(&self) -> Result<CleanupReport> {
+        let mut report = CleanupReport::default();
+
+        {
+            let mut nameplates = self.nameplates.write().await;
+            let before = nameplates.len();
+            nameplates.retain(|_, np| !np.is_expired());
+            report.nameplates = before - nameplates.len();
+        }
+
+        {
+            let mut mailboxes = self.mailboxes.write().await;
+            mailboxes.retain(|_, mb| {
+                if mb.can_delete() {
+                    report.mailboxes.push(mb.created_at);
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+
+        Ok(report)
+    }
+
+    async fn counts(&self) -> Result<(usize, usize)> {
+        Ok((
+            self.nameplates.read().await.len(),
+            self.mailboxes.read().await.len(),
+        ))
+    }
+}
@@ -0,0 +1,494 @@
+//! Storage backends for the Mailbox Server
+//!
+//! Nameplates, mailboxes and their messages live behind the [`Storage`]
+//! trait so that the server can keep them in memory or persist them in
+//! SQLite. With a persistent backend, clients reconnecting after a server
+//! restart can claim the same nameplate, reopen their mailbox and receive
+//! the messages that were stored before the restart. The Redis backend
+//! shares state between clustered server replicas.
+
+mod memory;
+pub(crate) mod redis;
+mod sqlite;
+
+#[cfg(test)]
+mod model_tests;
+
+pub use memory::MemoryStorage;
+pub use redis::RedisStorage;
+pub use sqlite::SqliteStorage;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::models::{MailboxMessage, Mood};
+use crate::resp;
+
+/// Error type for storage operations
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    #[error("SQLite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("Redis error: {0}")]
+    Redis(String),
+    #[error("Storage task failed: {0}")]
+    Task(String),
+    #[error("Unknown storage backend: {0}")]
+    UnknownBackend(String),
+    #[error("No free nameplates")]
+    NoFreeNameplates,
+}
+
+pub type Result<T> = std::result::Result<T, StorageError>;
+
+/// Random nameplate IDs tried before allocation gives up
+///
+/// There are only 999 IDs, so a nearly full server refuses to allocate
+/// rather than searching for the last free ones.
+pub(crate) const ALLOCATE_ATTEMPTS: usize = 100;
+
+/// Outcome of claiming a nameplate for one side
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClaimOutcome {
+    /// The side holds a claim on the nameplate
+    Claimed { mailbox_id: String },
+    /// The nameplate does not exist for this app
+    Unknown,
+    /// Two other sides already claimed the nameplate
+    Crowded,
+}
+
+/// Outcome of releasing a nameplate for one side
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReleaseOutcome {
+    /// The side's claim was dropped
+    Released,
+    /// The side holds no claim on the nameplate
+    NotClaimed,
+}
+
+/// Outcome of opening a mailbox for one side
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenOutcome {
+    /// The side has the mailbox open
+    Opened,
+    /// The mailbox does not exist for this app
+    Unknown,
+    /// Two other sides already opened the mailbox
+    Crowded,
+}
+
+/// Outcome of closing a mailbox for one side
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CloseOutcome {
+    /// The mailbox does not exist
+    NotFound,
+    /// The mailbox is kept; `was_open` says whether the side had it open
+    Closed { was_open: bool },
+    /// The mailbox was deleted, with the moods its sides closed with
+    Removed {
+        was_open: bool,
+        created_at: DateTime<Utc>,
+        moods: Vec<Mood>,
+    },
+}
+
+/// What a cleanup pass removed
+#[derive(Debug, Clone, Default)]
+pub struct CleanupReport {
+    /// Number of expired nameplates removed
+    pub nameplates: usize,
+    /// Creation times of the mailboxes removed
+    pub mailboxes: Vec<DateTime<Utc>>,
+}
+
+/// State operations of the mailbox protocol
+///
+/// Implementations must be safe to share between connections. Every
+/// operation is atomic with respect to the others.
+///
+/// A nameplate belongs to the app of the mailbox it points at, and is
+/// unknown to other apps and once that mailbox is gone. Nameplates and
+/// mailboxes each admit at most two sides; a side that already holds a
+/// claim or has the mailbox open may repeat the operation.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Allocate a new nameplate with a fresh mailbox
+    ///
+    /// Fails with [`StorageError::NoFreeNameplates`] when no free ID is found
+    /// in [`ALLOCATE_ATTEMPTS`] tries.
+    async fn allocate_nameplate(&self, appid: &str) -> Result<String>;
+
+    /// Claim a nameplate for a side
+    ///
+    /// With `create`, a nameplate that does not exist yet is created with a
+    /// fresh mailbox; otherwise it is reported as unknown, as is an expired
+    /// nameplate.
+    async fn claim_nameplate(
+        &self,
+        nameplate_id: &str,
+        side: &str,
+        appid: &str,
+        create: bool,
+    ) -> Result<ClaimOutcome>;
+
+    /// Release a side's claim, removing the nameplate once unclaimed
+    async fn release_nameplate(&self, nameplate_id: &str, side: &str) -> Result<ReleaseOutcome>;
+
+    /// List the nameplates of an app
+    async fn list_nameplates(&self, appid: &str) -> Result<Vec<String>>;
+
+    /// Open a mailbox of an app for a side
+    ///
+    /// Reopening by a side that already opened the mailbox succeeds, so a
+    /// reconnecting client can resume.
+    async fn open_mailbox(&self, mailbox_id: &str, side: &str, appid: &str) -> Result<OpenOutcome>;
+
+    /// Add a message to an open mailbox
+    async fn add_message(
+        &self,
+        mailbox_id: &str,
+        side: &str,
+        phase: &str,
+        body: &str,
+    ) -> Result<Option<MailboxMessage>>;
+
+    /// Get the messages of a mailbox with an ID greater than `after_id`
+    async fn get_messages_after(
+        &self,
+        mailbox_id: &str,
+        after_id: u64,
+    ) -> Result<Vec<MailboxMessage>>;
+
+    /// Close a mailbox for a side, deleting it once it can be deleted
+    ///
+    /// The mood is kept with the mailbox if the side had it open.
+    async fn close_mailbox(
+        &self,
+        mailbox_id: &str,
+        side: &str,
+        mood: Option<Mood>,
+    ) -> Result<CloseOutcome>;
+
+    /// Remove expired nameplates and deletable mailboxes
+    async fn cleanup_expired(&self) -> Result<CleanupReport>;
+
+    /// Number of stored nameplates and mailboxes
+    async fn counts(&self) -> Result<(usize, usize)>;
+}
+
+/// Create the storage backend selected in the configuration
+pub async fn from_config(config: &Config) -> Result<Arc<dyn Storage>> {
+    match config.storage_backend.as_str() {
+        "memory" => Ok(Arc::new(MemoryStorage::new(config.session_timeout_secs))),
+        "sqlite" => Ok(Arc::new(SqliteStorage::open(
+            &config.database_path,
+            config.session_timeout_secs,
+        )?)),
+        "redis" => {
+            let client = resp::Client::new(resp::parse_url(&config.redis_url)?);
+            Ok(Arc::new(
+                RedisStorage::connect(Arc::new(client), config.session_timeout_secs).await?,
+            ))
+        }
+        other => Err(StorageError::UnknownBackend(other.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    //! Behaviour shared by every backend
+
+    use super::*;
+    use crate::resp::stub::RedisStub;
+
+    async fn redis(timeout_secs: u64) -> Arc<dyn Storage> {
+        let stub = RedisStub::start().await;
+        let client = Arc::new(resp::Client::new(stub.addr()));
+        Arc::new(RedisStorage::connect(client, timeout_secs).await.unwrap())
+    }
+
+    async fn backends() -> Vec<(&'static str, Arc<dyn Storage>)> {
+        vec![
+            ("memory", Arc::new(MemoryStorage::new(300))),
+            (
+                "sqlite",
+                Arc::new(SqliteStorage::open_in_memory(300).unwrap()),
+            ),
+            ("redis", redis(300).await),
+        ]
+    }
+
+    /// Claim a nameplate that is expected to be claimable
+    async fn claim(storage: &dyn Storage, nameplate: &str, side: &str) -> String {
+        match storage
+            .claim_nameplate(nameplate, side, "app", true)
+            .await
+            .unwrap()
+        {
+            ClaimOutcome::Claimed { mailbox_id } => mailbox_id,
+            other => panic!("claim by {} failed: {:?}", side, other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_redis_url_fails_at_startup() {
+        let config = Config {
+            storage_backend: "redis".to_string(),
+            redis_url: "rediss://redis:6380".to_string(),
+            ..Config::default()
+        };
+        let Err(StorageError::Redis(reason)) = from_config(&config).await else {
+            panic!("rediss:// was accepted");
+        };
+        assert!(reason.contains("not supported"), "{}", reason);
+    }
+
+    #[tokio::test]
+    async fn test_allocate_claim_and_release() {
+        for (name, storage) in backends().await {
+            let nameplate = storage.allocate_nameplate("app").await.unwrap();
+            assert_eq!(
+                storage.list_nameplates("app").await.unwrap(),
+                vec![nameplate.clone()],
+                "{}",
+                name
+            );
+            assert!(storage.list_nameplates("other").await.unwrap().is_empty());
+
+            let a = claim(&*storage, &nameplate, "a").await;
+            let b = claim(&*storage, &nameplate, "b").await;
+            assert_eq!(a, b, "{}", name);
+            // Claiming again is idempotent, a third side is turned away
+            assert_eq!(claim(&*storage, &nameplate, "a").await, a, "{}", name);
+            assert_eq!(
+                storage
+                    .claim_nameplate(&nameplate, "c", "app", true)
+                    .await
+                    .unwrap(),
+                ClaimOutcome::Crowded,
+                "{}",
+                name
+            );
+
+            assert_eq!(
+                storage.release_nameplate(&nameplate, "c").await.unwrap(),
+                ReleaseOutcome::NotClaimed,
+                "{}",
+                name
+            );
+            assert_eq!(
+                storage.release_nameplate(&nameplate, "a").await.unwrap(),
+                ReleaseOutcome::Released
+            );
+            assert_eq!(storage.counts().await.unwrap().0, 1, "{}", name);
+            assert_eq!(
+                storage.release_nameplate(&nameplate, "b").await.unwrap(),
+                ReleaseOutcome::Released
+            );
+            assert_eq!(storage.counts().await.unwrap().0, 0, "{}", name);
+            assert_eq!(
+                storage.release_nameplate(&nameplate, "b").await.unwrap(),
+                ReleaseOutcome::NotClaimed,
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_allocate_gives_up_when_full() {
+        for (name, storage) in backends().await {
+            for id in 1..1000 {
+                claim(&*storage, &id.to_string(), "a").await;
+            }
+            let counts = storage.counts().await.unwrap();
+            assert!(
+                matches!(
+                    storage.allocate_nameplate("app").await,
+                    Err(StorageError::NoFreeNameplates)
+                ),
+                "{}",
+                name
+            );
+            // No mailbox is left behind
+            assert_eq!(storage.counts().await.unwrap(), counts, "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_claim_unknown_nameplate() {
+        for (name, storage) in backends().await {
+            assert_eq!(
+                storage
+                    .claim_nameplate("42", "a", "app", false)
+                    .await
+                    .unwrap(),
+                ClaimOutcome::Unknown,
+                "{}",
+                name
+            );
+            assert_eq!(storage.counts().await.unwrap(), (0, 0), "{}", name);
+
+            claim(&*storage, "42", "a").await;
+            assert_eq!(storage.counts().await.unwrap(), (1, 1), "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_nameplates_belong_to_their_app() {
+        for (name, storage) in backends().await {
+            let nameplate = storage.allocate_nameplate("app").await.unwrap();
+            assert_eq!(
+                storage
+                    .claim_nameplate(&nameplate, "a", "other", true)
+                    .await
+                    .unwrap(),
+                ClaimOutcome::Unknown,
+                "{}",
+                name
+            );
+
+            let mailbox = claim(&*storage, &nameplate, "a").await;
+            assert_eq!(
+                storage.open_mailbox(&mailbox, "a", "other").await.unwrap(),
+                OpenOutcome::Unknown,
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mailbox_messages() {
+        for (name, storage) in backends().await {
+            let mailbox = claim(&*storage, "7", "a").await;
+            for (side, expected) in [
+                ("a", OpenOutcome::Opened),
+                ("b", OpenOutcome::Opened),
+                ("c", OpenOutcome::Crowded),
+                // Reopening by a known side resumes
+                ("a", OpenOutcome::Opened),
+            ] {
+                assert_eq!(
+                    storage.open_mailbox(&mailbox, side, "app").await.unwrap(),
+                    expected,
+                    "{} {}",
+                    name,
+                    side
+                );
+            }
+
+            let first = storage
+                .add_message(&mailbox, "a", "pake", "aa")
+                .await
+                .unwrap()
+                .unwrap();
+            let second = storage
+                .add_message(&mailbox, "b", "pake", "bb")
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!((first.id, second.id), (1, 2), "{}", name);
+
+            let after = storage.get_messages_after(&mailbox, 1).await.unwrap();
+            assert_eq!(after.len(), 1, "{}", name);
+            assert_eq!(after[0].side, "b");
+            assert_eq!(after[0].body, "bb");
+
+            assert_eq!(
+                storage.open_mailbox("missing", "a", "app").await.unwrap(),
+                OpenOutcome::Unknown
+            );
+            assert!(storage
+                .add_message("missing", "a", "pake", "aa")
+                .await
+                .unwrap()
+                .is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_close_removes_mailbox_when_both_sides_leave() {
+        for (name, storage) in backends().await {
+            let mailbox = claim(&*storage, "9", "a").await;
+            storage.open_mailbox(&mailbox, "a", "app").await.unwrap();
+            storage.open_mailbox(&mailbox, "b", "app").await.unwrap();
+            storage.add_message(&mailbox, "a", "0", "00").await.unwrap();
+
+            // A side that never opened the mailbox leaves no mood
+            assert_eq!(
+                storage
+                    .close_mailbox(&mailbox, "c", Some(Mood::Scary))
+                    .await
+                    .unwrap(),
+                CloseOutcome::Closed { was_open: false },
+                "{}",
+                name
+            );
+            assert_eq!(
+                storage
+                    .close_mailbox(&mailbox, "a", Some(Mood::Happy))
+                    .await
+                    .unwrap(),
+                CloseOutcome::Closed { was_open: true },
+                "{}",
+                name
+            );
+            let CloseOutcome::Removed {
+                was_open: true,
+                mut moods,
+                ..
+            } = storage
+                .close_mailbox(&mailbox, "b", Some(Mood::Lonely))
+                .await
+                .unwrap()
+            else {
+                panic!("{} kept the mailbox", name);
+            };
+            moods.sort_by_key(|m| m.as_str());
+            assert_eq!(moods, vec![Mood::Happy, Mood::Lonely], "{}", name);
+            assert_eq!(
+                storage.close_mailbox(&mailbox, "b", None).await.unwrap(),
+                CloseOutcome::NotFound,
+                "{}",
+                name
+            );
+            assert!(storage
+                .get_messages_after(&mailbox, 0)
+                .await
+                .unwrap()
+                .is_empty());
+
+            // The nameplate no longer leads anywhere
+            assert_eq!(
+                storage
+                    .claim_nameplate("9", "b", "app", false)
+                    .await
+                    .unwrap(),
+                ClaimOutcome::Unknown,
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_expired() {
+        for storage in [
+            Arc::new(MemoryStorage::new(0)) as Arc<dyn Storage>,
+            Arc::new(SqliteStorage::open_in_memory(0).unwrap()),
+            redis(0).await,
+        ] {
+            storage.allocate_nameplate("app").await.unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+            let report = storage.cleanup_expired().await.unwrap();
+            assert_eq!(report.nameplates, 1);
+            assert_eq!(report.mailboxes.len(), 1);
+            assert_eq!(storage.counts().await.unwrap(), (0, 0));
+        }
+    }
+}
@@ -0,0 +1,336 @@
+//! Property tests of the nameplate and mailbox lifecycle
+//!
+//! Random operation sequences run against every backend and against a
+//! simple reference model; each outcome has to match the model.
+
+use proptest::prelude::*;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+use super::*;
+use crate::resp::stub::RedisStub;
+
+const APPS: [&str; 2] = ["app-a", "app-b"];
+const SIDES: [&str; 3] = ["a", "b", "c"];
+/// Nameplates nobody allocated, outside the allocation range
+const CHOSEN: [&str; 2] = ["5001", "5002"];
+
+#[derive(Debug, Clone)]
+enum Op {
+    Allocate {
+        app: usize,
+    },
+    Claim {
+        nameplate: usize,
+        side: usize,
+        app: usize,
+        create: bool,
+    },
+    Release {
+        nameplate: usize,
+        side: usize,
+    },
+    Open {
+        mailbox: usize,
+        side: usize,
+        app: usize,
+    },
+    Close {
+        mailbox: usize,
+        side: usize,
+    },
+    List {
+        app: usize,
+    },
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        1 => (0..2usize).prop_map(|app| Op::Allocate { app }),
+        3 => (0..8usize, 0..3usize, 0..2usize, any::<bool>()).prop_map(
+            |(nameplate, side, app, create)| Op::Claim {
+                nameplate,
+                side,
+                app,
+                create,
+            }
+        ),
+        1 => (0..8usize, 0..3usize).prop_map(|(nameplate, side)| Op::Release { nameplate, side }),
+        3 => (0..8usize, 0..3usize, 0..2usize)
+            .prop_map(|(mailbox, side, app)| Op::Open { mailbox, side, app }),
+        1 => (0..8usize, 0..3usize).prop_map(|(mailbox, side)| Op::Close { mailbox, side }),
+        1 => (0..2usize).prop_map(|app| Op::List { app }),
+    ]
+}
+
+struct NameplateModel {
+    app: &'static str,
+    /// Learned from the first successful claim
+    mailbox: Option<String>,
+    sides: BTreeSet<&'static str>,
+}
+
+struct MailboxModel {
+    app: &'static str,
+    sides: BTreeSet<&'static str>,
+    removed: bool,
+}
+
+/// Reference model of the lifecycle rules
+#[derive(Default)]
+struct Model {
+    nameplates: HashMap<String, NameplateModel>,
+    mailboxes: HashMap<String, MailboxModel>,
+    /// Nameplate IDs operations can refer to
+    known_nameplates: Vec<String>,
+    /// Mailbox IDs operations can refer to, including a missing one
+    known_mailboxes: Vec<String>,
+}
+
+impl Model {
+    fn new() -> Self {
+        Self {
+            known_nameplates: CHOSEN.iter().map(|id| id.to_string()).collect(),
+            known_mailboxes: vec!["missing".to_string()],
+            ..Default::default()
+        }
+    }
+
+    fn pick_nameplate(&self, index: usize) -> String {
+        self.known_nameplates[index % self.known_nameplates.len()].clone()
+    }
+
+    fn pick_mailbox(&self, index: usize) -> String {
+        self.known_mailboxes[index % self.known_mailboxes.len()].clone()
+    }
+
+    /// Whether a nameplate exists for `app` and still leads to a mailbox
+    fn visible(&self, np: &NameplateModel, app: &str) -> bool {
+        let alive = match &np.mailbox {
+            Some(id) => !self.mailboxes[id].removed,
+            None => true,
+        };
+        np.app == app && alive
+    }
+
+    fn learn_mailbox(&mut self, id: &str, app: &'static str) {
+        if !self.mailboxes.contains_key(id) {
+            self.mailboxes.insert(
+                id.to_string(),
+                MailboxModel {
+                    app,
+                    sides: BTreeSet::new(),
+                    removed: false,
+                },
+            );
+            self.known_mailboxes.push(id.to_string());
+        }
+    }
+
+    fn claim(
+        &mut self,
+        id: &str,
+        side: &'static str,
+        app: &'static str,
+        create: bool,
+        actual: &ClaimOutcome,
+    ) -> ClaimOutcome {
+        let Some(np) = self.nameplates.get(id) else {
+            if !create {
+                return ClaimOutcome::Unknown;
+            }
+            let ClaimOutcome::Claimed { mailbox_id } = actual else {
+                return ClaimOutcome::Claimed {
+                    mailbox_id: "<new>".to_string(),
+                };
+            };
+            self.nameplates.insert(
+                id.to_string(),
+                NameplateModel {
+                    app,
+                    mailbox: Some(mailbox_id.clone()),
+                    sides: BTreeSet::from([side]),
+                },
+            );
+            self.learn_mailbox(mailbox_id, app);
+            return actual.clone();
+        };
+
+        if !self.visible(np, app) {
+            return ClaimOutcome::Unknown;
+        }
+        if !np.sides.contains(side) && np.sides.len() >= 2 {
+            return ClaimOutcome::Crowded;
+        }
+
+        let mailbox_id = match (&np.mailbox, actual) {
+            (Some(known), _) => known.clone(),
+            (None, ClaimOutcome::Claimed { mailbox_id }) => mailbox_id.clone(),
+            (None, _) => "<allocated>".to_string(),
+        };
+        let np = self.nameplates.get_mut(id).unwrap();
+        np.sides.insert(side);
+        np.mailbox = Some(mailbox_id.clone());
+        self.learn_mailbox(&mailbox_id, app);
+        ClaimOutcome::Claimed { mailbox_id }
+    }
+
+    fn release(&mut self, id: &str, side: &'static str) -> ReleaseOutcome {
+        let Some(np) = self.nameplates.get_mut(id) else {
+            return ReleaseOutcome::NotClaimed;
+        };
+        if !np.sides.remove(side) {
+            return ReleaseOutcome::NotClaimed;
+        }
+        if np.sides.is_empty() {
+            self.nameplates.remove(id);
+        }
+        ReleaseOutcome::Released
+    }
+
+    fn open(&mut self, id: &str, side: &'static str, app: &str) -> OpenOutcome {
+        match self.mailboxes.get_mut(id) {
+            Some(mb) if !mb.removed && mb.app == app => {
+                if !mb.sides.contains(side) && mb.sides.len() >= 2 {
+                    return OpenOutcome::Crowded;
+                }
+                mb.sides.insert(side);
+                OpenOutcome::Opened
+            }
+            _ => OpenOutcome::Unknown,
+        }
+    }
+
+    /// Expected close outcome; for removals only `was_open` is compared
+    fn close(&mut self, id: &str, side: &str) -> CloseOutcome {
+        match self.mailboxes.get_mut(id) {
+            Some(mb) if !mb.removed => {
+                let was_open = mb.sides.remove(side);
+                if mb.sides.is_empty() {
+                    mb.removed = true;
+                    CloseOutcome::Removed {
+                        was_open,
+                        created_at: DateTime::<Utc>::MIN_UTC,
+                        moods: Vec::new(),
+                    }
+                } else {
+                    CloseOutcome::Closed { was_open }
+                }
+            }
+            _ => CloseOutcome::NotFound,
+        }
+    }
+
+    fn list(&self, app: &str) -> BTreeSet<String> {
+        self.nameplates
+            .iter()
+            .filter(|(_, np)| self.visible(np, app))
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+}
+
+/// Apply `ops` to `storage` and check every outcome against the model
+async fn check(name: &str, storage: &dyn Storage, ops: &[Op]) {
+    let mut model = Model::new();
+
+    for (step, op) in ops.iter().enumerate() {
+        let context = format!("{} step {}: {:?}", name, step, op);
+        match *op {
+            Op::Allocate { app } => {
+                let id = storage.allocate_nameplate(APPS[app]).await.unwrap();
+                assert!(!model.nameplates.contains_key(&id), "{}", context);
+                model.nameplates.insert(
+                    id.clone(),
+                    NameplateModel {
+                        app: APPS[app],
+                        mailbox: None,
+                        sides: BTreeSet::new(),
+                    },
+                );
+                if !model.known_nameplates.contains(&id) {
+                    model.known_nameplates.push(id);
+                }
+            }
+            Op::Claim {
+                nameplate,
+                side,
+                app,
+                create,
+            } => {
+                let id = model.pick_nameplate(nameplate);
+                let actual = storage
+                    .claim_nameplate(&id, SIDES[side], APPS[app], create)
+                    .await
+                    .unwrap();
+                let expected = model.claim(&id, SIDES[side], APPS[app], create, &actual);
+                assert_eq!(actual, expected, "{}", context);
+            }
+            Op::Release { nameplate, side } => {
+                let id = model.pick_nameplate(nameplate);
+                let actual = storage.release_nameplate(&id, SIDES[side]).await.unwrap();
+                assert_eq!(actual, model.release(&id, SIDES[side]), "{}", context);
+            }
+            Op::Open { mailbox, side, app } => {
+                let id = model.pick_mailbox(mailbox);
+                let actual = storage
+                    .open_mailbox(&id, SIDES[side], APPS[app])
+                    .await
+                    .unwrap();
+                assert_eq!(
+                    actual,
+                    model.open(&id, SIDES[side], APPS[app]),
+                    "{}",
+                    context
+                );
+            }
+            Op::Close { mailbox, side } => {
+                let id = model.pick_mailbox(mailbox);
+                let actual = storage.close_mailbox(&id, SIDES[side], None).await.unwrap();
+                match (model.close(&id, SIDES[side]), &actual) {
+                    (
+                        CloseOutcome::Removed { was_open, .. },
+                        CloseOutcome::Removed {
+                            was_open: actual, ..
+                        },
+                    ) => assert_eq!(*actual, was_open, "{}", context),
+                    (expected, _) => assert_eq!(actual, expected, "{}", context),
+                }
+            }
+            Op::List { app } => {
+                let actual: BTreeSet<String> = storage
+                    .list_nameplates(APPS[app])
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .collect();
+                assert_eq!(actual, model.list(APPS[app]), "{}", context);
+            }
+        }
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(48))]
+
+    #[test]
+    fn test_backends_follow_lifecycle_model(ops in prop::collection::vec(op(), 1..40)) {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let stub = RedisStub::start().await;
+            let client = Arc::new(resp::Client::new(stub.addr()));
+            let backends: [(&str, Arc<dyn Storage>); 3] = [
+                ("memory", Arc::new(MemoryStorage::new(300))),
+                ("sqlite", Arc::new(SqliteStorage::open_in_memory(300).unwrap())),
+                ("redis", Arc::new(RedisStorage::connect(client, 300).await.unwrap())),
+            ];
+            for (name, storage) in backends {
+                check(name, &*storage, &ops).await;
+            }
+        });
+    }
+}
@@ -0,0 +1,540 @@
+//! Redis storage backend for clustered deployments
+//!
+//! Every server replica talks to the same Redis-protocol server, so a
+//! nameplate allocated on one node can be claimed on another. Nameplates
+//! and mailboxes use key expiry for their timeout, with sorted-set indexes
+//! (scored by creation time) for listing, counting and cleanup.
+//!
+//! Several nodes may claim a nameplate or open a mailbox at once, so the
+//! check for a free slot and the join are one server-side script.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use super::{
+    ClaimOutcome, CleanupReport, CloseOutcome, OpenOutcome, ReleaseOutcome, Result, Storage,
+    StorageError, ALLOCATE_ATTEMPTS,
+};
+use crate::models::{generate_nameplate_id, MailboxMessage, Mood};
+use crate::resp::{Client, RespError, Value};
+
+const NAMEPLATE_INDEX: &str = "securebeam:nameplates";
+const MAILBOX_INDEX: &str = "securebeam:mailboxes";
+
+/// Sides a nameplate or mailbox admits
+const MAX_SIDES: &str = "2";
+
+/// Add side ARGV[1] to the set KEYS[1] unless it already holds ARGV[3]
+/// other sides, then set its expiry to ARGV[2] ms; returns 1 if the side
+/// is in the set
+pub(crate) const JOIN_SIDES: &str = "\
+if redis.call('SISMEMBER', KEYS[1], ARGV[1]) == 0 then
+  if redis.call('SCARD', KEYS[1]) >= tonumber(ARGV[3]) then
+    return 0
+  end
+  redis.call('SADD', KEYS[1], ARGV[1])
+end
+redis.call('PEXPIRE', KEYS[1], ARGV[2])
+return 1
+";
+
+impl From<RespError> for StorageError {
+    fn from(e: RespError) -> Self {
+        StorageError::Redis(e.to_string())
+    }
+}
+
+/// Mailbox metadata stored under the mailbox key
+#[derive(Serialize, Deserialize)]
+struct StoredMailbox {
+    appid: String,
+    /// Creation time in milliseconds since the Unix epoch
+    created_at: i64,
+}
+
+/// A message as stored in the mailbox list
+#[derive(Serialize, Deserialize)]
+struct StoredMessage {
+    id: u64,
+    side: String,
+    phase: String,
+    body: String,
+    /// Milliseconds since the Unix epoch
+    added_at: i64,
+}
+
+impl From<StoredMessage> for MailboxMessage {
+    fn from(m: StoredMessage) -> Self {
+        MailboxMessage {
+            id: m.id,
+            side: m.side,
+            phase: m.phase,
+            body: m.body,
+            added_at: from_ms(m.added_at),
+        }
+    }
+}
+
+fn nameplate_key(id: &str) -> String {
+    format!("securebeam:nameplate:{}", id)
+}
+
+fn nameplate_sides_key(id: &str) -> String {
+    format!("securebeam:nameplate:{}:sides", id)
+}
+
+fn mailbox_key(id: &str) -> String {
+    format!("securebeam:mailbox:{}", id)
+}
+
+fn mailbox_sides_key(id: &str) -> String {
+    format!("securebeam:mailbox:{}:sides", id)
+}
+
+fn mailbox_moods_key(id: &str) -> String {
+    format!("securebeam:mailbox:{}:moods", id)
+}
+
+fn mailbox_next_key(id: &str) -> String {
+    format!("securebeam:mailbox:{}:next", id)
+}
+
+fn mailbox_messages_key(id: &str) -> String {
+    format!("securebeam:mailbox:{}:messages", id)
+}
+
+fn from_ms(ms: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp_millis(ms).unwrap_or_default()
+}
+
+fn to_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).expect("stored records always serialize")
+}
+
+/// Storage shared between server replicas through Redis
+pub struct RedisStorage {
+    client: Arc<Client>,
+    /// Timeout in seconds for new nameplates and mailboxes
+    timeout_secs: u64,
+}
+
+impl RedisStorage {
+    /// Connect to the Redis server and check that it responds
+    pub async fn connect(client: Arc<Client>, timeout_secs: u64) -> Result<Self> {
+        client.command(&[b"PING"]).await?;
+        Ok(Self {
+            client,
+            timeout_secs,
+        })
+    }
+
+    async fn cmd(&self, args: &[&str]) -> Result<Value> {
+        let args: Vec<&[u8]> = args.iter().map(|a| a.as_bytes()).collect();
+        Ok(self.client.command(&args).await?)
+    }
+
+    async fn int(&self, args: &[&str]) -> Result<i64> {
+        Ok(self.cmd(args).await?.as_int()?)
+    }
+
+    fn timeout_ms(&self) -> String {
+        // PX requires a positive value
+        (self.timeout_secs * 1000).max(1).to_string()
+    }
+
+    /// Load mailbox metadata, or `None` if it does not exist or has expired
+    async fn get_mailbox(&self, mailbox_id: &str) -> Result<Option<StoredMailbox>> {
+        let Some(json) = self
+            .cmd(&["GET", &mailbox_key(mailbox_id)])
+            .await?
+            .into_string()?
+        else {
+            return Ok(None);
+        };
+        serde_json::from_str(&json)
+            .map(Some)
+            .map_err(|e| StorageError::Redis(format!("corrupt mailbox record: {}", e)))
+    }
+
+    /// Milliseconds until a mailbox created at `created_at` expires
+    fn remaining_ms(&self, created_at: i64) -> String {
+        let expires_at = created_at + self.timeout_secs as i64 * 1000;
+        (expires_at - Utc::now().timestamp_millis())
+            .max(1)
+            .to_string()
+    }
+
+    /// Create a new mailbox and return its ID
+    async fn create_mailbox(&self, appid: &str) -> Result<String> {
+        let id = Uuid::new_v4().to_string();
+        let created_at = Utc::now().timestamp_millis();
+        let record = to_json(&StoredMailbox {
+            appid: appid.to_string(),
+            created_at,
+        });
+        self.cmd(&["SET", &mailbox_key(&id), &record, "PX", &self.timeout_ms()])
+            .await?;
+        self.cmd(&["ZADD", MAILBOX_INDEX, &created_at.to_string(), &id])
+            .await?;
+        Ok(id)
+    }
+
+    /// Reserve a nameplate ID pointing at `mailbox_id`, false if it is taken
+    async fn reserve_nameplate(&self, nameplate_id: &str, mailbox_id: &str) -> Result<bool> {
+        let reply = self
+            .cmd(&[
+                "SET",
+                &nameplate_key(nameplate_id),
+                mailbox_id,
+                "NX",
+                "PX",
+                &self.timeout_ms(),
+            ])
+            .await?;
+        if reply == Value::Bulk(None) {
+            return Ok(false);
+        }
+        let now = Utc::now().timestamp_millis().to_string();
+        self.cmd(&["ZADD", NAMEPLATE_INDEX, &now, nameplate_id])
+            .await?;
+        Ok(true)
+    }
+
+    /// Add `side` to a set of sides unless it is full, keeping the set for
+    /// `ttl_ms`; false if the set was full
+    async fn join_sides(&self, key: &str, side: &str, ttl_ms: &str) -> Result<bool> {
+        let joined = self
+            .int(&["EVAL", JOIN_SIDES, "1", key, side, ttl_ms, MAX_SIDES])
+            .await?;
+        Ok(joined == 1)
+    }
+
+    /// Record a side's claim unless two other sides hold one
+    async fn claim_side(&self, nameplate_id: &str, side: &str) -> Result<bool> {
+        self.join_sides(&nameplate_sides_key(nameplate_id), side, &self.timeout_ms())
+            .await
+    }
+
+    /// Whether a mailbox exists and belongs to `appid`
+    async fn owned_by(&self, mailbox_id: &str, appid: &str) -> Result<bool> {
+        Ok(self
+            .get_mailbox(mailbox_id)
+            .await?
+            .is_some_and(|mailbox| mailbox.appid == appid))
+    }
+
+    /// Delete every key of a mailbox
+    async fn delete_mailbox(&self, mailbox_id: &str) -> Result<()> {
+        self.cmd(&[
+            "DEL",
+            &mailbox_key(mailbox_id),
+            &mailbox_sides_key(mailbox_id),
+            &mailbox_moods_key(mailbox_id),
+            &mailbox_next_key(mailbox_id),
+            &mailbox_messages_key(mailbox_id),
+        ])
+        .await?;
+        Ok(())
+    }
+
+    /// Index members with their creation time
+    async fn index(&self, index: &str) -> Result<Vec<(String, i64)>> {
+        let flat = self
+            .cmd(&["ZRANGE", index, "0", "-1", "WITHSCORES"])
+            .await?
+            .into_strings()?;
+        Ok(flat
+            .chunks(2)
+            .filter_map(|pair| match pair {
+                [id, score] => Some((id.clone(), score.parse::<f64>().ok()? as i64)),
+                _ => None,
+            })
+            .collect())
+    }
+}
+
+#[async_trait]
+impl Storage for RedisStorage {
+    async fn allocate_nameplate(&self, appid: &str) -> Result<String> {
+        let mailbox_id = self.create_mailbox(appid).await?;
+
+        // Generate unique nameplate ID
+        for _ in 0..ALLOCATE_ATTEMPTS {
+            let candidate = generate_nameplate_id();
+            if self.reserve_nameplate(&candidate, &mailbox_id).await? {
+                return Ok(candidate);
+            }
+        }
+        self.delete_mailbox(&mailbox_id).await?;
+        self.cmd(&["ZREM", MAILBOX_INDEX, &mailbox_id]).await?;
+        Err(StorageError::NoFreeNameplates)
+    }
+
+    async fn claim_nameplate(
+        &self,
+        nameplate_id: &str,
+        side: &str,
+        appid: &str,
+        create: bool,
+    ) -> Result<ClaimOutcome> {
+        loop {
+            if let Some(mailbox_id) = self
+                .cmd(&["GET", &nameplate_key(nameplate_id)])
+                .await?
+                .into_string()?
+            {
+                if !self.owned_by(&mailbox_id, appid).await? {
+                    return Ok(ClaimOutcome::Unknown);
+                }
+                if !self.claim_side(nameplate_id, side).await? {
+                    return Ok(ClaimOutcome::Crowded);
+                }
+                return Ok(ClaimOutcome::Claimed { mailbox_id });
+            }
+
+            if !create {
+                return Ok(ClaimOutcome::Unknown);
+            }
+
+            // Create the nameplate for a side that chose its own code
+            let mailbox_id = self.create_mailbox(appid).await?;
+            if self.reserve_nameplate(nameplate_id, &mailbox_id).await? {
+                self.claim_side(nameplate_id, side).await?;
+                return Ok(ClaimOutcome::Claimed { mailbox_id });
+            }
+
+            // Another node created it first; use theirs
+            self.delete_mailbox(&mailbox_id).await?;
+            self.cmd(&["ZREM", MAILBOX_INDEX, &mailbox_id]).await?;
+        }
+    }
+
+    async fn release_nameplate(&self, nameplate_id: &str, side: &str) -> Result<ReleaseOutcome> {
+        let sides = nameplate_sides_key(nameplate_id);
+        if self.int(&["SREM", &sides, side]).await? == 0 {
+            return Ok(ReleaseOutcome::NotClaimed);
+        }
+        if self.int(&["SCARD", &sides]).await? == 0 {
+            self.cmd(&["DEL", &nameplate_key(nameplate_id), &sides])
+                .await?;
+            self.cmd(&["ZREM", NAMEPLATE_INDEX, nameplate_id]).await?;
+        }
+        Ok(ReleaseOutcome::Released)
+    }
+
+    async fn list_nameplates(&self, appid: &str) -> Result<Vec<String>> {
+        let mut nameplates = Vec::new();
+        for (id, _) in self.index(NAMEPLATE_INDEX).await? {
+            let Some(mailbox_id) = self
+                .cmd(&["GET", &nameplate_key(&id)])
+                .await?
+                .into_string()?
+            else {
+                continue;
+            };
+            if self.owned_by(&mailbox_id, appid).await? {
+                nameplates.push(id);
+            }
+        }
+        Ok(nameplates)
+    }
+
+    async fn open_mailbox(&self, mailbox_id: &str, side: &str, appid: &str) -> Result<OpenOutcome> {
+        let Some(mailbox) = self
+            .get_mailbox(mailbox_id)
+            .await?
+            .filter(|mailbox| mailbox.appid == appid)
+        else {
+            return Ok(OpenOutcome::Unknown);
+        };
+
+        let sides = mailbox_sides_key(mailbox_id);
+        let ttl = self.remaining_ms(mailbox.created_at);
+        if self.join_sides(&sides, side, &ttl).await? {
+            Ok(OpenOutcome::Opened)
+        } else {
+            Ok(OpenOutcome::Crowded)
+        }
+    }
+
+    async fn add_message(
+        &self,
+        mailbox_id: &str,
+        side: &str,
+        phase: &str,
+        body: &str,
+    ) -> Result<Option<MailboxMessage>> {
+        let Some(mailbox) = self.get_mailbox(mailbox_id).await? else {
+            return Ok(None);
+        };
+        let ttl = self.remaining_ms(mailbox.created_at);
+
+        let next = mailbox_next_key(mailbox_id);
+        let id = self.int(&["INCR", &next]).await? as u64;
+        self.cmd(&["PEXPIRE", &next, &ttl]).await?;
+
+        let message = StoredMessage {
+            id,
+            side: side.to_string(),
+            phase: phase.to_string(),
+            body: body.to_string(),
+            added_at: Utc::now().timestamp_millis(),
+        };
+        let list = mailbox_messages_key(mailbox_id);
+        self.cmd(&["RPUSH", &list, &to_json(&message)]).await?;
+        self.cmd(&["PEXPIRE", &list, &ttl]).await?;
+
+        Ok(Some(message.into()))
+    }
+
+    async fn get_messages_after(
+        &self,
+        mailbox_id: &str,
+        after_id: u64,
+    ) -> Result<Vec<MailboxMessage>> {
+        let raw = self
+            .cmd(&["LRANGE", &mailbox_messages_key(mailbox_id), "0", "-1"])
+            .await?
+            .into_strings()?;
+
+        // Concurrent adders may push out of ID order
+        let mut messages: Vec<MailboxMessage> = raw
+            .iter()
+            .filter_map(|json| serde_json::from_str::<StoredMessage>(json).ok())
+            .filter(|m| m.id > after_id)
+            .map(MailboxMessage::from)
+            .collect();
+        messages.sort_by_key(|m| m.id);
+        Ok(messages)
+    }
+
+    async fn close_mailbox(
+        &self,
+        mailbox_id: &str,
+        side: &str,
+        mood: Option<Mood>,
+    ) -> Result<CloseOutcome> {
+        let Some(mailbox) = self.get_mailbox(mailbox_id).await? else {
+            return Ok(CloseOutcome::NotFound);
+        };
+
+        let sides = mailbox_sides_key(mailbox_id);
+        let moods = mailbox_moods_key(mailbox_id);
+        let was_open = self.int(&["SREM", &sides, side]).await? == 1;
+        if let (true, Some(mood)) = (was_open, mood) {
+            self.cmd(&["RPUSH", &moods, mood.as_str()]).await?;
+            self.cmd(&["PEXPIRE", &moods, &self.remaining_ms(mailbox.created_at)])
+                .await?;
+        }
+        if self.int(&["SCARD", &sides]).await? > 0 {
+            return Ok(CloseOutcome::Closed { was_open });
+        }
+
+        let moods = self
+            .cmd(&["LRANGE", &moods, "0", "-1"])
+            .await?
+            .into_strings()?;
+        self.delete_mailbox(mailbox_id).await?;
+        self.cmd(&["ZREM", MAILBOX_INDEX, mailbox_id]).await?;
+        Ok(CloseOutcome::Removed {
+            was_open,
+            created_at: from_ms(mailbox.created_at),
+            moods: moods.iter().filter_map(|m| Mood::parse(m)).collect(),
+        })
+    }
+
+    async fn cleanup_expired(&self) -> Result<CleanupReport> {
+        let mut report = CleanupReport::default();
+
+        // Keys expire on their own; prune index entries whose key is gone.
+        // Only the node whose ZREM succeeds reports the removal.
+        for (id, _) in self.index(NAMEPLATE_INDEX).await? {
+            if self.int(&["EXISTS", &nameplate_key(&id)]).await? == 0 {
+                self.cmd(&["DEL", &nameplate_sides_key(&id)]).await?;
+                if self.int(&["ZREM", NAMEPLATE_INDEX, &id]).await? == 1 {
+                    report.nameplates += 1;
+                }
+            }
+        }
+
+        for (id, created_at) in self.index(MAILBOX_INDEX).await? {
+            if self.int(&["EXISTS", &mailbox_key(&id)]).await? == 0 {
+                self.delete_mailbox(&id).await?;
+                if self.int(&["ZREM", MAILBOX_INDEX, &id]).await? == 1 {
+                    report.mailboxes.push(from_ms(created_at));
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    async fn counts(&self) -> Result<(usize, usize)> {
+        let nameplates = self.int(&["ZCARD", NAMEPLATE_INDEX]).await?;
+        let mailboxes = self.int(&["ZCARD", MAILBOX_INDEX]).await?;
+        Ok((nameplates as usize, mailboxes as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::stub::RedisStub;
+
+    /// Two server nodes sharing one Redis
+    async fn nodes() -> [Arc<RedisStorage>; 2] {
+        let stub = RedisStub::start().await;
+        let node = || async {
+            let client = Arc::new(Client::new(stub.addr()));
+            Arc::new(RedisStorage::connect(client, 300).await.unwrap())
+        };
+        [node().await, node().await]
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_claims_admit_two_sides() {
+        let nodes = nodes().await;
+        for _ in 0..10 {
+            let nameplate = nodes[0].allocate_nameplate("app").await.unwrap();
+            let claims = (0..6).map(|i| {
+                let (node, nameplate) = (nodes[i % 2].clone(), nameplate.clone());
+                tokio::spawn(async move {
+                    node.claim_nameplate(&nameplate, &format!("side-{}", i), "app", false)
+                        .await
+                        .unwrap()
+                })
+            });
+            let outcomes = futures::future::join_all(claims).await;
+            let claimed = outcomes
+                .iter()
+                .filter(|o| matches!(o, Ok(ClaimOutcome::Claimed { .. })))
+                .count();
+            assert_eq!(claimed, 2, "{:?}", outcomes);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_opens_admit_two_sides() {
+        let nodes = nodes().await;
+        for _ in 0..10 {
+            let mailbox = nodes[0].create_mailbox("app").await.unwrap();
+            let opens = (0..6).map(|i| {
+                let (node, mailbox) = (nodes[i % 2].clone(), mailbox.clone());
+                tokio::spawn(async move {
+                    node.open_mailbox(&mailbox, &format!("side-{}", i), "app")
+                        .await
+                        .unwrap()
+                })
+            });
+            let outcomes = futures::future::join_all(opens).await;
+            let opened = outcomes
+                .iter()
+                .filter(|o| matches!(o, Ok(OpenOutcome::Opened)))
+                .count();
+            assert_eq!(opened, 2, "{:?}", outcomes);
+        }
+    }
+}
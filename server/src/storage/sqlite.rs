@@ -0,0 +1,580 @@
+//! SQLite storage backend
+//!
+//! Nameplates, mailboxes and messages are persisted so that they survive a
+//! server restart. Queries run on the blocking thread pool.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use super::{
+    ClaimOutcome, CleanupReport, CloseOutcome, OpenOutcome, ReleaseOutcome, Result, Storage,
+    StorageError, ALLOCATE_ATTEMPTS,
+};
+use crate::models::{generate_nameplate_id, MailboxMessage, Mood};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS nameplates (
+    id TEXT PRIMARY KEY,
+    mailbox_id TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS nameplate_sides (
+    nameplate_id TEXT NOT NULL,
+    side TEXT NOT NULL,
+    PRIMARY KEY (nameplate_id, side)
+);
+CREATE TABLE IF NOT EXISTS mailboxes (
+    id TEXT PRIMARY KEY,
+    appid TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS mailbox_sides (
+    mailbox_id TEXT NOT NULL,
+    side TEXT NOT NULL,
+    PRIMARY KEY (mailbox_id, side)
+);
+CREATE TABLE IF NOT EXISTS mailbox_moods (
+    mailbox_id TEXT NOT NULL,
+    side TEXT NOT NULL,
+    mood TEXT NOT NULL,
+    PRIMARY KEY (mailbox_id, side)
+);
+CREATE TABLE IF NOT EXISTS messages (
+    mailbox_id TEXT NOT NULL,
+    id INTEGER NOT NULL,
+    side TEXT NOT NULL,
+    phase TEXT NOT NULL,
+    body TEXT NOT NULL,
+    added_at INTEGER NOT NULL,
+    PRIMARY KEY (mailbox_id, id)
+);
+";
+
+/// Storage backed by a SQLite database
+pub struct SqliteStorage {
+    conn: Arc<Mutex<Connection>>,
+    /// Timeout in seconds for new nameplates and mailboxes
+    timeout_secs: u64,
+}
+
+impl SqliteStorage {
+    /// Open (or create) the database at `path`
+    pub fn open(path: impl AsRef<Path>, timeout_secs: u64) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        Self::with_connection(conn, timeout_secs)
+    }
+
+    /// Open a private in-memory database
+    #[cfg(test)]
+    pub fn open_in_memory(timeout_secs: u64) -> Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?, timeout_secs)
+    }
+
+    fn with_connection(conn: Connection, timeout_secs: u64) -> Result<Self> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            timeout_secs,
+        })
+    }
+
+    /// Run `f` in a transaction on the blocking thread pool
+    async fn transaction<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Transaction, u64) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        let timeout_secs = self.timeout_secs;
+
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().unwrap_or_else(|e| e.into_inner());
+            let tx = conn.transaction()?;
+            let result = f(&tx, timeout_secs)?;
+            tx.commit()?;
+            Ok(result)
+        })
+        .await
+        .map_err(|e| StorageError::Task(e.to_string()))?
+    }
+}
+
+/// Current time in milliseconds since the Unix epoch
+fn now_ms() -> i64 {
+    Utc::now().timestamp_millis()
+}
+
+fn from_ms(ms: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp_millis(ms).unwrap_or_default()
+}
+
+/// Insert a new mailbox and return its ID
+fn insert_mailbox(tx: &Transaction, appid: &str, timeout_secs: u64) -> rusqlite::Result<String> {
+    let id = Uuid::new_v4().to_string();
+    let now = now_ms();
+    tx.execute(
+        "INSERT INTO mailboxes (id, appid, created_at, expires_at) VALUES (?1, ?2, ?3, ?4)",
+        params![id, appid, now, now + timeout_secs as i64 * 1000],
+    )?;
+    Ok(id)
+}
+
+/// Insert a new nameplate pointing at `mailbox_id`
+fn insert_nameplate(
+    tx: &Transaction,
+    nameplate_id: &str,
+    mailbox_id: &str,
+    timeout_secs: u64,
+) -> rusqlite::Result<()> {
+    let now = now_ms();
+    tx.execute(
+        "INSERT INTO nameplates (id, mailbox_id, created_at, expires_at) VALUES (?1, ?2, ?3, ?4)",
+        params![
+            nameplate_id,
+            mailbox_id,
+            now,
+            now + timeout_secs as i64 * 1000
+        ],
+    )?;
+    Ok(())
+}
+
+/// Record a side's claim unless two other sides hold one
+fn claim_side(tx: &Transaction, nameplate_id: &str, side: &str) -> rusqlite::Result<bool> {
+    let sides: Vec<String> = tx
+        .prepare("SELECT side FROM nameplate_sides WHERE nameplate_id = ?1")?
+        .query_map(params![nameplate_id], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    if sides.contains(&side.to_string()) {
+        return Ok(true);
+    }
+    if sides.len() >= 2 {
+        return Ok(false);
+    }
+    tx.execute(
+        "INSERT INTO nameplate_sides (nameplate_id, side) VALUES (?1, ?2)",
+        params![nameplate_id, side],
+    )?;
+    Ok(true)
+}
+
+/// Delete a mailbox with its sides and messages
+fn delete_mailbox(tx: &Transaction, mailbox_id: &str) -> rusqlite::Result<()> {
+    tx.execute(
+        "DELETE FROM messages WHERE mailbox_id = ?1",
+        params![mailbox_id],
+    )?;
+    tx.execute(
+        "DELETE FROM mailbox_sides WHERE mailbox_id = ?1",
+        params![mailbox_id],
+    )?;
+    tx.execute(
+        "DELETE FROM mailbox_moods WHERE mailbox_id = ?1",
+        params![mailbox_id],
+    )?;
+    tx.execute("DELETE FROM mailboxes WHERE id = ?1", params![mailbox_id])?;
+    Ok(())
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn allocate_nameplate(&self, appid: &str) -> Result<String> {
+        let appid = appid.to_string();
+        self.transaction(move |tx, timeout_secs| {
+            // Generate unique nameplate ID
+            let mut nameplate_id = None;
+            for _ in 0..ALLOCATE_ATTEMPTS {
+                let candidate = generate_nameplate_id();
+                let taken = tx
+                    .query_row(
+                        "SELECT 1 FROM nameplates WHERE id = ?1",
+                        params![candidate],
+                        |_| Ok(()),
+                    )
+                    .optional()?
+                    .is_some();
+                if !taken {
+                    nameplate_id = Some(candidate);
+                    break;
+                }
+            }
+            let Some(nameplate_id) = nameplate_id else {
+                return Ok(None);
+            };
+
+            let mailbox_id = insert_mailbox(tx, &appid, timeout_secs)?;
+            insert_nameplate(tx, &nameplate_id, &mailbox_id, timeout_secs)?;
+            Ok(Some(nameplate_id))
+        })
+        .await?
+        .ok_or(StorageError::NoFreeNameplates)
+    }
+
+    async fn claim_nameplate(
+        &self,
+        nameplate_id: &str,
+        side: &str,
+        appid: &str,
+        create: bool,
+    ) -> Result<ClaimOutcome> {
+        let (nameplate_id, side, appid) = (
+            nameplate_id.to_string(),
+            side.to_string(),
+            appid.to_string(),
+        );
+        self.transaction(move |tx, timeout_secs| {
+            let existing: Option<(String, i64, Option<String>)> = tx
+                .query_row(
+                    "SELECT n.mailbox_id, n.expires_at, m.appid FROM nameplates n
+                     LEFT JOIN mailboxes m ON m.id = n.mailbox_id WHERE n.id = ?1",
+                    params![nameplate_id],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                )
+                .optional()?;
+
+            if let Some((mailbox_id, expires_at, owner)) = existing {
+                if now_ms() > expires_at || owner.as_deref() != Some(appid.as_str()) {
+                    return Ok(ClaimOutcome::Unknown);
+                }
+                if !claim_side(tx, &nameplate_id, &side)? {
+                    return Ok(ClaimOutcome::Crowded);
+                }
+                return Ok(ClaimOutcome::Claimed { mailbox_id });
+            }
+
+            if !create {
+                return Ok(ClaimOutcome::Unknown);
+            }
+
+            // Create the nameplate for a side that chose its own code
+            let mailbox_id = insert_mailbox(tx, &appid, timeout_secs)?;
+            insert_nameplate(tx, &nameplate_id, &mailbox_id, timeout_secs)?;
+            claim_side(tx, &nameplate_id, &side)?;
+            Ok(ClaimOutcome::Claimed { mailbox_id })
+        })
+        .await
+    }
+
+    async fn release_nameplate(&self, nameplate_id: &str, side: &str) -> Result<ReleaseOutcome> {
+        let (nameplate_id, side) = (nameplate_id.to_string(), side.to_string());
+        self.transaction(move |tx, _| {
+            let released = tx.execute(
+                "DELETE FROM nameplate_sides WHERE nameplate_id = ?1 AND side = ?2",
+                params![nameplate_id, side],
+            )?;
+            if released == 0 {
+                return Ok(ReleaseOutcome::NotClaimed);
+            }
+
+            let remaining: i64 = tx.query_row(
+                "SELECT COUNT(*) FROM nameplate_sides WHERE nameplate_id = ?1",
+                params![nameplate_id],
+                |row| row.get(0),
+            )?;
+            if remaining == 0 {
+                tx.execute(
+                    "DELETE FROM nameplates WHERE id = ?1",
+                    params![nameplate_id],
+                )?;
+            }
+            Ok(ReleaseOutcome::Released)
+        })
+        .await
+    }
+
+    async fn list_nameplates(&self, appid: &str) -> Result<Vec<String>> {
+        let appid = appid.to_string();
+        self.transaction(move |tx, _| {
+            let mut stmt = tx.prepare(
+                "SELECT n.id FROM nameplates n
+                 JOIN mailboxes m ON m.id = n.mailbox_id WHERE m.appid = ?1",
+            )?;
+            let ids = stmt
+                .query_map(params![appid], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<String>>>()?;
+            Ok(ids)
+        })
+        .await
+    }
+
+    async fn open_mailbox(&self, mailbox_id: &str, side: &str, appid: &str) -> Result<OpenOutcome> {
+        let (mailbox_id, side, appid) =
+            (mailbox_id.to_string(), side.to_string(), appid.to_string());
+        self.transaction(move |tx, _| {
+            let expires_at: Option<i64> = tx
+                .query_row(
+                    "SELECT expires_at FROM mailboxes WHERE id = ?1 AND appid = ?2",
+                    params![mailbox_id, appid],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if expires_at.is_none_or(|expires_at| now_ms() > expires_at) {
+                return Ok(OpenOutcome::Unknown);
+            }
+
+            let sides: Vec<String> = tx
+                .prepare("SELECT side FROM mailbox_sides WHERE mailbox_id = ?1")?
+                .query_map(params![mailbox_id], |row| row.get(0))?
+                .collect::<rusqlite::Result<_>>()?;
+            if sides.contains(&side) {
+                return Ok(OpenOutcome::Opened);
+            }
+            if sides.len() >= 2 {
+                return Ok(OpenOutcome::Crowded);
+            }
+
+            tx.execute(
+                "INSERT INTO mailbox_sides (mailbox_id, side) VALUES (?1, ?2)",
+                params![mailbox_id, side],
+            )?;
+            Ok(OpenOutcome::Opened)
+        })
+        .await
+    }
+
+    async fn add_message(
+        &self,
+        mailbox_id: &str,
+        side: &str,
+        phase: &str,
+        body: &str,
+    ) -> Result<Option<MailboxMessage>> {
+        let (mailbox_id, side, phase, body) = (
+            mailbox_id.to_string(),
+            side.to_string(),
+            phase.to_string(),
+            body.to_string(),
+        );
+        self.transaction(move |tx, _| {
+            // Mailboxes are deleted once their last side closes them
+            let exists = tx
+                .query_row(
+                    "SELECT 1 FROM mailboxes WHERE id = ?1",
+                    params![mailbox_id],
+                    |_| Ok(()),
+                )
+                .optional()?
+                .is_some();
+            if !exists {
+                return Ok(None);
+            }
+
+            let id: i64 = tx.query_row(
+                "SELECT COALESCE(MAX(id), 0) + 1 FROM messages WHERE mailbox_id = ?1",
+                params![mailbox_id],
+                |row| row.get(0),
+            )?;
+            let added_at = now_ms();
+            tx.execute(
+                "INSERT INTO messages (mailbox_id, id, side, phase, body, added_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![mailbox_id, id, side, phase, body, added_at],
+            )?;
+
+            Ok(Some(MailboxMessage {
+                id: id as u64,
+                side,
+                phase,
+                body,
+                added_at: from_ms(added_at),
+            }))
+        })
+        .await
+    }
+
+    async fn get_messages_after(
+        &self,
+        mailbox_id: &str,
+        after_id: u64,
+    ) -> Result<Vec<MailboxMessage>> {
+        let mailbox_id = mailbox_id.to_string();
+        self.transaction(move |tx, _| {
+            let mut stmt = tx.prepare(
+                "SELECT id, side, phase, body, added_at FROM messages
+                 WHERE mailbox_id = ?1 AND id > ?2 ORDER BY id",
+            )?;
+            let messages = stmt
+                .query_map(params![mailbox_id, after_id as i64], |row| {
+                    Ok(MailboxMessage {
+                        id: row.get::<_, i64>(0)? as u64,
+                        side: row.get(1)?,
+                        phase: row.get(2)?,
+                        body: row.get(3)?,
+                        added_at: from_ms(row.get(4)?),
+                    })
+                })?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(messages)
+        })
+        .await
+    }
+
+    async fn close_mailbox(
+        &self,
+        mailbox_id: &str,
+        side: &str,
+        mood: Option<Mood>,
+    ) -> Result<CloseOutcome> {
+        let (mailbox_id, side) = (mailbox_id.to_string(), side.to_string());
+        self.transaction(move |tx, _| {
+            let mailbox: Option<(i64, i64)> = tx
+                .query_row(
+                    "SELECT created_at, expires_at FROM mailboxes WHERE id = ?1",
+                    params![mailbox_id],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()?;
+            let Some((created_at, expires_at)) = mailbox else {
+                return Ok(CloseOutcome::NotFound);
+            };
+
+            let was_open = tx.execute(
+                "DELETE FROM mailbox_sides WHERE mailbox_id = ?1 AND side = ?2",
+                params![mailbox_id, side],
+            )? > 0;
+            if let (true, Some(mood)) = (was_open, mood) {
+                tx.execute(
+                    "INSERT OR REPLACE INTO mailbox_moods (mailbox_id, side, mood)
+                     VALUES (?1, ?2, ?3)",
+                    params![mailbox_id, side, mood.as_str()],
+                )?;
+            }
+            let remaining: i64 = tx.query_row(
+                "SELECT COUNT(*) FROM mailbox_sides WHERE mailbox_id = ?1",
+                params![mailbox_id],
+                |row| row.get(0),
+            )?;
+
+            if remaining == 0 || now_ms() > expires_at {
+                let moods = tx
+                    .prepare("SELECT mood FROM mailbox_moods WHERE mailbox_id = ?1")?
+                    .query_map(params![mailbox_id], |row| row.get::<_, String>(0))?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                delete_mailbox(tx, &mailbox_id)?;
+                return Ok(CloseOutcome::Removed {
+                    was_open,
+                    created_at: from_ms(created_at),
+                    moods: moods.iter().filter_map(|m| Mood::parse(m)).collect(),
+                });
+            }
+            Ok(CloseOutcome::Closed { was_open })
+        })
+        .await
+    }
+
+    async fn cleanup_expired(&self) -> Result<CleanupReport> {
+        self.transaction(|tx, _| {
+            let now = now_ms();
+
+            tx.execute(
+                "DELETE FROM nameplate_sides WHERE nameplate_id IN
+                 (SELECT id FROM nameplates WHERE expires_at < ?1)",
+                params![now],
+            )?;
+            let nameplates =
+                tx.execute("DELETE FROM nameplates WHERE expires_at < ?1", params![now])?;
+
+            let expired: Vec<(String, i64)> = tx
+                .prepare("SELECT id, created_at FROM mailboxes WHERE expires_at < ?1")?
+                .query_map(params![now], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<rusqlite::Result<_>>()?;
+            for (id, _) in &expired {
+                delete_mailbox(tx, id)?;
+            }
+
+            Ok(CleanupReport {
+                nameplates,
+                mailboxes: expired.into_iter().map(|(_, at)| from_ms(at)).collect(),
+            })
+        })
+        .await
+    }
+
+    async fn counts(&self) -> Result<(usize, usize)> {
+        self.transaction(|tx, _| {
+            let nameplates: i64 =
+                tx.query_row("SELECT COUNT(*) FROM nameplates", [], |row| row.get(0))?;
+            let mailboxes: i64 =
+                tx.query_row("SELECT COUNT(*) FROM mailboxes", [], |row| row.get(0))?;
+            Ok((nameplates as usize, mailboxes as usize))
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_state_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mailbox.db");
+
+        let (nameplate, mailbox) = {
+            let storage = SqliteStorage::open(&path, 300).unwrap();
+            let nameplate = storage.allocate_nameplate("app").await.unwrap();
+            let ClaimOutcome::Claimed {
+                mailbox_id: mailbox,
+            } = storage
+                .claim_nameplate(&nameplate, "sender", "app", false)
+                .await
+                .unwrap()
+            else {
+                panic!("allocated nameplate was not claimable");
+            };
+            assert_eq!(
+                storage
+                    .open_mailbox(&mailbox, "sender", "app")
+                    .await
+                    .unwrap(),
+                OpenOutcome::Opened
+            );
+            storage
+                .add_message(&mailbox, "sender", "pake", "deadbeef")
+                .await
+                .unwrap();
+            (nameplate, mailbox)
+        };
+
+        // A fresh handle sees the same nameplate, mailbox and messages
+        let storage = SqliteStorage::open(&path, 300).unwrap();
+        let resumed = storage
+            .claim_nameplate(&nameplate, "receiver", "app", false)
+            .await
+            .unwrap();
+        assert_eq!(
+            resumed,
+            ClaimOutcome::Claimed {
+                mailbox_id: mailbox.clone()
+            }
+        );
+        for side in ["sender", "receiver"] {
+            assert_eq!(
+                storage.open_mailbox(&mailbox, side, "app").await.unwrap(),
+                OpenOutcome::Opened
+            );
+        }
+
+        let messages = storage.get_messages_after(&mailbox, 0).await.unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].side, "sender");
+        assert_eq!(messages[0].phase, "pake");
+        assert_eq!(messages[0].body, "deadbeef");
+
+        // Message IDs continue after the restart
+        let next = storage
+            .add_message(&mailbox, "receiver", "pake", "cafebabe")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(next.id, 2);
+    }
+}
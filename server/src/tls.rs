@@ -0,0 +1,215 @@
+//! Built-in TLS termination
+//!
+//! With a certificate and key configured the server speaks `wss://` itself
+//! instead of relying on a reverse proxy. Both are PEM files; they are read
+//! again on SIGHUP so renewed certificates take effect without a restart.
+//! A reload that fails keeps the current certificate.
+
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::ServerConfig;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Errors loading the certificate and key
+#[derive(Debug, thiserror::Error)]
+pub enum TlsError {
+    #[error("TLS_CERT_PATH and TLS_KEY_PATH must be set together")]
+    Incomplete,
+    #[error("cannot read {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("no PEM certificates found in {0}")]
+    NoCertificates(PathBuf),
+    #[error("no PEM private key found in {0}")]
+    NoPrivateKey(PathBuf),
+    #[error("cannot use certificate {0} with key {1}: {2}")]
+    Invalid(PathBuf, PathBuf, rustls::Error),
+}
+
+/// Locations of the PEM certificate chain and private key
+#[derive(Debug, Clone)]
+pub struct TlsPaths {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl TlsPaths {
+    /// Read and check the certificate and key
+    pub fn load(&self) -> Result<Arc<ServerConfig>, TlsError> {
+        let open = |path: &PathBuf| {
+            File::open(path)
+                .map(BufReader::new)
+                .map_err(|e| TlsError::Io(path.clone(), e))
+        };
+
+        let certs = rustls_pemfile::certs(&mut open(&self.cert)?)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| TlsError::Io(self.cert.clone(), e))?;
+        if certs.is_empty() {
+            return Err(TlsError::NoCertificates(self.cert.clone()));
+        }
+        let key = rustls_pemfile::private_key(&mut open(&self.key)?)
+            .map_err(|e| TlsError::Io(self.key.clone(), e))?
+            .ok_or_else(|| TlsError::NoPrivateKey(self.key.clone()))?;
+
+        let invalid = |e| TlsError::Invalid(self.cert.clone(), self.key.clone(), e);
+        let mut config =
+            ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .map_err(invalid)?
+                .with_no_client_auth()
+                .with_single_cert(certs, key)
+                .map_err(invalid)?;
+        // WebSocket upgrades need HTTP/1.1
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(Arc::new(config))
+    }
+
+    /// Replace the certificate served by `config` with the files on disk
+    pub fn reload(&self, config: &RustlsConfig) -> Result<(), TlsError> {
+        config.reload_from_config(self.load()?);
+        Ok(())
+    }
+}
+
+/// Reload the certificate and key whenever the process receives SIGHUP
+#[cfg(unix)]
+pub async fn reload_on_sighup(paths: TlsPaths, config: RustlsConfig) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            tracing::error!("Cannot listen for SIGHUP, TLS reload disabled: {}", e);
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        match paths.reload(&config) {
+            Ok(()) => tracing::info!("Reloaded TLS certificate {}", paths.cert.display()),
+            Err(e) => tracing::error!("Keeping the current TLS certificate: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::get, Router};
+    use futures::StreamExt;
+    use rustls::pki_types::{CertificateDer, ServerName};
+    use std::net::SocketAddr;
+    use std::path::Path;
+    use tokio_rustls::TlsConnector;
+    use tokio_tungstenite::tungstenite::Message;
+
+    use crate::models::AppState;
+    use crate::ws::ws_handler;
+
+    /// Write a fresh self-signed certificate for localhost to `dir`
+    fn self_signed(dir: &Path) -> (TlsPaths, CertificateDer<'static>) {
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let paths = TlsPaths {
+            cert: dir.join("cert.pem"),
+            key: dir.join("key.pem"),
+        };
+        std::fs::write(&paths.cert, generated.cert.pem()).unwrap();
+        std::fs::write(&paths.key, generated.key_pair.serialize_pem()).unwrap();
+        (paths, generated.cert.der().clone())
+    }
+
+    /// Open a websocket to `/v1` trusting only `cert`, returning the welcome
+    async fn wss_welcome(addr: SocketAddr, cert: &CertificateDer<'static>) -> Option<String> {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(cert.clone()).unwrap();
+        let client = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+        let tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let name = ServerName::try_from("localhost").unwrap();
+        let tls = TlsConnector::from(Arc::new(client))
+            .connect(name, tcp)
+            .await
+            .ok()?;
+        let (mut ws, _) = tokio_tungstenite::client_async("wss://localhost/v1", tls)
+            .await
+            .unwrap();
+        match ws.next().await {
+            Some(Ok(Message::Text(text))) => Some(text),
+            other => panic!("expected a welcome message, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_load_reports_bad_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let (paths, _) = self_signed(dir.path());
+        assert!(paths.load().is_ok());
+
+        let missing = TlsPaths {
+            cert: dir.path().join("missing.pem"),
+            key: paths.key.clone(),
+        };
+        let error = missing.load().unwrap_err();
+        assert!(matches!(error, TlsError::Io(..)));
+        assert!(error.to_string().contains("missing.pem"));
+
+        let swapped = TlsPaths {
+            cert: paths.key.clone(),
+            key: paths.cert.clone(),
+        };
+        assert!(matches!(swapped.load(), Err(TlsError::NoCertificates(_))));
+
+        let keyless = TlsPaths {
+            cert: paths.cert.clone(),
+            key: paths.cert.clone(),
+        };
+        assert!(matches!(keyless.load(), Err(TlsError::NoPrivateKey(_))));
+
+        // A key belonging to another certificate
+        let other = tempfile::tempdir().unwrap();
+        let (other, _) = self_signed(other.path());
+        let mismatched = TlsPaths {
+            cert: paths.cert.clone(),
+            key: other.key,
+        };
+        assert!(matches!(mismatched.load(), Err(TlsError::Invalid(..))));
+    }
+
+    #[tokio::test]
+    async fn test_serves_wss_and_reloads() {
+        let dir = tempfile::tempdir().unwrap();
+        let (paths, first) = self_signed(dir.path());
+        let config = RustlsConfig::from_config(paths.load().unwrap());
+
+        let app = Router::new()
+            .route("/v1", get(ws_handler))
+            .with_state(Arc::new(AppState::new(300)));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = axum_server::from_tcp_rustls(listener, config.clone())
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>());
+        tokio::spawn(server);
+
+        let welcome = wss_welcome(addr, &first).await.unwrap();
+        assert!(welcome.contains(r#""type":"welcome""#));
+
+        // A renewed certificate is served once reloaded
+        let (_, second) = self_signed(dir.path());
+        paths.reload(&config).unwrap();
+        assert!(wss_welcome(addr, &first).await.is_none());
+        assert!(wss_welcome(addr, &second).await.is_some());
+
+        // Broken files keep the renewed certificate in place
+        std::fs::write(&paths.cert, "not a certificate").unwrap();
+        assert!(paths.reload(&config).is_err());
+        assert!(wss_welcome(addr, &second).await.is_some());
+    }
+}
@@ -0,0 +1,175 @@
+//! Welcome message sent to every client
+//!
+//! The message of the day and the error come from configuration and can be
+//! changed while the server runs, by editing the welcome file or through
+//! the admin API. While an error is set the server is unavailable: clients
+//! are told why in the welcome message and their binds are refused.
+
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::models::WelcomeInfo;
+
+/// How often the welcome file is checked for changes
+pub const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The configurable parts of the welcome message
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WelcomeSettings {
+    /// Message of the day shown to users
+    #[serde(default)]
+    pub motd: Option<String>,
+    /// Reason the server is unavailable
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// Errors loading the welcome file
+#[derive(Debug, thiserror::Error)]
+pub enum WelcomeError {
+    #[error("cannot read {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("invalid welcome file {0}: {1}")]
+    Parse(PathBuf, serde_json::Error),
+}
+
+/// Current welcome settings, optionally backed by a JSON file
+#[derive(Debug, Default)]
+pub struct Welcome {
+    settings: RwLock<WelcomeSettings>,
+    file: Option<PathBuf>,
+    /// Modification time of the file when it was last loaded
+    loaded: Mutex<Option<SystemTime>>,
+}
+
+impl Welcome {
+    pub fn new(settings: WelcomeSettings) -> Self {
+        Self {
+            settings: RwLock::new(settings),
+            ..Default::default()
+        }
+    }
+
+    /// Load settings from a JSON file like `{"motd": "...", "error": null}`
+    ///
+    /// The file takes precedence over the initial settings whenever it
+    /// changes.
+    pub fn with_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.file = Some(path.into());
+        self
+    }
+
+    /// The current settings
+    pub fn settings(&self) -> WelcomeSettings {
+        self.settings.read().unwrap().clone()
+    }
+
+    /// Replace the current settings until the welcome file changes
+    pub fn set(&self, settings: WelcomeSettings) {
+        tracing::info!(
+            "Welcome updated: motd={:?} error={:?}",
+            settings.motd,
+            settings.error
+        );
+        *self.settings.write().unwrap() = settings;
+    }
+
+    /// The welcome message for a new connection
+    pub fn info(&self) -> WelcomeInfo {
+        let settings = self.settings();
+        WelcomeInfo {
+            motd: settings.motd,
+            error: settings.error,
+            ..WelcomeInfo::default()
+        }
+    }
+
+    /// The reason the server is unavailable, if it is
+    pub fn error(&self) -> Option<String> {
+        self.settings.read().unwrap().error.clone()
+    }
+
+    /// Reload the welcome file if it changed since it was last loaded
+    ///
+    /// Returns whether the settings were replaced.
+    pub fn reload_if_changed(&self) -> Result<bool, WelcomeError> {
+        let Some(path) = &self.file else {
+            return Ok(false);
+        };
+        let io_error = |e| WelcomeError::Io(path.clone(), e);
+
+        let modified = std::fs::metadata(path)
+            .and_then(|m| m.modified())
+            .map_err(io_error)?;
+        // Remember the version even if it is broken, to report it only once
+        if self.loaded.lock().unwrap().replace(modified) == Some(modified) {
+            return Ok(false);
+        }
+
+        let text = std::fs::read_to_string(path).map_err(io_error)?;
+        let settings =
+            serde_json::from_str(&text).map_err(|e| WelcomeError::Parse(path.clone(), e))?;
+        self.set(settings);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_info_keeps_server_version() {
+        let welcome = Welcome::new(WelcomeSettings {
+            motd: Some("hello".to_string()),
+            error: None,
+        });
+        let info = welcome.info();
+        assert_eq!(info.motd.as_deref(), Some("hello"));
+        assert_eq!(info.server_version, WelcomeInfo::default().server_version);
+        assert_eq!(welcome.error(), None);
+    }
+
+    #[test]
+    fn test_reload_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("welcome.json");
+        std::fs::write(&path, r#"{"motd": "maintenance at 18:00"}"#).unwrap();
+
+        let welcome = Welcome::default().with_file(&path);
+        assert!(welcome.reload_if_changed().unwrap());
+        assert_eq!(welcome.info().motd.as_deref(), Some("maintenance at 18:00"));
+        assert!(!welcome.reload_if_changed().unwrap());
+
+        // A newer file replaces the settings, including runtime changes
+        welcome.set(WelcomeSettings::default());
+        std::fs::write(&path, r#"{"error": "down for maintenance"}"#).unwrap();
+        let later = SystemTime::now() + Duration::from_secs(10);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert!(welcome.reload_if_changed().unwrap());
+        assert_eq!(welcome.error().as_deref(), Some("down for maintenance"));
+        assert_eq!(welcome.info().motd, None);
+
+        // A broken file keeps the last good settings
+        std::fs::write(&path, "{").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later + Duration::from_secs(10))
+            .unwrap();
+        assert!(matches!(
+            welcome.reload_if_changed(),
+            Err(WelcomeError::Parse(..))
+        ));
+        assert!(!welcome.reload_if_changed().unwrap());
+        assert_eq!(welcome.error().as_deref(), Some("down for maintenance"));
+    }
+}
@@ -5,27 +5,82 @@
 use axum::{
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
-        State,
+        ConnectInfo, State,
     },
-    response::IntoResponse,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
 };
 use futures::{sink::SinkExt, stream::StreamExt};
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 use tokio::sync::mpsc;
 use uuid::Uuid;
 
-use crate::models::{AppState, ClientMessage, NameplateInfo, ServerMessage};
+use crate::limits::{ConnectionPermit, Rejection};
+use crate::models::{
+    AppState, ClientMessage, LifecycleError, Mood, NameplateInfo, PermissionRequired, ServerMessage,
+};
+use crate::permission::PermissionError;
 
 /// WebSocket upgrade handler
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     State(state): State<Arc<AppState>>,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, state))
+) -> Response {
+    let ip = client_ip(addr, &headers, state.trust_proxy_headers);
+    match state.limiter.connect(ip) {
+        Ok(permit) => ws
+            .on_upgrade(move |socket| handle_socket(socket, state, permit))
+            .into_response(),
+        Err(e) => refuse(ip, e),
+    }
+}
+
+/// Address of the client, optionally taken from proxy headers
+///
+/// Only the last X-Forwarded-For entry is used: the proxy appends the
+/// address it saw, while earlier entries come from the client.
+pub(crate) fn client_ip(
+    addr: SocketAddr,
+    headers: &HeaderMap,
+    trust_proxy_headers: bool,
+) -> IpAddr {
+    if trust_proxy_headers {
+        let header = |name| {
+            headers
+                .get_all(name)
+                .iter()
+                .next_back()
+                .and_then(|v| v.to_str().ok())
+        };
+        let forwarded = header("x-forwarded-for")
+            .and_then(|v| v.rsplit(',').next())
+            .and_then(|v| v.trim().parse().ok())
+            .or_else(|| header("x-real-ip").and_then(|v| v.trim().parse().ok()));
+        if let Some(ip) = forwarded {
+            return ip;
+        }
+    }
+    addr.ip()
+}
+
+/// Response refusing a connection from `ip`
+pub(crate) fn refuse(ip: IpAddr, rejection: Rejection) -> Response {
+    tracing::warn!("Refused connection from {}: {}", ip, rejection);
+    let status = match rejection {
+        Rejection::Banned => StatusCode::FORBIDDEN,
+        _ => StatusCode::TOO_MANY_REQUESTS,
+    };
+    (status, rejection.to_string()).into_response()
 }
 
 /// Handle a WebSocket connection
-async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
+///
+/// The permit holds the client's connection slot until the socket closes.
+async fn handle_socket(socket: WebSocket, state: Arc<AppState>, permit: ConnectionPermit) {
+    let ip = permit.ip();
     let (mut sender, mut receiver) = socket.split();
 
     // Create channel for outgoing messages
@@ -36,17 +91,25 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
     tracing::info!("Client {} connected", client_id);
 
     // Send welcome message
-    let welcome = ServerMessage::welcome();
+    let mut welcome = state.welcome.info();
+    welcome.permission_required = state
+        .get_client_challenge(client_id)
+        .await
+        .map(|hashcash| PermissionRequired { hashcash });
+    let welcome = ServerMessage::welcome(welcome);
     if sender.send(Message::Text(welcome.to_json())).await.is_err() {
         tracing::error!("Failed to send welcome message");
+        state.metrics.websocket_error();
         state.unregister_client(client_id).await;
         return;
     }
 
     // Spawn task to forward outgoing messages
+    let send_state = state.clone();
     let send_task = tokio::spawn(async move {
         while let Some(msg) = rx.recv().await {
             if sender.send(Message::Text(msg)).await.is_err() {
+                send_state.metrics.websocket_error();
                 break;
             }
         }
@@ -58,11 +121,17 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
         while let Some(result) = receiver.next().await {
             match result {
                 Ok(Message::Text(text)) => {
-                    if let Err(e) = handle_message(&state_clone, client_id, &text).await {
+                    let result = match state_clone.limiter.check_message(ip) {
+                        Ok(()) => handle_message(&state_clone, client_id, ip, &text).await,
+                        Err(e) => Err(e.to_string()),
+                    };
+                    if let Err(e) = result {
                         tracing::warn!("Error handling message: {}", e);
-                        // Send error response
+                        state_clone.metrics.protocol_error();
+                        // Send error response, echoing the offending message
                         if let Some(sender) = state_clone.get_client_sender(client_id).await {
-                            let error = ServerMessage::error(&e, None);
+                            let orig: Option<ClientMessage> = serde_json::from_str(&text).ok();
+                            let error = ServerMessage::error(&e, orig.as_ref());
                             let _ = sender.send(error.to_json());
                         }
                     }
@@ -73,6 +142,7 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
                 }
                 Err(e) => {
                     tracing::error!("WebSocket error for client {}: {}", client_id, e);
+                    state_clone.metrics.websocket_error();
                     break;
                 }
                 _ => {}
@@ -88,11 +158,17 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
 
     // Cleanup
     state.unregister_client(client_id).await;
+    drop(permit);
     tracing::info!("Client {} disconnected", client_id);
 }
 
 /// Handle an incoming message
-async fn handle_message(state: &Arc<AppState>, client_id: Uuid, text: &str) -> Result<(), String> {
+async fn handle_message(
+    state: &Arc<AppState>,
+    client_id: Uuid,
+    ip: IpAddr,
+    text: &str,
+) -> Result<(), String> {
     let message: ClientMessage =
         serde_json::from_str(text).map_err(|e| format!("Invalid message format: {}", e))?;
 
@@ -102,7 +178,27 @@ async fn handle_message(state: &Arc<AppState>, client_id: Uuid, text: &str) -> R
         .ok_or("Client not found")?;
 
     match message {
+        ClientMessage::SubmitPermissions { method, stamp } => {
+            if let Some(challenge) = state.get_client_challenge(client_id).await {
+                if method != "hashcash" {
+                    return Err(PermissionError::UnsupportedMethod.to_string());
+                }
+                challenge.verify(&stamp).map_err(|e| e.to_string())?;
+                state.grant_permission(client_id).await;
+            }
+
+            let ack = ServerMessage::Ack;
+            let _ = sender.send(ack.to_json());
+        }
+
         ClientMessage::Bind { appid, side } => {
+            // The welcome message already told the client why
+            if let Some(error) = state.welcome.error() {
+                return Err(error);
+            }
+            if state.get_client_challenge(client_id).await.is_some() {
+                return Err(PermissionError::Required.to_string());
+            }
             state
                 .bind_client(client_id, appid.clone(), side.clone())
                 .await;
@@ -134,7 +230,14 @@ async fn handle_message(state: &Arc<AppState>, client_id: Uuid, text: &str) -> R
         ClientMessage::Allocate => {
             let appid = state.get_client_appid(client_id).await.ok_or("Not bound")?;
 
-            let nameplate = state.allocate_nameplate(&appid).await;
+            state
+                .limiter
+                .check_allocate(ip)
+                .map_err(|e| e.to_string())?;
+            let nameplate = state
+                .allocate_nameplate(&appid)
+                .await
+                .map_err(|e| e.to_string())?;
             let response = ServerMessage::Allocated { nameplate };
             let _ = sender.send(response.to_json());
         }
@@ -143,10 +246,22 @@ async fn handle_message(state: &Arc<AppState>, client_id: Uuid, text: &str) -> R
             let appid = state.get_client_appid(client_id).await.ok_or("Not bound")?;
             let side = state.get_client_side(client_id).await.ok_or("Not bound")?;
 
-            let mailbox_id = state
-                .claim_nameplate(&nameplate, &side, &appid)
-                .await
-                .ok_or("Failed to claim nameplate")?;
+            state.limiter.check_claim(ip).map_err(|e| e.to_string())?;
+            let mailbox_id = match state.claim_nameplate(&nameplate, &side, &appid).await {
+                Ok(mailbox_id) => mailbox_id,
+                Err(e) => {
+                    // Failed claims hint at nameplate guessing
+                    if matches!(
+                        e,
+                        LifecycleError::UnknownNameplate | LifecycleError::Crowded
+                    ) {
+                        state.limiter.claim_failed(ip);
+                    }
+                    return Err(e.to_string());
+                }
+            };
+            state.limiter.claim_succeeded(ip);
+            state.set_client_nameplate(client_id, Some(nameplate)).await;
 
             let response = ServerMessage::Claimed {
                 mailbox: mailbox_id,
@@ -157,40 +272,62 @@ async fn handle_message(state: &Arc<AppState>, client_id: Uuid, text: &str) -> R
         ClientMessage::Release { nameplate } => {
             let side = state.get_client_side(client_id).await.ok_or("Not bound")?;
 
-            if let Some(np) = nameplate {
-                state.release_nameplate(&np, &side).await;
-            }
+            // Without an explicit nameplate, release the one claimed here
+            let nameplate = match nameplate {
+                Some(np) => np,
+                None => state
+                    .get_client_nameplate(client_id)
+                    .await
+                    .ok_or("No nameplate to release")?,
+            };
+            state
+                .release_nameplate(&nameplate, &side)
+                .await
+                .map_err(|e| e.to_string())?;
+            state.set_client_nameplate(client_id, None).await;
 
             let response = ServerMessage::Released;
             let _ = sender.send(response.to_json());
         }
 
-        ClientMessage::Open { mailbox } => {
+        ClientMessage::Open { mailbox, last_seen } => {
+            let appid = state.get_client_appid(client_id).await.ok_or("Not bound")?;
             let side = state.get_client_side(client_id).await.ok_or("Not bound")?;
 
-            if !state.open_mailbox(&mailbox, &side).await {
-                return Err("Failed to open mailbox".to_string());
-            }
+            state
+                .limiter
+                .check_open(&mailbox, ip)
+                .map_err(|e| e.to_string())?;
+            state
+                .open_mailbox(&mailbox, &side, &appid)
+                .await
+                .map_err(|e| e.to_string())?;
+            state.limiter.mailbox_opened(&mailbox, &side, ip);
 
             state.set_client_mailbox(client_id, mailbox.clone()).await;
 
-            // Send ack
+            // Replay the messages the client has not seen yet, its own
+            // included, so a client that reconnects can tell which of its
+            // messages were stored. They go out before the ack so the
+            // client has them by the time the open completes.
+            let messages = state
+                .get_messages_after(&mailbox, last_seen.unwrap_or(0))
+                .await;
+            for msg in &messages {
+                let response = ServerMessage::Message {
+                    side: msg.side.clone(),
+                    phase: msg.phase.clone(),
+                    body: msg.body.clone(),
+                    id: msg.id,
+                };
+                let _ = sender.send(response.to_json());
+            }
+            if let Some(last) = messages.last() {
+                state.update_client_last_seen(client_id, last.id).await;
+            }
+
             let ack = ServerMessage::Ack;
             let _ = sender.send(ack.to_json());
-
-            // Send any existing messages
-            let messages = state.get_all_messages(&mailbox).await;
-            for msg in messages {
-                if msg.side != side {
-                    let response = ServerMessage::Message {
-                        side: msg.side.clone(),
-                        phase: msg.phase.clone(),
-                        body: msg.body.clone(),
-                        id: msg.id,
-                    };
-                    let _ = sender.send(response.to_json());
-                }
-            }
         }
 
         ClientMessage::Add { phase, body } => {
@@ -203,6 +340,10 @@ async fn handle_message(state: &Arc<AppState>, client_id: Uuid, text: &str) -> R
                 .and_then(|c| c.mailbox_id.clone())
                 .ok_or("No mailbox open")?;
             drop(clients);
+            state
+                .limiter
+                .check_mailbox(&mailbox_id)
+                .map_err(|e| e.to_string())?;
 
             let msg = state
                 .add_message(&mailbox_id, &side, &phase, &body)
@@ -225,7 +366,7 @@ async fn handle_message(state: &Arc<AppState>, client_id: Uuid, text: &str) -> R
                 .await;
         }
 
-        ClientMessage::Close { mailbox, mood: _ } => {
+        ClientMessage::Close { mailbox, mood } => {
             let side = state.get_client_side(client_id).await.ok_or("Not bound")?;
 
             // Get mailbox ID
@@ -239,7 +380,11 @@ async fn handle_message(state: &Arc<AppState>, client_id: Uuid, text: &str) -> R
                     .ok_or("No mailbox to close")?
             };
 
-            state.close_mailbox(&mailbox_id, &side).await;
+            let mood = mood.as_deref().and_then(Mood::parse);
+            if let Some(mood) = mood {
+                state.limiter.report_mood(&mailbox_id, &side, mood);
+            }
+            state.close_mailbox(&mailbox_id, &side, mood).await;
 
             let response = ServerMessage::Closed;
             let _ = sender.send(response.to_json());
@@ -253,3 +398,303 @@ async fn handle_message(state: &Arc<AppState>, client_id: Uuid, text: &str) -> R
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mpsc::UnboundedReceiver;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    use crate::limits::Limits;
+    use crate::permission::tests::mint;
+    use crate::permission::HashcashChallenge;
+    use crate::storage::{MemoryStorage, Storage};
+    use crate::welcome::{Welcome, WelcomeSettings};
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    async fn connect(state: &Arc<AppState>, side: &str) -> (Uuid, UnboundedReceiver<String>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let client = state.register_client(tx).await;
+        let bind = format!(r#"{{"type":"bind","appid":"test-app","side":"{}"}}"#, side);
+        handle_message(state, client, IP, &bind).await.unwrap();
+        (client, rx)
+    }
+
+    async fn claimed_mailbox(state: &Arc<AppState>, side: &str) -> String {
+        let nameplate = state.allocate_nameplate("test-app").await.unwrap();
+        state
+            .claim_nameplate(&nameplate, side, "test-app")
+            .await
+            .unwrap()
+    }
+
+    fn replayed_ids(rx: &mut UnboundedReceiver<String>) -> Vec<u64> {
+        let mut ids = Vec::new();
+        while let Ok(text) = rx.try_recv() {
+            if let Ok(ServerMessage::Message { id, .. }) = serde_json::from_str(&text) {
+                ids.push(id);
+            }
+        }
+        ids
+    }
+
+    #[test]
+    fn test_client_ip_from_proxy_headers() {
+        let addr = SocketAddr::from(([10, 0, 0, 2], 40000));
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "1.2.3.4, 203.0.113.7".parse().unwrap());
+        headers.insert("x-real-ip", "198.51.100.1".parse().unwrap());
+
+        // The client made up the first entry; the proxy appended the last
+        assert_eq!(
+            client_ip(addr, &headers, true),
+            "203.0.113.7".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(client_ip(addr, &headers, false), addr.ip());
+
+        headers.insert("x-forwarded-for", "garbage".parse().unwrap());
+        assert_eq!(
+            client_ip(addr, &headers, true),
+            "198.51.100.1".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reopen_replays_only_unseen_messages() {
+        let state = Arc::new(AppState::new(300));
+
+        let (a, _rx_a) = connect(&state, "side-a").await;
+        let mailbox = claimed_mailbox(&state, "side-a").await;
+        let open = format!(r#"{{"type":"open","mailbox":"{}"}}"#, mailbox);
+        handle_message(&state, a, IP, &open).await.unwrap();
+        for phase in ["pake", "version", "0"] {
+            let add = format!(r#"{{"type":"add","phase":"{}","body":"00"}}"#, phase);
+            handle_message(&state, a, IP, &add).await.unwrap();
+        }
+
+        // A fresh open replays everything
+        let (b, mut rx_b) = connect(&state, "side-b").await;
+        handle_message(&state, b, IP, &open).await.unwrap();
+        assert_eq!(replayed_ids(&mut rx_b), vec![1, 2, 3]);
+        assert_eq!(state.clients.read().await[&b].last_seen_message_id, 3);
+
+        // The connection drops and the same side resumes after message 1
+        state.unregister_client(b).await;
+        let (b, mut rx_b) = connect(&state, "side-b").await;
+        let reopen = format!(r#"{{"type":"open","mailbox":"{}","last_seen":1}}"#, mailbox);
+        handle_message(&state, b, IP, &reopen).await.unwrap();
+        assert_eq!(replayed_ids(&mut rx_b), vec![2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_reopen_replays_own_messages_before_ack() {
+        let state = Arc::new(AppState::new(300));
+
+        let (a, _rx_a) = connect(&state, "side-a").await;
+        let mailbox = claimed_mailbox(&state, "side-a").await;
+        let open = format!(r#"{{"type":"open","mailbox":"{}"}}"#, mailbox);
+        handle_message(&state, a, IP, &open).await.unwrap();
+        let add = r#"{"type":"add","phase":"pake","body":"00"}"#;
+        handle_message(&state, a, IP, add).await.unwrap();
+
+        // The side reconnects and learns that its message was stored
+        state.unregister_client(a).await;
+        let (a, mut rx_a) = connect(&state, "side-a").await;
+        handle_message(&state, a, IP, &open).await.unwrap();
+        let replies: Vec<ServerMessage> = std::iter::from_fn(|| rx_a.try_recv().ok())
+            .filter_map(|text| serde_json::from_str(&text).ok())
+            .collect();
+        assert!(matches!(
+            replies.as_slice(),
+            [.., ServerMessage::Message { side, .. }, ServerMessage::Ack] if side == "side-a"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_keeps_mailbox_open() {
+        let state = Arc::new(AppState::new(300));
+
+        let (a, _rx_a) = connect(&state, "side-a").await;
+        let mailbox = claimed_mailbox(&state, "side-a").await;
+        let open = format!(r#"{{"type":"open","mailbox":"{}"}}"#, mailbox);
+        handle_message(&state, a, IP, &open).await.unwrap();
+        state.unregister_client(a).await;
+
+        let (a, _rx_a) = connect(&state, "side-a").await;
+        assert!(handle_message(&state, a, IP, &open).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle_errors() {
+        let state = Arc::new(AppState::new(300));
+        let nameplate = state.allocate_nameplate("test-app").await.unwrap();
+        let claim = format!(r#"{{"type":"claim","nameplate":"{}"}}"#, nameplate);
+
+        let (a, _rx_a) = connect(&state, "side-a").await;
+        let (b, _rx_b) = connect(&state, "side-b").await;
+        let (c, _rx_c) = connect(&state, "side-c").await;
+        handle_message(&state, a, IP, &claim).await.unwrap();
+        handle_message(&state, b, IP, &claim).await.unwrap();
+
+        let crowded = handle_message(&state, c, IP, &claim).await;
+        assert_eq!(crowded.unwrap_err(), "crowded");
+
+        let unknown = handle_message(&state, c, IP, r#"{"type":"claim","nameplate":"1000"}"#).await;
+        assert_eq!(unknown.unwrap_err(), "unknown nameplate");
+
+        // Only a side holding a claim may release it
+        let release = format!(r#"{{"type":"release","nameplate":"{}"}}"#, nameplate);
+        let rejected = handle_message(&state, c, IP, &release).await;
+        assert_eq!(rejected.unwrap_err(), "nameplate not claimed");
+        handle_message(&state, a, IP, r#"{"type":"release"}"#)
+            .await
+            .unwrap();
+        assert_eq!(state.get_client_nameplate(a).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_claim_create_is_configurable() {
+        let state = Arc::new(AppState::new(300).with_claim_create(true));
+        let (a, mut rx_a) = connect(&state, "side-a").await;
+
+        handle_message(&state, a, IP, r#"{"type":"claim","nameplate":"42"}"#)
+            .await
+            .unwrap();
+        let claimed = std::iter::from_fn(|| rx_a.try_recv().ok())
+            .any(|text| text.contains(r#""type":"claimed""#));
+        assert!(claimed);
+    }
+
+    #[tokio::test]
+    async fn test_failed_claims_back_off() {
+        let state = Arc::new(AppState::new(300).with_limits(Limits {
+            claim_failures_allowed: 1,
+            claim_backoff_base: Duration::from_secs(60),
+            ..Limits::default()
+        }));
+        let (a, _rx_a) = connect(&state, "side-a").await;
+        let guess = r#"{"type":"claim","nameplate":"1000"}"#;
+
+        for _ in 0..2 {
+            let unknown = handle_message(&state, a, IP, guess).await;
+            assert_eq!(unknown.unwrap_err(), "unknown nameplate");
+        }
+
+        // Even a valid claim waits out the backoff
+        let nameplate = state.allocate_nameplate("test-app").await.unwrap();
+        let claim = format!(r#"{{"type":"claim","nameplate":"{}"}}"#, nameplate);
+        let refused = handle_message(&state, a, IP, &claim).await;
+        assert_eq!(refused.unwrap_err(), "too many failed claims, retry in 60s");
+    }
+
+    #[tokio::test]
+    async fn test_allocate_is_limited() {
+        let state = Arc::new(AppState::new(300).with_limits(Limits {
+            allocations_per_min: 2,
+            ..Limits::default()
+        }));
+        let (a, _rx_a) = connect(&state, "side-a").await;
+        let allocate = r#"{"type":"allocate"}"#;
+
+        handle_message(&state, a, IP, allocate).await.unwrap();
+        handle_message(&state, a, IP, allocate).await.unwrap();
+        let refused = handle_message(&state, a, IP, allocate).await;
+        assert_eq!(refused.unwrap_err(), "too many allocations");
+    }
+
+    #[tokio::test]
+    async fn test_allocate_without_free_nameplates() {
+        let storage = Arc::new(MemoryStorage::new(300));
+        let state = Arc::new(
+            AppState::with_storage(storage.clone(), 300).with_limits(Limits {
+                allocations_per_min: 0,
+                ..Limits::default()
+            }),
+        );
+        for id in 1..1000 {
+            storage
+                .claim_nameplate(&id.to_string(), "side-a", "test-app", true)
+                .await
+                .unwrap();
+        }
+
+        let (a, _rx_a) = connect(&state, "side-a").await;
+        let refused = handle_message(&state, a, IP, r#"{"type":"allocate"}"#).await;
+        assert_eq!(refused.unwrap_err(), "no free nameplates");
+    }
+
+    #[tokio::test]
+    async fn test_scary_mailbox_is_refused() {
+        let state = Arc::new(AppState::new(300));
+        let mailbox = claimed_mailbox(&state, "side-a").await;
+        let open = format!(r#"{{"type":"open","mailbox":"{}"}}"#, mailbox);
+
+        let (a, _rx_a) = connect(&state, "side-a").await;
+        let (b, _rx_b) = connect(&state, "side-b").await;
+        handle_message(&state, a, IP, &open).await.unwrap();
+        handle_message(&state, b, IP, &open).await.unwrap();
+
+        // The PAKE failed on side A
+        handle_message(&state, a, IP, r#"{"type":"close","mood":"scary"}"#)
+            .await
+            .unwrap();
+
+        let add = r#"{"type":"add","phase":"version","body":"00"}"#;
+        assert_eq!(
+            handle_message(&state, b, IP, add).await.unwrap_err(),
+            "scary"
+        );
+        let (c, _rx_c) = connect(&state, "side-a").await;
+        assert_eq!(
+            handle_message(&state, c, IP, &open).await.unwrap_err(),
+            "scary"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unavailable_server_refuses_bind() {
+        let state = Arc::new(
+            AppState::new(300).with_welcome(Welcome::new(WelcomeSettings {
+                motd: None,
+                error: Some("down for maintenance".to_string()),
+            })),
+        );
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let client = state.register_client(tx).await;
+
+        let bind = r#"{"type":"bind","appid":"test-app","side":"side-a"}"#;
+        let refused = handle_message(&state, client, IP, bind).await;
+        assert_eq!(refused.unwrap_err(), "down for maintenance");
+    }
+
+    #[tokio::test]
+    async fn test_bind_requires_hashcash() {
+        let state = Arc::new(AppState::new(300).with_hashcash(8));
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let client = state.register_client(tx).await;
+        let challenge = state.get_client_challenge(client).await.unwrap();
+        assert_eq!(challenge.bits, 8);
+
+        let bind = r#"{"type":"bind","appid":"test-app","side":"side-a"}"#;
+        assert_eq!(
+            handle_message(&state, client, IP, bind).await.unwrap_err(),
+            "permission required"
+        );
+
+        // A stamp for another connection's resource is refused
+        let other = HashcashChallenge::new(8);
+        let submit = |stamp: String| {
+            serde_json::json!({"type": "submit-permissions", "method": "hashcash", "stamp": stamp})
+                .to_string()
+        };
+        let wrong = submit(mint(&other));
+        assert!(handle_message(&state, client, IP, &wrong).await.is_err());
+        assert!(handle_message(&state, client, IP, bind).await.is_err());
+
+        let valid = submit(mint(&challenge));
+        handle_message(&state, client, IP, &valid).await.unwrap();
+        handle_message(&state, client, IP, bind).await.unwrap();
+    }
+}
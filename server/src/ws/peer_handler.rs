@@ -1,23 +1,38 @@
 //! Simple peer-pairing WebSocket handler
 //!
-//! Pairs two clients by code and relays messages between them.
+//! Pairs two clients by code and relays messages between them. Clients
+//! are held to the same per-IP limits as on the mailbox endpoint: a code
+//! that is already paired counts as a failed claim. The endpoint has no
+//! permission step, so it is closed when the server requires hashcash.
 
 use axum::{
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
-        Path, State,
+        ConnectInfo, Path, State,
     },
-    response::IntoResponse,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
 };
 use futures::{sink::SinkExt, stream::StreamExt};
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
 use tokio::sync::{mpsc, RwLock};
 
+use super::handler::{client_ip, refuse};
+use crate::limits::ConnectionPermit;
+use crate::models::AppState;
+use crate::permission::PermissionError;
+
 /// Type alias for peer sender channel
 type PeerSender = mpsc::UnboundedSender<String>;
 
 /// Shared state for peer connections
 pub struct PeerState {
+    /// Mailbox server state, for its limits and settings
+    app: Arc<AppState>,
     /// Active peers waiting for a partner, keyed by code
     waiting: RwLock<HashMap<String, PeerSender>>,
     /// Connected peer pairs, keyed by code
@@ -25,17 +40,27 @@ pub struct PeerState {
 }
 
 impl PeerState {
-    pub fn new() -> Self {
+    pub fn new(app: Arc<AppState>) -> Self {
         Self {
+            app,
             waiting: RwLock::new(HashMap::new()),
             pairs: RwLock::new(HashMap::new()),
         }
     }
-}
 
-impl Default for PeerState {
-    fn default() -> Self {
-        Self::new()
+    /// Decide whether `ip` may join `code`, taking a connection slot
+    async fn admit(&self, code: &str, ip: IpAddr) -> Result<ConnectionPermit, Response> {
+        if self.app.hashcash_bits > 0 {
+            let refusal = PermissionError::Required.to_string();
+            return Err((StatusCode::FORBIDDEN, refusal).into_response());
+        }
+        let limiter = &self.app.limiter;
+        limiter.check_claim(ip).map_err(|e| refuse(ip, e))?;
+        if self.pairs.read().await.contains_key(code) {
+            limiter.claim_failed(ip);
+            return Err((StatusCode::CONFLICT, "crowded").into_response());
+        }
+        limiter.connect(ip).map_err(|e| refuse(ip, e))
     }
 }
 
@@ -43,14 +68,30 @@ impl Default for PeerState {
 pub async fn peer_ws_handler(
     ws: WebSocketUpgrade,
     Path(code): Path<String>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     State(state): State<Arc<PeerState>>,
-) -> impl IntoResponse {
+) -> Response {
     tracing::info!("Peer connection request for code: {}", code);
-    ws.on_upgrade(move |socket| handle_peer_socket(socket, code, state))
+    let ip = client_ip(addr, &headers, state.app.trust_proxy_headers);
+    match state.admit(&code, ip).await {
+        Ok(permit) => ws
+            .on_upgrade(move |socket| handle_peer_socket(socket, code, state, permit))
+            .into_response(),
+        Err(refusal) => refusal,
+    }
 }
 
 /// Handle a peer WebSocket connection
-async fn handle_peer_socket(socket: WebSocket, code: String, state: Arc<PeerState>) {
+///
+/// The permit holds the client's connection slot until the socket closes.
+async fn handle_peer_socket(
+    socket: WebSocket,
+    code: String,
+    state: Arc<PeerState>,
+    permit: ConnectionPermit,
+) {
+    let ip = permit.ip();
     let (mut ws_sender, mut ws_receiver) = socket.split();
 
     // Create channel for outgoing messages to this peer
@@ -82,6 +123,10 @@ async fn handle_peer_socket(socket: WebSocket, code: String, state: Arc<PeerStat
         while let Some(result) = ws_receiver.next().await {
             match result {
                 Ok(Message::Text(text)) => {
+                    if let Err(e) = state.app.limiter.check_message(ip) {
+                        tracing::warn!("Dropping peer for code {}: {}", code, e);
+                        break;
+                    }
                     tracing::debug!("Relaying message for code {}: {} bytes", code, text.len());
                     if partner_tx.send(text).is_err() {
                         tracing::warn!("Partner disconnected for code: {}", code);
@@ -126,6 +171,10 @@ async fn handle_peer_socket(socket: WebSocket, code: String, state: Arc<PeerStat
         while let Some(result) = ws_receiver.next().await {
             match result {
                 Ok(Message::Text(text)) => {
+                    if let Err(e) = state_clone.app.limiter.check_message(ip) {
+                        tracing::warn!("Dropping peer for code {}: {}", code, e);
+                        break;
+                    }
                     // Try to forward to partner if connected
                     let pairs = state_clone.pairs.read().await;
                     if let Some((_peer1_tx, peer2_tx)) = pairs.get(&code) {
@@ -158,5 +207,63 @@ async fn handle_peer_socket(socket: WebSocket, code: String, state: Arc<PeerStat
         pairs.remove(&code);
     }
 
+    drop(permit);
     tracing::info!("Peer disconnected for code: {}", code);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    use crate::limits::Limits;
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    fn status(admitted: Result<ConnectionPermit, Response>) -> StatusCode {
+        admitted
+            .map(|_| StatusCode::OK)
+            .unwrap_or_else(|r| r.status())
+    }
+
+    #[tokio::test]
+    async fn test_hashcash_closes_the_endpoint() {
+        let peers = PeerState::new(Arc::new(AppState::new(300).with_hashcash(8)));
+        assert_eq!(
+            status(peers.admit("7-guitarist-revenge", IP).await),
+            StatusCode::FORBIDDEN
+        );
+
+        let peers = PeerState::new(Arc::new(AppState::new(300)));
+        assert_eq!(
+            status(peers.admit("7-guitarist-revenge", IP).await),
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn test_crowded_codes_back_off() {
+        let app = AppState::new(300).with_limits(Limits {
+            claim_failures_allowed: 0,
+            claim_backoff_base: Duration::from_secs(60),
+            ..Limits::default()
+        });
+        let peers = PeerState::new(Arc::new(app));
+        let (tx, _rx) = mpsc::unbounded_channel();
+        peers
+            .pairs
+            .write()
+            .await
+            .insert("7-guitarist-revenge".to_string(), (tx.clone(), tx));
+
+        assert_eq!(
+            status(peers.admit("7-guitarist-revenge", IP).await),
+            StatusCode::CONFLICT
+        );
+        assert_eq!(
+            status(peers.admit("8-other-code", IP).await),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+}